@@ -0,0 +1,24 @@
+use rustycoat::bench::{bench, bench_load_store, bench_reset, to_json, DriveMode};
+
+// Benchmarks the emulator end to end, running the same compute-heavy ROM in each way the CPU
+// can be driven, then a loop of loads and stores to measure memory access, and finally how
+// quickly a machine can be reset. Usage: bench [cycles] [--json <file>]
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cycles = args.first().and_then(|arg| arg.parse().ok()).unwrap_or(10_000_000);
+
+    let mut results = Vec::new();
+    for mode in DriveMode::ALL {
+        let result = bench(mode, cycles);
+        println!("{}", result);
+        results.push(result);
+    }
+    let result = bench_load_store(cycles);
+    println!("{}", result);
+    results.push(result);
+    println!("{}", bench_reset(10_000));
+
+    if let Some(path) = args.iter().position(|arg| arg == "--json").and_then(|i| args.get(i + 1)) {
+        std::fs::write(path, to_json(&results)).expect("Couldn't write results");
+    }
+}
@@ -16,5 +16,5 @@ fn main() {
     c.add_async(clock);
     c.add_ui(led);
 
-    c.run();
+    c.run().unwrap();
 }
@@ -0,0 +1,19 @@
+use rustycoat::core::clock::*;
+use rustycoat::core::*;
+use rustycoat::gpio::*;
+
+fn main() {
+    // Drive an LED wired to GPIO 17 (header pin 11 on a Raspberry Pi).
+    let mut led = GpioOutput::new(SysfsGpio::new(), 17);
+
+    // Create a 5Hz clock and wire it up to the LED.
+    let mut clock = Clock::new(5);
+    clock.output().connect_to(led.input());
+
+    // Create the computer, add components, and start it up.
+    let mut c = Computer::new();
+    c.add_async(clock);
+    c.add_async(led);
+
+    c.run().unwrap();
+}
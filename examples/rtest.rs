@@ -1,45 +1,161 @@
+use crossbeam_channel::{unbounded, Sender};
+use std::io;
 use std::io::stdin;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use rustycoat::core::clock::*;
 use rustycoat::core::memory::*;
+use rustycoat::core::ports::*;
 use rustycoat::core::*;
 use rustycoat::cpus::c6502::*;
+use rustycoat::roms;
 
-const RESET_PROGRAM: &[u8] = &[
-    0xA9, 0x00, // LDA #$00
-    0x85, 0x05, // STA $05
-    0xA5, 0x05, // LDA $05
-    0x69, 0x01, // ADC #$01
-    0x85, 0x05, // STA $05
-    0x4C, 0x04, 0xE0, // JMP $E004
-];
+fn main() {
+    if std::env::args().any(|arg| arg == "--selftest") {
+        let report = rustycoat::selftest();
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
-const NMI_PROGRAM: &[u8] = &[];
-const IRQ_PROGRAM: &[u8] = &[];
+    // Create a new memory object with the counting ROM loaded into the top 8K, or with --rom
+    // <file>, a ROM image (including its vectors) loaded into the top of memory. With --ram
+    // <pattern>, RAM powers on filled with the pattern: zero, ones, stripes[:<period>] or
+    // random[:<seed>].
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1));
+    let pattern = match arg_value("--ram") {
+        Some(pattern) => pattern.parse().unwrap_or_else(|e| panic!("Bad --ram: {}", e)),
+        None => InitPattern::Zero,
+    };
+    let memory = Memory::new_with_pattern(pattern);
+    let rom_path = arg_value("--rom").map(PathBuf::from);
+    let (origin, end) = match &rom_path {
+        Some(path) => {
+            let rom = RomBank::from_file(path).expect("Couldn't read ROM file");
+            let size = rom.size();
+            assert!(size > 0 && size <= 0x8000 && size.is_multiple_of(0x100), "Bad ROM size");
+            let origin = (0x10000 - size) as u16;
+            memory.configure_banks(vec![rom], &[(origin, size as u16, 1, 0x0000)]).unwrap();
+            (origin, 0xFFFF)
+        },
+        None => {
+            roms::COUNT.install(&memory);
+            (roms::COUNT.origin, roms::COUNT.origin + roms::COUNT.code.len() as u16 - 1)
+        },
+    };
 
-fn main() {
-    let mut rom_bytes: [u8; 0x2000] = [0; 0x2000];
-    rom_bytes[0..RESET_PROGRAM.len()].copy_from_slice(RESET_PROGRAM);
-    rom_bytes[0x1000..0x1000 + NMI_PROGRAM.len()].copy_from_slice(NMI_PROGRAM);
-    rom_bytes[0x1100..0x1100 + IRQ_PROGRAM.len()].copy_from_slice(IRQ_PROGRAM);
-    rom_bytes[0x1ffa..].copy_from_slice(&[0x00, 0xf0, 0x00, 0xe0, 0x00, 0xf1]);
+    // With --coverage, record which ROM bytes get used, and report on them when stopped.
+    if std::env::args().any(|arg| arg == "--coverage") {
+        memory.enable_coverage();
+    }
+
+    // Create a CPU instance wired to the memory, which runs the reset sequence as it powers on.
+    let mut cpu = C6502::new_with(&memory, CpuConfig { start: CpuStart::Reset, ..CpuConfig::default() });
+
+    // With --trace <file>, write a binary trace of every instruction, for rustycoat-trace to
+    // convert to text.
+    if let Some(path) = arg_value("--trace") {
+        let file = std::io::BufWriter::new(std::fs::File::create(path).expect("Couldn't create trace file"));
+        let writer = trace::BinaryTraceWriter::new(file).expect("Couldn't write trace file");
+        cpu.set_trace_sink(Some(Box::new(writer))).unwrap();
+    }
 
-    // Create a new memory object with a ROM loaded into the top 8K
-    let memory = Memory::new();
-    memory.configure_banks(vec![RomBank::with_bytes(&rom_bytes)], &[(0xe000, 0x2000, 1, 0x0000)]);
+    // Wire up the reset line, for --watch to pulse.
+    let mut reset = OutputPin::with_initial_value(true);
+    reset.connect_to(cpu.reset_in());
 
-    // Create a CPU instance wired to the memory.
-    let mut cpu = C6502::new(&memory);
-    cpu.reset();
+    // With --watch, reload the ROM file and reset whenever it changes, reporting each reload.
+    let watcher = match rom_path {
+        Some(path) if args.iter().any(|arg| arg == "--watch") => {
+            let (events, reloads) = unbounded();
+            let display = path.display().to_string();
+            std::thread::spawn(move || {
+                for event in reloads {
+                    match event {
+                        RomEvent::Reloaded => println!("Reloaded {}", display),
+                        RomEvent::TooLarge => println!("Couldn't reload {}: image too large", display),
+                        RomEvent::ReadFailed(e) => println!("Couldn't reload {}: {}", display, e),
+                    }
+                }
+            });
+            Some(RomWatcher { memory: memory.clone(), path, reset, events })
+        },
+        _ => None,
+    };
 
     // Create a 1MHz clock and wire it up to the CPU.
     let mut clock = Clock::new(1_000_000);
     clock.output().connect_to(cpu.phi0_in());
 
     // Create a computer, add components, and start it up.
+    let stats = cpu.stats();
     let mut c = Computer::new();
     c.add_async(cpu);
     c.add_async(clock);
+    if let Some(watcher) = watcher {
+        c.add_async(watcher);
+    }
+
+    c.run().unwrap();
+    println!("{}", stats);
+    if let Some(e) = stats.trace_error() {
+        println!("Trace stopped: {}", e);
+    }
+
+    if let Some(report) = memory.coverage_report() {
+        print!("{}", report);
+        print!("{}", profile::coverage_listing(&memory, &report, origin, end));
+    }
+}
+
+/// What happened when the watched ROM file changed.
+///
+enum RomEvent {
+    Reloaded,
+    TooLarge,
+    ReadFailed(io::Error),
+}
+
+/// Polls a ROM file for changes, reloading it into the ROM bank with the CPU held in reset when
+/// it changes, and sending a `RomEvent` to `events` each time. The new image must be no larger
+/// than the one the machine started with.
+///
+struct RomWatcher {
+    memory: Memory,
+    path: PathBuf,
+    reset: OutputPin,
+    events: Sender<RomEvent>,
+}
+
+impl RomWatcher {
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+}
 
-    c.run();
+impl AsyncComponent for RomWatcher {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        let mut last_modified = self.modified();
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(250));
+            let modified = self.modified();
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+            // Hold the CPU in reset while the ROM changes under it, resetting it once done.
+            let hold = ResetHold::new(&mut self.reset);
+            let event = match std::fs::read(&self.path) {
+                Ok(image) if self.memory.reload_bank(1, &image, &hold) => RomEvent::Reloaded,
+                Ok(_) => RomEvent::TooLarge,
+                Err(e) => RomEvent::ReadFailed(e),
+            };
+            drop(hold);
+            // Nobody may be listening any more, which doesn't matter to the watcher.
+            self.events.send(event).ok();
+        }
+    }
 }
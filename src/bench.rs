@@ -0,0 +1,211 @@
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::core::memory::{CowBank, Memory, RamBank};
+use crate::core::ports::OutputPin;
+use crate::core::AsyncComponent;
+use crate::cpus::c6502::{CpuConfig, CpuStart, C6502};
+use crate::roms;
+
+/// The ways a CPU can be driven, from cheapest to most realistic.
+///
+/// * `Step` calls `C6502::step` directly, once per cycle.
+/// * `ClockEdges` calls `C6502::clock_edge` for both edges of each cycle, on the same thread,
+///   so the phase outputs are driven too.
+/// * `Channel` runs the CPU on its own thread, sending it each clock edge over a port, as a
+///   `Clock` component does.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DriveMode {
+    Step,
+    ClockEdges,
+    Channel,
+}
+
+impl DriveMode {
+    pub const ALL: [DriveMode; 3] = [DriveMode::Step, DriveMode::ClockEdges, DriveMode::Channel];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DriveMode::Step => "step",
+            DriveMode::ClockEdges => "clock-edges",
+            DriveMode::Channel => "channel",
+        }
+    }
+}
+
+/// The throughput of one benchmark run.
+///
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub program: &'static str,
+    pub mode: DriveMode,
+    pub cycles: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// The effective speed of the emulated CPU, in MHz.
+    ///
+    pub fn mhz(&self) -> f64 {
+        self.cycles as f64 / self.elapsed.as_secs_f64() / 1_000_000.0
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} {:<12} {} cycles in {} ms, {:.2} MHz",
+            self.program,
+            self.mode.name(),
+            self.cycles,
+            self.elapsed.as_millis(),
+            self.mhz()
+        )
+    }
+}
+
+/// Run the `MULTIPLY` ROM for `cycles` emulated cycles, driving the CPU in the given mode, and
+/// measure how long it takes. Every mode runs the same instructions, so the results of each
+/// are directly comparable, and comparable across builds.
+///
+pub fn bench(mode: DriveMode, cycles: u64) -> BenchResult {
+    let memory = Memory::new();
+    roms::MULTIPLY.install(&memory);
+    let mut cpu = C6502::new(&memory);
+    cpu.reset();
+    run(cpu, roms::MULTIPLY.name, mode, cycles)
+}
+
+/// Run a tight loop of absolute loads and stores for `cycles` emulated cycles, stepping the CPU
+/// directly. Every cycle reads or writes memory, so this measures the cost of memory accesses
+/// more than `bench` does.
+///
+pub fn bench_load_store(cycles: u64) -> BenchResult {
+    let memory = Memory::new();
+    // loop: LDA $2000; STA $2001; JMP loop
+    memory.write_block(0x0400, &[0xAD, 0x00, 0x20, 0x8D, 0x01, 0x20, 0x4C, 0x00, 0x04]);
+    let cpu = C6502::new_with(&memory, CpuConfig { pc: 0x0400, start: CpuStart::Run, ..CpuConfig::default() });
+    run(cpu, "load-store", DriveMode::Step, cycles)
+}
+
+fn run(mut cpu: C6502, program: &'static str, mode: DriveMode, cycles: u64) -> BenchResult {
+    let start = Instant::now();
+    match mode {
+        DriveMode::Step => {
+            for _ in 0..cycles {
+                cpu.step();
+            }
+        },
+        DriveMode::ClockEdges => {
+            for _ in 0..cycles {
+                cpu.clock_edge(false);
+                cpu.clock_edge(true);
+            }
+        },
+        DriveMode::Channel => {
+            let mut clock = OutputPin::new();
+            clock.connect_to(cpu.phi0_in());
+            let handle = thread::spawn(move || cpu.run(Arc::new(AtomicBool::new(false))));
+            for _ in 0..cycles {
+                clock.send(false);
+                clock.send(true);
+            }
+            // The CPU takes every edge sent, then stops once the clock has gone away.
+            drop(clock);
+            handle.join().unwrap();
+        },
+    }
+    BenchResult { program, mode, cycles, elapsed: start.elapsed() }
+}
+
+/// How long it took to get a fresh machine with a 32K image at $8000 a number of times, each
+/// time after writing to a few of its pages: by rebuilding its memory, and by resetting it with
+/// `Memory::reset_to_initial`, with the image in a `CowBank`.
+///
+#[derive(Debug, Clone)]
+pub struct ResetBenchResult {
+    pub iterations: u64,
+    pub rebuild: Duration,
+    pub reset: Duration,
+}
+
+impl fmt::Display for ResetBenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "reset      {} machines: rebuilt in {} ms, reset in {} ms",
+            self.iterations,
+            self.rebuild.as_millis(),
+            self.reset.as_millis()
+        )
+    }
+}
+
+pub fn bench_reset(iterations: u64) -> ResetBenchResult {
+    let image: Vec<u8> = (0..0x8000).map(|i| i as u8).collect();
+    let dirty = |memory: &Memory| {
+        for addr in [0x0200, 0x8000, 0x9000, 0xA000] {
+            memory.write_byte(addr, 0xFF);
+        }
+    };
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let memory = Memory::new();
+        memory.configure_banks(vec![RamBank::with_bytes(&image)], &[(0x8000, 0x8000, 1, 0x0000)]).unwrap();
+        dirty(&memory);
+    }
+    let rebuild = start.elapsed();
+
+    let memory = Memory::new();
+    memory.configure_banks(vec![CowBank::new(&image)], &[(0x8000, 0x8000, 1, 0x0000)]).unwrap();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        memory.reset_to_initial();
+        dirty(&memory);
+    }
+    ResetBenchResult { iterations, rebuild, reset: start.elapsed() }
+}
+
+/// Format benchmark results as a JSON array, for tracking them over time.
+///
+pub fn to_json(results: &[BenchResult]) -> String {
+    let entries: Vec<String> = results
+        .iter()
+        .map(|r| {
+            format!(
+                "  {{\"program\": \"{}\", \"mode\": \"{}\", \"cycles\": {}, \"seconds\": {:.6}, \"mhz\": {:.3}}}",
+                r.program,
+                r.mode.name(),
+                r.cycles,
+                r.elapsed.as_secs_f64(),
+                r.mhz()
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let mut results: Vec<BenchResult> = DriveMode::ALL.iter().map(|&mode| bench(mode, 2000)).collect();
+        results.push(bench_load_store(2000));
+        assert!(results.iter().all(|r| r.cycles == 2000 && r.mhz() > 0.0));
+        let json = to_json(&results);
+        assert!(json.starts_with("[\n  {\"program\": \"multiply\", \"mode\": \"step\", \"cycles\": 2000, "));
+        assert_eq!(json.matches("\"mode\"").count(), 4);
+        assert!(json.contains("\"program\": \"load-store\""));
+
+        let reset = bench_reset(10);
+        assert!(reset.to_string().starts_with("reset      10 machines: rebuilt in "));
+    }
+}
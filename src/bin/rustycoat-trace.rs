@@ -0,0 +1,43 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::process;
+
+use rustycoat::cpus::c6502::trace::{convert, TraceFormat};
+
+const USAGE: &str = "usage: rustycoat-trace convert <trace.bin> [--format text|nestest]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, format) = match parse_args(&args) {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+
+    let result = File::open(input).and_then(|f| {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        convert(BufReader::new(f), &mut out, format)
+    });
+    if let Err(e) = result {
+        eprintln!("rustycoat-trace: {}: {}", input, e);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<(&str, TraceFormat)> {
+    match args {
+        [command, input] if command == "convert" => Some((input, TraceFormat::Text)),
+        [command, input, flag, format] if command == "convert" && flag == "--format" => {
+            let format = match format.as_str() {
+                "text" => TraceFormat::Text,
+                "nestest" => TraceFormat::Nestest,
+                _ => return None,
+            };
+            Some((input, format))
+        },
+        _ => None,
+    }
+}
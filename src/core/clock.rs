@@ -4,7 +4,7 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::core::ports::OutputPin;
-use crate::core::AsyncComponent;
+use crate::core::{AsyncComponent, ShutdownPhase};
 
 pub struct Clock {
     interval: Duration,
@@ -29,6 +29,10 @@ impl Clock {
 }
 
 impl AsyncComponent for Clock {
+    fn shutdown_phase(&self) -> ShutdownPhase {
+        ShutdownPhase::Source
+    }
+
     fn run(&mut self, stop: Arc<AtomicBool>) {
         let start = Instant::now();
         let time;
@@ -0,0 +1,222 @@
+use std::fmt;
+
+pub(crate) const FETCHED: u8 = 0x01;
+pub(crate) const READ: u8 = 0x02;
+pub(crate) const WRITTEN: u8 = 0x04;
+
+/// Bitmaps of the addresses fetched as code, read as data, and written, packed as one byte of
+/// flags per address.
+///
+pub(crate) struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Self { flags: vec![0; 0x10000] }
+    }
+
+    #[inline(always)]
+    pub(crate) fn record(&mut self, addr: u16, kind: u8) {
+        self.flags[addr as usize] |= kind;
+    }
+
+    /// Build a report of the given ROM regions, as inclusive address ranges.
+    ///
+    pub(crate) fn report(&self, regions: &[(u16, u16)]) -> CoverageReport {
+        let regions = regions.iter().map(|&(start, end)| self.region(start, end)).collect();
+        CoverageReport { regions, flags: self.flags.clone() }
+    }
+
+    fn region(&self, start: u16, end: u16) -> RegionCoverage {
+        let mut ranges: Vec<CoverageRange> = Vec::new();
+        let mut covered = 0;
+        for addr in start..=end {
+            let class = classify(self.flags[addr as usize]);
+            if class != Classification::Untouched {
+                covered += 1;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.class == class => range.end = addr,
+                _ => ranges.push(CoverageRange { start: addr, end: addr, class }),
+            }
+        }
+        RegionCoverage { start, end, covered, ranges }
+    }
+}
+
+fn classify(flags: u8) -> Classification {
+    if flags & FETCHED != 0 {
+        Classification::Code
+    } else if flags & READ != 0 {
+        Classification::Data
+    } else if flags & WRITTEN != 0 {
+        Classification::Written
+    } else {
+        Classification::Untouched
+    }
+}
+
+/// How a byte was accessed. A byte that was accessed in several ways takes the first of these
+/// that applies.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Classification {
+    /// Fetched by the CPU as an opcode or operand.
+    Code,
+    /// Read, but never fetched.
+    Data,
+    /// Written, but never fetched or read.
+    Written,
+    Untouched,
+}
+
+/// A run of consecutive bytes with the same classification.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CoverageRange {
+    pub start: u16,
+    pub end: u16,
+    pub class: Classification,
+}
+
+/// Coverage of one ROM region, from `start` to `end` inclusive.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RegionCoverage {
+    pub start: u16,
+    pub end: u16,
+    pub covered: usize,
+    pub ranges: Vec<CoverageRange>,
+}
+
+impl RegionCoverage {
+    pub fn size(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+
+    /// The percentage of the region's bytes that were accessed at all.
+    ///
+    pub fn percent_covered(&self) -> f64 {
+        self.covered as f64 * 100.0 / self.size() as f64
+    }
+}
+
+/// The coverage of each ROM region, as returned by `Memory::coverage_report`.
+///
+#[derive(Clone)]
+pub struct CoverageReport {
+    pub regions: Vec<RegionCoverage>,
+    flags: Vec<u8>,
+}
+
+impl CoverageReport {
+    pub fn classify(&self, addr: u16) -> Classification {
+        classify(self.flags[addr as usize])
+    }
+
+    pub fn was_fetched(&self, addr: u16) -> bool {
+        self.flags[addr as usize] & FETCHED != 0
+    }
+
+    pub fn was_read(&self, addr: u16) -> bool {
+        self.flags[addr as usize] & READ != 0
+    }
+
+    pub fn was_written(&self, addr: u16) -> bool {
+        self.flags[addr as usize] & WRITTEN != 0
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, region) in self.regions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "ROM ${:04X}-${:04X}: {:.1}% covered", region.start, region.end, region.percent_covered())?;
+            for range in region.ranges.iter() {
+                writeln!(f, "  ${:04X}-${:04X} {:?}", range.start, range.end, range.class)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many times each address was read and written, as counted by `Memory::enable_stats`.
+/// Reads include fetches.
+///
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl MemoryStats {
+    pub(crate) fn new() -> Self {
+        Self { reads: vec![0; 0x10000], writes: vec![0; 0x10000] }
+    }
+
+    #[inline(always)]
+    pub(crate) fn record(&mut self, addr: u16, kind: u8) {
+        if kind == WRITTEN {
+            self.writes[addr as usize] += 1;
+        } else {
+            self.reads[addr as usize] += 1;
+        }
+    }
+
+    pub fn reads(&self, addr: u16) -> u64 {
+        self.reads[addr as usize]
+    }
+
+    pub fn writes(&self, addr: u16) -> u64 {
+        self.writes[addr as usize]
+    }
+
+    /// Returns up to `n` of the pages accessed the most, with their total reads and writes,
+    /// busiest first. Pages that weren't accessed are left out.
+    ///
+    pub fn top_pages(&self, n: usize) -> Vec<(u8, u64)> {
+        let mut pages: Vec<(u8, u64)> = (0..=0xFF)
+            .map(|page: u8| {
+                let range = (page as usize) << 8..((page as usize) + 1) << 8;
+                (page, self.reads[range.clone()].iter().chain(&self.writes[range]).sum())
+            })
+            .filter(|&(_, accesses)| accesses > 0)
+            .collect();
+        pages.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pages.truncate(n);
+        pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_ranges() {
+        let mut coverage = Coverage::new();
+        coverage.record(0xE000, FETCHED);
+        coverage.record(0xE001, FETCHED | READ);
+        coverage.record(0xE002, READ);
+        coverage.record(0xE003, WRITTEN);
+        let report = coverage.report(&[(0xE000, 0xE007)]);
+
+        let region = &report.regions[0];
+        assert_eq!(region.covered, 4);
+        assert_eq!(region.percent_covered(), 50.0);
+        assert_eq!(
+            region.ranges,
+            vec![
+                CoverageRange { start: 0xE000, end: 0xE001, class: Classification::Code },
+                CoverageRange { start: 0xE002, end: 0xE002, class: Classification::Data },
+                CoverageRange { start: 0xE003, end: 0xE003, class: Classification::Written },
+                CoverageRange { start: 0xE004, end: 0xE007, class: Classification::Untouched },
+            ]
+        );
+        assert!(report.was_read(0xE001));
+        assert!(!report.was_fetched(0xE002));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::core::memory::Memory;
+use crate::core::ports::Connections;
+
+/// A set of faults to inject into a machine, to test how emulated firmware copes with failing
+/// hardware, e.g. that its checksum and retry logic catch corrupted reads.
+///
+/// * `flip_bit` flips a bit of a byte, from its Nth read on, as a failing memory cell would.
+/// * `corrupt` makes reads of a range of addresses return the wrong data with a given
+///   probability.
+/// * `drop_every` and `duplicate_every` lose or repeat every kth value sent over a connection
+///   between ports.
+///
+/// Faults are fully deterministic: random corruption is driven by the plan's seed, so a failing
+/// run can be reproduced exactly. Only reads made through `Memory::read_byte` and
+/// `Memory::fetch_byte` are affected, so tools that read memory in blocks see the real contents.
+///
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    seed: u64,
+    flips: Vec<BitFlip>,
+    corruptions: Vec<Corruption>,
+    port_faults: Vec<PortFault>,
+}
+
+#[derive(Debug, Clone)]
+struct BitFlip {
+    address: u16,
+    mask: u8,
+    after_reads: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Corruption {
+    start: u16,
+    end: u16,
+    probability: f64,
+}
+
+#[derive(Debug, Clone)]
+struct PortFault {
+    producer: String,
+    kind: PortFaultKind,
+    every: u64,
+}
+
+/// What happens to every kth value sent over a faulty connection.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub(crate) enum PortFaultKind {
+    Drop,
+    Duplicate,
+}
+
+impl FaultPlan {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, flips: Vec::new(), corruptions: Vec::new(), port_faults: Vec::new() }
+    }
+
+    /// Flip `bit` of the byte at `address` on its `after_reads`th read, and every read after.
+    ///
+    pub fn flip_bit(mut self, address: u16, bit: u8, after_reads: u64) -> Self {
+        assert!(bit < 8 && after_reads > 0);
+        self.flips.push(BitFlip { address, mask: 1 << bit, after_reads });
+        self
+    }
+
+    /// Make each read from `start` to `end` inclusive return a random wrong value with the given
+    /// probability.
+    ///
+    pub fn corrupt(mut self, start: u16, end: u16, probability: f64) -> Self {
+        assert!(start <= end && (0.0..=1.0).contains(&probability));
+        self.corruptions.push(Corruption { start, end, probability });
+        self
+    }
+
+    /// Drop every `every`th value sent by the output port named `producer` in the machine's
+    /// `Connections` (see `OutputPort::connect_named`).
+    ///
+    pub fn drop_every(self, producer: &str, every: u64) -> Self {
+        self.port_fault(producer, PortFaultKind::Drop, every)
+    }
+
+    /// Send every `every`th value sent by the output port named `producer` twice.
+    ///
+    pub fn duplicate_every(self, producer: &str, every: u64) -> Self {
+        self.port_fault(producer, PortFaultKind::Duplicate, every)
+    }
+
+    fn port_fault(mut self, producer: &str, kind: PortFaultKind, every: u64) -> Self {
+        assert!(every > 0);
+        self.port_faults.push(PortFault { producer: producer.to_string(), kind, every });
+        self
+    }
+
+    /// Inject the planned faults into `memory` and into the connections in `connections` named
+    /// in the plan, replacing any previous plan. Apply the plan once the machine is wired, as the
+    /// connections must exist.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no live connection from a port named in the plan.
+    ///
+    pub fn apply(&self, memory: &Memory, connections: &Connections) {
+        memory.set_faults(Some(MemoryFaults::new(self)));
+        connections.clear_faults();
+        for fault in self.port_faults.iter() {
+            if !connections.set_fault(&fault.producer, fault.kind, fault.every) {
+                panic!("No connection from {}", fault.producer);
+            }
+        }
+    }
+
+    /// Remove the faults injected into `memory` and `connections` by a previous plan.
+    ///
+    pub fn clear(memory: &Memory, connections: &Connections) {
+        memory.set_faults(None);
+        connections.clear_faults();
+    }
+}
+
+/// The state of the faults injected into memory.
+///
+pub(crate) struct MemoryFaults {
+    flips: Vec<(BitFlip, u64)>,
+    corruptions: Vec<Corruption>,
+    rng: SplitMix64,
+}
+
+impl MemoryFaults {
+    fn new(plan: &FaultPlan) -> Self {
+        Self {
+            flips: plan.flips.iter().map(|f| (f.clone(), 0)).collect(),
+            corruptions: plan.corruptions.clone(),
+            rng: SplitMix64(plan.seed),
+        }
+    }
+
+    /// Returns the value a read of `address` sees, given the value actually stored.
+    ///
+    pub(crate) fn read(&mut self, address: u16, mut value: u8) -> u8 {
+        for (flip, reads) in self.flips.iter_mut().filter(|(f, _)| f.address == address) {
+            *reads += 1;
+            if *reads >= flip.after_reads {
+                value ^= flip.mask;
+            }
+        }
+        for c in self.corruptions.iter().filter(|c| (c.start..=c.end).contains(&address)) {
+            let r = self.rng.next();
+            if ((r >> 11) as f64 / (1u64 << 53) as f64) < c.probability {
+                // Any non-zero mask, so the value is always wrong.
+                value ^= (r as u8).max(1);
+            }
+        }
+        value
+    }
+}
+
+/// A small, seedable pseudo-random generator, so fault runs and random memory patterns repeat
+/// exactly.
+///
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ports::{InputPort8, OutputPort8};
+
+    #[test]
+    fn bit_flip() {
+        let memory = Memory::new();
+        memory.write_byte(0x1234, 0x0F);
+        memory.write_byte(0x1235, 0x0F);
+        FaultPlan::new(0).flip_bit(0x1234, 7, 3).apply(&memory, &Connections::new());
+
+        assert_eq!(memory.read_byte(0x1234), 0x0F);
+        assert_eq!(memory.read_byte(0x1235), 0x0F);
+        assert_eq!(memory.read_byte(0x1234), 0x0F);
+        assert_eq!(memory.read_byte(0x1234), 0x8F);
+        assert_eq!(memory.fetch_byte(0x1234), 0x8F);
+        assert_eq!(memory.read_byte(0x1235), 0x0F);
+
+        let mut block = [0; 1];
+        memory.read_block(0x1234, &mut block);
+        assert_eq!(block[0], 0x0F);
+    }
+
+    #[test]
+    fn corruption() {
+        let reads = |seed| {
+            let memory = Memory::new();
+            FaultPlan::new(seed).corrupt(0x2000, 0x20FF, 0.25).apply(&memory, &Connections::new());
+            (0..4000).map(|i| memory.read_byte(0x2000 + (i % 0x200) as u16)).collect::<Vec<_>>()
+        };
+        let first = reads(42);
+        assert_eq!(first, reads(42));
+        assert_ne!(first, reads(43));
+
+        let (inside, outside) = first.chunks(0x100).enumerate().fold((0, 0), |(inside, outside), (i, chunk)| {
+            let wrong = chunk.iter().filter(|&&v| v != 0).count();
+            if i % 2 == 0 {
+                (inside + wrong, outside)
+            } else {
+                (inside, outside + wrong)
+            }
+        });
+        assert_eq!(outside, 0);
+        assert!((400..600).contains(&inside), "{}", inside);
+    }
+
+    #[test]
+    fn port_faults() {
+        let memory = Memory::new();
+        let mut output = OutputPort8::new();
+        let mut input = InputPort8::new();
+        let connections = Connections::new();
+        output.connect_named(&mut input, &connections, "faults.output", "faults.input");
+        let plan = FaultPlan::new(0).drop_every("faults.output", 3).duplicate_every("faults.output", 4);
+        plan.apply(&memory, &connections);
+
+        for i in 1..=8 {
+            output.send(i);
+        }
+        let mut received = Vec::new();
+        while let Some(value) = input.try_recv() {
+            received.push(value);
+        }
+        assert_eq!(received, vec![1, 2, 4, 4, 5, 7, 8, 8]);
+    }
+
+    #[test]
+    fn clear_faults() {
+        let memory = Memory::new();
+        let connections = Connections::new();
+        let mut output = OutputPort8::new();
+        let mut input = InputPort8::new();
+        output.connect_named(&mut input, &connections, "clear.output", "clear.input");
+        let mut send = |output: &mut OutputPort8| {
+            for i in 1..=4 {
+                output.send(i);
+            }
+            let mut received = Vec::new();
+            while let Some(value) = input.try_recv() {
+                received.push(value);
+            }
+            received
+        };
+
+        FaultPlan::new(0).flip_bit(0x1234, 0, 1).drop_every("clear.output", 2).apply(&memory, &connections);
+        assert_eq!(send(&mut output), vec![1, 3]);
+        assert_eq!(memory.read_byte(0x1234), 0x01);
+
+        // A new plan replaces the old one.
+        FaultPlan::new(0).duplicate_every("clear.output", 3).apply(&memory, &connections);
+        assert_eq!(send(&mut output), vec![1, 2, 3, 3, 4]);
+        assert_eq!(memory.read_byte(0x1234), 0x00);
+
+        FaultPlan::clear(&memory, &connections);
+        assert_eq!(send(&mut output), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "No connection from faults.missing")]
+    fn missing_connection() {
+        FaultPlan::new(0).drop_every("faults.missing", 2).apply(&Memory::new(), &Connections::new());
+    }
+}
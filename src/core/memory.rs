@@ -1,46 +1,522 @@
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 
+use crate::core::coverage::{self, Coverage, CoverageReport, MemoryStats};
+use crate::core::faults::{MemoryFaults, SplitMix64};
+use crate::core::ports::{InputPort8, OutputPin, OutputPort8};
+use crate::core::savestate::Stateful;
+use crate::core::{ResetKind, Resettable};
+
+pub mod loaders;
+
+/// The 64K address space the CPU and devices share. Clones share the same memory.
+///
+/// Reads and writes of pages that map the flat RAM go straight to it without taking the lock,
+/// unless something (coverage, stats, faults, or a watch on the page) needs to see every access.
+/// Everything else goes through `MemoryImpl`, under the lock.
+///
 #[derive(Clone)]
-pub struct Memory(Arc<Mutex<MemoryImpl>>);
+pub struct Memory {
+    ram: Arc<Ram>,
+    inner: Arc<Mutex<MemoryImpl>>,
+}
 
 impl Memory {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(MemoryImpl {
-            ram: vec![0; 65536],
-            banks: Vec::new(),
-            map: [(0, 0); 256],
-        })))
+        Self::new_with_pattern(InitPattern::Zero)
+    }
+
+    /// Create memory whose RAM starts out filled with `pattern`, as real RAM powers on with
+    /// something other than zeros in it. A hard reset or `reset_to_initial` fills it with the
+    /// same pattern again.
+    ///
+    pub fn new_with_pattern(pattern: InitPattern) -> Self {
+        let ram = Arc::new(Ram::new(pattern));
+        Self {
+            ram: ram.clone(),
+            inner: Arc::new(Mutex::new(MemoryImpl {
+                ram,
+                view: None,
+                banks: Vec::new(),
+                map: [(0, 0); 256],
+                coverage: None,
+                stats: None,
+                watches: Vec::new(),
+                next_watch: 0,
+                faults: None,
+            })),
+        }
     }
 
-    pub fn configure_banks(&self, banks: Vec<Box<dyn MemoryBank + Send>>, configs: &[(u16, u16, usize, u16)]) {
-        self.0.lock().unwrap().configure_banks(banks, configs);
+    /// Replace the banks, and map them into the address space. Each region in `configs` is a
+    /// tuple of its start address, its length, the bank it maps (numbered from 1 in the order of
+    /// `banks`, with 0 mapping the flat RAM back in), and the offset within the bank it starts
+    /// at. Regions must start and end on page boundaries, and mustn't overlap. To map a bank
+    /// smaller than a page, map a `PartialPageBank` containing it.
+    ///
+    /// If any region is invalid, the configuration is left as it was.
+    ///
+    pub fn configure_banks(
+        &self,
+        banks: Vec<Box<dyn MemoryBank + Send>>,
+        configs: &[(u16, u16, usize, u16)],
+    ) -> Result<(), MemoryConfigError> {
+        self.inner.lock().unwrap().configure_banks(banks, configs)
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
-        self.0.lock().unwrap().read_byte(address)
+        if let Some(value) = self.ram.read_direct(address) {
+            return value;
+        }
+        let mut mem = self.inner.lock().unwrap();
+        mem.record(address, coverage::READ);
+        let value = mem.faulty_read_byte(address);
+        mem.notify(address, value, false);
+        value
+    }
+
+    /// Read a byte as the CPU fetching an instruction. This is the same as `read_byte`, except
+    /// when coverage is enabled, where the byte is recorded as code rather than data.
+    ///
+    pub fn fetch_byte(&self, address: u16) -> u8 {
+        if let Some(value) = self.ram.read_direct(address) {
+            return value;
+        }
+        let mut mem = self.inner.lock().unwrap();
+        mem.record(address, coverage::FETCHED);
+        let value = mem.faulty_read_byte(address);
+        mem.notify(address, value, false);
+        value
     }
 
     pub fn write_byte(&self, address: u16, value: u8) {
-        self.0.lock().unwrap().write_byte(address, value)
+        if self.ram.write_direct(address, value) {
+            return;
+        }
+        let mut mem = self.inner.lock().unwrap();
+        mem.record(address, coverage::WRITTEN);
+        mem.write_byte(address, value);
+        mem.notify(address, value, true);
+    }
+
+    /// Read a little-endian word, as two calls to `read_byte`. The high byte is read from the next
+    /// address, wrapping from $FFFF to $0000 as the address space does, rather than within the
+    /// page as the CPU's zero page indirect addressing does.
+    ///
+    pub fn read_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read_byte(address), self.read_byte(address.wrapping_add(1))])
+    }
+
+    /// Write a little-endian word, as two calls to `write_byte`, wrapping as `read_word` does.
+    ///
+    pub fn write_word(&self, address: u16, value: u16) {
+        self.write_byte(address, lo_byte!(value));
+        self.write_byte(address.wrapping_add(1), hi_byte!(value));
+    }
+
+    /// Call `callback` with the address, the value, and whether it was a write, for every access
+    /// of the given kind to an address in `range`, by any component sharing the memory, e.g. to
+    /// display memory as it changes without polling it. Block reads and writes, including `fill`,
+    /// `copy`, `load_image` and `set_vectors`, call it once per byte. Restoring a snapshot or
+    /// resetting the memory doesn't. Returns a handle to pass to `remove_watch`.
+    ///
+    /// The callback is called on the accessing thread with the memory locked, so it must be
+    /// quick, and mustn't access the memory itself.
+    ///
+    pub fn add_watch<F>(&self, range: RangeInclusive<u16>, kind: WatchKind, callback: F) -> WatchHandle
+    where
+        F: FnMut(u16, u8, bool) + Send + 'static,
+    {
+        let mut mem = self.inner.lock().unwrap();
+        let handle = WatchHandle(mem.next_watch);
+        mem.next_watch += 1;
+        mem.watches.push(Watch { handle, range, kind, callback: Box::new(callback) });
+        mem.update_direct();
+        handle
+    }
+
+    /// Remove a watch added by `add_watch`. Returns false if it was already removed.
+    ///
+    pub fn remove_watch(&self, handle: WatchHandle) -> bool {
+        let mut mem = self.inner.lock().unwrap();
+        let count = mem.watches.len();
+        mem.watches.retain(|watch| watch.handle != handle);
+        mem.update_direct();
+        mem.watches.len() != count
+    }
+
+    pub(crate) fn set_faults(&self, faults: Option<MemoryFaults>) {
+        let mut mem = self.inner.lock().unwrap();
+        mem.faults = faults.map(Box::new);
+        mem.update_direct();
+    }
+
+    /// Replace the contents of bank `bank_id` (as numbered by `configure_banks`) in place,
+    /// keeping its mapping, e.g. to load a rebuilt ROM without rebuilding the machine. Bytes
+    /// patched into the bank, such as vectors set by `set_vectors`, are replaced too. Returns
+    /// false if there's no such bank, or it can't be reloaded with the bytes (see
+    /// `MemoryBank::reload`).
+    ///
+    /// Code running from the bank would carry on with whatever is at its PC, so the CPU running
+    /// from this memory should be held in reset, and go through its reset sequence once `hold`
+    /// is dropped. Asking for `hold` is a reminder to do so: it can't tell which CPU's RESET line
+    /// the pin it drives is wired to, if any. To reload a bank under a CPU that is stepped
+    /// directly rather than run, use `C6502::reload_bank`.
+    ///
+    pub fn reload_bank(&self, bank_id: usize, bytes: &[u8], _hold: &ResetHold) -> bool {
+        self.reload_bank_unchecked(bank_id, bytes)
+    }
+
+    pub(crate) fn reload_bank_unchecked(&self, bank_id: usize, bytes: &[u8]) -> bool {
+        let mut mem = self.inner.lock().unwrap();
+        match bank_id.checked_sub(1).and_then(|i| mem.banks.get_mut(i)) {
+            Some(bank) => bank.reload(bytes),
+            None => false,
+        }
+    }
+
+    /// Put memory back as it was when the machine was built: refill the flat RAM with its power-on
+    /// pattern, and reset every bank that keeps its initial contents (see
+    /// `MemoryBank::reset_to_initial`), e.g. to run many programs on a fresh machine without
+    /// rebuilding it each time.
+    ///
+    pub fn reset_to_initial(&self) {
+        let mut mem = self.inner.lock().unwrap();
+        for bank in mem.banks.iter_mut() {
+            bank.reset_to_initial();
+        }
+        self.ram.clear();
+    }
+
+    /// Call `f` with bank `bank_id` (as numbered by `configure_banks`), e.g. to inspect it, or
+    /// change its state directly. Use `as_any_mut` to get at the bank's own type. Returns `None`
+    /// if there's no such bank.
+    ///
+    /// As with watches, `f` is called with the memory locked, so it mustn't access the memory.
+    ///
+    pub fn with_bank<R, F>(&self, bank_id: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut dyn MemoryBank) -> R,
+    {
+        let mut mem = self.inner.lock().unwrap();
+        let bank = bank_id.checked_sub(1).and_then(|i| mem.banks.get_mut(i))?;
+        Some(f(bank.as_mut()))
+    }
+
+    /// Returns a copy of the contents of bank `bank_id` (as numbered by `configure_banks`), e.g.
+    /// to save battery-backed RAM once the machine stops. Returns `None` if there's no such bank,
+    /// or it doesn't hold its contents (see `MemoryBank::contents`).
+    ///
+    pub fn bank_contents(&self, bank_id: usize) -> Option<Vec<u8>> {
+        let mem = self.inner.lock().unwrap();
+        let bank = bank_id.checked_sub(1).and_then(|i| mem.banks.get(i))?;
+        bank.contents().map(|bytes| bytes.to_vec())
+    }
+
+    /// Capture RAM, the contents of every writeable bank, and the bank mapping, to `restore`
+    /// later or `diff` against another snapshot. Read-only banks are recorded by a hash of their
+    /// contents rather than copied, and banks that don't hold their contents, such as I/O, aren't
+    /// recorded at all.
+    ///
+    pub fn snapshot(&self) -> MemorySnapshot {
+        let mem = self.inner.lock().unwrap();
+        let banks = mem
+            .banks
+            .iter()
+            .map(|bank| match bank.contents() {
+                Some(bytes) if (0..bytes.len()).any(|addr| bank.is_writeable(addr as u16)) => {
+                    BankSnapshot::Contents(bytes.to_vec())
+                },
+                Some(bytes) => BankSnapshot::ReadOnly { hash: hash_bytes(bytes) },
+                None => BankSnapshot::Unrecorded,
+            })
+            .collect();
+        MemorySnapshot { ram: mem.ram.to_vec(), banks, map: mem.map.to_vec() }
+    }
+
+    /// Put memory back as it was when `snapshot` was taken. The banks must be the ones that were
+    /// configured then, with the read-only ones unchanged; if not, nothing is restored.
+    ///
+    pub fn restore(&self, snapshot: &MemorySnapshot) -> Result<(), String> {
+        let mut mem = self.inner.lock().unwrap();
+        if snapshot.ram.len() != 0x10000 || snapshot.map.len() != mem.map.len() {
+            return Err("snapshot is malformed".to_string());
+        }
+        if snapshot.banks.len() != mem.banks.len() {
+            return Err(format!("expected {} banks, found {}", snapshot.banks.len(), mem.banks.len()));
+        }
+        for (i, (saved, bank)) in snapshot.banks.iter().zip(mem.banks.iter()).enumerate() {
+            let matches = match (saved, bank.contents()) {
+                (BankSnapshot::Contents(bytes), Some(current)) => bytes.len() == current.len(),
+                (BankSnapshot::ReadOnly { hash }, Some(current)) => *hash == hash_bytes(current),
+                (BankSnapshot::Unrecorded, None) => true,
+                _ => false,
+            };
+            if !matches {
+                return Err(format!("bank {} differs from the one in the snapshot", i + 1));
+            }
+            if let BankSnapshot::Contents(bytes) = saved {
+                if !bank.can_reload(bytes) {
+                    return Err(format!("bank {} can't be reloaded", i + 1));
+                }
+            }
+        }
+        if let Some(&(bank_id, _)) = snapshot.map.iter().find(|&&(bank_id, _)| bank_id > mem.banks.len()) {
+            return Err(format!("snapshot maps bank {}, but there are only {}", bank_id, mem.banks.len()));
+        }
+
+        // Everything has been checked, so the reloads can't fail part way through.
+        for (saved, bank) in snapshot.banks.iter().zip(mem.banks.iter_mut()) {
+            if let BankSnapshot::Contents(bytes) = saved {
+                bank.reload(bytes);
+            }
+        }
+        mem.ram.copy_from_slice(&snapshot.ram);
+        mem.map.copy_from_slice(&snapshot.map);
+        mem.update_direct();
+        Ok(())
     }
 
     pub fn read_block(&self, start: u16, data: &mut [u8]) {
-        self.0.lock().unwrap().read_block(start, data)
+        let mut mem = self.inner.lock().unwrap();
+        mem.read_block(start, data);
+        mem.notify_block(start, data, false);
     }
 
     pub fn write_block(&self, start: u16, data: &[u8]) {
-        self.0.lock().unwrap().write_block(start, data)
+        let mut mem = self.inner.lock().unwrap();
+        mem.write_block(start, data);
+        mem.notify_block(start, data, true);
+    }
+
+    /// Returns the bytes in `range`, read through the bank mapping as `read_block` does, locking
+    /// the memory once per 256 bytes rather than per byte. Ranges don't wrap around: one whose
+    /// start is past its end, such as `0xFFF0..=0x000F`, is empty.
+    ///
+    pub fn iter_range(&self, range: RangeInclusive<u16>) -> impl Iterator<Item = u8> + '_ {
+        const CHUNK: u32 = 0x100;
+        let (start, end) = (*range.start() as u32, *range.end() as u32);
+        (start..=end).step_by(CHUNK as usize).flat_map(move |chunk| {
+            let mut bytes = vec![0; (end - chunk + 1).min(CHUNK) as usize];
+            self.read_block(chunk as u16, &mut bytes);
+            bytes
+        })
+    }
+
+    /// Format the bytes in `range` 16 to a line, each line giving the address of its first byte,
+    /// the bytes in hex, and the bytes as ASCII, with `.` for anything unprintable, e.g.
+    /// "$0400  48 45 4C 4C 4F 00 01 02  03 04 05 06 07 08 09 0A  |HELLO...........|". As with
+    /// `iter_range`, ranges don't wrap around.
+    ///
+    pub fn hexdump(&self, range: RangeInclusive<u16>) -> String {
+        let bytes: Vec<u8> = self.iter_range(range.clone()).collect();
+        let mut out = String::new();
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+            let (left, right) = hex.split_at(hex.len().min(8));
+            let ascii: String = line.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+            out += &format!(
+                "${:04X}  {:<23}  {:<23}  |{}|\n",
+                *range.start() as usize + i * 16,
+                left.join(" "),
+                right.join(" "),
+                ascii
+            );
+        }
+        out
+    }
+
+    /// Load a raw binary image from `path` at `base_addr`, returning its length. As with
+    /// `set_vectors`, bytes that fall in a read-only bank are patched into the bank, and the
+    /// rest are written to RAM or whatever writeable bank is mapped there.
+    ///
+    /// An image that would run past $FFFF is rejected, with nothing loaded.
+    ///
+    pub fn load_image(&self, path: &Path, base_addr: u16) -> io::Result<usize> {
+        let image = fs::read(path)?;
+        if image.len() > 0x10000 - base_addr as usize {
+            let message = format!("image of {} bytes at ${:04X} would run past $FFFF", image.len(), base_addr);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let mut mem = self.inner.lock().unwrap();
+        for (i, &value) in image.iter().enumerate() {
+            mem.patch_byte(base_addr + i as u16, value);
+        }
+        mem.notify_block(base_addr, &image, true);
+        Ok(image.len())
+    }
+
+    /// Fill `range` with `value`, as block writes do. Returns how many of the bytes were
+    /// writeable: bytes in a read-only bank go to the RAM underneath, and don't count.
+    ///
+    pub fn fill(&self, range: RangeInclusive<u16>, value: u8) -> usize {
+        let mut mem = self.inner.lock().unwrap();
+        range
+            .filter(|&address| {
+                let writeable = mem.write_byte(address, value);
+                mem.notify(address, value, true);
+                writeable
+            })
+            .count()
+    }
+
+    /// Copy the bytes in `src` to `dest_start`, as block reads and writes do, as if through a
+    /// buffer, so the ranges can overlap. Returns how many of the bytes were writeable, as
+    /// `fill` does. A destination that runs past $FFFF wraps around to $0000.
+    ///
+    pub fn copy(&self, src: RangeInclusive<u16>, dest_start: u16) -> usize {
+        let mut mem = self.inner.lock().unwrap();
+        let start = *src.start();
+        let bytes: Vec<u8> = src.map(|address| mem.read_byte(address)).collect();
+        mem.notify_block(start, &bytes, false);
+        let mut writeable = 0;
+        for (i, &value) in bytes.iter().enumerate() {
+            if mem.write_byte(dest_start.wrapping_add(i as u16), value) {
+                writeable += 1;
+            }
+        }
+        mem.notify_block(dest_start, &bytes, true);
+        writeable
+    }
+
+    /// Compare the bytes in `range` with `expected`, returning the first address that doesn't
+    /// match, or `None` if they all do. If `range` and `expected` differ in length and the
+    /// shorter matches, the first address past the shorter one is returned.
+    ///
+    pub fn compare(&self, range: RangeInclusive<u16>, expected: &[u8]) -> Option<u16> {
+        let len = range.clone().count();
+        let mut mem = self.inner.lock().unwrap();
+        let mismatch = range.clone().zip(expected).find(|&(address, &value)| mem.read_byte(address) != value);
+        match mismatch {
+            Some((address, _)) => Some(address),
+            None if len != expected.len() => Some(range.start().wrapping_add(len.min(expected.len()) as u16)),
+            None => None,
+        }
+    }
+
+    /// Write the interrupt vectors at $FFFA-$FFFF as little-endian words.
+    ///
+    /// Vectors that fall in a read-only bank are patched directly into the bank, so this works
+    /// for ROM-mapped vector tables too. Vectors left as `None` are not written.
+    ///
+    pub fn set_vectors(&self, vectors: Vectors) {
+        let mut mem = self.inner.lock().unwrap();
+        let entries = [(Vectors::NMI, vectors.nmi), (Vectors::RESET, Some(vectors.reset)), (Vectors::IRQ, vectors.irq)];
+        for (addr, value) in entries {
+            if let Some(value) = value {
+                mem.patch_byte(addr, lo_byte!(value));
+                mem.patch_byte(addr + 1, hi_byte!(value));
+                mem.notify_block(addr, &value.to_le_bytes(), true);
+            }
+        }
+    }
+
+    /// Read the interrupt vectors at $FFFA-$FFFF, as seen through the current bank mapping.
+    ///
+    pub fn vectors(&self) -> Vectors {
+        let mut mem = self.inner.lock().unwrap();
+        Vectors {
+            nmi: Some(mem.read_word(Vectors::NMI)),
+            reset: mem.read_word(Vectors::RESET),
+            irq: Some(mem.read_word(Vectors::IRQ)),
+        }
+    }
+
+    /// Start recording which addresses are fetched, read and written, clearing anything
+    /// recorded before. Block reads and writes aren't recorded, as they're used for loading and
+    /// inspecting memory rather than by the code being run.
+    ///
+    pub fn enable_coverage(&self) {
+        let mut mem = self.inner.lock().unwrap();
+        mem.coverage = Some(Box::new(Coverage::new()));
+        mem.update_direct();
+    }
+
+    pub fn disable_coverage(&self) {
+        let mut mem = self.inner.lock().unwrap();
+        mem.coverage = None;
+        mem.update_direct();
+    }
+
+    /// Returns how each byte of the ROM regions was accessed since coverage was enabled, or
+    /// `None` if it isn't. A ROM region is a run of pages mapped to the same read-only bank.
+    ///
+    pub fn coverage_report(&self) -> Option<CoverageReport> {
+        let mem = self.inner.lock().unwrap();
+        mem.coverage.as_ref().map(|c| c.report(&mem.rom_regions()))
+    }
+
+    /// Start counting the reads and writes of each address, clearing any previous counts. As
+    /// with coverage, block reads and writes aren't counted.
+    ///
+    pub fn enable_stats(&self) {
+        let mut mem = self.inner.lock().unwrap();
+        mem.stats = Some(Box::new(MemoryStats::new()));
+        mem.update_direct();
+    }
+
+    pub fn disable_stats(&self) {
+        let mut mem = self.inner.lock().unwrap();
+        mem.stats = None;
+        mem.update_direct();
+    }
+
+    /// Returns the counts since counting was enabled, or `None` if it isn't.
+    ///
+    pub fn stats(&self) -> Option<MemoryStats> {
+        self.inner.lock().unwrap().stats.as_deref().cloned()
     }
 
     #[allow(dead_code)]
     fn read_bank_byte(&self, bank_id: usize, addr: u16, offset: u16) -> u8 {
-        let mem = self.0.lock().unwrap();
-        mem.banks[bank_id - 1].read_byte(addr, offset, &mem.ram)
+        self.inner.lock().unwrap().read_bank(bank_id, addr, offset)
     }
 
     #[allow(dead_code)]
     fn ram(&self, addr: u16) -> u8 {
-        self.0.lock().unwrap().ram[addr as usize]
+        self.ram.load(addr)
+    }
+}
+
+impl Resettable for Memory {
+    /// A hard reset refills RAM with its power-on pattern. Banks keep their contents either way,
+    /// so battery-backed banks survive a power cycle.
+    ///
+    fn reset(&mut self, kind: ResetKind) {
+        if kind == ResetKind::Hard {
+            self.ram.clear();
+        }
+    }
+}
+
+/// Saves the contents of RAM. Banks are part of the machine's configuration, and aren't saved.
+///
+impl Stateful for Memory {
+    fn state_name(&self) -> &str {
+        "memory"
+    }
+
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != 0x10000 {
+            return Err(format!("expected {} bytes of RAM, found {}", 0x10000, data.len()));
+        }
+        self.ram.copy_from_slice(data);
+        Ok(())
     }
 }
 
@@ -50,98 +526,1192 @@ impl Default for Memory {
     }
 }
 
-pub trait MemoryBank {
+/// The 6502 interrupt vector table.
+///
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct Vectors {
+    pub nmi: Option<u16>,
+    pub reset: u16,
+    pub irq: Option<u16>,
+}
+
+impl Vectors {
+    pub const NMI: u16 = 0xFFFA;
+    pub const RESET: u16 = 0xFFFC;
+    pub const IRQ: u16 = 0xFFFE;
+}
+
+/// The state of memory, taken with `Memory::snapshot`.
+///
+/// With the `serde` feature, snapshots can be serialized.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemorySnapshot {
+    ram: Vec<u8>,
+    banks: Vec<BankSnapshot>,
+    map: Vec<(usize, u16)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum BankSnapshot {
+    Contents(Vec<u8>),
+    ReadOnly { hash: u64 },
+    Unrecorded,
+}
+
+impl MemorySnapshot {
+    /// Returns the byte the CPU would read at `address`, or `None` if it's in a bank whose
+    /// contents weren't recorded.
+    ///
+    fn byte(&self, address: u16) -> Option<u8> {
+        let (bank_id, offset) = self.map[(address >> 8) as usize];
+        if bank_id == 0 {
+            return Some(self.ram[address as usize]);
+        }
+        match &self.banks[bank_id - 1] {
+            BankSnapshot::Contents(bytes) => Some(bytes.get((address - offset) as usize).copied().unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    /// List the addresses whose contents differ in `other`, with the value here and the value in
+    /// `other`, e.g. to see what a routine changed. Addresses in read-only and I/O banks in either
+    /// snapshot aren't compared.
+    ///
+    pub fn diff(&self, other: &MemorySnapshot) -> Vec<(u16, u8, u8)> {
+        (0..=0xFFFF)
+            .filter_map(|address| match (self.byte(address), other.byte(address)) {
+                (Some(old), Some(new)) if old != new => Some((address, old, new)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Conversion to `Any`, implemented for every `MemoryBank`, so that a bank can be downcast to its
+/// own type.
+///
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: MemoryBank + 'static> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The FNV-1a hash of `bytes`.
+///
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// A bank that can be mapped into the address space with `Memory::configure_banks`.
+///
+/// `read_byte` and `write_byte` are given the flat RAM, for banks that pass accesses through to
+/// the RAM underneath them. Banks that never look at it should say so with `uses_ram`, as
+/// keeping it up to date for them takes time.
+///
+/// Every bank can be downcast to its own type through `AsAny`, e.g. from `Memory::with_bank`.
+///
+pub trait MemoryBank: AsAny {
     fn size(&self) -> usize;
     fn is_writeable(&self, addr: u16) -> bool;
     fn read_byte(&self, addr: u16, offset: u16, ram: &[u8]) -> u8;
     fn write_byte(&mut self, addr: u16, offset: u16, val: u8, ram: &mut [u8]);
+
+    /// Returns false if the bank ignores the RAM it's given in `read_byte` and `write_byte`, which
+    /// are then given an empty slice instead. The default is true.
+    ///
+    fn uses_ram(&self) -> bool {
+        true
+    }
+
+    /// Overwrite a byte of a read-only bank's contents, bypassing write protection. Returns
+    /// false if the bank doesn't support patching, or the address is outside the bank.
+    ///
+    fn patch_byte(&mut self, _addr: u16, _offset: u16, _val: u8) -> bool {
+        false
+    }
+
+    /// Replace a bank's contents, padding them with zeros if they're shorter than the bank.
+    /// Returns false if the bank doesn't support reloading, or the bytes don't fit.
+    ///
+    fn reload(&mut self, _bytes: &[u8]) -> bool {
+        false
+    }
+
+    /// Returns true if `reload` would accept `bytes`, without changing anything.
+    ///
+    fn can_reload(&self, _bytes: &[u8]) -> bool {
+        false
+    }
+
+    /// Returns the bank's contents, if it holds them rather than computing or forwarding them.
+    ///
+    fn contents(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Put the bank's contents back as they were when it was created, for banks that keep them,
+    /// such as `CowBank`. Called by `Memory::reset_to_initial`. The default implementation does
+    /// nothing.
+    ///
+    fn reset_to_initial(&mut self) {}
+}
+
+/// Which accesses a watch added by `Memory::add_watch` is called for.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, is_write: bool) -> bool {
+        match self {
+            WatchKind::Read => !is_write,
+            WatchKind::Write => is_write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// Identifies a watch added by `Memory::add_watch`, to remove it.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WatchHandle(u64);
+
+struct Watch {
+    handle: WatchHandle,
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+    callback: Box<dyn FnMut(u16, u8, bool) + Send>,
+}
+
+/// What RAM holds when it powers on, for `Memory::new_with_pattern`.
+///
+/// * `Zero` and `Ones` fill it with $00 or $FF.
+/// * `Stripes` alternates runs of `period` bytes of $00 and $FF, starting with $00 at $0000, as
+///   many DRAM chips power on. `period` must be non-zero.
+/// * `Random` fills it with pseudo-random bytes, the same for the same `seed`.
+///
+/// Patterns can also be parsed from "zero", "ones", "stripes" or "stripes:<period>", and
+/// "random" or "random:<seed>", e.g. from the command line. The period defaults to 64, and the
+/// seed to 0.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum InitPattern {
+    #[default]
+    Zero,
+    Ones,
+    Stripes { period: u16 },
+    Random { seed: u64 },
+}
+
+impl InitPattern {
+    fn bytes(&self) -> Vec<u8> {
+        match *self {
+            InitPattern::Zero => vec![0x00; 0x10000],
+            InitPattern::Ones => vec![0xFF; 0x10000],
+            InitPattern::Stripes { period } => {
+                assert!(period > 0, "Stripe period must be non-zero");
+                (0..0x10000).map(|addr| if (addr / period as usize) & 1 == 0 { 0x00 } else { 0xFF }).collect()
+            },
+            InitPattern::Random { seed } => {
+                let mut rng = SplitMix64(seed);
+                (0..0x10000 / 8).flat_map(|_| rng.next().to_le_bytes()).collect()
+            },
+        }
+    }
+}
+
+impl FromStr for InitPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let bad_arg = |arg: &str| format!("bad argument \"{}\" for RAM pattern {}", arg, name);
+        match (name, arg) {
+            ("zero", None) => Ok(InitPattern::Zero),
+            ("ones", None) => Ok(InitPattern::Ones),
+            ("stripes", None) => Ok(InitPattern::Stripes { period: 64 }),
+            ("stripes", Some(arg)) => match arg.parse() {
+                Ok(period) if period > 0 => Ok(InitPattern::Stripes { period }),
+                _ => Err(bad_arg(arg)),
+            },
+            ("random", None) => Ok(InitPattern::Random { seed: 0 }),
+            ("random", Some(arg)) => arg.parse().map(|seed| InitPattern::Random { seed }).map_err(|_| bad_arg(arg)),
+            _ => Err(format!("unknown RAM pattern \"{}\"", s)),
+        }
+    }
+}
+
+/// Pulls an output pin low for as long as it lives, to hold a CPU whose RESET line it's wired to
+/// in reset, e.g. while `Memory::reload_bank` replaces the code the CPU runs. When the hold is
+/// dropped the pin goes high again, and the CPU goes through its reset sequence, starting on
+/// whatever code is there by then. Nothing checks that the pin is wired to the right CPU.
+///
+/// The CPU sees the line go low at its next cycle, so it may run a cycle or two after the hold
+/// is taken, but none of the code it runs then survives the reset.
+///
+pub struct ResetHold<'a> {
+    reset: &'a mut OutputPin,
+}
+
+impl<'a> ResetHold<'a> {
+    pub fn new(reset: &'a mut OutputPin) -> Self {
+        reset.send(false);
+        Self { reset }
+    }
+}
+
+impl Drop for ResetHold<'_> {
+    fn drop(&mut self) {
+        self.reset.send(true);
+    }
+}
+
+/// The flat RAM, with a flag per page saying whether accesses to the page can go straight to
+/// it. Bytes are atomic so that they can be accessed without the lock; `MemoryImpl` keeps the
+/// flags up to date under it.
+///
+/// A page's flag can change while another thread is part way through a direct access. The
+/// access then lands just before the change, as it would have if it had taken the lock first,
+/// since either way it reads or writes the same RAM.
+///
+/// Each page also has a flag saying whether it has been written since it was last cleared, so
+/// that clearing the RAM only has to touch those pages. A page that isn't dirty holds its
+/// power-on pattern, which is kept unless it's all zeros. Another flag says whether the page
+/// has changed since it was last copied into the `RamView` banks see.
+///
+struct Ram {
+    bytes: Box<[AtomicU8; 0x10000]>,
+    direct: [AtomicBool; 256],
+    dirty: [AtomicBool; 256],
+    changed: [AtomicBool; 256],
+    any_changed: AtomicBool,
+    pattern: Option<Vec<u8>>,
+}
+
+impl Ram {
+    fn new(pattern: InitPattern) -> Self {
+        let pattern = pattern.bytes();
+        let bytes: Box<[AtomicU8]> = pattern.iter().map(|&b| AtomicU8::new(b)).collect();
+        Self {
+            bytes: bytes.try_into().unwrap(),
+            direct: std::array::from_fn(|_| AtomicBool::new(true)),
+            dirty: std::array::from_fn(|_| AtomicBool::new(false)),
+            changed: std::array::from_fn(|_| AtomicBool::new(true)),
+            any_changed: AtomicBool::new(true),
+            pattern: if pattern.iter().all(|&b| b == 0) { None } else { Some(pattern) },
+        }
+    }
+
+    #[inline(always)]
+    fn read_direct(&self, address: u16) -> Option<u8> {
+        if self.direct[(address >> 8) as usize].load(Ordering::Acquire) {
+            Some(self.load(address))
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn write_direct(&self, address: u16, value: u8) -> bool {
+        if self.direct[(address >> 8) as usize].load(Ordering::Acquire) {
+            self.store(address, value);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline(always)]
+    fn load(&self, address: u16) -> u8 {
+        self.bytes[address as usize].load(Ordering::Acquire)
+    }
+
+    #[inline(always)]
+    fn store(&self, address: u16, value: u8) {
+        self.bytes[address as usize].store(value, Ordering::Release);
+        // Marked after the store, so that a page cleared in between is still marked.
+        self.dirty[(address >> 8) as usize].store(true, Ordering::Release);
+        self.mark_changed(address >> 8);
+    }
+
+    #[inline(always)]
+    fn mark_changed(&self, page: u16) {
+        self.changed[page as usize].store(true, Ordering::Release);
+        self.any_changed.store(true, Ordering::Release);
+    }
+
+    /// Call `copy` with the range of each page changed since it was last called. Each page's flag
+    /// is cleared before the page is copied, so a write that lands during the copy is copied
+    /// next time.
+    ///
+    fn take_changed<F>(&self, mut copy: F)
+    where
+        F: FnMut(std::ops::Range<usize>),
+    {
+        if !self.any_changed.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        for (page, changed) in self.changed.iter().enumerate() {
+            if changed.swap(false, Ordering::AcqRel) {
+                copy(page << 8..(page + 1) << 8);
+            }
+        }
+    }
+
+    /// Refill the pages written since they were last cleared with the power-on pattern.
+    ///
+    fn clear(&self) {
+        for (page, dirty) in self.dirty.iter().enumerate() {
+            if dirty.swap(false, Ordering::AcqRel) {
+                let range = page << 8..(page + 1) << 8;
+                match &self.pattern {
+                    Some(pattern) => {
+                        self.bytes[range.clone()].iter().zip(&pattern[range]).for_each(|(b, &value)| {
+                            b.store(value, Ordering::Release);
+                        })
+                    },
+                    None => self.bytes[range].iter().for_each(|b| b.store(0, Ordering::Release)),
+                }
+                self.mark_changed(page as u16);
+            }
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.bytes.iter().map(|b| b.load(Ordering::Acquire)).collect()
+    }
+
+    fn copy_from_slice(&self, data: &[u8]) {
+        self.bytes.iter().zip(data).for_each(|(b, &value)| b.store(value, Ordering::Release));
+        self.dirty.iter().for_each(|dirty| dirty.store(true, Ordering::Release));
+        (0..=0xFF).for_each(|page| self.mark_changed(page));
+    }
+}
+
+/// A plain copy of the RAM, which is what banks are given as a slice in `MemoryBank::read_byte`
+/// and `write_byte`. It's brought up to date with the pages of the RAM that have changed before
+/// each bank access, and whatever a bank writes to it is copied back to the RAM afterwards.
+///
+struct RamView {
+    bytes: Vec<u8>,
+    // The view as it was before the bank access, to find what the bank wrote.
+    before: Vec<u8>,
+}
+
+impl RamView {
+    fn new() -> Self {
+        Self { bytes: vec![0; 0x10000], before: vec![0; 0x10000] }
+    }
+
+    fn sync(&mut self, ram: &Ram) {
+        ram.take_changed(|range| {
+            for (address, view) in range.clone().zip(&mut self.bytes[range.clone()]) {
+                *view = ram.load(address as u16);
+            }
+            self.before[range.clone()].copy_from_slice(&self.bytes[range]);
+        });
+    }
+
+    /// Copy the bytes a bank has written to the view back to the RAM.
+    ///
+    fn write_back(&mut self, ram: &Ram) {
+        for (page, (view, before)) in self.bytes.chunks(0x100).zip(self.before.chunks_mut(0x100)).enumerate() {
+            if view != before {
+                for (i, (&value, old)) in view.iter().zip(before.iter_mut()).enumerate() {
+                    if value != *old {
+                        ram.store((page << 8 | i) as u16, value);
+                        *old = value;
+                    }
+                }
+            }
+        }
+    }
 }
 
 struct MemoryImpl {
-    ram: Vec<u8>,
+    ram: Arc<Ram>,
+    view: Option<Box<RamView>>,
     banks: Vec<Box<dyn MemoryBank + Send>>,
     map: [(usize, u16); 256],
+    coverage: Option<Box<Coverage>>,
+    stats: Option<Box<MemoryStats>>,
+    watches: Vec<Watch>,
+    next_watch: u64,
+    faults: Option<Box<MemoryFaults>>,
+}
+
+impl MemoryImpl {
+    fn configure_banks(
+        &mut self,
+        banks: Vec<Box<dyn MemoryBank + Send>>,
+        configs: &[(u16, u16, usize, u16)],
+    ) -> Result<(), MemoryConfigError> {
+        // The pages each region covers, checked before anything changes.
+        let mut pages = Vec::with_capacity(configs.len());
+        for (region, &(start_addr, length, bank_id, target_offset)) in configs.iter().enumerate() {
+            if start_addr & 0xFF != 0 {
+                return Err(MemoryConfigError::UnalignedStart { region, start: start_addr });
+            }
+            if length == 0 {
+                return Err(MemoryConfigError::ZeroLength { region });
+            }
+            if length & 0xFF != 0 {
+                return Err(MemoryConfigError::UnalignedLength { region, length });
+            }
+            if start_addr as usize + length as usize > 0x10000 || target_offset > start_addr {
+                return Err(MemoryConfigError::RegionOutOfRange { region });
+            }
+            if bank_id > banks.len() {
+                return Err(MemoryConfigError::BankIndexOutOfRange { region, bank_id });
+            }
+            let start_page = (start_addr >> 8) as usize;
+            let end_page = start_page + (length >> 8) as usize - 1;
+            if let Some(first) = pages.iter().position(|&(start, end)| start <= end_page && start_page <= end) {
+                return Err(MemoryConfigError::OverlappingRegions { first, second: region });
+            }
+            pages.push((start_page, end_page));
+        }
+
+        self.banks = banks;
+        self.map.fill((0, 0));
+        for (&(start_addr, _, bank_id, target_offset), &(start_page, end_page)) in configs.iter().zip(&pages) {
+            for page in start_page..=end_page {
+                self.map[page] = (bank_id, start_addr - target_offset);
+            }
+        }
+        self.update_direct();
+        Ok(())
+    }
+
+    /// Let accesses to pages that map the flat RAM bypass the lock, unless every access to the
+    /// page has to be seen here: by coverage, stats or faults, which see every page, or by a
+    /// watch on part of the page.
+    ///
+    fn update_direct(&self) {
+        let observed = self.coverage.is_some() || self.stats.is_some() || self.faults.is_some();
+        for (page, (flag, &(bank_id, _))) in self.ram.direct.iter().zip(self.map.iter()).enumerate() {
+            let start = (page << 8) as u16;
+            let watched = self.watches.iter().any(|w| *w.range.start() <= start | 0xFF && start <= *w.range.end());
+            flag.store(!observed && !watched && bank_id == 0, Ordering::Release);
+        }
+    }
+
+    fn read_bank(&mut self, bank_id: usize, address: u16, offset: u16) -> u8 {
+        let bank = &self.banks[bank_id - 1];
+        if !bank.uses_ram() {
+            return bank.read_byte(address, offset, &[]);
+        }
+        let view = self.view.get_or_insert_with(|| Box::new(RamView::new()));
+        view.sync(&self.ram);
+        bank.read_byte(address, offset, &view.bytes)
+    }
+
+    #[inline(always)]
+    fn record(&mut self, address: u16, kind: u8) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(address, kind);
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record(address, kind);
+        }
+    }
+
+    #[inline(always)]
+    fn notify(&mut self, address: u16, value: u8, is_write: bool) {
+        if self.watches.is_empty() {
+            return;
+        }
+        for watch in self.watches.iter_mut() {
+            if watch.kind.matches(is_write) && watch.range.contains(&address) {
+                (watch.callback)(address, value, is_write);
+            }
+        }
+    }
+
+    fn notify_block(&mut self, start: u16, data: &[u8], is_write: bool) {
+        for (i, &value) in data.iter().enumerate() {
+            self.notify(start.wrapping_add(i as u16), value, is_write);
+        }
+    }
+
+    fn rom_regions(&self) -> Vec<(u16, u16)> {
+        let mut regions: Vec<(u16, u16)> = Vec::new();
+        let mut last_bank = 0;
+        for (page, &(bank_id, offset)) in self.map.iter().enumerate() {
+            let start = (page << 8) as u16;
+            if bank_id == 0 || self.banks[bank_id - 1].is_writeable(start - offset) {
+                last_bank = 0;
+                continue;
+            }
+            match regions.last_mut() {
+                Some(region) if bank_id == last_bank => region.1 = start | 0xFF,
+                _ => regions.push((start, start | 0xFF)),
+            }
+            last_bank = bank_id;
+        }
+        regions
+    }
+
+    fn read_byte(&mut self, address: u16) -> u8 {
+        let (bank_id, offset) = self.map[(address >> 8) as usize];
+        if bank_id > 0 {
+            self.read_bank(bank_id, address, offset)
+        } else {
+            self.ram.load(address)
+        }
+    }
+
+    fn read_word(&mut self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read_byte(address), self.read_byte(address.wrapping_add(1))])
+    }
+
+    #[inline(always)]
+    fn faulty_read_byte(&mut self, address: u16) -> u8 {
+        let value = self.read_byte(address);
+        match self.faults.as_mut() {
+            Some(faults) => faults.read(address, value),
+            None => value,
+        }
+    }
+
+    /// Write a byte, returning false if it fell in a read-only bank, and went to the RAM
+    /// underneath instead.
+    ///
+    fn write_byte(&mut self, address: u16, value: u8) -> bool {
+        let (bank_id, offset) = self.map[(address >> 8) as usize];
+        if bank_id > 0 && self.banks[bank_id - 1].is_writeable(address - offset) {
+            let bank = &mut self.banks[bank_id - 1];
+            if !bank.uses_ram() {
+                bank.write_byte(address, offset, value, &mut []);
+            } else {
+                let view = self.view.get_or_insert_with(|| Box::new(RamView::new()));
+                view.sync(&self.ram);
+                bank.write_byte(address, offset, value, &mut view.bytes);
+                view.write_back(&self.ram);
+            }
+            true
+        } else {
+            self.ram.store(address, value);
+            bank_id == 0
+        }
+    }
+
+    fn patch_byte(&mut self, address: u16, value: u8) {
+        let (bank_id, offset) = self.map[(address >> 8) as usize];
+        if bank_id > 0
+            && !self.banks[bank_id - 1].is_writeable(address - offset)
+            && self.banks[bank_id - 1].patch_byte(address, offset, value)
+        {
+            return;
+        }
+        self.write_byte(address, value);
+    }
+
+    fn read_block(&mut self, start: u16, data: &mut [u8]) {
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = self.read_byte(start.wrapping_add(i as u16));
+        }
+    }
+
+    fn write_block(&mut self, start: u16, data: &[u8]) {
+        for (i, d) in data.iter().enumerate() {
+            self.write_byte(start.wrapping_add(i as u16), *d);
+        }
+    }
+}
+
+/// Why `Memory::configure_banks` rejected a configuration. `region` is the index of the
+/// offending region in the configuration.
+///
+/// * `UnalignedStart` and `UnalignedLength` are for regions that don't start or end on a page
+///   boundary.
+/// * `RegionOutOfRange` is for a region that runs past $FFFF, or starts further into its bank
+///   than its own address.
+/// * `BankIndexOutOfRange` is for a region that maps a bank that wasn't given.
+/// * `OverlappingRegions` is for a region that overlaps an earlier one.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MemoryConfigError {
+    UnalignedStart { region: usize, start: u16 },
+    ZeroLength { region: usize },
+    UnalignedLength { region: usize, length: u16 },
+    RegionOutOfRange { region: usize },
+    BankIndexOutOfRange { region: usize, bank_id: usize },
+    OverlappingRegions { first: usize, second: usize },
+}
+
+impl fmt::Display for MemoryConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryConfigError::UnalignedStart { region, start } => {
+                write!(f, "region {} starts at ${:04X}, not on a page boundary", region, start)
+            },
+            MemoryConfigError::ZeroLength { region } => write!(f, "region {} is empty", region),
+            MemoryConfigError::UnalignedLength { region, length } => {
+                write!(f, "region {} is ${:04X} bytes long, not a whole number of pages", region, length)
+            },
+            MemoryConfigError::RegionOutOfRange { region } => write!(f, "region {} is out of range", region),
+            MemoryConfigError::BankIndexOutOfRange { region, bank_id } => {
+                write!(f, "region {} maps bank {}, which doesn't exist", region, bank_id)
+            },
+            MemoryConfigError::OverlappingRegions { first, second } => {
+                write!(f, "regions {} and {} overlap", first, second)
+            },
+        }
+    }
+}
+
+impl std::error::Error for MemoryConfigError {}
+
+pub struct RomBank {
+    bytes: Vec<u8>,
+}
+
+impl RomBank {
+    pub fn with_bytes(bytes: &[u8]) -> Box<Self> {
+        Box::new(Self { bytes: bytes.to_vec() })
+    }
+
+    /// Create a bank holding the raw binary image at `path`.
+    ///
+    pub fn from_file(path: &Path) -> io::Result<Box<Self>> {
+        Ok(Box::new(Self { bytes: fs::read(path)? }))
+    }
+}
+
+impl MemoryBank for RomBank {
+    fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        false
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let addr = (addr - offset) as usize;
+        if addr < self.bytes.len() {
+            self.bytes[addr]
+        } else {
+            0
+        }
+    }
+
+    fn write_byte(&mut self, _addr: u16, _offset: u16, _val: u8, _ram: &mut [u8]) {
+        panic!("Attempted to write to ROM bank");
+    }
+
+    fn patch_byte(&mut self, addr: u16, offset: u16, val: u8) -> bool {
+        let addr = (addr - offset) as usize;
+        if addr < self.bytes.len() {
+            self.bytes[addr] = val;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn can_reload(&self, bytes: &[u8]) -> bool {
+        bytes.len() <= self.bytes.len()
+    }
+
+    fn reload(&mut self, bytes: &[u8]) -> bool {
+        if !self.can_reload(bytes) {
+            return false;
+        }
+        self.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.bytes[bytes.len()..].fill(0);
+        true
+    }
+
+    fn contents(&self) -> Option<&[u8]> {
+        Some(&self.bytes)
+    }
+}
+
+/// A writeable bank with its own storage, separate from the flat RAM, such as battery-backed
+/// cartridge RAM or bank-switched expansion RAM. Mapping the bank at more than one window
+/// makes the windows aliases of each other.
+///
+/// Unlike the flat RAM, the bank keeps its contents over a hard reset.
+///
+/// Parts of the bank can be write-protected with `set_write_protect`, e.g. to emulate a
+/// write-enable jumper. As with a ROM, writes to protected bytes go to the flat RAM underneath.
+///
+pub struct RamBank {
+    bytes: Vec<u8>,
+    protected: Vec<bool>,
+}
+
+impl RamBank {
+    pub fn new(size: usize) -> Box<Self> {
+        Box::new(Self { bytes: vec![0; size], protected: vec![false; size] })
+    }
+
+    /// Create a bank initialized with `bytes`, e.g. battery-backed RAM saved from an earlier run.
+    ///
+    pub fn with_bytes(bytes: &[u8]) -> Box<Self> {
+        Box::new(Self { bytes: bytes.to_vec(), protected: vec![false; bytes.len()] })
+    }
+
+    /// Protect, or unprotect, the bytes in `range`, given as offsets within the bank. Offsets past
+    /// the end of the bank are ignored.
+    ///
+    pub fn set_write_protect(&mut self, range: RangeInclusive<u16>, protect: bool) {
+        let end = (*range.end() as usize + 1).min(self.protected.len());
+        if let Some(protected) = self.protected.get_mut(*range.start() as usize..end) {
+            protected.fill(protect);
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl MemoryBank for RamBank {
+    fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn is_writeable(&self, addr: u16) -> bool {
+        !self.protected.get(addr as usize).copied().unwrap_or(false)
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let addr = (addr - offset) as usize;
+        if addr < self.bytes.len() {
+            self.bytes[addr]
+        } else {
+            0
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, val: u8, _ram: &mut [u8]) {
+        let addr = (addr - offset) as usize;
+        if addr < self.bytes.len() {
+            self.bytes[addr] = val;
+        }
+    }
+
+    fn can_reload(&self, bytes: &[u8]) -> bool {
+        bytes.len() <= self.bytes.len()
+    }
+
+    fn reload(&mut self, bytes: &[u8]) -> bool {
+        if !self.can_reload(bytes) {
+            return false;
+        }
+        self.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.bytes[bytes.len()..].fill(0);
+        true
+    }
+
+    fn contents(&self) -> Option<&[u8]> {
+        Some(&self.bytes)
+    }
+}
+
+/// A writeable bank that remembers its initial contents, and which pages have been written
+/// since, so that `reset` can put it back as it was by copying back just those pages.
+///
+pub struct CowBank {
+    bytes: Vec<u8>,
+    initial: Vec<u8>,
+    dirty: Vec<bool>,
+    dirty_pages: Vec<usize>,
+}
+
+impl CowBank {
+    pub fn new(initial: &[u8]) -> Box<Self> {
+        Box::new(Self {
+            bytes: initial.to_vec(),
+            initial: initial.to_vec(),
+            dirty: vec![false; initial.len().div_ceil(0x100)],
+            dirty_pages: Vec::new(),
+        })
+    }
+
+    /// Put the bank back to its initial contents, in time proportional to the number of pages
+    /// written since the last reset.
+    ///
+    pub fn reset(&mut self) {
+        for page in self.dirty_pages.drain(..) {
+            let range = page << 8..((page + 1) << 8).min(self.bytes.len());
+            self.bytes[range.clone()].copy_from_slice(&self.initial[range]);
+            self.dirty[page] = false;
+        }
+    }
+
+    /// Returns how many pages have been written since the last reset.
+    ///
+    pub fn dirty_pages(&self) -> usize {
+        self.dirty_pages.len()
+    }
+
+    fn mark_dirty(&mut self, page: usize) {
+        if !self.dirty[page] {
+            self.dirty[page] = true;
+            self.dirty_pages.push(page);
+        }
+    }
+}
+
+impl MemoryBank for CowBank {
+    fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        self.bytes.get((addr - offset) as usize).copied().unwrap_or(0)
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, val: u8, _ram: &mut [u8]) {
+        let addr = (addr - offset) as usize;
+        if addr < self.bytes.len() {
+            self.bytes[addr] = val;
+            self.mark_dirty(addr >> 8);
+        }
+    }
+
+    fn can_reload(&self, bytes: &[u8]) -> bool {
+        bytes.len() <= self.bytes.len()
+    }
+
+    /// Replace the bank's current contents, keeping its initial contents to reset to.
+    ///
+    fn reload(&mut self, bytes: &[u8]) -> bool {
+        if !self.can_reload(bytes) {
+            return false;
+        }
+        self.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.bytes[bytes.len()..].fill(0);
+        for page in 0..self.dirty.len() {
+            let range = page << 8..((page + 1) << 8).min(self.bytes.len());
+            if self.bytes[range.clone()] != self.initial[range] {
+                self.mark_dirty(page);
+            }
+        }
+        true
+    }
+
+    fn contents(&self) -> Option<&[u8]> {
+        Some(&self.bytes)
+    }
+
+    fn reset_to_initial(&mut self) {
+        self.reset();
+    }
+}
+
+/// A bank made of a `base` bank with an `overlay` bank covering part of it, such as character
+/// RAM in a window of a ROM. `window` gives the offsets within the bank that the overlay covers;
+/// the overlay sees them as offsets from the start of the window.
+///
+/// Whether each address is writeable is up to the bank that covers it, so writes to a read-only
+/// base outside the window go to the flat RAM underneath, as they would for the base alone.
+///
+pub struct CompositeBank {
+    base: Box<dyn MemoryBank + Send>,
+    overlay: Box<dyn MemoryBank + Send>,
+    window: RangeInclusive<u16>,
+}
+
+impl CompositeBank {
+    pub fn new(
+        base: Box<dyn MemoryBank + Send>,
+        overlay: Box<dyn MemoryBank + Send>,
+        window: RangeInclusive<u16>,
+    ) -> Box<Self> {
+        Box::new(Self { base, overlay, window })
+    }
+
+    /// Returns the bank covering offset `addr` within the bank, and how far into the bank `addr`
+    /// is.
+    ///
+    fn bank_at(&self, addr: u16) -> (&(dyn MemoryBank + Send), u16) {
+        if self.window.contains(&addr) {
+            (self.overlay.as_ref(), addr - self.window.start())
+        } else {
+            (self.base.as_ref(), addr)
+        }
+    }
+}
+
+impl MemoryBank for CompositeBank {
+    fn size(&self) -> usize {
+        self.base.size()
+    }
+
+    fn is_writeable(&self, addr: u16) -> bool {
+        let (bank, addr) = self.bank_at(addr);
+        bank.is_writeable(addr)
+    }
+
+    fn uses_ram(&self) -> bool {
+        self.base.uses_ram() || self.overlay.uses_ram()
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, ram: &[u8]) -> u8 {
+        if self.window.contains(&(addr - offset)) {
+            self.overlay.read_byte(addr, offset + self.window.start(), ram)
+        } else {
+            self.base.read_byte(addr, offset, ram)
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, val: u8, ram: &mut [u8]) {
+        if self.window.contains(&(addr - offset)) {
+            self.overlay.write_byte(addr, offset + self.window.start(), val, ram)
+        } else {
+            self.base.write_byte(addr, offset, val, ram)
+        }
+    }
+
+    fn patch_byte(&mut self, addr: u16, offset: u16, val: u8) -> bool {
+        if self.window.contains(&(addr - offset)) {
+            self.overlay.patch_byte(addr, offset + self.window.start(), val)
+        } else {
+            self.base.patch_byte(addr, offset, val)
+        }
+    }
+
+    fn reset_to_initial(&mut self) {
+        self.base.reset_to_initial();
+        self.overlay.reset_to_initial();
+    }
+}
+
+/// A read-only bank whose contents are computed on demand by a function of the offset
+/// within the bank, such as an address-echo pattern, a lookup table, or a font.
+///
+pub struct FnBank {
+    size: usize,
+    f: Box<dyn Fn(u16) -> u8 + Send>,
+    cache: Option<Vec<u8>>,
+}
+
+impl FnBank {
+    pub fn new<F>(size: usize, f: F) -> Box<Self>
+    where
+        F: Fn(u16) -> u8 + Send + 'static,
+    {
+        Box::new(Self { size, f: Box::new(f), cache: None })
+    }
+
+    /// Create a bank that computes its contents once, up front, rather than on every read.
+    ///
+    pub fn new_cached<F>(size: usize, f: F) -> Box<Self>
+    where
+        F: Fn(u16) -> u8 + Send + 'static,
+    {
+        let cache = (0..size).map(|i| f(i as u16)).collect();
+        Box::new(Self { size, f: Box::new(f), cache: Some(cache) })
+    }
+}
+
+impl MemoryBank for FnBank {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        false
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let addr = addr - offset;
+        if (addr as usize) >= self.size {
+            0
+        } else if let Some(cache) = &self.cache {
+            cache[addr as usize]
+        } else {
+            (self.f)(addr)
+        }
+    }
+
+    fn write_byte(&mut self, _addr: u16, _offset: u16, _val: u8, _ram: &mut [u8]) {
+        panic!("Attempted to write to function bank");
+    }
+}
+
+/// A bank of device registers, whose reads and writes are handled by functions of the offset
+/// within the bank, or forwarded to ports, one pair per register. Offsets past the end of the
+/// bank read as zero, and writes to them are dropped.
+///
+/// Every read goes to the device, including block reads made to inspect memory, e.g. by a
+/// disassembler, so a device whose registers change when read will see those too.
+///
+pub struct IoBank {
+    size: usize,
+    read: Box<dyn Fn(u16) -> u8 + Send>,
+    write: Box<dyn FnMut(u16, u8) + Send>,
+}
+
+impl IoBank {
+    pub fn new<R, W>(size: usize, read: R, write: W) -> Box<Self>
+    where
+        R: Fn(u16) -> u8 + Send + 'static,
+        W: FnMut(u16, u8) + Send + 'static,
+    {
+        Box::new(Self { size, read: Box::new(read), write: Box::new(write) })
+    }
+
+    /// Create a bank with a register for each pair of ports. Writing a register sends the value
+    /// on its output port, and reading it returns the last value received on its input port.
+    ///
+    pub fn with_ports(registers: Vec<(OutputPort8, InputPort8)>) -> Box<Self> {
+        let size = registers.len();
+        let (mut outputs, inputs): (Vec<OutputPort8>, Vec<InputPort8>) = registers.into_iter().unzip();
+        let inputs = Mutex::new(inputs);
+        Self::new(
+            size,
+            move |offset| {
+                let input = &mut inputs.lock().unwrap()[offset as usize];
+                input.drain();
+                input.value()
+            },
+            move |offset, value| outputs[offset as usize].send(value),
+        )
+    }
 }
 
-impl MemoryImpl {
-    fn configure_banks(&mut self, banks: Vec<Box<dyn MemoryBank + Send>>, configs: &[(u16, u16, usize, u16)]) {
-        self.banks = banks;
-        self.map.fill((0, 0));
-        for e in configs {
-            let (start_addr, length, bank_id, target_offset) = *e;
-            assert!(start_addr & 0xFF == 0);
-            assert!(length > 0 && length & 0xFF == 0);
-            assert!(start_addr >= target_offset);
-            let start_page = (start_addr >> 8) as usize;
-            let end_page = start_page + (length >> 8) as usize - 1;
-            assert!(end_page <= 0xff);
-            for page in start_page..=end_page as usize {
-                self.map[page] = (bank_id, start_addr - target_offset);
-            }
-        }
+impl MemoryBank for IoBank {
+    fn size(&self) -> usize {
+        self.size
     }
 
-    fn read_byte(&self, address: u16) -> u8 {
-        let (bank_id, offset) = self.map[(address >> 8) as usize];
-        if bank_id > 0 {
-            self.banks[bank_id - 1].read_byte(address, offset, &self.ram)
-        } else {
-            self.ram[address as usize]
-        }
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
     }
 
-    fn write_byte(&mut self, address: u16, value: u8) {
-        let (bank_id, offset) = self.map[(address >> 8) as usize];
-        if bank_id > 0 && self.banks[bank_id - 1].is_writeable(address - offset) {
-            self.banks[bank_id - 1].write_byte(address, offset, value, &mut self.ram);
-        } else {
-            self.ram[address as usize] = value;
-        }
+    fn uses_ram(&self) -> bool {
+        false
     }
 
-    fn read_block(&self, start: u16, data: &mut [u8]) {
-        for (i, d) in data.iter_mut().enumerate() {
-            *d = self.read_byte(start + i as u16);
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let addr = addr - offset;
+        if (addr as usize) < self.size {
+            (self.read)(addr)
+        } else {
+            0
         }
     }
 
-    fn write_block(&mut self, start: u16, data: &[u8]) {
-        for (i, d) in data.iter().enumerate() {
-            self.write_byte(start + i as u16, *d);
+    fn write_byte(&mut self, addr: u16, offset: u16, val: u8, _ram: &mut [u8]) {
+        let addr = addr - offset;
+        if (addr as usize) < self.size {
+            (self.write)(addr, val);
         }
     }
 }
 
-pub struct RomBank {
-    bytes: Vec<u8>,
+/// A bank that splits its pages between the flat RAM and smaller banks, for devices that only
+/// occupy a few bytes, such as a 6522's 16 registers. Each of `ranges` gives offsets within the
+/// bank, and the bank that handles them, which sees them as offsets from the start of the range.
+/// Everywhere else reads and writes the RAM underneath. Where ranges overlap, the first wins.
+///
+pub struct PartialPageBank {
+    size: usize,
+    ranges: Vec<(RangeInclusive<u16>, Box<dyn MemoryBank + Send>)>,
 }
 
-impl RomBank {
-    pub fn with_bytes(bytes: &[u8]) -> Box<Self> {
-        Box::new(Self { bytes: bytes.to_vec() })
+impl PartialPageBank {
+    pub fn new(size: usize, ranges: Vec<(RangeInclusive<u16>, Box<dyn MemoryBank + Send>)>) -> Box<Self> {
+        Box::new(Self { size, ranges })
+    }
+
+    fn range_index(&self, addr: u16) -> Option<usize> {
+        self.ranges.iter().position(|(range, _)| range.contains(&addr))
     }
 }
 
-impl MemoryBank for RomBank {
+impl MemoryBank for PartialPageBank {
     fn size(&self) -> usize {
-        self.bytes.len()
+        self.size
     }
 
-    fn is_writeable(&self, _addr: u16) -> bool {
-        false
+    fn is_writeable(&self, addr: u16) -> bool {
+        match self.range_index(addr) {
+            Some(i) => self.ranges[i].1.is_writeable(addr - self.ranges[i].0.start()),
+            None => true,
+        }
     }
 
-    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
-        let addr = (addr - offset) as usize;
-        if addr < self.bytes.len() {
-            self.bytes[addr]
-        } else {
-            0
+    fn read_byte(&self, addr: u16, offset: u16, ram: &[u8]) -> u8 {
+        match self.range_index(addr - offset) {
+            Some(i) => self.ranges[i].1.read_byte(addr, offset + self.ranges[i].0.start(), ram),
+            None => ram[addr as usize],
         }
     }
 
-    fn write_byte(&mut self, _addr: u16, _offset: u16, _val: u8, _ram: &mut [u8]) {
-        panic!("Attempted to write to ROM bank");
+    fn write_byte(&mut self, addr: u16, offset: u16, val: u8, ram: &mut [u8]) {
+        match self.range_index(addr - offset) {
+            Some(i) => {
+                let start = *self.ranges[i].0.start();
+                self.ranges[i].1.write_byte(addr, offset + start, val, ram)
+            },
+            None => ram[addr as usize] = val,
+        }
+    }
+
+    fn patch_byte(&mut self, addr: u16, offset: u16, val: u8) -> bool {
+        match self.range_index(addr - offset) {
+            Some(i) => {
+                let start = *self.ranges[i].0.start();
+                self.ranges[i].1.patch_byte(addr, offset + start, val)
+            },
+            None => false,
+        }
+    }
+
+    fn reset_to_initial(&mut self) {
+        for (_, bank) in self.ranges.iter_mut() {
+            bank.reset_to_initial();
+        }
     }
 }
 
@@ -190,13 +1760,32 @@ mod tests {
         assert_eq!(mem.read_byte(0xBADA), 0xFC);
     }
 
+    #[test]
+    fn words() {
+        let mem = Memory::new();
+        mem.write_word(0x1234, 0xBEEF);
+        assert_eq!((mem.read_byte(0x1234), mem.read_byte(0x1235)), (0xEF, 0xBE));
+        assert_eq!(mem.read_word(0x1234), 0xBEEF);
+
+        // At the top of memory, the high byte wraps around to $0000.
+        mem.write_word(0xFFFF, 0x1234);
+        assert_eq!((mem.read_byte(0xFFFF), mem.read_byte(0x0000)), (0x34, 0x12));
+        assert_eq!(mem.read_word(0xFFFF), 0x1234);
+
+        // Within the zero page, it carries on into page one.
+        mem.write_word(0x00FF, 0x5678);
+        assert_eq!(mem.read_word(0x00FF), 0x5678);
+        assert_eq!(mem.read_byte(0x0100), 0x56);
+    }
+
     #[test]
     fn banked_ram() {
         let mem = Memory::new();
         mem.configure_banks(
             vec![TestBank::new_boxed(2048, true)],
             &[(0x3000, 1024, 1, 0x0000), (0x8000, 1024, 1, 0x0400)],
-        );
+        )
+        .unwrap();
 
         mem.write_byte(0xBADA, 0xFC);
         assert_eq!(mem.read_byte(0xBADA), 0xFC);
@@ -211,13 +1800,308 @@ mod tests {
         assert_eq!(mem.read_bank_byte(1, 0x0401, 0), 0xAB);
     }
 
+    #[test]
+    fn ram_bank() {
+        let mem = Memory::new();
+        mem.configure_banks(
+            vec![RamBank::with_bytes(&[0x11, 0x22, 0x33, 0x44]), RamBank::new(0x100)],
+            &[(0x3000, 0x100, 1, 0x0000), (0x8000, 0x100, 1, 0x0000), (0x9000, 0x200, 2, 0x0000)],
+        )
+        .unwrap();
+        assert_eq!(mem.read_byte(0x3001), 0x22);
+        assert_eq!(mem.read_byte(0x8001), 0x22);
+
+        // Both windows see the same bytes, held by the bank rather than RAM.
+        mem.write_byte(0x3002, 0xCD);
+        assert_eq!(mem.read_byte(0x8002), 0xCD);
+        mem.write_byte(0x8000, 0xAB);
+        assert_eq!(mem.read_byte(0x3000), 0xAB);
+        assert_eq!(mem.ram(0x3002), 0x00);
+        assert_eq!(mem.bank_contents(1), Some(vec![0xAB, 0x22, 0xCD, 0x44]));
+
+        // Past the end of the bank, writes are dropped, and reads return zero.
+        mem.write_byte(0x9100, 0xEF);
+        assert_eq!(mem.read_byte(0x9100), 0x00);
+        assert_eq!(mem.bank_contents(2), Some(vec![0; 0x100]));
+        assert_eq!(mem.bank_contents(3), None);
+    }
+
+    #[test]
+    fn write_protect() {
+        let mem = Memory::new();
+        mem.configure_banks(vec![RamBank::new(0x100)], &[(0x8000, 0x100, 1, 0x0000)]).unwrap();
+        let protect = |range, protect| {
+            mem.with_bank(1, |bank| {
+                let ram = bank.as_any_mut().downcast_mut::<RamBank>().unwrap();
+                ram.set_write_protect(range, protect)
+            })
+        };
+        mem.write_byte(0x8010, 0x11);
+        protect(0x10..=0x1F, true);
+
+        // Writes to the protected bytes fall through to the flat RAM, and reads still see the bank.
+        mem.write_byte(0x8010, 0x22);
+        assert_eq!((mem.read_byte(0x8010), mem.ram(0x8010)), (0x11, 0x22));
+        assert_eq!(mem.fill(0x800F..=0x8020, 0x33), 2);
+        assert_eq!((mem.read_byte(0x800F), mem.read_byte(0x801F), mem.read_byte(0x8020)), (0x33, 0x00, 0x33));
+        assert_eq!(mem.ram(0x801F), 0x33);
+
+        protect(0x10..=0xFFFF, false);
+        mem.write_byte(0x8010, 0x44);
+        assert_eq!((mem.read_byte(0x8010), mem.ram(0x8010)), (0x44, 0x33));
+    }
+
+    #[test]
+    fn composite_bank() {
+        let mem = Memory::new();
+        let bank = CompositeBank::new(RomBank::with_bytes(&[0xEA; 0x200]), RamBank::new(0x40), 0x0100..=0x013F);
+        mem.configure_banks(vec![bank], &[(0xC000, 0x200, 1, 0x0000)]).unwrap();
+        assert_eq!((mem.read_byte(0xC000), mem.read_byte(0xC100), mem.read_byte(0xC140)), (0xEA, 0x00, 0xEA));
+
+        // Writes inside the window land in the overlay, and outside it fall through to flat RAM.
+        mem.write_byte(0xC13F, 0x5A);
+        mem.write_byte(0xC140, 0xA5);
+        assert_eq!((mem.read_byte(0xC13F), mem.ram(0xC13F)), (0x5A, 0x00));
+        assert_eq!((mem.read_byte(0xC140), mem.ram(0xC140)), (0xEA, 0xA5));
+        assert_eq!(mem.fill(0xC0FE..=0xC101, 0x77), 2);
+        assert_eq!(mem.read_byte(0xC0FF), 0xEA);
+        assert_eq!(mem.read_byte(0xC101), 0x77);
+
+        // Patching reaches the read-only base.
+        mem.configure_banks(
+            vec![CompositeBank::new(RomBank::with_bytes(&[0; 0x100]), RamBank::new(0x10), 0x0000..=0x000F)],
+            &[(0xFF00, 0x100, 1, 0x0000)],
+        )
+        .unwrap();
+        mem.set_vectors(Vectors { nmi: None, reset: 0xC000, irq: None });
+        assert_eq!(mem.read_word(Vectors::RESET), 0xC000);
+        assert_eq!(mem.ram(0xFFFC), 0x00);
+    }
+
+    #[test]
+    fn partial_page_bank() {
+        let registers = Arc::new(Mutex::new([0u8; 0x10]));
+        let (read_registers, write_registers) = (registers.clone(), registers.clone());
+        let via = IoBank::new(
+            0x10,
+            move |offset| read_registers.lock().unwrap()[offset as usize] ^ 0xFF,
+            move |offset, value| write_registers.lock().unwrap()[offset as usize] = value,
+        );
+        let mem = Memory::new();
+        mem.write_byte(0xD40F, 0x0F);
+        mem.write_byte(0xD410, 0x10);
+        mem.configure_banks(vec![PartialPageBank::new(0x100, vec![(0x0000..=0x000F, via)])], &[(0xD400, 0x100, 1, 0)])
+            .unwrap();
+
+        // The first 16 bytes go to the device, and the RAM underneath them is left alone.
+        mem.write_byte(0xD400, 0x12);
+        mem.write_byte(0xD40F, 0x34);
+        assert_eq!(registers.lock().unwrap()[0x0], 0x12);
+        assert_eq!(registers.lock().unwrap()[0xF], 0x34);
+        assert_eq!((mem.read_byte(0xD400), mem.read_byte(0xD40F)), (0xED, 0xCB));
+        assert_eq!(mem.ram(0xD40F), 0x0F);
+
+        // The rest of the page is RAM.
+        assert_eq!(mem.read_byte(0xD410), 0x10);
+        mem.write_byte(0xD4FF, 0x56);
+        assert_eq!((mem.read_byte(0xD4FF), mem.ram(0xD4FF)), (0x56, 0x56));
+        let mut block = [0; 3];
+        mem.read_block(0xD40E, &mut block);
+        assert_eq!(block, [0xFF, 0xCB, 0x10]);
+        assert_eq!(mem.fill(0xD40F..=0xD411, 0x77), 3);
+        assert_eq!((registers.lock().unwrap()[0xF], mem.ram(0xD40F), mem.ram(0xD411)), (0x77, 0x0F, 0x77));
+    }
+
+    #[test]
+    fn hexdump() {
+        let mem = Memory::new();
+        mem.write_block(0x0400, b"HELLO, world!\x00\x01\x7F\xFFAB");
+        mem.configure_banks(vec![RomBank::with_bytes(b"ROM!")], &[(0xFF00, 0x100, 1, 0x0000)]).unwrap();
+        assert_eq!(
+            mem.hexdump(0x0400..=0x0412),
+            concat!(
+                "$0400  48 45 4C 4C 4F 2C 20 77  6F 72 6C 64 21 00 01 7F  |HELLO, world!...|\n",
+                "$0410  FF 41 42                                          |.AB|\n",
+            )
+        );
+
+        // ROM windows show the ROM, and the range can run to the end of memory.
+        assert_eq!(
+            mem.hexdump(0xFFFC..=0xFFFF),
+            "$FFFC  00 00 00 00                                       |....|\n"
+        );
+        assert_eq!(mem.iter_range(0xFF00..=0xFF03).collect::<Vec<u8>>(), b"ROM!");
+        assert_eq!(mem.iter_range(0x0000..=0xFFFF).count(), 0x10000);
+        assert_eq!(mem.iter_range(0x03FF..=0x0501).filter(|&b| b == b'L').count(), 2);
+        assert_eq!(mem.iter_range(RangeInclusive::new(0xFFF0, 0x000F)).count(), 0);
+        assert_eq!(mem.hexdump(RangeInclusive::new(0xFFF0, 0x000F)), "");
+    }
+
+    #[test]
+    fn cow_bank() {
+        let initial: Vec<u8> = (0..0x300).map(|i| i as u8).collect();
+        let mem = Memory::new();
+        mem.configure_banks(
+            vec![CowBank::new(&initial), RamBank::new(0x100)],
+            &[(0x8000, 0x300, 1, 0x0000), (0x9000, 0x100, 2, 0x0000)],
+        )
+        .unwrap();
+        mem.write_byte(0x8005, 0xAA);
+        mem.write_byte(0x80FF, 0xBB);
+        mem.write_byte(0x8201, 0xCC);
+        mem.write_byte(0x9000, 0xDD);
+        mem.write_byte(0x0010, 0xEE);
+        assert_eq!((mem.read_byte(0x8005), mem.read_byte(0x80FF), mem.read_byte(0x8201)), (0xAA, 0xBB, 0xCC));
+        let dirty = |mem: &Memory| {
+            mem.with_bank(1, |bank| bank.as_any().downcast_ref::<CowBank>().unwrap().dirty_pages()).unwrap()
+        };
+        assert_eq!(dirty(&mem), 2);
+
+        // Only the dirty pages are copied back, and the rest were never touched.
+        mem.reset_to_initial();
+        assert_eq!(dirty(&mem), 0);
+        assert_eq!(mem.iter_range(0x8000..=0x82FF).collect::<Vec<u8>>(), initial);
+        assert_eq!(mem.read_byte(0x0010), 0x00);
+        // Other banks keep their contents.
+        assert_eq!(mem.read_byte(0x9000), 0xDD);
+
+        // Restoring a snapshot marks the pages that differ from the initial contents as dirty.
+        mem.write_byte(0x8100, 0x11);
+        let snapshot = mem.snapshot();
+        mem.reset_to_initial();
+        mem.restore(&snapshot).unwrap();
+        assert_eq!((mem.read_byte(0x8100), dirty(&mem)), (0x11, 1));
+        mem.reset_to_initial();
+        assert_eq!(mem.read_byte(0x8100), 0x00);
+    }
+
+    #[test]
+    fn init_patterns() {
+        let mem = Memory::new_with_pattern(InitPattern::Stripes { period: 4 });
+        let mut bytes = [0; 10];
+        mem.read_block(0x0000, &mut bytes);
+        assert_eq!(bytes, [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(mem.read_byte(0xFFFF), 0xFF);
+        let mem = Memory::new_with_pattern(InitPattern::Stripes { period: 64 });
+        assert_eq!((mem.read_byte(0x003F), mem.read_byte(0x0040), mem.read_byte(0x0080)), (0x00, 0xFF, 0x00));
+        assert!(Memory::new_with_pattern(InitPattern::Ones).iter_range(0x0000..=0xFFFF).all(|b| b == 0xFF));
+
+        // The same seed gives the same bytes, and a different one doesn't.
+        let random = |seed| Memory::new_with_pattern(InitPattern::Random { seed }).snapshot();
+        assert_eq!(random(42), random(42));
+        assert_ne!(random(42).diff(&random(43)), vec![]);
+
+        // A hard reset puts the pattern back.
+        let mut mem = Memory::new_with_pattern(InitPattern::Random { seed: 7 });
+        let before = mem.snapshot();
+        mem.write_byte(0x1234, !mem.read_byte(0x1234));
+        mem.reset(ResetKind::Hard);
+        assert_eq!(mem.snapshot(), before);
+
+        assert_eq!("stripes:16".parse(), Ok(InitPattern::Stripes { period: 16 }));
+        assert_eq!("random".parse(), Ok(InitPattern::Random { seed: 0 }));
+        assert_eq!("ones".parse(), Ok(InitPattern::Ones));
+        assert!("stripes:0".parse::<InitPattern>().is_err());
+        assert!("plaid".parse::<InitPattern>().is_err());
+    }
+
+    #[test]
+    fn with_bank() {
+        let mem = Memory::new();
+        mem.configure_banks(
+            vec![RomBank::with_bytes(&[0xEA; 0x100]), RamBank::new(0x100)],
+            &[(0x8000, 0x100, 1, 0x0000), (0x9000, 0x100, 2, 0x0000)],
+        )
+        .unwrap();
+        mem.write_byte(0x9010, 0x5A);
+
+        let byte = mem.with_bank(2, |bank| bank.as_any().downcast_ref::<RamBank>().map(|ram| ram.bytes()[0x10]));
+        assert_eq!(byte, Some(Some(0x5A)));
+        let changed = mem.with_bank(2, |bank| match bank.as_any_mut().downcast_mut::<RamBank>() {
+            Some(ram) => {
+                ram.bytes_mut()[0x20] = 0xA5;
+                true
+            },
+            None => false,
+        });
+        assert_eq!(changed, Some(true));
+        assert_eq!(mem.read_byte(0x9020), 0xA5);
+
+        // The ROM isn't a RamBank, and there's no bank 3.
+        assert_eq!(mem.with_bank(1, |bank| bank.as_any().is::<RamBank>()), Some(false));
+        assert_eq!(mem.with_bank(1, |bank| bank.size()), Some(0x100));
+        assert_eq!(mem.with_bank(3, |bank| bank.size()), None);
+    }
+
+    #[test]
+    fn fill_copy_compare() {
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&[0xEE; 0x100])], &[(0x3000, 0x100, 1, 0x0000)]).unwrap();
+
+        // Filling across into the ROM bank writes the RAM underneath it, but doesn't count it.
+        assert_eq!(mem.fill(0x2F80..=0x307F, 0x5A), 0x80);
+        assert_eq!(mem.compare(0x2FFE..=0x3001, &[0x5A, 0x5A, 0xEE, 0xEE]), None);
+        assert_eq!(mem.compare(0x2FFE..=0x3001, &[0x5A, 0x5A, 0x5A, 0x5A]), Some(0x3000));
+        // A shorter or longer pattern is compared as far as it goes.
+        assert_eq!(mem.compare(0x2FFE..=0x3001, &[0x5A, 0x5A]), Some(0x3000));
+        assert_eq!(mem.compare(0x2FFE..=0x2FFF, &[0x5A, 0x5A, 0xEE]), Some(0x3000));
+        assert_eq!(mem.compare(0x2FFE..=0x3001, &[0x5A, 0x00]), Some(0x2FFF));
+        assert_eq!(mem.ram(0x3000), 0x5A);
+
+        // Overlapping copies, forwards and backwards, copy the original bytes.
+        mem.write_block(0x1000, &[1, 2, 3, 4, 5]);
+        assert_eq!(mem.copy(0x1000..=0x1004, 0x1002), 5);
+        assert_eq!(mem.compare(0x1000..=0x1006, &[1, 2, 1, 2, 3, 4, 5]), None);
+        assert_eq!(mem.copy(0x1002..=0x1006, 0x1001), 5);
+        assert_eq!(mem.compare(0x1000..=0x1006, &[1, 1, 2, 3, 4, 5, 5]), None);
+
+        // Copying into ROM, and wrapping past $FFFF.
+        assert_eq!(mem.copy(0x1000..=0x1003, 0x3010), 0);
+        assert_eq!(mem.copy(0x1000..=0x1003, 0x2FFE), 2);
+        assert_eq!(mem.copy(0x1000..=0x1002, 0xFFFF), 3);
+        assert_eq!(mem.compare(0xFFFF..=0xFFFF, &[1]), None);
+        assert_eq!(mem.compare(0x0000..=0x0001, &[1, 2]), None);
+    }
+
+    #[test]
+    fn bad_bank_configs() {
+        use MemoryConfigError::*;
+
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&[0xEE])], &[(0x3000, 0x100, 1, 0x0000)]).unwrap();
+        let configure = |configs: &[(u16, u16, usize, u16)]| {
+            mem.configure_banks(vec![RomBank::with_bytes(&[0x11]), RomBank::with_bytes(&[0x22])], configs)
+        };
+        assert_eq!(
+            configure(&[(0x1000, 0x100, 1, 0), (0x2010, 0x100, 2, 0)]),
+            Err(UnalignedStart { region: 1, start: 0x2010 })
+        );
+        assert_eq!(configure(&[(0x1000, 0, 1, 0)]), Err(ZeroLength { region: 0 }));
+        assert_eq!(configure(&[(0x1000, 0x180, 1, 0)]), Err(UnalignedLength { region: 0, length: 0x180 }));
+        assert_eq!(configure(&[(0xFF00, 0x200, 1, 0)]), Err(RegionOutOfRange { region: 0 }));
+        assert_eq!(configure(&[(0x1000, 0x100, 1, 0x1100)]), Err(RegionOutOfRange { region: 0 }));
+        assert_eq!(configure(&[(0x1000, 0x100, 3, 0)]), Err(BankIndexOutOfRange { region: 0, bank_id: 3 }));
+        assert_eq!(
+            configure(&[(0x1000, 0x100, 1, 0), (0x2000, 0x200, 1, 0), (0x2100, 0x100, 2, 0)]),
+            Err(OverlappingRegions { first: 1, second: 2 })
+        );
+        let message = configure(&[(0x1000, 0x100, 3, 0)]).unwrap_err().to_string();
+        assert_eq!(message, "region 0 maps bank 3, which doesn't exist");
+
+        // The configuration from before is left in place, and a good one replaces it.
+        assert_eq!(mem.read_byte(0x3000), 0xEE);
+        assert_eq!(configure(&[(0xFF00, 0x100, 1, 0), (0x3000, 0x100, 2, 0)]), Ok(()));
+        assert_eq!((mem.read_byte(0xFF00), mem.read_byte(0x3000)), (0x11, 0x22));
+    }
+
     #[test]
     fn banked_rom() {
         let mem = Memory::new();
         mem.configure_banks(
             vec![RomBank::with_bytes(&[0xDE, 0xAD, 0xBE, 0xEF])],
             &[(0x3000, 1024, 1, 0x0000)],
-        );
+        )
+        .unwrap();
 
         assert_eq!(mem.read_byte(0x3000), 0xDE);
         assert_eq!(mem.read_byte(0x3003), 0xEF);
@@ -225,4 +2109,452 @@ mod tests {
         assert_eq!(mem.read_byte(0x3003), 0xEF);
         assert_eq!(mem.ram(0x3003), 0xCD);
     }
+
+    #[test]
+    fn function_bank() {
+        let mem = Memory::new();
+        mem.configure_banks(
+            vec![
+                FnBank::new(0x300, |addr| (addr >> 8) as u8 ^ addr as u8),
+                FnBank::new_cached(0x100, |addr| !addr as u8),
+            ],
+            &[(0x4000, 0x400, 1, 0x0000), (0x8000, 0x100, 2, 0x0000)],
+        )
+        .unwrap();
+
+        assert_eq!(mem.read_byte(0x4000), 0x00);
+        assert_eq!(mem.read_byte(0x4012), 0x12);
+        assert_eq!(mem.read_byte(0x41FF), 0xFE);
+        assert_eq!(mem.read_byte(0x42F0), 0xF2);
+        assert_eq!(mem.read_byte(0x4300), 0x00);
+        assert_eq!(mem.read_byte(0x8000), 0xFF);
+        assert_eq!(mem.read_byte(0x80A5), 0x5A);
+
+        mem.write_byte(0x4012, 0x00);
+        assert_eq!(mem.read_byte(0x4012), 0x12);
+    }
+
+    #[test]
+    fn io_bank() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let writes = Arc::new(AtomicUsize::new(0));
+        let handler_writes = writes.clone();
+        let mem = Memory::new();
+        mem.configure_banks(
+            vec![IoBank::new(
+                0x10,
+                |offset| 0x40 | offset as u8,
+                move |offset, _value| {
+                    if offset == 0 {
+                        handler_writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+            )],
+            &[(0xD000, 0x100, 1, 0x0000)],
+        )
+        .unwrap();
+        mem.write_byte(0xD000, 0x01);
+        mem.write_byte(0xD000, 0x02);
+        mem.write_byte(0xD001, 0x03);
+        assert_eq!(writes.load(Ordering::Relaxed), 2);
+        assert_eq!(mem.read_byte(0xD001), 0x41);
+        assert_eq!(mem.read_byte(0xD00F), 0x4F);
+        assert_eq!(mem.read_byte(0xD010), 0x00);
+        assert_eq!(mem.ram(0xD000), 0x00);
+
+        // Registers backed by ports
+        let (mut to_device, mut from_bank) = (OutputPort8::new(), InputPort8::new());
+        let (mut from_device, mut to_bank) = (OutputPort8::new(), InputPort8::new());
+        to_device.connect_to(&mut from_bank);
+        from_device.connect_to(&mut to_bank);
+        mem.configure_banks(
+            vec![IoBank::with_ports(vec![(OutputPort8::new(), InputPort8::new()), (to_device, to_bank)])],
+            &[(0xD000, 0x100, 1, 0x0000)],
+        )
+        .unwrap();
+        mem.write_byte(0xD001, 0x5A);
+        assert_eq!(from_bank.try_recv(), Some(0x5A));
+        from_device.send(0x11);
+        from_device.send(0x22);
+        assert_eq!(mem.read_byte(0xD001), 0x22);
+        assert_eq!(mem.read_byte(0xD000), 0x00);
+    }
+
+    #[test]
+    fn reset() {
+        let mut mem = Memory::new();
+        mem.configure_banks(vec![TestBank::new_boxed(256, true)], &[(0x3000, 256, 1, 0x0000)]).unwrap();
+        mem.write_byte(0x0200, 0x5A);
+        mem.write_byte(0x3000, 0xA5);
+
+        mem.reset(ResetKind::Soft);
+        assert_eq!(mem.read_byte(0x0200), 0x5A);
+        assert_eq!(mem.read_byte(0x3000), 0xA5);
+
+        mem.reset(ResetKind::Hard);
+        assert_eq!(mem.read_byte(0x0200), 0x00);
+        assert_eq!(mem.read_byte(0x3000), 0xA5);
+    }
+
+    #[test]
+    fn vectors_in_ram() {
+        let mem = Memory::new();
+        mem.set_vectors(Vectors { nmi: Some(0xF000), reset: 0xE000, irq: None });
+        assert_eq!(mem.read_byte(0xFFFA), 0x00);
+        assert_eq!(mem.read_byte(0xFFFB), 0xF0);
+        assert_eq!(mem.read_byte(0xFFFD), 0xE0);
+        assert_eq!(mem.vectors(), Vectors { nmi: Some(0xF000), reset: 0xE000, irq: Some(0x0000) });
+    }
+
+    #[test]
+    fn vectors_in_rom() {
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&[0; 0x2000])], &[(0xE000, 0x2000, 1, 0x0000)]).unwrap();
+        mem.set_vectors(Vectors { nmi: Some(0xF000), reset: 0xE000, irq: Some(0xF100) });
+        assert_eq!(mem.vectors(), Vectors { nmi: Some(0xF000), reset: 0xE000, irq: Some(0xF100) });
+        assert_eq!(mem.read_bank_byte(1, 0x1FFC, 0), 0x00);
+        assert_eq!(mem.read_bank_byte(1, 0x1FFD, 0), 0xE0);
+        assert_eq!(mem.ram(0xFFFD), 0x00);
+    }
+
+    #[test]
+    fn vectors_past_end_of_rom() {
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&[0; 0x10])], &[(0xFF00, 0x100, 1, 0x0000)]).unwrap();
+        mem.set_vectors(Vectors { nmi: None, reset: 0x1234, irq: None });
+        assert_eq!(mem.read_byte(0xFFFC), 0x00);
+        assert_eq!(mem.ram(0xFFFC), 0x34);
+        assert_eq!(mem.ram(0xFFFD), 0x12);
+    }
+
+    #[test]
+    fn load_image() {
+        let path = std::env::temp_dir().join(format!("rustycoat-image-{}", std::process::id()));
+        fs::write(&path, [0xA9, 0x01, 0x00, 0x60]).unwrap();
+
+        // Into RAM, and into a ROM bank mapped over part of the image.
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&[0; 0x100])], &[(0xF000, 0x100, 1, 0x0000)]).unwrap();
+        assert_eq!(mem.load_image(&path, 0x0400).unwrap(), 4);
+        let mut bytes = [0; 4];
+        mem.read_block(0x0400, &mut bytes);
+        assert_eq!(bytes, [0xA9, 0x01, 0x00, 0x60]);
+        assert_eq!(mem.load_image(&path, 0xEFFE).unwrap(), 4);
+        mem.read_block(0xEFFE, &mut bytes);
+        assert_eq!(bytes, [0xA9, 0x01, 0x00, 0x60]);
+        assert_eq!(mem.ram(0xF000), 0x00);
+
+        // As a ROM bank of its own.
+        assert_eq!(RomBank::from_file(&path).unwrap().contents(), Some(&[0xA9, 0x01, 0x00, 0x60][..]));
+
+        // Watches see each byte loaded.
+        let loaded = Arc::new(Mutex::new(Vec::new()));
+        let log = loaded.clone();
+        let watch = mem.add_watch(0x0000..=0xFFFF, WatchKind::Write, move |addr, _, _| log.lock().unwrap().push(addr));
+        mem.load_image(&path, 0x0400).unwrap();
+        assert_eq!(*loaded.lock().unwrap(), vec![0x0400, 0x0401, 0x0402, 0x0403]);
+        mem.remove_watch(watch);
+
+        // Right up to the end of memory, but no further.
+        assert_eq!(mem.load_image(&path, 0xFFFC).unwrap(), 4);
+        let err = mem.load_image(&path, 0xFFFD).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "image of 4 bytes at $FFFD would run past $FFFF");
+        assert_eq!(mem.read_byte(0xFFFD), 0x01);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mem.load_image(&path, 0x0400).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(RomBank::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn watches() {
+        let mem = Memory::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let all = mem.add_watch(0x1000..=0x1003, WatchKind::ReadWrite, move |addr, value, is_write| {
+            log.lock().unwrap().push((addr, value, is_write))
+        });
+        let writes = Arc::new(Mutex::new(0));
+        let count = writes.clone();
+        mem.add_watch(0x0000..=0xFFFF, WatchKind::Write, move |_, _, _| *count.lock().unwrap() += 1);
+
+        mem.write_byte(0x1000, 0x11);
+        mem.read_byte(0x1000);
+        mem.fetch_byte(0x2000);
+        assert_eq!(*seen.lock().unwrap(), vec![(0x1000, 0x11, true), (0x1000, 0x11, false)]);
+
+        // Block operations call back once per byte, and only for the bytes in range.
+        seen.lock().unwrap().clear();
+        mem.write_block(0x0FFF, &[1, 2, 3]);
+        let mut data = [0; 2];
+        mem.read_block(0x1001, &mut data);
+        mem.fill(0x1003..=0x1004, 0xFF);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(0x1000, 2, true), (0x1001, 3, true), (0x1001, 3, false), (0x1002, 0, false), (0x1003, 0xFF, true)]
+        );
+        seen.lock().unwrap().clear();
+        assert_eq!(mem.copy(0x1000..=0x1001, 0x1002), 2);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(0x1000, 2, false), (0x1001, 3, false), (0x1002, 2, true), (0x1003, 3, true)]
+        );
+        assert_eq!(*writes.lock().unwrap(), 8);
+
+        assert!(mem.remove_watch(all));
+        assert!(!mem.remove_watch(all));
+        mem.write_byte(0x1000, 0x22);
+        assert_eq!(seen.lock().unwrap().len(), 4);
+        assert_eq!(*writes.lock().unwrap(), 9);
+
+        // Bytes patched into a read-only bank are seen as writes too.
+        mem.configure_banks(vec![RomBank::with_bytes(&[0; 0x100])], &[(0xFF00, 0x100, 1, 0x0000)]).unwrap();
+        let vectors = Arc::new(Mutex::new(Vec::new()));
+        let log = vectors.clone();
+        mem.add_watch(0xFFFC..=0xFFFD, WatchKind::Write, move |addr, value, _| log.lock().unwrap().push((addr, value)));
+        mem.set_vectors(Vectors { nmi: None, reset: 0x1234, irq: None });
+        assert_eq!(*vectors.lock().unwrap(), vec![(0xFFFC, 0x34), (0xFFFD, 0x12)]);
+        assert_eq!(*writes.lock().unwrap(), 11);
+    }
+
+    #[test]
+    fn watches_only_slow_their_pages() {
+        let mem = Memory::new();
+        let direct = |page: usize| mem.ram.direct[page].load(Ordering::Acquire);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let watch = mem.add_watch(0x10FF..=0x1100, WatchKind::Write, move |addr, _, _| log.lock().unwrap().push(addr));
+        assert_eq!((direct(0x0F), direct(0x10), direct(0x11), direct(0x12)), (true, false, false, true));
+
+        mem.write_byte(0x0FFF, 1);
+        mem.write_byte(0x10FF, 2);
+        mem.write_byte(0x1100, 3);
+        mem.write_byte(0x1200, 4);
+        assert_eq!(*seen.lock().unwrap(), vec![0x10FF, 0x1100]);
+
+        mem.remove_watch(watch);
+        assert!((0..=0xFF).all(direct));
+    }
+
+    #[test]
+    fn stats() {
+        use crate::cpus::c6502::{CpuConfig, CpuStart, StopCondition, C6502};
+
+        // LDX #$03; loop: INC $10; DEX; BNE loop
+        let mem = Memory::new();
+        mem.write_block(0x0400, &[0xA2, 0x03, 0xE6, 0x10, 0xCA, 0xD0, 0xFB]);
+        assert!(mem.stats().is_none());
+        mem.enable_stats();
+        let mut cpu = C6502::new_with(&mem, CpuConfig { pc: 0x0400, start: CpuStart::Run, ..CpuConfig::default() });
+        cpu.run_until(&[StopCondition::PcEquals(0x0407)]);
+
+        let stats = mem.stats().unwrap();
+        assert_eq!((stats.reads(0x0400), stats.reads(0x0402), stats.reads(0x0403)), (1, 3, 3));
+        // DEX reads the BNE opcode after its own, and the taken branches read the byte after.
+        assert_eq!((stats.reads(0x0404), stats.reads(0x0405), stats.reads(0x0407)), (3, 6, 2));
+        // INC writes the value back unchanged before writing the result.
+        assert_eq!((stats.reads(0x0010), stats.writes(0x0010)), (3, 6));
+        assert_eq!(stats.writes(0x0402), 0);
+        assert_eq!(stats.top_pages(3), vec![(0x04, 22), (0x00, 9)]);
+        assert_eq!(mem.read_byte(0x0010), 0x03);
+
+        mem.disable_stats();
+        assert!(mem.stats().is_none());
+    }
+
+    #[test]
+    fn snapshots() {
+        let mem = Memory::new();
+        let banks: Vec<Box<dyn MemoryBank + Send>> =
+            vec![RamBank::new(0x200), RomBank::with_bytes(&[0xEA; 0x100]), IoBank::new(0x100, |_| 0xFF, |_, _| {})];
+        let configs = [(0x8000, 0x100, 1, 0x0000), (0x9000, 0x100, 2, 0x0000), (0xD000, 0x100, 3, 0x0000)];
+        mem.configure_banks(banks, &configs).unwrap();
+        mem.write_byte(0x0010, 0x11);
+        mem.write_byte(0x8000, 0x22);
+        let before = mem.snapshot();
+
+        mem.write_byte(0x0010, 0x33);
+        mem.write_byte(0x8000, 0x44);
+        mem.write_byte(0x80FF, 0x55);
+        mem.write_byte(0x9000, 0x66);
+        mem.write_byte(0xD000, 0x77);
+        let after = mem.snapshot();
+        // The write to ROM lands in the RAM underneath, which isn't visible, and I/O isn't compared.
+        assert_eq!(before.diff(&after), vec![(0x0010, 0x11, 0x33), (0x8000, 0x22, 0x44), (0x80FF, 0x00, 0x55)]);
+
+        // Restoring needs the same banks.
+        mem.configure_banks(vec![RamBank::new(0x200)], &[(0x8000, 0x100, 1, 0x0000)]).unwrap();
+        assert_eq!(mem.restore(&after), Err("expected 3 banks, found 1".to_string()));
+
+        let banks: Vec<Box<dyn MemoryBank + Send>> =
+            vec![RamBank::new(0x200), RomBank::with_bytes(&[0xEA; 0x100]), IoBank::new(0x100, |_| 0xFF, |_, _| {})];
+        mem.configure_banks(banks, &configs).unwrap();
+        mem.restore(&before).unwrap();
+        assert_eq!((mem.read_byte(0x0010), mem.read_byte(0x8000), mem.read_byte(0x80FF)), (0x11, 0x22, 0x00));
+        assert_eq!(mem.snapshot(), before);
+        mem.restore(&after).unwrap();
+        assert_eq!((mem.read_byte(0x0010), mem.read_byte(0x8000)), (0x33, 0x44));
+
+        // A snapshot that maps a bank that isn't there can't be restored.
+        let mut bad_map = before.clone();
+        bad_map.map[0x80] = (4, 0x8000);
+        assert_eq!(mem.restore(&bad_map), Err("snapshot maps bank 4, but there are only 3".to_string()));
+        assert_eq!(mem.read_byte(0x0010), 0x33);
+
+        // Nor can a different ROM be restored over.
+        assert!(mem.reload_bank_unchecked(2, &[0x00]));
+        assert_eq!(mem.restore(&before), Err("bank 2 differs from the one in the snapshot".to_string()));
+        assert_eq!(mem.read_byte(0x0010), 0x33);
+    }
+
+    /// Mirrors the RAM $1000 bytes below it, as incompletely decoded address lines do.
+    ///
+    struct MirrorBank;
+
+    impl MemoryBank for MirrorBank {
+        fn size(&self) -> usize {
+            0x100
+        }
+
+        fn is_writeable(&self, _addr: u16) -> bool {
+            true
+        }
+
+        fn read_byte(&self, addr: u16, _offset: u16, ram: &[u8]) -> u8 {
+            ram[(addr - 0x1000) as usize]
+        }
+
+        fn write_byte(&mut self, addr: u16, _offset: u16, val: u8, ram: &mut [u8]) {
+            ram[(addr - 0x1000) as usize] = val;
+        }
+    }
+
+    #[test]
+    fn banks_see_all_of_ram() {
+        let mut mem = Memory::new();
+        mem.configure_banks(vec![Box::new(MirrorBank)], &[(0x1100, 0x100, 1, 0x0000)]).unwrap();
+        mem.write_byte(0x0110, 0x12);
+        assert_eq!(mem.read_byte(0x1110), 0x12);
+        mem.write_byte(0x1120, 0x34);
+        assert_eq!(mem.read_byte(0x0120), 0x34);
+
+        // Changes made behind the bank's back are seen too.
+        mem.write_block(0x0110, &[0x56, 0x78]);
+        assert_eq!(mem.read_byte(0x1111), 0x78);
+        mem.reset(ResetKind::Hard);
+        assert_eq!(mem.read_byte(0x1110), 0x00);
+        assert_eq!(mem.read_byte(0x1120), 0x00);
+    }
+
+    /// A writeable bank that can't be reloaded.
+    ///
+    struct FixedBank {
+        bytes: Vec<u8>,
+    }
+
+    impl MemoryBank for FixedBank {
+        fn size(&self) -> usize {
+            self.bytes.len()
+        }
+
+        fn is_writeable(&self, _addr: u16) -> bool {
+            true
+        }
+
+        fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+            self.bytes[(addr - offset) as usize]
+        }
+
+        fn write_byte(&mut self, addr: u16, offset: u16, val: u8, _ram: &mut [u8]) {
+            self.bytes[(addr - offset) as usize] = val;
+        }
+
+        fn contents(&self) -> Option<&[u8]> {
+            Some(&self.bytes)
+        }
+    }
+
+    #[test]
+    fn restore_checks_before_changing() {
+        let mem = Memory::new();
+        let banks: Vec<Box<dyn MemoryBank + Send>> =
+            vec![RamBank::new(0x100), Box::new(FixedBank { bytes: vec![0; 0x100] })];
+        mem.configure_banks(banks, &[(0x8000, 0x100, 1, 0x0000), (0x9000, 0x100, 2, 0x0000)]).unwrap();
+        mem.write_byte(0x0010, 0x11);
+        mem.write_byte(0x8000, 0x22);
+        let snapshot = mem.snapshot();
+
+        // The second bank can't be reloaded, so neither the first bank nor the RAM is restored.
+        mem.write_byte(0x0010, 0x33);
+        mem.write_byte(0x8000, 0x44);
+        assert_eq!(mem.restore(&snapshot), Err("bank 2 can't be reloaded".to_string()));
+        assert_eq!((mem.read_byte(0x0010), mem.read_byte(0x8000)), (0x33, 0x44));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serialized_snapshot() {
+        let mem = Memory::new();
+        mem.configure_banks(vec![RamBank::new(0x100)], &[(0x8000, 0x100, 1, 0x0000)]).unwrap();
+        mem.write_byte(0x8000, 0x12);
+        let snapshot = mem.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<MemorySnapshot>(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn reload_bank() {
+        use crate::cpus::c6502::C6502;
+
+        // LDA #value; STA $10; hang: JMP hang, with the reset vector pointing at it.
+        let image = |value| {
+            let mut image = vec![0; 0x100];
+            image[0..7].copy_from_slice(&[0xA9, value, 0x85, 0x10, 0x4C, 0x04, 0xFF]);
+            image[0xFC..0xFE].copy_from_slice(&[0x00, 0xFF]);
+            image
+        };
+        let mem = Memory::new();
+        mem.configure_banks(vec![RomBank::with_bytes(&image(0x11))], &[(0xFF00, 0x100, 1, 0x0000)]).unwrap();
+        let mut cpu = C6502::new(&mem);
+        cpu.reset();
+        for _ in 0..20 {
+            cpu.step();
+        }
+        assert_eq!(mem.read_byte(0x10), 0x11);
+
+        assert!(cpu.reload_bank(1, &image(0x22), true));
+        for _ in 0..20 {
+            cpu.step();
+        }
+        assert_eq!(mem.read_byte(0x10), 0x22);
+
+        assert!(cpu.reload_bank(1, &[0xEA], false));
+        assert_eq!(mem.read_byte(0xFF01), 0x00);
+        assert!(!cpu.reload_bank(1, &[0; 0x101], false));
+        assert!(!cpu.reload_bank(2, &[0xEA], false));
+        assert!(!cpu.reload_bank(0, &[0xEA], false));
+
+        // Held in reset through its RESET line, the CPU restarts on the new code once released.
+        let run = |cpu: &mut C6502| {
+            for _ in 0..20 {
+                // Apply the line between cycles, as `run` does.
+                while let Some(level) = cpu.reset_in().try_recv() {
+                    cpu.set_reset(!level);
+                }
+                cpu.step();
+            }
+        };
+        let mut reset = OutputPin::with_initial_value(true);
+        reset.connect_to(cpu.reset_in());
+        {
+            let hold = ResetHold::new(&mut reset);
+            assert!(mem.reload_bank(1, &image(0x33), &hold));
+            run(&mut cpu);
+            assert_eq!(mem.read_byte(0x10), 0x22);
+        }
+        run(&mut cpu);
+        assert_eq!(mem.read_byte(0x10), 0x33);
+    }
 }
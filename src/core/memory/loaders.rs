@@ -0,0 +1,85 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use super::Memory;
+
+/// Why a program couldn't be loaded.
+///
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    MissingLoadAddress,
+    PastEndOfMemory { addr: u16, len: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::MissingLoadAddress => write!(f, "file too short to hold a load address"),
+            LoadError::PastEndOfMemory { addr, len } => {
+                write!(f, "program of {} bytes at ${:04X} would run past $FFFF", len, addr)
+            },
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Load a Commodore PRG file, a little-endian load address followed by the program, into
+/// memory at its load address. Returns the load address and the length of the program.
+///
+pub fn load_prg<R: Read>(reader: R, memory: &Memory) -> Result<(u16, usize), LoadError> {
+    load_prg_at(reader, memory, None)
+}
+
+/// Load a PRG file as `load_prg` does, but at `addr` rather than its own load address, if
+/// given. A program that would run past $FFFF is rejected, with nothing loaded.
+///
+pub fn load_prg_at<R: Read>(mut reader: R, memory: &Memory, addr: Option<u16>) -> Result<(u16, usize), LoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() < 2 {
+        return Err(LoadError::MissingLoadAddress);
+    }
+    let addr = addr.unwrap_or(u16::from_le_bytes([bytes[0], bytes[1]]));
+    let program = &bytes[2..];
+    if program.len() > 0x10000 - addr as usize {
+        return Err(LoadError::PastEndOfMemory { addr, len: program.len() });
+    }
+    memory.write_block(addr, program);
+    Ok((addr, program.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA #$01; STA $D020; RTS, to load at $C000.
+    const SAMPLE: &[u8] = include_bytes!("sample.prg");
+
+    #[test]
+    fn prg() {
+        let memory = Memory::new();
+        assert_eq!(load_prg(SAMPLE, &memory).unwrap(), (0xC000, 6));
+        let mut bytes = [0; 7];
+        memory.read_block(0xC000, &mut bytes);
+        assert_eq!(bytes, [0xA9, 0x01, 0x8D, 0x20, 0xD0, 0x60, 0x00]);
+
+        assert_eq!(load_prg_at(SAMPLE, &memory, Some(0xFFFA)).unwrap(), (0xFFFA, 6));
+        assert_eq!(memory.read_byte(0xFFFF), 0x60);
+
+        let err = load_prg_at(SAMPLE, &memory, Some(0xFFFB)).unwrap_err();
+        assert_eq!(err.to_string(), "program of 6 bytes at $FFFB would run past $FFFF");
+        assert_eq!(memory.read_byte(0xFFFB), 0x01);
+        let err = load_prg(&[0xFE, 0xFF, 0xEA, 0xEA, 0xEA][..], &memory).unwrap_err();
+        assert!(matches!(err, LoadError::PastEndOfMemory { addr: 0xFFFE, len: 3 }));
+        assert!(matches!(load_prg(&[0x00][..], &memory), Err(LoadError::MissingLoadAddress)));
+    }
+}
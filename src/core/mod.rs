@@ -1,20 +1,62 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
+#[cfg(feature = "ui")]
 use iui::controls::*;
+#[cfg(feature = "ui")]
 use iui::prelude::*;
+use std::any::Any;
 use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub mod clock;
+pub mod coverage;
+pub mod faults;
 pub mod memory;
 pub mod ports;
+pub mod savestate;
+
+use ports::{ConnectionDepth, Connections};
+
+/// How often components waiting for input check whether they've been asked to stop, so they
+/// stop even if no more input arrives.
+///
+pub const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub trait AsyncComponent: Send {
     fn run(&mut self, stop: Arc<AtomicBool>);
+
+    /// When the component is stopped, relative to the others (see `Computer::stop`). The
+    /// default is `ShutdownPhase::Processor`.
+    ///
+    fn shutdown_phase(&self) -> ShutdownPhase {
+        ShutdownPhase::Processor
+    }
+}
+
+/// The order async components are stopped in, so no component is left waiting on one that has
+/// already gone, and nothing sent is lost.
+///
+/// * Sources, like clocks, produce signals without consuming any, and stop first.
+/// * Processors, like CPUs, consume signals and produce others, and stop once the sources have.
+/// * Sinks, like trace writers or physical outputs, only consume signals, and stop last, once
+///   everything sent to them has arrived.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ShutdownPhase {
+    Source,
+    Processor,
+    Sink,
+}
+
+impl ShutdownPhase {
+    pub const ALL: [ShutdownPhase; 3] = [ShutdownPhase::Source, ShutdownPhase::Processor, ShutdownPhase::Sink];
 }
 
 enum AsyncComponentEntry {
@@ -27,33 +69,163 @@ pub trait SyncComponent {
     fn start(&mut self);
     fn tick(&mut self);
     fn stop(&mut self);
+
+    /// Do part of the work of a tick, taking no more than roughly `budget`. Computer calls this
+    /// instead of `tick` for components whose ticks have run over the tick budget (see
+    /// `Computer::set_tick_budget`), so components with heavy ticks should spread their work
+    /// over several calls. The default implementation does a full tick.
+    ///
+    fn tick_partial(&mut self, _budget: Duration) -> Progress {
+        self.tick();
+        Progress::Done
+    }
 }
 
+/// Whether a `SyncComponent::tick_partial` call finished the component's work, or left some
+/// for later calls.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Progress {
+    Done,
+    Pending,
+}
+
+#[cfg(feature = "ui")]
 pub trait UiComponent: SyncComponent {
     fn create_control(&mut self, ui: iui::UI) -> Control;
 }
 
+/// The flavor of a machine reset.
+///
+/// * A hard reset is a power cycle: memory contents are lost, and every component returns to its
+///   power-on state.
+/// * A soft reset is the reset line being pulled: memory is preserved, and components only reset
+///   their registers.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ResetKind {
+    Soft,
+    Hard,
+}
+
+pub trait Resettable {
+    fn reset(&mut self, kind: ResetKind);
+}
+
 enum SyncComponentEntry {
-    UI(Rc<RefCell<dyn UiComponent>>),
+    #[cfg(feature = "ui")]
+    UI(Rc<RefCell<dyn UiComponent>>, &'static str),
     NonUI(Rc<RefCell<dyn SyncComponent>>),
 }
 
 pub struct Computer {
     async_components: Vec<AsyncComponentEntry>,
+    async_info: Vec<(&'static str, ShutdownPhase)>,
+    shutdown_timeout: Duration,
     sync_components: Vec<SyncComponentEntry>,
-    stop: Arc<AtomicBool>,
+    budget_states: Vec<BudgetState>,
+    tick_budget: Option<Duration>,
+    // The time the tick budget is measured by, since some fixed point.
+    budget_clock: Box<dyn Fn() -> Duration>,
+    resettables: Vec<Box<dyn Resettable>>,
+    connections: Connections,
+    depth_alarm: Option<DepthAlarm>,
+    stops: [Arc<AtomicBool>; 3],
+    faults: Arc<Mutex<Vec<ComponentFault>>>,
+    #[cfg(feature = "ui")]
     requires_ui: bool,
+    #[cfg(feature = "ui")]
     iui: Option<iui::UI>,
+    #[cfg(feature = "ui")]
+    ui_optional: bool,
+    #[cfg(feature = "ui")]
+    headless: bool,
+    #[cfg(feature = "ui")]
+    ui_init: fn() -> Result<iui::UI, String>,
+}
+
+/// An error starting a `Computer`.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ComputerError {
+    /// The UI library couldn't be initialized, e.g. because there's no display. See
+    /// `Computer::ui_optional` for running without the UI instead.
+    ///
+    UiUnavailable(String),
+
+    /// One or more async components panicked, and the computer was stopped.
+    ///
+    Faulted(Vec<ComponentFault>),
+}
+
+impl fmt::Display for ComputerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComputerError::UiUnavailable(reason) => write!(f, "couldn't initialize UI library: {}", reason),
+            ComputerError::Faulted(faults) => {
+                let faults: Vec<String> = faults.iter().map(|fault| fault.to_string()).collect();
+                write!(f, "{}", faults.join("; "))
+            },
+        }
+    }
+}
+
+impl Error for ComputerError {}
+
+/// An async component that panicked: its type, and the panic's message.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ComponentFault {
+    pub component: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ComponentFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} panicked: {}", self.component, self.message)
+    }
+}
+
+/// Returns the message a panic was raised with, given its payload.
+///
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
 }
 
 impl Computer {
     pub fn new() -> Self {
         Self {
             async_components: Vec::new(),
+            async_info: Vec::new(),
+            shutdown_timeout: Duration::from_secs(1),
             sync_components: Vec::new(),
-            stop: Arc::new(AtomicBool::new(false)),
+            budget_states: Vec::new(),
+            tick_budget: None,
+            budget_clock: {
+                let epoch = Instant::now();
+                Box::new(move || epoch.elapsed())
+            },
+            resettables: Vec::new(),
+            connections: Connections::new(),
+            depth_alarm: None,
+            stops: Default::default(),
+            faults: Arc::default(),
+            #[cfg(feature = "ui")]
             requires_ui: false,
+            #[cfg(feature = "ui")]
             iui: None,
+            #[cfg(feature = "ui")]
+            ui_optional: false,
+            #[cfg(feature = "ui")]
+            headless: false,
+            #[cfg(feature = "ui")]
+            ui_init: || UI::init().map_err(|e| format!("{:?}", e)),
         }
     }
 
@@ -62,6 +234,7 @@ impl Computer {
         T: AsyncComponent + Sized + 'static,
     {
         let c = Box::new(c);
+        self.async_info.push((std::any::type_name::<T>(), c.shutdown_phase()));
         self.async_components.push(AsyncComponentEntry::Initial(c));
         match self.async_components.last_mut().unwrap() {
             AsyncComponentEntry::Initial(c) => c.as_mut(),
@@ -76,52 +249,154 @@ impl Computer {
         let c = Rc::new(RefCell::new(c));
         let ret = c.clone();
         self.sync_components.push(SyncComponentEntry::NonUI(c));
+        self.budget_states.push(BudgetState::default());
         ret
     }
 
+    #[cfg(feature = "ui")]
     pub fn add_ui<T>(&mut self, c: T) -> Rc<RefCell<dyn UiComponent>>
     where
         T: UiComponent + Sized + 'static,
     {
         let c = Rc::new(RefCell::new(c));
         let ret = c.clone();
-        self.sync_components.push(SyncComponentEntry::UI(c));
+        self.sync_components.push(SyncComponentEntry::UI(c, std::any::type_name::<T>()));
+        self.budget_states.push(BudgetState::default());
         self.requires_ui = true;
         ret
     }
 
-    pub fn run(&mut self) {
-        self.start();
-        let iui = self.iui.clone();
-        if let Some(iui) = &iui {
+    /// Register something to reset with `reset`. Components that run on their own thread once
+    /// added with `add_async` can't be registered themselves, so register a handle that shares
+    /// their state instead: a clone of `Memory`, or a CPU's `C6502::reset_handle`.
+    ///
+    pub fn add_resettable<T>(&mut self, r: T)
+    where
+        T: Resettable + 'static,
+    {
+        self.resettables.push(Box::new(r));
+    }
+
+    pub fn reset(&mut self, kind: ResetKind) {
+        for r in self.resettables.iter_mut() {
+            r.reset(kind);
+        }
+    }
+
+    /// Returns the computer's named connections between ports. Pass them to
+    /// `OutputPort::connect_named` as the machine is wired, to watch the connections' traffic and
+    /// inject faults into them.
+    ///
+    pub fn connections(&self) -> Connections {
+        self.connections.clone()
+    }
+
+    /// Returns the traffic on every live named connection between ports. Connections made with
+    /// `OutputPort::connect_to` aren't included unless they're added to `connections` too.
+    ///
+    pub fn connection_depths(&self) -> Vec<ConnectionDepth> {
+        self.connections.depths()
+    }
+
+    /// Call `alarm` from `tick` when more than `threshold` values are queued on a named
+    /// connection, which usually means the consumer can't keep up with the producer. Each
+    /// connection is reported once, and again only after its queue has drained to half the
+    /// threshold.
+    ///
+    pub fn set_depth_alarm<F>(&mut self, threshold: usize, alarm: F)
+    where
+        F: FnMut(&ConnectionDepth) + 'static,
+    {
+        self.depth_alarm = Some(DepthAlarm { threshold, alarm: Box::new(alarm) });
+    }
+
+    pub fn clear_depth_alarm(&mut self) {
+        self.depth_alarm = None;
+    }
+
+    /// Set the time each sync component may take to tick. A component whose tick runs over the
+    /// budget is asked to do its work incrementally through `tick_partial`, so it doesn't hold up
+    /// the other components, or the UI, until it finishes a tick within the budget again. `None`,
+    /// the default, disables the budget.
+    ///
+    pub fn set_tick_budget(&mut self, budget: Option<Duration>) {
+        self.tick_budget = budget;
+        self.budget_states.fill(BudgetState::default());
+    }
+
+    /// Set how long `stop` waits for each phase of async components to stop (see
+    /// `ShutdownPhase`) before giving up on them and moving on to the next. The default is one
+    /// second.
+    ///
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    /// Whether to carry on without the UI if the UI library can't be initialized, e.g. when
+    /// running in CI or over ssh. UI components are then left out entirely: they're never given
+    /// a control, started or ticked. When not set, the default, `start` and `run` fail instead.
+    ///
+    #[cfg(feature = "ui")]
+    pub fn ui_optional(&mut self, optional: bool) {
+        self.ui_optional = optional;
+    }
+
+    pub fn run(&mut self) -> Result<(), ComputerError> {
+        self.start()?;
+        #[cfg(feature = "ui")]
+        if let Some(iui) = self.iui.clone() {
             let mut event_loop = iui.event_loop();
-            event_loop.on_tick(iui, || self.tick());
-            event_loop.run_delay(iui, 1);
-        } else {
-            let (s, r): (Sender<()>, Receiver<()>) = unbounded();
-            ctrlc::set_handler(move || {
-                s.send(()).unwrap();
-            })
-            .expect("Error setting Ctrl-C handler");
-            println!("Hit Ctrl-C to stop");
-            while r.try_recv().is_err() {
-                thread::sleep(Duration::from_millis(1));
-                self.tick();
-            }
+            event_loop.on_tick(&iui, || self.tick());
+            event_loop.run_delay(&iui, 1);
+            self.stop();
+            return self.health().map_err(ComputerError::Faulted);
+        }
+
+        let (s, r): (Sender<()>, Receiver<()>) = unbounded();
+        ctrlc::set_handler(move || {
+            s.send(()).unwrap();
+        })
+        .expect("Error setting Ctrl-C handler");
+        println!("Hit Ctrl-C to stop");
+        while r.try_recv().is_err() && self.health().is_ok() {
+            thread::sleep(Duration::from_millis(1));
+            self.tick();
         }
         self.stop();
+        self.health().map_err(ComputerError::Faulted)
     }
 
-    pub fn start(&mut self) {
+    pub fn start(&mut self) -> Result<(), ComputerError> {
+        #[cfg(feature = "ui")]
         if self.requires_ui {
-            self.iui = Some(UI::init().expect("Couldn't initialize UI library"));
+            match (self.ui_init)() {
+                Ok(ui) => self.iui = Some(ui),
+                Err(reason) if self.ui_optional => {
+                    let skipped: Vec<&str> = self
+                        .sync_components
+                        .iter()
+                        .filter_map(|c| match c {
+                            SyncComponentEntry::UI(_, name) => Some(*name),
+                            _ => None,
+                        })
+                        .collect();
+                    println!("Warning: UI unavailable ({}), skipping {}", reason, skipped.join(", "));
+                    self.headless = true;
+                },
+                Err(reason) => return Err(ComputerError::UiUnavailable(reason)),
+            }
         }
-        self.stop = Arc::new(AtomicBool::new(false));
-        for component in self.async_components.iter_mut() {
+        self.stops = Default::default();
+        for (component, &(name, phase)) in self.async_components.iter_mut().zip(self.async_info.iter()) {
             if let AsyncComponentEntry::Initial(mut c) = mem::replace(component, AsyncComponentEntry::None) {
-                let stop_clone = self.stop.clone();
+                let stop_clone = self.stops[phase as usize].clone();
+                let faults = self.faults.clone();
                 let handle = thread::spawn(move || {
-                    c.run(stop_clone);
+                    // Record a panic rather than letting it go unnoticed until the thread is joined.
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| c.run(stop_clone))) {
+                        let message = panic_message(payload.as_ref());
+                        faults.lock().unwrap().push(ComponentFault { component: name, message });
+                    }
                 });
                 *component = AsyncComponentEntry::Running(handle);
             } else {
@@ -130,7 +405,10 @@ impl Computer {
         }
         for component in self.sync_components.iter_mut() {
             match component {
-                SyncComponentEntry::UI(component) => {
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(..) if self.headless => {},
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(component, _) => {
                     let ui = self.iui.as_ref().unwrap();
                     let mut c = component.borrow_mut();
                     let mut window = Window::new(ui, "Rustycoat", 100, 100, WindowType::NoMenubar);
@@ -144,31 +422,81 @@ impl Computer {
                 },
             }
         }
+        Ok(())
     }
 
     pub fn tick(&mut self) {
-        for component in self.sync_components.iter_mut() {
+        if let Some(DepthAlarm { threshold, alarm }) = &mut self.depth_alarm {
+            for c in self.connections.check_depth_alarm(*threshold) {
+                alarm(&c);
+            }
+        }
+        let (budget, now) = (self.tick_budget, &self.budget_clock);
+        for (component, state) in self.sync_components.iter_mut().zip(self.budget_states.iter_mut()) {
             match component {
-                SyncComponentEntry::UI(c) => {
-                    c.borrow_mut().tick();
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(..) if self.headless => {},
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(c, _) => {
+                    tick_within_budget(&mut *c.borrow_mut(), budget, state, now);
                 },
                 SyncComponentEntry::NonUI(c) => {
-                    c.borrow_mut().tick();
+                    tick_within_budget(&mut *c.borrow_mut(), budget, state, now);
                 },
             }
         }
     }
 
+    /// Returns the async components that have panicked, if any. Components that panic stop
+    /// running, but the rest carry on until the computer is stopped; `run` stops it as soon as
+    /// a component panics.
+    ///
+    pub fn health(&self) -> Result<(), Vec<ComponentFault>> {
+        let faults = self.faults.lock().unwrap();
+        if faults.is_empty() {
+            Ok(())
+        } else {
+            Err(faults.clone())
+        }
+    }
+
+    /// Stop every component. Async components are stopped a phase at a time, sources first and
+    /// sinks last (see `ShutdownPhase`), waiting for each phase to finish before signalling the
+    /// next. A component still running after the shutdown timeout is abandoned with a warning,
+    /// so one stuck component can't hang the computer. Sync components are stopped last.
+    ///
     pub fn stop(&mut self) {
-        self.stop.store(true, Ordering::Relaxed);
-        for component in self.async_components.iter_mut() {
-            if let AsyncComponentEntry::Running(handle) = mem::replace(component, AsyncComponentEntry::None) {
-                handle.join().ok();
+        for phase in ShutdownPhase::ALL {
+            self.stops[phase as usize].store(true, Ordering::Relaxed);
+            let deadline = Instant::now() + self.shutdown_timeout;
+            for (component, (name, _)) in self
+                .async_components
+                .iter_mut()
+                .zip(self.async_info.iter())
+                .filter(|(_, (_, p))| *p == phase)
+            {
+                if let AsyncComponentEntry::Running(handle) = mem::replace(component, AsyncComponentEntry::None) {
+                    while !handle.is_finished() && Instant::now() < deadline {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    if handle.is_finished() {
+                        handle.join().ok();
+                    } else {
+                        println!(
+                            "Warning: {} didn't stop within {} ms, abandoning it",
+                            name,
+                            self.shutdown_timeout.as_millis()
+                        );
+                    }
+                }
             }
         }
         for component in self.sync_components.iter_mut() {
             match component {
-                SyncComponentEntry::UI(c) => {
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(..) if self.headless => {},
+                #[cfg(feature = "ui")]
+                SyncComponentEntry::UI(c, _) => {
                     c.borrow_mut().stop();
                 },
                 SyncComponentEntry::NonUI(c) => {
@@ -179,8 +507,354 @@ impl Computer {
     }
 }
 
+struct DepthAlarm {
+    threshold: usize,
+    alarm: Box<dyn FnMut(&ConnectionDepth)>,
+}
+
+/// How a sync component is getting on with the tick budget: whether its last full tick ran
+/// over, and if so, how long the incremental tick in progress has taken so far.
+///
+#[derive(Default, Copy, Clone)]
+struct BudgetState {
+    over: bool,
+    spent: Duration,
+}
+
+/// Tick `c`, or do part of a tick if it's over `budget`, timing it by `now`.
+///
+fn tick_within_budget<C>(c: &mut C, budget: Option<Duration>, state: &mut BudgetState, now: &dyn Fn() -> Duration)
+where
+    C: SyncComponent + ?Sized,
+{
+    let Some(budget) = budget else {
+        c.tick();
+        return;
+    };
+    let start = now();
+    if state.over {
+        let progress = c.tick_partial(budget);
+        state.spent += now() - start;
+        if progress == Progress::Done {
+            // The component can go back to full ticks once a whole tick fits in the budget.
+            state.over = state.spent > budget;
+            state.spent = Duration::ZERO;
+        }
+    } else {
+        c.tick();
+        state.over = now() - start > budget;
+    }
+}
+
 impl Default for Computer {
     fn default() -> Computer {
         Computer::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct Counter {
+        ticks: Rc<RefCell<usize>>,
+    }
+
+    impl SyncComponent for Counter {
+        fn start(&mut self) {}
+
+        fn tick(&mut self) {
+            *self.ticks.borrow_mut() += 1;
+        }
+
+        fn stop(&mut self) {}
+    }
+
+    // Times the tick budget by a simulated clock, which components move on to do their work.
+    fn simulate_time(computer: &mut Computer) -> Rc<Cell<Duration>> {
+        let time = Rc::new(Cell::new(Duration::ZERO));
+        let now = time.clone();
+        computer.budget_clock = Box::new(move || now.get());
+        time
+    }
+
+    // Does 20ms of work per full tick, or a slice of it within the budget.
+    struct Slow {
+        time: Rc<Cell<Duration>>,
+        remaining: Duration,
+    }
+
+    impl SyncComponent for Slow {
+        fn start(&mut self) {}
+
+        fn tick(&mut self) {
+            self.time.set(self.time.get() + Duration::from_millis(20));
+        }
+
+        fn stop(&mut self) {}
+
+        fn tick_partial(&mut self, budget: Duration) -> Progress {
+            let slice = self.remaining.min(budget);
+            self.time.set(self.time.get() + slice);
+            self.remaining -= slice;
+            if self.remaining.is_zero() {
+                self.remaining = Duration::from_millis(20);
+                Progress::Done
+            } else {
+                Progress::Pending
+            }
+        }
+    }
+
+    fn ticks_in(
+        computer: &mut Computer, time: &Rc<Cell<Duration>>, ticks: &Rc<RefCell<usize>>, duration: Duration,
+    ) -> usize {
+        *ticks.borrow_mut() = 0;
+        let end = time.get() + duration;
+        while time.get() < end {
+            computer.tick();
+        }
+        let count = *ticks.borrow();
+        count
+    }
+
+    #[test]
+    fn tick_budget() {
+        let ticks = Rc::new(RefCell::new(0));
+        let mut computer = Computer::new();
+        let time = simulate_time(&mut computer);
+        computer.add_sync(Slow { time: time.clone(), remaining: Duration::from_millis(20) });
+        computer.add_sync(Counter { ticks: ticks.clone() });
+
+        // Without a budget, the slow component holds up every pass.
+        assert_eq!(ticks_in(&mut computer, &time, &ticks, Duration::from_millis(200)), 10);
+
+        // With a budget, the slow component is ticked incrementally once it runs over, a
+        // millisecond a pass.
+        computer.set_tick_budget(Some(Duration::from_millis(1)));
+        assert_eq!(ticks_in(&mut computer, &time, &ticks, Duration::from_millis(200)), 181);
+    }
+
+    // Does `cost` of work per tick, counting the full ticks.
+    struct Variable {
+        time: Rc<Cell<Duration>>,
+        cost: Rc<RefCell<Duration>>,
+        remaining: Duration,
+        full_ticks: Rc<RefCell<usize>>,
+    }
+
+    impl SyncComponent for Variable {
+        fn start(&mut self) {}
+
+        fn tick(&mut self) {
+            self.time.set(self.time.get() + *self.cost.borrow());
+            *self.full_ticks.borrow_mut() += 1;
+        }
+
+        fn stop(&mut self) {}
+
+        fn tick_partial(&mut self, budget: Duration) -> Progress {
+            let slice = self.remaining.min(budget);
+            self.time.set(self.time.get() + slice);
+            self.remaining -= slice;
+            if self.remaining.is_zero() {
+                self.remaining = *self.cost.borrow();
+                Progress::Done
+            } else {
+                Progress::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn tick_budget_recovers() {
+        let cost = Rc::new(RefCell::new(Duration::from_millis(20)));
+        let full_ticks = Rc::new(RefCell::new(0));
+        let mut computer = Computer::new();
+        let time = simulate_time(&mut computer);
+        let remaining = *cost.borrow();
+        computer.add_sync(Variable { time, cost: cost.clone(), remaining, full_ticks: full_ticks.clone() });
+        computer.set_tick_budget(Some(Duration::from_millis(5)));
+
+        // The first tick runs over, so the next few are incremental.
+        computer.tick();
+        for _ in 0..8 {
+            computer.tick();
+        }
+        assert_eq!(*full_ticks.borrow(), 1);
+
+        // Once the work gets cheap, a whole incremental tick fits in the budget, and the
+        // component goes back to full ticks. The incremental tick in progress finishes first.
+        *cost.borrow_mut() = Duration::ZERO;
+        for _ in 0..10 {
+            computer.tick();
+        }
+        assert_eq!(*full_ticks.borrow(), 6);
+    }
+
+    #[test]
+    fn depth_alarm() {
+        let mut computer = Computer::new();
+        let other = Computer::new();
+        let alarms = Rc::new(RefCell::new(Vec::new()));
+        let a = alarms.clone();
+        computer.set_depth_alarm(10, move |c: &ConnectionDepth| a.borrow_mut().push((c.producer.clone(), c.depth)));
+
+        let mut output = ports::OutputPin::new();
+        let mut input = ports::InputPin::new();
+        output.connect_named(&mut input, &computer.connections(), "alarm.output", "alarm.input");
+        let mut other_output = ports::OutputPin::new();
+        let mut other_input = ports::InputPin::new();
+        other_output.connect_named(&mut other_input, &other.connections(), "other.output", "other.input");
+        for _ in 0..11 {
+            output.send(true);
+            other_output.send(true);
+        }
+        computer.tick();
+        computer.tick();
+        assert_eq!(*alarms.borrow(), vec![("alarm.output".to_string(), 11)]);
+        assert_eq!(other.connection_depths().len(), 1);
+        assert_eq!(other.connection_depths()[0].producer, "other.output");
+
+        computer.clear_depth_alarm();
+        for _ in 0..11 {
+            input.recv();
+        }
+        for _ in 0..11 {
+            output.send(true);
+        }
+        computer.tick();
+        assert_eq!(alarms.borrow().len(), 1);
+    }
+
+    // Writes every edge it receives to a file, as a trace writer would.
+    struct EdgeWriter {
+        input: ports::InputPin,
+        path: std::path::PathBuf,
+    }
+
+    impl AsyncComponent for EdgeWriter {
+        fn shutdown_phase(&self) -> ShutdownPhase {
+            ShutdownPhase::Sink
+        }
+
+        fn run(&mut self, stop: Arc<AtomicBool>) {
+            use std::io::Write;
+            let mut w = std::io::BufWriter::new(std::fs::File::create(&self.path).unwrap());
+            while let Some(level) = self.input.recv_checked() {
+                writeln!(w, "{}", level as u8).unwrap();
+                if stop.load(Ordering::Relaxed) {
+                    // Write whatever is still queued, which is everything if the CPU has stopped.
+                    while let Some(level) = self.input.try_recv() {
+                        writeln!(w, "{}", level as u8).unwrap();
+                    }
+                    break;
+                }
+            }
+            w.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn ordered_shutdown() {
+        use crate::core::clock::Clock;
+        use crate::core::memory::Memory;
+        use crate::cpus::c6502::C6502;
+
+        let dir = std::env::temp_dir();
+        for i in 0..20 {
+            let memory = Memory::new();
+            crate::roms::MULTIPLY.install(&memory);
+            let mut cpu = C6502::new(&memory);
+            cpu.reset();
+            let mut clock = Clock::new(200_000);
+            clock.output().connect_to(cpu.phi0_in());
+            let paths = [1, 2].map(|phi| dir.join(format!("rustycoat-shutdown-{}-{}-{}", std::process::id(), i, phi)));
+            let mut phi1 = EdgeWriter { input: ports::InputPin::new(), path: paths[0].clone() };
+            let mut phi2 = EdgeWriter { input: ports::InputPin::new(), path: paths[1].clone() };
+            cpu.phi1_out().connect_to(&mut phi1.input);
+            cpu.phi2_out().connect_to(&mut phi2.input);
+
+            // Add the components in the reverse of the order they have to stop in.
+            let stats = cpu.stats();
+            let mut computer = Computer::new();
+            computer.add_async(phi1);
+            computer.add_async(phi2);
+            computer.add_async(cpu);
+            computer.add_async(clock);
+            computer.start().unwrap();
+            // Stop part way through, once the CPU has had a few edges however slowly it started.
+            while stats.cycles() < 100 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            computer.stop();
+
+            // The CPU sends both phases on every edge, so neither trace may be missing any.
+            let lines = paths.map(|path| {
+                let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+                std::fs::remove_file(&path).ok();
+                lines
+            });
+            assert!(lines[0] > 0);
+            assert_eq!(lines[0], lines[1]);
+        }
+    }
+
+    // A display that never gets a control, since the UI library fails to initialize.
+    #[cfg(feature = "ui")]
+    struct Display {
+        ticks: Rc<RefCell<usize>>,
+    }
+
+    #[cfg(feature = "ui")]
+    impl SyncComponent for Display {
+        fn start(&mut self) {}
+
+        fn tick(&mut self) {
+            *self.ticks.borrow_mut() += 1;
+        }
+
+        fn stop(&mut self) {}
+    }
+
+    #[cfg(feature = "ui")]
+    impl UiComponent for Display {
+        fn create_control(&mut self, _ui: iui::UI) -> Control {
+            unreachable!()
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    fn headless_computer(logic_ticks: &Rc<RefCell<usize>>, display_ticks: &Rc<RefCell<usize>>) -> Computer {
+        let mut computer = Computer::new();
+        computer.ui_init = || Err("no display".to_string());
+        computer.add_sync(Counter { ticks: logic_ticks.clone() });
+        computer.add_ui(Display { ticks: display_ticks.clone() });
+        computer
+    }
+
+    #[test]
+    #[cfg(feature = "ui")]
+    fn ui_unavailable() {
+        let logic_ticks = Rc::new(RefCell::new(0));
+        let display_ticks = Rc::new(RefCell::new(0));
+        let mut computer = headless_computer(&logic_ticks, &display_ticks);
+        assert_eq!(computer.start(), Err(ComputerError::UiUnavailable("no display".to_string())));
+    }
+
+    #[test]
+    #[cfg(feature = "ui")]
+    fn ui_optional() {
+        let logic_ticks = Rc::new(RefCell::new(0));
+        let display_ticks = Rc::new(RefCell::new(0));
+        let mut computer = headless_computer(&logic_ticks, &display_ticks);
+        computer.ui_optional(true);
+        assert_eq!(computer.start(), Ok(()));
+        computer.tick();
+        computer.tick();
+        computer.stop();
+        assert_eq!(*logic_ticks.borrow(), 2);
+        assert_eq!(*display_ticks.borrow(), 0);
+    }
+}
@@ -1,16 +1,209 @@
-use crossbeam_channel::{unbounded, Receiver, Select, Sender};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Select, Sender, TryRecvError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
+use crate::core::faults::PortFaultKind;
+
+/// Counters for the traffic on a connection between an output port and an input port.
+///
+#[derive(Default)]
+struct ConnectionStats {
+    sent: AtomicU64,
+    depth: AtomicUsize,
+    offered: AtomicU64,
+    drop_every: AtomicU64,
+    duplicate_every: AtomicU64,
+    disconnected: AtomicBool,
+}
+
+impl ConnectionStats {
+    /// Count a value about to be sent as queued. It's counted before it's sent, so the receiver
+    /// can't take it off the queue first; `unsent` takes it back off if it couldn't be sent.
+    ///
+    fn sending(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn unsent(&self) {
+        self.received();
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of copies of the next value to send: normally one, but none or two
+    /// if a fault has been injected into the connection (see `FaultPlan`).
+    ///
+    fn copies_to_send(&self) -> usize {
+        let n = self.offered.fetch_add(1, Ordering::Relaxed) + 1;
+        let every = |faulty: &AtomicU64| match faulty.load(Ordering::Relaxed) {
+            0 => false,
+            k => n.is_multiple_of(k),
+        };
+        if every(&self.drop_every) {
+            0
+        } else if every(&self.duplicate_every) {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn received(&self) {
+        let _ = self.depth.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| depth.checked_sub(1));
+    }
+}
+
+/// A snapshot of the traffic on a connection: the number of values sent over it, and the
+/// approximate number that have been sent but not yet received.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionDepth {
+    pub producer: String,
+    pub consumer: String,
+    pub depth: usize,
+    pub sent: u64,
+}
+
+/// The named connections between the ports of one machine, whose traffic can be watched, and
+/// into which faults can be injected. Clones share the same set of connections.
+///
+#[derive(Clone, Default)]
+pub struct Connections(Arc<Mutex<Vec<NamedConnection>>>);
+
+struct NamedConnection {
+    producer: String,
+    consumer: String,
+    stats: Weak<ConnectionStats>,
+    alarmed: bool,
+}
+
+impl NamedConnection {
+    fn depth(&self, stats: &ConnectionStats) -> ConnectionDepth {
+        ConnectionDepth {
+            producer: self.producer.clone(),
+            consumer: self.consumer.clone(),
+            depth: stats.depth.load(Ordering::Relaxed),
+            sent: stats.sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the connection behind `handle`, naming its two ends. It's dropped from the set once
+    /// both ports have gone away.
+    ///
+    pub fn add(&self, handle: &ConnectionHandle, producer: &str, consumer: &str) {
+        let mut connections = self.0.lock().unwrap();
+        connections.retain(|c| c.stats.strong_count() > 0);
+        connections.push(NamedConnection {
+            producer: producer.to_string(),
+            consumer: consumer.to_string(),
+            stats: Arc::downgrade(&handle.stats),
+            alarmed: false,
+        });
+    }
+
+    /// Returns the traffic on every live connection.
+    ///
+    pub fn depths(&self) -> Vec<ConnectionDepth> {
+        let connections = self.0.lock().unwrap();
+        connections.iter().filter_map(|c| c.stats.upgrade().map(|stats| c.depth(&stats))).collect()
+    }
+
+    /// Inject a fault into every live connection from the output port named `producer`, returning
+    /// false if there are none.
+    ///
+    pub(crate) fn set_fault(&self, producer: &str, kind: PortFaultKind, every: u64) -> bool {
+        let connections = self.0.lock().unwrap();
+        let mut found = false;
+        for stats in connections.iter().filter(|c| c.producer == producer).filter_map(|c| c.stats.upgrade()) {
+            let faulty = match kind {
+                PortFaultKind::Drop => &stats.drop_every,
+                PortFaultKind::Duplicate => &stats.duplicate_every,
+            };
+            faulty.store(every, Ordering::Relaxed);
+            found = true;
+        }
+        found
+    }
+
+    /// Remove the faults injected into every connection, so the next faults injected count
+    /// values from the next one sent.
+    ///
+    pub(crate) fn clear_faults(&self) {
+        let connections = self.0.lock().unwrap();
+        for stats in connections.iter().filter_map(|c| c.stats.upgrade()) {
+            stats.offered.store(0, Ordering::Relaxed);
+            stats.drop_every.store(0, Ordering::Relaxed);
+            stats.duplicate_every.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the connections whose depth has newly exceeded `threshold`. A connection is
+    /// reported once, and again only after its depth has dropped back to half the threshold.
+    ///
+    pub(crate) fn check_depth_alarm(&self, threshold: usize) -> Vec<ConnectionDepth> {
+        let mut connections = self.0.lock().unwrap();
+        let mut alarms = Vec::new();
+        for c in connections.iter_mut() {
+            let Some(stats) = c.stats.upgrade() else {
+                continue;
+            };
+            let depth = stats.depth.load(Ordering::Relaxed);
+            if depth > threshold {
+                if !c.alarmed {
+                    c.alarmed = true;
+                    alarms.push(c.depth(&stats));
+                }
+            } else if depth <= threshold / 2 {
+                c.alarmed = false;
+            }
+        }
+        alarms
+    }
+}
+
+/// An output port, which sends each value it's given to every input port connected to it.
+///
 pub struct OutputPort<T>
 where
     T: Send + Default + Copy,
 {
     value: T,
-    sender: Option<Sender<T>>,
+    connections: Vec<(Box<dyn Deliver<T>>, Arc<ConnectionStats>)>,
+}
+
+/// The sending end of a connection, which maps each value to the input port's type, e.g. to
+/// invert a pin, as it sends it.
+///
+trait Deliver<T>: Send {
+    fn deliver(&self, value: T) -> bool;
+}
+
+struct MappedSender<T, U> {
+    sender: Sender<U>,
+    map: fn(T) -> U,
+}
+
+impl<T, U> Deliver<T> for MappedSender<T, U>
+where
+    U: Send,
+{
+    fn deliver(&self, value: T) -> bool {
+        self.sender.send((self.map)(value)).is_ok()
+    }
 }
 
 impl<T> Default for OutputPort<T>
 where
-    T: Send + Default + Copy,
+    T: Send + Default + Copy + 'static,
 {
     fn default() -> Self {
         Self::new()
@@ -19,30 +212,84 @@ where
 
 impl<T> OutputPort<T>
 where
-    T: Send + Default + Copy,
+    T: Send + Default + Copy + 'static,
 {
     pub fn new() -> Self {
         Self::with_initial_value(T::default())
     }
 
     pub fn with_initial_value(initial_value: T) -> Self {
-        Self { value: initial_value, sender: None }
+        Self { value: initial_value, connections: Vec::new() }
     }
 
-    pub fn connect_to(&mut self, target: &mut InputPort<T>) {
-        let (s, r): (Sender<T>, Receiver<T>) = unbounded();
-        if self.sender.is_some() {
-            panic!("Output port already connected");
-        }
-        self.sender = Some(s);
+    /// Connect to `target`. An output port can be connected to any number of input ports, each
+    /// of which receives every value sent from then on.
+    ///
+    /// If `target` was already connected, it's disconnected from its old output port first.
+    /// Returns a handle to the connection, which can be used to disconnect just this one;
+    /// dropping the handle leaves the ports connected. The connection's traffic is only watched
+    /// once the handle is added to a machine's `Connections`, as `connect_named` does.
+    ///
+    pub fn connect_to(&mut self, target: &mut InputPort<T>) -> ConnectionHandle {
+        self.connect_mapped_to(target, |value| value)
+    }
+
+    /// Connect to `target`, adding the connection to `connections` with its two ends named, to
+    /// watch its traffic or inject faults into it.
+    ///
+    pub fn connect_named(
+        &mut self,
+        target: &mut InputPort<T>,
+        connections: &Connections,
+        producer: &str,
+        consumer: &str,
+    ) -> ConnectionHandle {
+        let handle = self.connect_to(target);
+        connections.add(&handle, producer, consumer);
+        handle
+    }
+
+    /// Connect to `target`, an input port of another type, passing each value sent through `map`
+    /// on the way. The value is mapped as it's sent, on the sending thread.
+    ///
+    pub fn connect_mapped_to<U>(&mut self, target: &mut InputPort<U>, map: fn(T) -> U) -> ConnectionHandle
+    where
+        U: Send + Default + Copy + 'static,
+    {
+        let (sender, r): (Sender<U>, Receiver<U>) = unbounded();
+        let stats = Arc::<ConnectionStats>::default();
+        self.connections.push((Box::new(MappedSender { sender, map }), stats.clone()));
         target.receiver = Some(r);
+        target.stats = Some(stats.clone());
+        ConnectionHandle { stats }
     }
 
+    /// Disconnect every input port. Those waiting for a value find the output port has gone
+    /// away.
+    ///
+    pub fn disconnect_all(&mut self) {
+        self.connections.clear();
+    }
+
+    /// Send `new_value` to every connected input port, in the order they were connected.
+    /// Connections to input ports that have gone away are dropped.
+    ///
     pub fn send(&mut self, new_value: T) {
         self.value = new_value;
-        if let Some(s) = self.sender.as_mut() {
-            s.send(new_value).ok();
-        }
+        self.connections.retain(|(s, stats)| {
+            if stats.disconnected.load(Ordering::Relaxed) {
+                return false;
+            }
+            for _ in 0..stats.copies_to_send() {
+                stats.sending();
+                if !s.deliver(new_value) {
+                    stats.unsent();
+                    return false;
+                }
+                stats.sent();
+            }
+            true
+        });
     }
 
     pub fn value(&self) -> T {
@@ -50,9 +297,34 @@ where
     }
 }
 
+impl OutputPin {
+    /// Connect to `target`, inverting the pin, e.g. to wire an active-low output to an
+    /// active-high input.
+    ///
+    pub fn connect_inverted_to(&mut self, target: &mut InputPin) -> ConnectionHandle {
+        self.connect_mapped_to(target, |level| !level)
+    }
+}
+
+/// A connection from an output port to an input port, returned when they're connected.
+///
+pub struct ConnectionHandle {
+    stats: Arc<ConnectionStats>,
+}
+
+impl ConnectionHandle {
+    /// Disconnect the ports. The output port sends nothing more to the input port, which can
+    /// still receive the values sent before, but then waits for values that never come.
+    ///
+    pub fn disconnect(self) {
+        self.stats.disconnected.store(true, Ordering::Relaxed);
+    }
+}
+
 pub type OutputPin = OutputPort<bool>;
 pub type OutputPort8 = OutputPort<u8>;
 pub type OutputPort16 = OutputPort<u16>;
+pub type OutputPortF64 = OutputPort<f64>;
 
 pub struct InputPort<T>
 where
@@ -60,6 +332,7 @@ where
 {
     value: T,
     receiver: Option<Receiver<T>>,
+    stats: Option<Arc<ConnectionStats>>,
 }
 
 impl<T> Default for InputPort<T>
@@ -80,62 +353,508 @@ where
     }
 
     pub fn with_initial_value(initial_value: T) -> Self {
-        Self { value: initial_value, receiver: None }
+        Self { value: initial_value, receiver: None, stats: None }
+    }
+
+    /// Disconnect from the output port, if any, dropping any values it has sent that haven't
+    /// been received. The port keeps its value, and can be connected again. Until then it acts as
+    /// if its output port had gone away, as does a port that was never connected.
+    ///
+    pub fn disconnect(&mut self) {
+        self.receiver = None;
+        self.stats = None;
+    }
+
+    fn received(&mut self, new_value: T) {
+        self.value = new_value;
+        if let Some(stats) = &self.stats {
+            stats.received();
+        }
     }
 
     pub fn recv(&mut self) -> T {
         if let Some(r) = self.receiver.as_mut() {
             if let Ok(new_value) = r.recv() {
-                self.value = new_value;
+                self.received(new_value);
             }
-            self.value
-        } else {
-            panic!("Input port not connected");
         }
+        self.value
     }
 
+    /// Wait for the next value, like `recv`, but return `None` once the output port has gone
+    /// away and every value it sent has been received, rather than the last value again.
+    ///
+    pub fn recv_checked(&mut self) -> Option<T> {
+        let new_value = self.receiver.as_mut()?.recv().ok()?;
+        self.received(new_value);
+        Some(new_value)
+    }
+
+    /// Wait up to `timeout` for the next value, returning `None` if none arrives in time, or the
+    /// output port has gone away, leaving `value` unchanged. Components use this to check their
+    /// stop flag while waiting for input.
+    ///
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<T> {
+        self.recv_timeout(timeout).ok()
+    }
+
+    /// Wait until `deadline` for the next value, like `wait_timeout`.
+    ///
+    pub fn wait_deadline(&mut self, deadline: Instant) -> Option<T> {
+        self.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok()
+    }
+
+    /// Wait up to `timeout` for the next value, telling a timeout apart from the output port
+    /// having gone away. A pending value is taken without reading the clock, so this is cheap
+    /// enough to call on every clock edge.
+    ///
+    pub(crate) fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let Some(r) = self.receiver.as_mut() else {
+            return Err(RecvTimeoutError::Disconnected);
+        };
+        let new_value = match r.try_recv() {
+            Ok(new_value) => new_value,
+            Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            Err(TryRecvError::Empty) => r.recv_timeout(timeout)?,
+        };
+        self.received(new_value);
+        Ok(new_value)
+    }
+
+    /// Take the next pending value, if there is one, without waiting. Each value sent is
+    /// returned in turn, so pulses aren't missed; use `drain` to skip to the newest.
+    ///
     pub fn try_recv(&mut self) -> Option<T> {
         if let Some(r) = self.receiver.as_mut() {
             if let Ok(new_value) = r.try_recv() {
-                self.value = new_value;
+                self.received(new_value);
                 return Some(self.value);
             }
         }
         None
     }
 
+    /// Take every pending value without waiting, returning the newest, if there were any. Use
+    /// this rather than `try_recv` where only the current level matters, e.g. to display it.
+    ///
+    pub fn try_recv_newest(&mut self) -> Option<T> {
+        if self.drain() > 0 {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+
+    /// Take every pending value without waiting, leaving the newest in `value`, and return how
+    /// many there were.
+    ///
+    pub fn drain(&mut self) -> usize {
+        let mut count = 0;
+        while self.try_recv().is_some() {
+            count += 1;
+        }
+        count
+    }
+
     pub fn value(&self) -> T {
         self.value
     }
 
+    /// Wait for a value on any of `ports`, returning the position in `ports` of the one that
+    /// received it. Unconnected ports are ignored. Returns `None` straight away if none of the
+    /// ports is connected, or if the output port of the one that became ready has gone away.
+    ///
     pub fn wait_any(ports: &mut [&mut Self]) -> Option<usize> {
-        let mut select = Select::new();
-        for port in ports.iter() {
-            if let Some(r) = &port.receiver {
-                select.recv(r);
-            }
+        match Self::select(ports, None) {
+            Selected::Received(i) => Some(i),
+            _ => None,
         }
-        let s = select.select();
-        let mut idx = s.index();
-        for (i, _) in ports.iter().enumerate() {
-            if let Some(r) = &ports[i].receiver {
-                if idx == 0 {
-                    if let Ok(val) = s.recv(r) {
-                        ports[i].value = val;
-                        return Some(i);
-                    } else {
-                        break;
-                    }
-                } else {
-                    idx -= 1;
-                }
+    }
+
+    /// Wait up to `timeout` for a value on any of `ports`, like `wait_any`, returning `None` if
+    /// none arrives in time.
+    ///
+    pub fn wait_any_timeout(ports: &mut [&mut Self], timeout: Duration) -> Option<usize> {
+        match Self::select(ports, Some(timeout)) {
+            Selected::Received(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Wait until every connected port in `ports` has received a new value, updating each one's
+    /// value. A port that receives more than one is only given the first, leaving the rest
+    /// pending. Unconnected ports are ignored. Returns false as soon as a port still waiting
+    /// finds its output port has gone away.
+    ///
+    pub fn wait_all(ports: &mut [&mut Self]) -> bool {
+        let mut waiting: Vec<usize> = (0..ports.len()).filter(|&i| ports[i].receiver.is_some()).collect();
+        while !waiting.is_empty() {
+            let mut select = Select::new();
+            for &i in waiting.iter() {
+                select.recv(ports[i].receiver.as_ref().unwrap());
             }
+            let s = select.select();
+            let i = waiting.remove(s.index());
+            let Ok(new_value) = s.recv(ports[i].receiver.as_ref().unwrap()) else {
+                return false;
+            };
+            ports[i].received(new_value);
         }
+        true
+    }
 
-        None
+    /// Wait for a value on any of `ports`, for up to `timeout` if one is given, telling apart
+    /// the ways the wait can end.
+    ///
+    pub(crate) fn select(ports: &mut [&mut Self], timeout: Option<Duration>) -> Selected {
+        // The select only sees the connected ports, so map its indices back to the slice's.
+        let connected: Vec<usize> = (0..ports.len()).filter(|&i| ports[i].receiver.is_some()).collect();
+        if connected.is_empty() {
+            return Selected::NoneConnected;
+        }
+        let mut select = Select::new();
+        for &i in connected.iter() {
+            select.recv(ports[i].receiver.as_ref().unwrap());
+        }
+        let s = match timeout {
+            Some(timeout) => match select.select_timeout(timeout) {
+                Ok(s) => s,
+                Err(_) => return Selected::TimedOut,
+            },
+            None => select.select(),
+        };
+        let i = connected[s.index()];
+        match s.recv(ports[i].receiver.as_ref().unwrap()) {
+            Ok(new_value) => {
+                ports[i].received(new_value);
+                Selected::Received(i)
+            },
+            Err(_) => Selected::Disconnected(i),
+        }
     }
 }
 
+/// How a wait on several input ports ended: with a value received on the port at the given
+/// position, with that port finding its output port has gone away, by timing out, or straight
+/// away because none of the ports were connected.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub(crate) enum Selected {
+    Received(usize),
+    Disconnected(usize),
+    TimedOut,
+    NoneConnected,
+}
+
 pub type InputPin = InputPort<bool>;
 pub type InputPort8 = InputPort<u8>;
 pub type InputPort16 = InputPort<u16>;
+pub type InputPortF64 = InputPort<f64>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn connection_depth() {
+        let mut output = OutputPort8::new();
+        let mut input = InputPort8::new();
+        let connections = Connections::new();
+        output.connect_named(&mut input, &connections, "depth.output", "depth.input");
+        // Only connections added to the set are watched.
+        let mut other = InputPort8::new();
+        output.connect_to(&mut other);
+        let depth = || {
+            let depths = connections.depths();
+            assert_eq!(depths.len(), 1);
+            depths[0].clone()
+        };
+
+        for i in 0..10 {
+            output.send(i);
+        }
+        assert_eq!(depth().depth, 10);
+        input.recv();
+        input.try_recv();
+        assert_eq!(depth().depth, 8);
+        assert_eq!(depth().sent, 10);
+
+        drop(output);
+        drop(input);
+        assert!(connections.depths().is_empty());
+    }
+
+    #[test]
+    fn depth_never_wraps() {
+        // A receiver that takes a value off the queue before the sender has counted it mustn't
+        // leave the depth wrapped round to a huge number.
+        let stats = ConnectionStats::default();
+        stats.received();
+        assert_eq!(stats.depth.load(Ordering::Relaxed), 0);
+        stats.sending();
+        stats.unsent();
+        assert_eq!(stats.depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn non_blocking() {
+        let mut output = OutputPort8::new();
+        let mut input = InputPort8::with_initial_value(0xFF);
+        assert_eq!(input.try_recv(), None);
+        assert_eq!(input.drain(), 0);
+        output.connect_to(&mut input);
+        assert_eq!(input.try_recv(), None);
+        assert_eq!(input.drain(), 0);
+        assert_eq!(input.value(), 0xFF);
+
+        output.send(1);
+        assert_eq!(input.try_recv(), Some(1));
+        assert_eq!(input.try_recv(), None);
+        output.send(2);
+        assert_eq!(input.drain(), 1);
+        assert_eq!(input.value(), 2);
+
+        for i in 3..=7 {
+            output.send(i);
+        }
+        assert_eq!(input.try_recv(), Some(3));
+        assert_eq!(input.drain(), 4);
+        assert_eq!(input.value(), 7);
+        assert_eq!(input.try_recv(), None);
+
+        // Only the newest of a backlog is returned, and the rest are gone.
+        assert_eq!(input.try_recv_newest(), None);
+        output.send(8);
+        assert_eq!(input.try_recv_newest(), Some(8));
+        for i in 9..=12 {
+            output.send(i);
+        }
+        assert_eq!(input.try_recv_newest(), Some(12));
+        assert_eq!(input.value(), 12);
+        assert_eq!(input.try_recv(), None);
+        assert_eq!(input.try_recv_newest(), None);
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut output = OutputPort8::new();
+        let mut input = InputPort8::with_initial_value(0xFF);
+        output.connect_to(&mut input);
+
+        let start = Instant::now();
+        assert_eq!(input.wait_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(input.value(), 0xFF);
+
+        output.send(1);
+        assert_eq!(input.wait_timeout(Duration::from_secs(5)), Some(1));
+        assert_eq!(input.wait_deadline(Instant::now()), None);
+        assert_eq!(input.value(), 1);
+
+        let mut other = InputPort8::new();
+        assert_eq!(InputPort8::wait_any_timeout(&mut [&mut input, &mut other], Duration::from_millis(1)), None);
+
+        // Once the output port has gone away, there's nothing to wait for.
+        drop(output);
+        assert_eq!(input.wait_timeout(Duration::from_secs(5)), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn wait_any() {
+        let mut outputs = [OutputPort8::new(), OutputPort8::new(), OutputPort8::new()];
+        let mut inputs = [InputPort8::new(), InputPort8::new(), InputPort8::new()];
+        for (output, input) in outputs.iter_mut().zip(inputs.iter_mut()) {
+            output.connect_to(input);
+        }
+        let [a, b, c] = &mut inputs;
+        outputs[1].send(0x22);
+        assert_eq!(InputPort8::wait_any(&mut [a, b, c]), Some(1));
+        assert_eq!(inputs[1].value(), 0x22);
+
+        // Unconnected ports don't throw off the positions of the rest.
+        let mut unconnected = [InputPort8::new(), InputPort8::new()];
+        let [u, v] = &mut unconnected;
+        let [a, _, c] = &mut inputs;
+        outputs[2].send(0x33);
+        assert_eq!(InputPort8::wait_any(&mut [u, a, v, c]), Some(3));
+        let [u, v] = &mut unconnected;
+        outputs[0].send(0x11);
+        assert_eq!(InputPort8::wait_any(&mut [u, v, &mut inputs[0]]), Some(2));
+        assert_eq!(InputPort8::wait_any(&mut [u, v]), None);
+        assert_eq!(InputPort8::wait_any(&mut []), None);
+
+        // A port whose output has gone away is ready, but has nothing to receive.
+        let [_, b, _] = outputs;
+        drop(b);
+        assert_eq!(InputPort8::wait_any(&mut [&mut inputs[1]]), None);
+    }
+
+    #[test]
+    fn wait_all() {
+        let mut outputs = [OutputPort8::new(), OutputPort8::new(), OutputPort8::new()];
+        let mut inputs = [InputPort8::new(), InputPort8::new(), InputPort8::new()];
+        for (output, input) in outputs.iter_mut().zip(inputs.iter_mut()) {
+            output.connect_to(input);
+        }
+
+        // Send to each port from its own thread, at staggered times.
+        let handles: Vec<_> = outputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut output)| {
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(20 * (i as u64 + 1)));
+                    let sent = Instant::now();
+                    output.send(i as u8 + 1);
+                    (output, sent)
+                })
+            })
+            .collect();
+        let mut unconnected = InputPort8::with_initial_value(0xFF);
+        let [a, b, c] = &mut inputs;
+        assert!(InputPort8::wait_all(&mut [c, &mut unconnected, a, b]));
+        let returned = Instant::now();
+        let (mut outputs, sent): (Vec<_>, Vec<_>) = handles.into_iter().map(|h| h.join().unwrap()).unzip();
+        assert!(returned >= sent[2]);
+        assert_eq!(inputs.iter().map(|p| p.value()).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(unconnected.value(), 0xFF);
+
+        // A port that receives more than one value keeps the rest for next time.
+        outputs[0].send(0x10);
+        outputs[0].send(0x11);
+        outputs[1].send(0x20);
+        outputs[2].send(0x30);
+        let [a, b, c] = &mut inputs;
+        assert!(InputPort8::wait_all(&mut [a, b, c]));
+        assert_eq!(inputs.iter().map(|p| p.value()).collect::<Vec<_>>(), vec![0x10, 0x20, 0x30]);
+        assert_eq!(inputs[0].try_recv(), Some(0x11));
+
+        // A port whose output has gone away won't get a new value.
+        outputs[0].send(0x01);
+        drop(outputs.remove(1));
+        let [a, b, c] = &mut inputs;
+        assert!(!InputPort8::wait_all(&mut [a, b, c]));
+        assert!(InputPort8::wait_all(&mut []));
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut output = OutputPort8::new();
+        let mut inputs = [InputPort8::new(), InputPort8::new(), InputPort8::new()];
+        let [a, b, c] = &mut inputs;
+        output.connect_to(a);
+        let handle = output.connect_to(b);
+        output.connect_to(c);
+        output.send(1);
+
+        // The values sent before disconnecting are still received, but none after.
+        handle.disconnect();
+        output.send(2);
+        assert_eq!(inputs[1].try_recv(), Some(1));
+        assert_eq!(inputs[1].wait_timeout(Duration::from_millis(10)), None);
+        assert_eq!(inputs[1].value(), 1);
+        assert_eq!(output.connections.len(), 2);
+
+        inputs[2].disconnect();
+        output.send(3);
+        assert_eq!(output.connections.len(), 1);
+        assert_eq!(inputs[2].value(), 0);
+
+        // Polling a disconnected port finds nothing, rather than panicking.
+        assert_eq!(inputs[2].recv(), 0);
+        assert_eq!(inputs[2].recv_checked(), None);
+        assert_eq!(inputs[2].wait_timeout(Duration::from_millis(10)), None);
+        assert_eq!(inputs[2].recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Disconnected));
+        assert_eq!(inputs[2].try_recv(), None);
+        assert_eq!(inputs[0].drain(), 3);
+        assert_eq!(inputs[0].value(), 3);
+
+        output.disconnect_all();
+        output.send(4);
+        assert_eq!(inputs[0].wait_timeout(Duration::from_millis(10)), None);
+        assert_eq!(inputs[0].value(), 3);
+
+        // Ports can be connected again once disconnected.
+        let [a, b, c] = &mut inputs;
+        output.connect_to(b);
+        output.connect_to(c);
+        output.send(5);
+        assert_eq!(InputPort8::wait_any(&mut [a, b, c]), Some(1));
+        assert_eq!(inputs[2].try_recv(), Some(5));
+    }
+
+    #[test]
+    fn mapped() {
+        let mut clock = OutputPin::new();
+        let mut direct = InputPin::new();
+        let mut inverted = InputPin::with_initial_value(true);
+        clock.connect_to(&mut direct);
+        clock.connect_inverted_to(&mut inverted);
+        for i in 0..6 {
+            clock.send(i & 1 == 0);
+        }
+        for _ in 0..6 {
+            assert_eq!(inverted.recv(), !direct.recv());
+        }
+
+        let mut data = OutputPort8::new();
+        let mut bit3 = InputPin::new();
+        data.connect_mapped_to(&mut bit3, |value| value & 0x08 != 0);
+        for value in [0x00, 0x08, 0xF7, 0xFF] {
+            data.send(value);
+        }
+        assert_eq!((0..4).map(|_| bit3.recv()).collect::<Vec<_>>(), vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn fan_out() {
+        let mut clock = OutputPin::new();
+        let mut inputs = [InputPin::new(), InputPin::new(), InputPin::new()];
+        for input in inputs.iter_mut() {
+            clock.connect_to(input);
+        }
+        for i in 0..10 {
+            clock.send(i & 1 == 0);
+        }
+        for input in inputs.iter_mut() {
+            let received: Vec<bool> = (0..10).map(|_| input.recv()).collect();
+            assert_eq!(received, (0..10).map(|i| i & 1 == 0).collect::<Vec<_>>());
+            assert!(input.try_recv().is_none());
+        }
+
+        // An input that goes away is dropped, and the rest still receive every value.
+        let [a, b, c] = inputs;
+        drop(b);
+        clock.send(true);
+        assert_eq!(clock.connections.len(), 2);
+        for mut input in [a, c] {
+            assert_eq!(input.try_recv(), Some(true));
+        }
+    }
+
+    #[test]
+    fn depth_alarm() {
+        let mut output = OutputPin::new();
+        let mut input = InputPin::new();
+        let connections = Connections::new();
+        output.connect_named(&mut input, &connections, "alarm.output", "alarm.input");
+        let alarmed = || !connections.check_depth_alarm(1000).is_empty();
+
+        for _ in 0..1001 {
+            output.send(true);
+        }
+        assert!(alarmed());
+        assert!(!alarmed());
+
+        for _ in 0..501 {
+            input.recv();
+        }
+        assert!(!alarmed());
+        for _ in 0..501 {
+            output.send(true);
+        }
+        assert!(alarmed());
+    }
+}
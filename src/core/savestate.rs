@@ -0,0 +1,401 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// A component whose state can be saved into, and restored from, a `SaveState`.
+///
+/// Each component saves a single chunk, identified by `state_name`, which must be unique within
+/// a machine. `state_version` is the version of the chunk's layout, and should be bumped on any
+/// breaking change to it, along with a `migrate_state` step from the previous version.
+///
+pub trait Stateful {
+    fn state_name(&self) -> &str;
+    fn state_version(&self) -> u16;
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String>;
+
+    /// Upgrade chunk data written with layout `from_version` to `from_version + 1`. The default
+    /// implementation supports no migrations.
+    ///
+    fn migrate_state(&self, from_version: u16, _data: Vec<u8>) -> Result<Vec<u8>, String> {
+        Err(format!("no migration from version {}", from_version))
+    }
+}
+
+/// A chunk of saved state belonging to one component.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub name: String,
+    pub version: u16,
+    pub data: Vec<u8>,
+}
+
+/// A saved machine state.
+///
+/// The file format is little-endian throughout. Strings are a u16 length followed by UTF-8
+/// bytes, and byte arrays are a u32 length followed by the bytes.
+///
+/// * Magic: `RCSS`.
+/// * Format version: u16, currently 1.
+/// * Version of the crate that wrote the state: string.
+/// * Topology hash: u64, the FNV-1a hash of the component names (see `topology_hash`).
+/// * Component names: u16 count, followed by one string per component, in machine order.
+/// * Chunks: u32 count, followed by each chunk's name (string), layout version (u16), and data
+///   (byte array).
+///
+/// Readers skip chunks they don't recognize, so chunks can be added without bumping the format
+/// version.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveState {
+    pub crate_version: String,
+    pub components: Vec<String>,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedFormat(u16),
+    /// The machine's components, in `actual` order, don't match the `expected` ones the state
+    /// was saved from. `missing` and `unexpected` list the names only one of them has, and are
+    /// both empty if the same components are just in a different order.
+    ///
+    TopologyMismatch { expected: Vec<String>, actual: Vec<String>, missing: Vec<String>, unexpected: Vec<String> },
+    Component { name: String, message: String },
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "I/O error: {}", e),
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedFormat(v) => write!(f, "unsupported save state format version {}", v),
+            SaveStateError::TopologyMismatch { expected, actual, missing, unexpected } => {
+                write!(f, "save state is for a different machine")?;
+                if !missing.is_empty() {
+                    write!(f, "; saved components not in this machine: {}", missing.join(", "))?;
+                }
+                if !unexpected.is_empty() {
+                    write!(f, "; components not in the save state: {}", unexpected.join(", "))?;
+                }
+                if missing.is_empty() && unexpected.is_empty() {
+                    write!(f, "; saved components in order: {}; ", expected.join(", "))?;
+                    write!(f, "this machine's components in order: {}", actual.join(", "))?;
+                }
+                Ok(())
+            },
+            SaveStateError::Component { name, message } => write!(f, "couldn't restore {}: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> Self {
+        SaveStateError::Io(e)
+    }
+}
+
+impl SaveState {
+    pub const MAGIC: &'static [u8; 4] = b"RCSS";
+    pub const FORMAT_VERSION: u16 = 1;
+
+    /// Save the state of each of the machine's components.
+    ///
+    pub fn capture(components: &[&dyn Stateful]) -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            components: components.iter().map(|c| c.state_name().to_string()).collect(),
+            chunks: components
+                .iter()
+                .map(|c| Chunk { name: c.state_name().to_string(), version: c.state_version(), data: c.save_state() })
+                .collect(),
+        }
+    }
+
+    /// Restore the state of each of the machine's components, migrating older chunks as needed.
+    /// Fails without changing anything if the machine's components don't match the saved ones.
+    ///
+    /// Returns warnings about chunks that were skipped.
+    ///
+    pub fn restore(&self, components: &mut [&mut dyn Stateful]) -> Result<Vec<String>, SaveStateError> {
+        let names: Vec<String> = components.iter().map(|c| c.state_name().to_string()).collect();
+        if topology_hash(&names) != topology_hash(&self.components) {
+            return Err(SaveStateError::TopologyMismatch {
+                missing: self.components.iter().filter(|n| !names.contains(n)).cloned().collect(),
+                unexpected: names.iter().filter(|n| !self.components.contains(n)).cloned().collect(),
+                expected: self.components.clone(),
+                actual: names,
+            });
+        }
+
+        let mut warnings = Vec::new();
+        let mut chunks = Vec::new();
+        for chunk in self.chunks.iter() {
+            let component = match components.iter().position(|c| c.state_name() == chunk.name) {
+                Some(i) => i,
+                None => {
+                    warnings.push(format!("skipped unknown chunk {}", chunk.name));
+                    continue;
+                },
+            };
+            let c = &components[component];
+            let error = |message| SaveStateError::Component { name: chunk.name.clone(), message };
+            if chunk.version > c.state_version() {
+                return Err(error(format!("saved by a newer version ({})", chunk.version)));
+            }
+            let mut data = chunk.data.clone();
+            for version in chunk.version..c.state_version() {
+                data = c.migrate_state(version, data).map_err(error)?;
+            }
+            chunks.push((component, data));
+        }
+
+        for (component, data) in chunks {
+            let c = &mut components[component];
+            if let Err(message) = c.load_state(&data) {
+                return Err(SaveStateError::Component { name: c.state_name().to_string(), message });
+            }
+        }
+        Ok(warnings)
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(Self::MAGIC)?;
+        w.write_all(&Self::FORMAT_VERSION.to_le_bytes())?;
+        write_str(&mut w, &self.crate_version)?;
+        w.write_all(&topology_hash(&self.components).to_le_bytes())?;
+        w.write_all(&(self.components.len() as u16).to_le_bytes())?;
+        for name in self.components.iter() {
+            write_str(&mut w, name)?;
+        }
+        w.write_all(&(self.chunks.len() as u32).to_le_bytes())?;
+        for chunk in self.chunks.iter() {
+            write_str(&mut w, &chunk.name)?;
+            w.write_all(&chunk.version.to_le_bytes())?;
+            w.write_all(&(chunk.data.len() as u32).to_le_bytes())?;
+            w.write_all(&chunk.data)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, SaveStateError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let format = read_u16(&mut r)?;
+        if format != Self::FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedFormat(format));
+        }
+        let crate_version = read_str(&mut r)?;
+        let hash = read_u64(&mut r)?;
+        let components = (0..read_u16(&mut r)?).map(|_| read_str(&mut r)).collect::<io::Result<Vec<_>>>()?;
+        if hash != topology_hash(&components) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt topology").into());
+        }
+        let mut chunks = Vec::new();
+        for _ in 0..read_u32(&mut r)? {
+            let name = read_str(&mut r)?;
+            let version = read_u16(&mut r)?;
+            let len = read_u32(&mut r)? as usize;
+            let data = read_bytes(&mut r, len)?;
+            chunks.push(Chunk { name, version, data });
+        }
+        Ok(Self { crate_version, components, chunks })
+    }
+}
+
+/// Hash of a machine's component names, in order, used to check that a save state belongs to
+/// the machine it's restored into. This is FNV-1a, so it's stable across platforms and
+/// releases.
+///
+pub fn topology_hash(components: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for name in components {
+        for b in name.bytes().chain(std::iter::once(0)) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_all(&(s.len() as u16).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read `len` bytes, failing if the input ends first. The buffer only grows as the bytes arrive,
+/// so a corrupt length can't make it allocate more than the input holds.
+///
+fn read_bytes<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated save state"));
+    }
+    Ok(buf)
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u16(r)? as usize;
+    let buf = read_bytes(r, len)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Version 1 of this component stored a single byte; version 2 stores it as a u16.
+    struct Counter {
+        name: &'static str,
+        value: u16,
+    }
+
+    impl Stateful for Counter {
+        fn state_name(&self) -> &str {
+            self.name
+        }
+
+        fn state_version(&self) -> u16 {
+            2
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.value.to_le_bytes().to_vec()
+        }
+
+        fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+            let bytes: [u8; 2] = data.try_into().map_err(|_| "bad length".to_string())?;
+            self.value = u16::from_le_bytes(bytes);
+            Ok(())
+        }
+
+        fn migrate_state(&self, from_version: u16, data: Vec<u8>) -> Result<Vec<u8>, String> {
+            match from_version {
+                1 => Ok(vec![data[0], 0]),
+                _ => Err(format!("no migration from version {}", from_version)),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let a = Counter { name: "a", value: 0x1234 };
+        let b = Counter { name: "b", value: 0x5678 };
+        let mut bytes = Vec::new();
+        SaveState::capture(&[&a, &b]).write_to(&mut bytes).unwrap();
+
+        let mut a = Counter { name: "a", value: 0 };
+        let mut b = Counter { name: "b", value: 0 };
+        let state = SaveState::read_from(&bytes[..]).unwrap();
+        assert_eq!(state.crate_version, env!("CARGO_PKG_VERSION"));
+        assert!(state.restore(&mut [&mut a, &mut b]).unwrap().is_empty());
+        assert_eq!((a.value, b.value), (0x1234, 0x5678));
+    }
+
+    #[test]
+    fn migrates_old_chunks_and_skips_unknown_ones() {
+        // A state written by an older release, with the counter at chunk version 1, and a chunk
+        // this release doesn't know about.
+        #[rustfmt::skip]
+        let fixture: &[u8] = &[
+            b'R', b'C', b'S', b'S', 0x01, 0x00,
+            0x05, 0x00, b'0', b'.', b'1', b'.', b'0',
+            0xE4, 0xF1, 0x44, 0xB5, 0x07, 0xE2, 0x9B, 0x08,
+            0x01, 0x00, 0x01, 0x00, b'a',
+            0x02, 0x00, 0x00, 0x00,
+            0x01, 0x00, b'a', 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x42,
+            0x04, 0x00, b'm', b'e', b't', b'a', 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let state = SaveState::read_from(fixture).unwrap();
+        assert_eq!(state.crate_version, "0.1.0");
+
+        let mut a = Counter { name: "a", value: 0 };
+        let warnings = state.restore(&mut [&mut a]).unwrap();
+        assert_eq!(a.value, 0x42);
+        assert_eq!(warnings, vec!["skipped unknown chunk meta"]);
+    }
+
+    #[test]
+    fn topology_mismatch() {
+        let a = Counter { name: "cpu", value: 1 };
+        let b = Counter { name: "ram", value: 2 };
+        let state = SaveState::capture(&[&a, &b]);
+
+        let mut a = Counter { name: "cpu", value: 0 };
+        let mut c = Counter { name: "via", value: 0 };
+        let e = state.restore(&mut [&mut a, &mut c]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "save state is for a different machine; saved components not in this machine: ram; \
+             components not in the save state: via"
+        );
+        assert_eq!(a.value, 0);
+
+        // The same components in a different order still don't match.
+        let mut b = Counter { name: "ram", value: 0 };
+        let e = state.restore(&mut [&mut b, &mut a]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "save state is for a different machine; saved components in order: cpu, ram; \
+             this machine's components in order: ram, cpu"
+        );
+        match e {
+            SaveStateError::TopologyMismatch { expected, actual, .. } => {
+                assert_eq!(expected, vec!["cpu", "ram"]);
+                assert_eq!(actual, vec!["ram", "cpu"]);
+            },
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!((a.value, b.value), (0, 0));
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(matches!(SaveState::read_from(&b"NOPE"[..]), Err(SaveStateError::BadMagic)));
+        assert!(matches!(SaveState::read_from(&b"RCSS\x09\x00"[..]), Err(SaveStateError::UnsupportedFormat(9))));
+    }
+
+    #[test]
+    fn truncated() {
+        let a = Counter { name: "a", value: 0x1234 };
+        let mut bytes = Vec::new();
+        SaveState::capture(&[&a]).write_to(&mut bytes).unwrap();
+        for len in 0..bytes.len() {
+            assert!(SaveState::read_from(&bytes[..len]).is_err());
+        }
+
+        // A chunk claiming to be 4GB long fails once the input runs out.
+        let len = bytes.len();
+        bytes[len - 6..len - 2].copy_from_slice(&u32::MAX.to_le_bytes());
+        match SaveState::read_from(&bytes[..]) {
+            Err(SaveStateError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("expected a truncation error, got {:?}", other),
+        }
+    }
+}
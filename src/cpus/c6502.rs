@@ -1,11 +1,29 @@
+use crossbeam_channel::RecvTimeoutError;
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Instant;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::core::memory::*;
-use crate::core::ports::{InputPin, OutputPin};
-use crate::core::AsyncComponent;
+use crate::core::ports::{InputPin, InputPort8, OutputPin, OutputPort16, OutputPort8};
+use crate::core::savestate::Stateful;
+use crate::core::{panic_message, AsyncComponent, ResetKind, Resettable, STOP_POLL_INTERVAL};
+
+pub mod disasm;
+mod dispatch;
+pub mod opcodes;
+pub mod profile;
+pub mod testing;
+pub mod timing;
+pub mod trace;
+
+use dispatch::{Dispatch, OPCODE_TABLE};
+use profile::{InstructionProfile, ProfileReport, Profiler, RegionMap, RegionProfile, RegionReport};
+use trace::{TraceEntry, TraceHook, TraceRecord, TraceSink};
 
 pub struct C6502 {
     pc: u16,
@@ -15,16 +33,60 @@ pub struct C6502 {
     p: u8,
     sp: u8,
     cycle: usize,
+    ins_pc: u16,
+    ins_start: u64,
     opcode: u8,
     value: u8,
     addr: u16,
     extra_addr: u16,
     memory: Memory,
     state: CpuState,
+    profiler: Option<Box<Profiler>>,
+    trace: Option<Box<dyn TraceSink>>,
+    trace_error: Option<io::Error>,
+    traps: HashMap<u16, TrapHandler>,
+    total_cycles: u64,
+    decimal_mode: bool,
+    // The constant ORed into the accumulator by the unstable ANE and LXA instructions.
+    magic: u8,
+    // The 6510's I/O port, if the CPU has one: its data direction and data registers.
+    io_port: bool,
+    io_direction: u8,
+    io_data: u8,
+    published: SharedRegisters,
+    reset_request: ResetHandle,
+    stats: Arc<CpuStats>,
+    reset_line: bool,
+    irq_line: bool,
+    nmi_line: bool,
+    so_line: bool,
+    nmi_pending: bool,
+    in_nmi: bool,
+    interrupt: Option<Interrupt>,
+    // The interrupt to service, if any, as polled at the start of the previous cycle and of this
+    // one. The older poll is the one acted on at an opcode fetch, since the 6502 polls at the end
+    // of each instruction's penultimate cycle.
+    polled: Option<Interrupt>,
+    sampled: Option<Interrupt>,
+    rdy: bool,
+    stalled: bool,
+    bus_activity: Option<BusActivity>,
 
     phi0_in: InputPin,
     phi1_out: OutputPin,
     phi2_out: OutputPin,
+    sync_out: OutputPin,
+    addr_out: OutputPort16,
+    data_out: OutputPort8,
+    rw_out: OutputPin,
+    bus_watched: bool,
+    reset_in: InputPin,
+    irq_in: InputPin,
+    nmi_in: InputPin,
+    rdy_in: InputPin,
+    so_in: InputPin,
+    port_out: OutputPort8,
+    port_in: InputPort8,
 }
 
 impl fmt::Debug for C6502 {
@@ -53,30 +115,215 @@ impl C6502 {
     pub const IRQ_VECTOR: u16 = 0xFFFE;
 
     pub fn new(memory: &Memory) -> Self {
-        Self {
-            pc: 0x00FF,
-            ac: 0xAA,
-            x: 0x00,
-            y: 0x00,
-            p: 0x00,
-            sp: 0xFF,
+        Self::new_with(memory, CpuConfig::default())
+    }
+
+    /// Create a CPU with the registers it powers up with, and how it starts, given by `config`.
+    ///
+    pub fn new_with(memory: &Memory, config: CpuConfig) -> Self {
+        let mut cpu = Self {
+            pc: config.pc,
+            ac: config.ac,
+            x: config.x,
+            y: config.y,
+            // The CPU doesn't hold the break and unused bits, which only exist on the stack.
+            p: config.p & !(Self::SR_BREAK | Self::SR_UNUSED),
+            sp: config.sp,
             cycle: 1,
+            ins_pc: 0x0000,
+            ins_start: 0,
             opcode: 0x00,
             value: 0x00,
             addr: 0x0000,
             extra_addr: 0x0000,
-            state: CpuState::Off,
+            state: if config.start == CpuStart::Run { CpuState::Running } else { CpuState::Off },
+            profiler: None,
+            trace: None,
+            trace_error: None,
+            traps: HashMap::new(),
+            total_cycles: 0,
+            decimal_mode: true,
+            magic: 0xEE,
+            io_port: false,
+            io_direction: 0x00,
+            io_data: 0x00,
+            published: SharedRegisters::default(),
+            reset_request: ResetHandle::default(),
+            stats: Arc::default(),
+            reset_line: false,
+            irq_line: false,
+            nmi_line: false,
+            so_line: false,
+            nmi_pending: false,
+            in_nmi: false,
+            interrupt: None,
+            polled: None,
+            sampled: None,
+            rdy: true,
+            stalled: false,
             memory: memory.clone(),
             phi0_in: InputPin::new(),
             phi1_out: OutputPin::new(),
             phi2_out: OutputPin::new(),
+            sync_out: OutputPin::new(),
+            addr_out: OutputPort16::new(),
+            data_out: OutputPort8::new(),
+            rw_out: OutputPin::new(),
+            bus_activity: None,
+            bus_watched: false,
+            reset_in: InputPin::with_initial_value(true),
+            irq_in: InputPin::with_initial_value(true),
+            nmi_in: InputPin::with_initial_value(true),
+            rdy_in: InputPin::with_initial_value(true),
+            so_in: InputPin::with_initial_value(true),
+            port_out: OutputPort8::new(),
+            port_in: InputPort8::new(),
+        };
+        if config.start == CpuStart::Reset {
+            cpu.reset();
         }
+        cpu
+    }
+
+    /// Create a CPU like the one in the NES's 2A03: identical to the NMOS 6502, except that
+    /// ADC and SBC ignore the decimal flag, and always add and subtract in binary. The flag can
+    /// still be set and cleared, and is pushed and pulled with the rest of the status.
+    ///
+    pub fn new_2a03(memory: &Memory) -> Self {
+        Self { decimal_mode: false, ..Self::new(memory) }
+    }
+
+    /// Create a CPU like the 6510 in the Commodore 64: an NMOS 6502 with an on-chip I/O port,
+    /// whose data direction register is at $0000 and data register at $0001. A set bit in the
+    /// direction register makes the bit an output, driven from the data register on
+    /// `port_out`; the other bits read from `port_in`. Writes to the registers also reach the
+    /// memory underneath, as they do on the hardware, but reads come from the port.
+    ///
+    pub fn new_6510(memory: &Memory) -> Self {
+        Self { io_port: true, ..Self::new(memory) }
+    }
+
+    /// Set the "magic" constant of the unstable ANE and LXA instructions, which ORs into the
+    /// accumulator before it's used. On real chips it depends on the chip and its temperature,
+    /// and is commonly taken as $EE, the default, or $FF.
+    ///
+    pub fn set_unstable_magic(&mut self, magic: u8) {
+        self.magic = magic;
     }
 
     pub fn state(&self) -> CpuState {
         self.state
     }
 
+    pub fn registers(&self) -> Registers {
+        Registers { pc: self.pc, ac: self.ac, x: self.x, y: self.y, p: self.p, sp: self.sp }
+    }
+
+    /// Set the registers, e.g. to seed a test or from a debugger. This is best done between
+    /// instructions, when `cycle` is 1: the instruction in progress carries on with the new
+    /// values otherwise.
+    ///
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.ac = registers.ac;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.p = registers.p;
+        self.sp = registers.sp;
+    }
+
+    /// The cycle of the current instruction the CPU will run next, counting from 1 for the
+    /// opcode fetch.
+    ///
+    pub fn cycle(&self) -> usize {
+        self.cycle
+    }
+
+    /// The number of cycles the CPU has been stepped since it was created.
+    ///
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Capture the CPU's state, including any instruction in progress, so stepping a CPU
+    /// restored from the snapshot carries on exactly where this one left off. The memory
+    /// and the devices connected to the CPU must be captured separately.
+    ///
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            registers: self.registers(),
+            state: self.state,
+            cycle: self.cycle,
+            total_cycles: self.total_cycles,
+            ins_pc: self.ins_pc,
+            ins_start: self.ins_start,
+            opcode: self.opcode,
+            value: self.value,
+            addr: self.addr,
+            extra_addr: self.extra_addr,
+            reset_line: self.reset_line,
+            irq_line: self.irq_line,
+            nmi_line: self.nmi_line,
+            so_line: self.so_line,
+            nmi_pending: self.nmi_pending,
+            in_nmi: self.in_nmi,
+            interrupt: self.interrupt,
+            polled: self.polled,
+            sampled: self.sampled,
+            io_direction: self.io_direction,
+            io_data: self.io_data,
+        }
+    }
+
+    /// Restore the state captured by `snapshot`.
+    ///
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.set_registers(snapshot.registers);
+        self.state = snapshot.state;
+        self.cycle = snapshot.cycle;
+        self.total_cycles = snapshot.total_cycles;
+        self.ins_pc = snapshot.ins_pc;
+        self.ins_start = snapshot.ins_start;
+        self.opcode = snapshot.opcode;
+        self.value = snapshot.value;
+        self.addr = snapshot.addr;
+        self.extra_addr = snapshot.extra_addr;
+        self.reset_line = snapshot.reset_line;
+        self.irq_line = snapshot.irq_line;
+        self.nmi_line = snapshot.nmi_line;
+        self.so_line = snapshot.so_line;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.in_nmi = snapshot.in_nmi;
+        self.interrupt = snapshot.interrupt;
+        self.polled = snapshot.polled;
+        self.sampled = snapshot.sampled;
+        self.io_direction = snapshot.io_direction;
+        self.io_data = snapshot.io_data;
+        self.published.store(self.registers());
+    }
+
+    /// Returns a handle to the registers as published by the CPU at the start of each
+    /// instruction, which can be read from any thread while the CPU is running.
+    ///
+    pub fn shared_registers(&self) -> SharedRegisters {
+        self.published.clone()
+    }
+
+    /// Returns a handle through which the CPU can be reset from any thread, e.g. by registering
+    /// it with `Computer::add_resettable` for a CPU that runs as an async component. The CPU
+    /// carries out the reset at the start of its next cycle.
+    ///
+    pub fn reset_handle(&self) -> ResetHandle {
+        self.reset_request.clone()
+    }
+
+    /// Returns a handle to the performance statistics the CPU keeps while it runs as an async
+    /// component. Take it before handing the CPU to a `Computer`.
+    ///
+    pub fn stats(&self) -> Arc<CpuStats> {
+        self.stats.clone()
+    }
+
     pub fn phi0_in(&mut self) -> &mut InputPin {
         &mut self.phi0_in
     }
@@ -89,213 +336,468 @@ impl C6502 {
         &mut self.phi2_out
     }
 
+    /// The SYNC line, which is high during the cycles in which the CPU fetches an opcode,
+    /// including the fetches overlapped with the last cycle of the previous instruction. It's
+    /// only sent when it changes.
+    ///
+    pub fn sync_out(&mut self) -> &mut OutputPin {
+        &mut self.sync_out
+    }
+
+    /// The address bus, sent for every memory access the CPU makes, along with `rw_out` and
+    /// `data_out`, in that order. The bus is only sent once one of its ports has been taken, so
+    /// a CPU whose bus nobody watches does no extra work.
+    ///
+    pub fn addr_out(&mut self) -> &mut OutputPort16 {
+        self.bus_watched = true;
+        &mut self.addr_out
+    }
+
+    /// The data bus: the byte read or written in each memory access (see `addr_out`).
+    ///
+    pub fn data_out(&mut self) -> &mut OutputPort8 {
+        self.bus_watched = true;
+        &mut self.data_out
+    }
+
+    /// The R/W line, which is high for a read and low for a write (see `addr_out`).
+    ///
+    pub fn rw_out(&mut self) -> &mut OutputPin {
+        self.bus_watched = true;
+        &mut self.rw_out
+    }
+
+    /// The RESET line, which is active low: the CPU resets while the line is held low, and
+    /// restarts through the reset vector once it is released. The line starts out high.
+    ///
+    pub fn reset_in(&mut self) -> &mut InputPin {
+        &mut self.reset_in
+    }
+
+    /// The IRQ line, which is active low. The line starts out high.
+    ///
+    pub fn irq_in(&mut self) -> &mut InputPin {
+        &mut self.irq_in
+    }
+
+    /// The NMI line, which is active low. The line starts out high.
+    ///
+    pub fn nmi_in(&mut self) -> &mut InputPin {
+        &mut self.nmi_in
+    }
+
+    /// The RDY line, which halts the CPU while it's low, e.g. for a DMA controller to take the
+    /// bus. The line starts out high.
+    ///
+    pub fn rdy_in(&mut self) -> &mut InputPin {
+        &mut self.rdy_in
+    }
+
+    /// The SO (set overflow) line, which is active low: a falling edge sets the overflow flag.
+    ///
+    pub fn so_in(&mut self) -> &mut InputPin {
+        &mut self.so_in
+    }
+
+    /// The output bits of the I/O port of a CPU created with `new_6510`. Bits that aren't
+    /// outputs are sent as 0.
+    ///
+    pub fn port_out(&mut self) -> &mut OutputPort8 {
+        &mut self.port_out
+    }
+
+    /// The input bits of the I/O port of a CPU created with `new_6510`.
+    ///
+    pub fn port_in(&mut self) -> &mut InputPort8 {
+        &mut self.port_in
+    }
+
+    /// Replace the contents of a bank of the CPU's memory, as `Memory::reload_bank` does, while
+    /// the CPU is paused between steps. With `reset`, the CPU is then reset, so it starts on the
+    /// new code; without, it carries on from its PC, e.g. when the bank holds data.
+    ///
+    pub fn reload_bank(&mut self, bank_id: usize, bytes: &[u8], reset: bool) -> bool {
+        let reloaded = self.memory.reload_bank_unchecked(bank_id, bytes);
+        if reloaded && reset {
+            self.reset();
+        }
+        reloaded
+    }
+
     pub fn reset(&mut self) {
         // TODO: Need to implement a more realistic reset mechanism.
         self.state = CpuState::Resetting;
         self.cycle = 1;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.reset();
+        }
+        self.nmi_pending = false;
+        self.in_nmi = false;
+        self.interrupt = None;
+        self.polled = None;
+        self.sampled = None;
+        if self.io_port {
+            // Every bit of the I/O port starts out as an input.
+            self.io_direction = 0x00;
+            self.port_out.send(0x00);
+        }
+    }
+
+    /// Set the level of the RESET line, where `asserted` corresponds to the line being pulled low.
+    ///
+    /// Asserting the line puts the CPU into the reset sequence, where it stays for as long as the
+    /// line is held. Releasing the line lets the reset sequence run to completion.
+    ///
+    pub fn set_reset(&mut self, asserted: bool) {
+        if asserted && !self.reset_line {
+            self.reset();
+        }
+        self.reset_line = asserted;
+    }
+
+    /// Set the level of the IRQ line, where `asserted` corresponds to the line being pulled low.
+    ///
+    /// IRQ is level-sensitive: while the line is asserted and the interrupt mask is clear, an
+    /// interrupt is serviced at each instruction boundary. The device raising the interrupt is
+    /// expected to hold the line until the handler acknowledges it.
+    ///
+    /// As on the hardware, interrupts are polled at the end of each instruction's penultimate
+    /// cycle, so a line asserted during an instruction's last cycle is serviced an instruction
+    /// later. The same goes for NMI.
+    ///
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Set the level of the NMI line, where `asserted` corresponds to the line being pulled low.
+    ///
+    /// NMI is edge-triggered: the transition to asserted latches a single interrupt, which is
+    /// serviced at the next instruction boundary regardless of the interrupt mask. Holding the
+    /// line asserted does not trigger further interrupts. An edge that arrives while an NMI
+    /// handler is running is remembered, and serviced once the handler returns with RTI.
+    ///
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Set whether the SO line is asserted. Asserting it sets the overflow flag straight away,
+    /// so it's seen by the next instruction to test it; holding it asserted has no further effect.
+    ///
+    pub fn set_so(&mut self, asserted: bool) {
+        if asserted && !self.so_line {
+            self.p |= Self::SR_OVERFLOW;
+        }
+        self.so_line = asserted;
+    }
+
+    /// Set the level of the RDY line. While the line is low, the CPU stalls at the next cycle
+    /// that reads from memory, repeating the cycle until the line goes high again. Write cycles
+    /// go ahead regardless, as on the hardware. Stalled cycles are counted like any others.
+    ///
+    pub fn set_rdy(&mut self, ready: bool) {
+        self.rdy = ready;
+    }
+
+    /// Drive the CPU with one edge of the phi0 clock.
+    ///
+    /// The phi1 and phi2 outputs follow the edge, and on a rising edge the CPU steps one cycle.
+    /// This is how the CPU is driven by its run loop, and should be used in place of `step` by
+    /// anything else that clocks the CPU and wants components wired to the phase outputs to
+    /// see the edges too.
+    ///
+    pub fn clock_edge(&mut self, signal: bool) -> Option<CpuAction> {
+        self.phi1_out.send(!signal);
+        self.phi2_out.send(signal);
+        if signal {
+            Some(self.step())
+        } else {
+            None
+        }
+    }
+
+    /// Step the CPU until it completes an instruction, returning the instruction's address and
+    /// opcode, and the cycles it took. If the CPU is in its reset sequence, the sequence is
+    /// finished first, and isn't counted. This needs no clock, so it can drive the CPU
+    /// directly, e.g. from a test or a script.
+    ///
+    /// The CPU must have been reset, and mustn't be held in reset, or this never returns.
+    ///
+    pub fn run_instruction(&mut self) -> InstructionResult {
+        assert!(self.state != CpuState::Off, "CPU hasn't been reset");
+        assert!(self.state != CpuState::Faulted, "CPU has faulted");
+        while self.state != CpuState::Running {
+            self.step();
+        }
+        loop {
+            let (pc, opcode, start) = (self.ins_pc, self.opcode, self.ins_start);
+            match self.step() {
+                CpuAction::Continue => {},
+                // The last cycle of the instruction was the fetch of the next one.
+                CpuAction::CompleteAndFetch => {
+                    return InstructionResult { pc, opcode, cycles: self.total_cycles - 1 - start };
+                },
+                CpuAction::Complete => {
+                    let cycles = self.total_cycles - self.ins_start;
+                    return InstructionResult { pc: self.ins_pc, opcode: self.opcode, cycles };
+                },
+            }
+        }
     }
 
-    pub fn set_irq(&mut self) {
-        if self.p & C6502::SR_INTERRUPT_MASK == 0 {
-            unimplemented!();
+    /// Run whole instructions, as `run_instruction` does, until one of `conditions` is met,
+    /// returning the first condition that was, and the cycles the instructions took. The
+    /// conditions are checked after each instruction, so at least one instruction runs.
+    ///
+    /// If none of the conditions can be met, this never returns, so a `MaxCycles` condition is
+    /// a useful backstop.
+    ///
+    pub fn run_until(&mut self, conditions: &[StopCondition]) -> RunResult {
+        let mut cycles = 0;
+        loop {
+            let result = self.run_instruction();
+            cycles += result.cycles;
+            // If the next opcode has already been fetched, the PC is past it.
+            let next_pc = if self.state == CpuState::Running && self.cycle == 2 { self.ins_pc } else { self.pc };
+            let met = conditions.iter().find(|condition| match **condition {
+                StopCondition::PcEquals(pc) => next_pc == pc,
+                StopCondition::BrkExecuted => result.opcode == 0x00 && self.interrupt.is_none(),
+                StopCondition::MaxCycles(max) => cycles >= max as u64,
+                StopCondition::PcUnchanged => next_pc == result.pc,
+            });
+            if let Some(&condition) = met {
+                return RunResult { condition, cycles };
+            }
         }
     }
 
-    pub fn set_nmi(&mut self) {
-        unimplemented!();
+    /// Step the CPU for `cycles` cycles, returning the number of instructions completed. The
+    /// CPU can be left part way through an instruction, which carries on with the next call.
+    ///
+    pub fn run_for_cycles(&mut self, cycles: usize) -> usize {
+        let mut completed = 0;
+        for _ in 0..cycles {
+            let was_running = self.state == CpuState::Running;
+            if self.step() != CpuAction::Continue && was_running {
+                completed += 1;
+            }
+        }
+        completed
+    }
+
+    /// Step the CPU for up to `budget` cycles, for a scheduler that runs components in lockstep,
+    /// returning the cycles left unused. Every step is a whole cycle, which may leave the CPU
+    /// part way through an instruction, so running a number of cycles in one call or in several
+    /// has exactly the same effect. The only cycles left unused are those from when the CPU is
+    /// off or has faulted, when it has nothing to do.
+    ///
+    pub fn step_cycles(&mut self, budget: usize) -> usize {
+        for used in 0..budget {
+            if matches!(self.state, CpuState::Off | CpuState::Faulted) {
+                return budget - used;
+            }
+            self.step();
+        }
+        0
     }
 
     pub fn step(&mut self) -> CpuAction {
+        if let Some(kind) = self.reset_request.take() {
+            Resettable::reset(self, kind);
+        }
+        let was_running = self.state == CpuState::Running;
+        let (ins_pc, opcode, cycle) = (self.ins_pc, self.opcode, self.cycle);
+        // While RDY is low, a cycle that reads is abandoned, and the CPU put back as it was
+        // before the cycle, to repeat it once RDY goes high.
+        let before_stall = if self.rdy { None } else { Some(self.snapshot()) };
+        let poll = self.poll_interrupts();
+        self.polled = mem::replace(&mut self.sampled, poll);
+        self.bus_activity = None;
+        let mut action = self.step_cycle();
+        if let Some(before) = before_stall.filter(|_| self.stalled) {
+            self.restore(&before);
+            self.stalled = false;
+            action = CpuAction::Continue;
+        }
+        self.total_cycles += 1;
+        let sync = self.state == CpuState::Running && self.cycle == 2;
+        if sync != self.sync_out.value() {
+            self.sync_out.send(sync);
+        }
+        if let Some(profiler) = self.profiler.as_mut().filter(|_| was_running) {
+            // A cycle belongs to the instruction running at its start, except a plain opcode
+            // fetch, which belongs to the instruction it fetches.
+            let fetched = self.cycle == 2 && (cycle == 1 || action == CpuAction::CompleteAndFetch);
+            let next = fetched.then_some((self.ins_pc, self.opcode));
+            let (pc, opcode) = if cycle == 1 { (self.ins_pc, self.opcode) } else { (ins_pc, opcode) };
+            profiler.record(pc, opcode, next);
+        }
+        action
+    }
+
+    /// Run a cycle, as `C6502::step` does, returning the bus access it made along with the action.
+    ///
+    pub fn step_with_bus(&mut self) -> (CpuAction, Option<BusActivity>) {
+        let action = self.step();
+        (action, self.bus_activity)
+    }
+
+    /// Returns the bus access made in the last cycle, or `None` if the cycle didn't access the
+    /// bus: the CPU was off or stalled, or the cycle is one the CPU treats as internal. A cycle
+    /// that completes an instruction and fetches the next one reports the fetch.
+    ///
+    pub fn last_bus_activity(&self) -> Option<BusActivity> {
+        self.bus_activity
+    }
+
+    /// Start counting the instructions executed and the cycles they take, by opcode and by
+    /// address, discarding any previous counts. Each cycle is attributed to the instruction it
+    /// belongs to, so an instruction's last cycle, which also fetches the next instruction,
+    /// counts towards the instruction it completes, with the next one counted as executed. The
+    /// cycles charged to an instruction can therefore differ by one from those `run_instruction`
+    /// reports for it, which leaves the overlapped fetch to the next instruction.
+    ///
+    pub fn enable_profiling(&mut self) {
+        self.profiler.get_or_insert_with(Box::default).instructions = Some(InstructionProfile::new());
+    }
+
+    pub fn disable_profiling(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.instructions = None;
+        }
+        self.drop_idle_profiler();
+    }
+
+    /// Returns the instructions and cycles counted so far, or an empty report if profiling
+    /// isn't enabled.
+    ///
+    pub fn profile(&self) -> ProfileReport {
+        self.profiler.as_ref().and_then(|p| p.instructions.as_ref()).map(|p| p.report()).unwrap_or_default()
+    }
+
+    /// Enable attribution of executed cycles to address regions, replacing any previous
+    /// region profile. Each cycle is attributed to the region containing the address of the
+    /// instruction it belongs to, as `enable_profiling` attributes it.
+    ///
+    pub fn enable_region_profiling(&mut self, regions: RegionMap) {
+        self.profiler.get_or_insert_with(Box::default).regions = Some(RegionProfile::new(regions));
+    }
+
+    pub fn disable_region_profiling(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.regions = None;
+        }
+        self.drop_idle_profiler();
+    }
+
+    /// Returns the cycles attributed to each region so far, or `None` if region profiling
+    /// isn't enabled.
+    ///
+    pub fn profile_report(&self) -> Option<RegionReport> {
+        self.profiler.as_ref().and_then(|p| p.regions.as_ref()).map(|p| p.report())
+    }
+
+    /// Stop checking for profiling every cycle once no profile is being collected.
+    ///
+    fn drop_idle_profiler(&mut self) {
+        if self.profiler.as_ref().is_some_and(|p| p.is_empty()) {
+            self.profiler = None;
+        }
+    }
+
+    /// Send a `TraceEntry` to `sink` for every instruction executed from now on, replacing
+    /// (and flushing) any previous sink. `None` turns tracing off.
+    ///
+    /// If a sink fails, tracing stops, and the error is returned by the next call, as is any
+    /// error flushing the previous sink. A CPU running as an async component reports the error
+    /// in its `CpuStats`, too.
+    ///
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink>>) -> io::Result<()> {
+        let old = mem::replace(&mut self.trace, sink);
+        *self.stats.trace_error.lock().unwrap() = None;
+        if let Some(e) = self.trace_error.take() {
+            return Err(e);
+        }
+        old.map_or(Ok(()), |mut old| old.flush())
+    }
+
+    /// Call `hook` with a `TraceRecord` for every instruction completed from now on, through a
+    /// `TraceHook` set as the trace sink, replacing any previous sink. `set_trace_sink(None)`
+    /// turns it off again.
+    ///
+    pub fn set_trace<F>(&mut self, hook: F) -> io::Result<()>
+    where
+        F: FnMut(&TraceRecord) + Send + 'static,
+    {
+        self.set_trace_sink(Some(Box::new(TraceHook::new(&self.memory, hook))))
+    }
+
+    /// Run `handler` in place of the code at `addr`, replacing any previous trap there, e.g. to
+    /// implement a ROM routine natively. The handler runs when the CPU is about to fetch the
+    /// instruction at `addr`, taking no cycles. It's given the registers, which it can change,
+    /// and the memory, and returns where execution carries on (see `TrapAction`). A trap
+    /// doesn't run when an interrupt is about to be serviced instead of the instruction.
+    ///
+    /// If the handler resumes at the address of another trap, or sets the PC to it, that trap
+    /// runs straight away, so a handler mustn't resume at its own address. The handler runs on the CPU's thread with
+    /// the CPU borrowed, so it can't reach the CPU other than through the registers it's given.
+    ///
+    pub fn add_trap<F>(&mut self, addr: u16, handler: F)
+    where
+        F: FnMut(&mut Registers, &Memory) -> TrapAction + Send + 'static,
+    {
+        self.traps.insert(addr, Box::new(handler));
+    }
+
+    pub fn remove_trap(&mut self, addr: u16) {
+        self.traps.remove(&addr);
+    }
+
+    fn run_traps(&mut self) {
+        while let Some(mut handler) = self.traps.remove(&self.pc) {
+            let addr = self.pc;
+            let mut registers = self.registers();
+            let action = handler(&mut registers, &self.memory);
+            self.traps.entry(addr).or_insert(handler);
+            self.ac = registers.ac;
+            self.x = registers.x;
+            self.y = registers.y;
+            self.p = registers.p;
+            self.sp = registers.sp;
+            self.pc = registers.pc;
+            match action {
+                // A handler that moved the PC falls through to the code there, which may have a
+                // trap of its own.
+                TrapAction::FallThrough if self.pc != addr => {},
+                TrapAction::FallThrough => break,
+                TrapAction::Resume(pc) => self.pc = pc,
+                TrapAction::Return => {
+                    self.incr_stack();
+                    set_lo_byte!(&mut self.pc, self.read_stack_byte());
+                    self.incr_stack();
+                    set_hi_byte!(&mut self.pc, self.read_stack_byte());
+                    self.pc = self.pc.wrapping_add(1);
+                },
+            }
+        }
+    }
+
+    fn step_cycle(&mut self) -> CpuAction {
         match self.state {
             CpuState::Running => {
                 // Fetch an opcode if we don't have one.
                 if self.cycle == 1 {
-                    self.opcode = self.read_pc_byte();
-                    self.pc += 1;
-                    self.cycle = 2;
+                    self.fetch_opcode();
                     return CpuAction::Continue;
                 }
 
-                let next_action = match self.opcode {
-                    0x00 => self.do_brk(),
-                    0x01 => self.do_op_indexed_indirect(Op::Read(Self::op_ora)),
-                    0x04 => self.do_op_zeropage(Op::Implied(Self::op_nop)),
-                    0x05 => self.do_op_zeropage(Op::Read(Self::op_ora)),
-                    0x06 => self.do_op_zeropage(Op::ReadWrite(Self::op_asl)),
-                    0x08 => self.do_php(),
-                    0x09 => self.do_op_immed(Op::Read(Self::op_ora)),
-                    0x0A => self.do_op_ac(Op::ReadWrite(Self::op_asl)),
-                    0x0C => self.do_op_abs(Op::Implied(Self::op_nop)),
-                    0x0D => self.do_op_abs(Op::Read(Self::op_ora)),
-                    0x0E => self.do_op_abs(Op::ReadWrite(Self::op_asl)),
-                    0x10 => self.do_branch(Self::br_bpl),
-                    0x11 => self.do_op_indirect_indexed(Op::Read(Self::op_ora)),
-                    0x14 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0x15 => self.do_op_zeropage_x(Op::Read(Self::op_ora)),
-                    0x16 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_asl)),
-                    0x18 => self.do_op_implied(Op::Implied(Self::op_clc)),
-                    0x19 => self.do_op_abs_y(Op::Read(Self::op_ora)),
-                    0x1A => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0x1C => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0x1D => self.do_op_abs_x(Op::Read(Self::op_ora)),
-                    0x1E => self.do_op_abs_x(Op::ReadWrite(Self::op_asl)),
-                    0x20 => self.do_jsr(),
-                    0x21 => self.do_op_indexed_indirect(Op::Read(Self::op_and)),
-                    0x24 => self.do_op_zeropage(Op::Read(Self::op_bit)),
-                    0x25 => self.do_op_zeropage(Op::Read(Self::op_and)),
-                    0x26 => self.do_op_zeropage(Op::ReadWrite(Self::op_rol)),
-                    0x28 => self.do_plp(),
-                    0x29 => self.do_op_immed(Op::Read(Self::op_and)),
-                    0x2A => self.do_op_ac(Op::ReadWrite(Self::op_rol)),
-                    0x2C => self.do_op_abs(Op::Read(Self::op_bit)),
-                    0x2D => self.do_op_abs(Op::Read(Self::op_and)),
-                    0x2E => self.do_op_abs(Op::ReadWrite(Self::op_rol)),
-                    0x30 => self.do_branch(Self::br_bmi),
-                    0x31 => self.do_op_indirect_indexed(Op::Read(Self::op_and)),
-                    0x34 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0x35 => self.do_op_zeropage_x(Op::Read(Self::op_and)),
-                    0x36 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_rol)),
-                    0x38 => self.do_op_implied(Op::Implied(Self::op_sec)),
-                    0x39 => self.do_op_abs_y(Op::Read(Self::op_and)),
-                    0x3A => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0x3C => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0x3D => self.do_op_abs_x(Op::Read(Self::op_and)),
-                    0x3E => self.do_op_abs_x(Op::ReadWrite(Self::op_rol)),
-                    0x40 => self.do_rti(),
-                    0x41 => self.do_op_indexed_indirect(Op::Read(Self::op_eor)),
-                    0x44 => self.do_op_zeropage(Op::Implied(Self::op_nop)),
-                    0x45 => self.do_op_zeropage(Op::Read(Self::op_eor)),
-                    0x46 => self.do_op_zeropage(Op::ReadWrite(Self::op_lsr)),
-                    0x48 => self.do_pha(),
-                    0x49 => self.do_op_immed(Op::Read(Self::op_eor)),
-                    0x4A => self.do_op_ac(Op::ReadWrite(Self::op_lsr)),
-                    0x4C => self.do_jmp_abs(),
-                    0x4D => self.do_op_abs(Op::Read(Self::op_eor)),
-                    0x4E => self.do_op_abs(Op::ReadWrite(Self::op_lsr)),
-                    0x50 => self.do_branch(Self::br_bvc),
-                    0x51 => self.do_op_indirect_indexed(Op::Read(Self::op_eor)),
-                    0x54 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0x55 => self.do_op_zeropage_x(Op::Read(Self::op_eor)),
-                    0x56 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_lsr)),
-                    0x58 => self.do_op_implied(Op::Implied(Self::op_cli)),
-                    0x59 => self.do_op_abs_y(Op::Read(Self::op_eor)),
-                    0x5A => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0x5C => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0x5D => self.do_op_abs_x(Op::Read(Self::op_eor)),
-                    0x5E => self.do_op_abs_x(Op::ReadWrite(Self::op_lsr)),
-                    0x60 => self.do_rts(),
-                    0x61 => self.do_op_indexed_indirect(Op::Read(Self::op_adc)),
-                    0x64 => self.do_op_zeropage(Op::Implied(Self::op_nop)),
-                    0x65 => self.do_op_zeropage(Op::Read(Self::op_adc)),
-                    0x66 => self.do_op_zeropage(Op::ReadWrite(Self::op_ror)),
-                    0x68 => self.do_pla(),
-                    0x69 => self.do_op_immed(Op::Read(Self::op_adc)),
-                    0x6A => self.do_op_ac(Op::ReadWrite(Self::op_ror)),
-                    0x6C => self.do_jmp_abs_indirect(),
-                    0x6D => self.do_op_abs(Op::Read(Self::op_adc)),
-                    0x6E => self.do_op_abs(Op::ReadWrite(Self::op_ror)),
-                    0x70 => self.do_branch(Self::br_bvs),
-                    0x71 => self.do_op_indirect_indexed(Op::Read(Self::op_adc)),
-                    0x74 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0x75 => self.do_op_zeropage_x(Op::Read(Self::op_adc)),
-                    0x76 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_ror)),
-                    0x78 => self.do_op_implied(Op::Implied(Self::op_sei)),
-                    0x79 => self.do_op_abs_y(Op::Read(Self::op_adc)),
-                    0x7A => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0x7C => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0x7D => self.do_op_abs_x(Op::Read(Self::op_adc)),
-                    0x7E => self.do_op_abs_x(Op::ReadWrite(Self::op_ror)),
-                    0x80 => self.do_op_immed(Op::Implied(Self::op_nop)),
-                    0x81 => self.do_op_indexed_indirect(Op::Write(Self::op_sta)),
-                    0x82 => self.do_op_immed(Op::Implied(Self::op_nop)),
-                    0x84 => self.do_op_zeropage(Op::Write(Self::op_sty)),
-                    0x85 => self.do_op_zeropage(Op::Write(Self::op_sta)),
-                    0x86 => self.do_op_zeropage(Op::Write(Self::op_stx)),
-                    0x88 => self.do_op_implied(Op::Implied(Self::op_dey)),
-                    0x89 => self.do_op_immed(Op::Implied(Self::op_nop)),
-                    0x8A => self.do_op_implied(Op::Implied(Self::op_txa)),
-                    0x8C => self.do_op_abs(Op::Write(Self::op_sty)),
-                    0x8D => self.do_op_abs(Op::Write(Self::op_sta)),
-                    0x8E => self.do_op_abs(Op::Write(Self::op_stx)),
-                    0x90 => self.do_branch(Self::br_bcc),
-                    0x91 => self.do_op_indirect_indexed(Op::Write(Self::op_sta)),
-                    0x94 => self.do_op_zeropage_x(Op::Write(Self::op_sty)),
-                    0x95 => self.do_op_zeropage_x(Op::Write(Self::op_sta)),
-                    0x96 => self.do_op_zeropage_y(Op::Write(Self::op_stx)),
-                    0x98 => self.do_op_implied(Op::Implied(Self::op_tya)),
-                    0x99 => self.do_op_abs_y(Op::Write(Self::op_sta)),
-                    0x9A => self.do_op_implied(Op::Implied(Self::op_txs)),
-                    0x9D => self.do_op_abs_x(Op::Write(Self::op_sta)),
-                    0xA0 => self.do_op_immed(Op::Read(Self::op_ldy)),
-                    0xA1 => self.do_op_indexed_indirect(Op::Read(Self::op_lda)),
-                    0xA2 => self.do_op_immed(Op::Read(Self::op_ldx)),
-                    0xA4 => self.do_op_zeropage(Op::Read(Self::op_ldy)),
-                    0xA5 => self.do_op_zeropage(Op::Read(Self::op_lda)),
-                    0xA6 => self.do_op_zeropage(Op::Read(Self::op_ldx)),
-                    0xA8 => self.do_op_implied(Op::Implied(Self::op_tay)),
-                    0xA9 => self.do_op_immed(Op::Read(Self::op_lda)),
-                    0xAA => self.do_op_implied(Op::Implied(Self::op_tax)),
-                    0xAC => self.do_op_abs(Op::Read(Self::op_ldy)),
-                    0xAD => self.do_op_abs(Op::Read(Self::op_lda)),
-                    0xAE => self.do_op_abs(Op::Read(Self::op_ldx)),
-                    0xB0 => self.do_branch(Self::br_bcs),
-                    0xB1 => self.do_op_indirect_indexed(Op::Read(Self::op_lda)),
-                    0xB4 => self.do_op_zeropage_x(Op::Read(Self::op_ldy)),
-                    0xB5 => self.do_op_zeropage_x(Op::Read(Self::op_lda)),
-                    0xB6 => self.do_op_zeropage_y(Op::Read(Self::op_ldx)),
-                    0xB8 => self.do_op_implied(Op::Implied(Self::op_clv)),
-                    0xB9 => self.do_op_abs_y(Op::Read(Self::op_lda)),
-                    0xBA => self.do_op_implied(Op::Implied(Self::op_tsx)),
-                    0xBC => self.do_op_abs_x(Op::Read(Self::op_ldy)),
-                    0xBD => self.do_op_abs_x(Op::Read(Self::op_lda)),
-                    0xBE => self.do_op_abs_y(Op::Read(Self::op_ldx)),
-                    0xC0 => self.do_op_immed(Op::Read(Self::op_cpy)),
-                    0xC1 => self.do_op_indexed_indirect(Op::Read(Self::op_cmp)),
-                    0xC2 => self.do_op_immed(Op::Implied(Self::op_nop)),
-                    0xC4 => self.do_op_zeropage(Op::Read(Self::op_cpy)),
-                    0xC5 => self.do_op_zeropage(Op::Read(Self::op_cmp)),
-                    0xC6 => self.do_op_zeropage(Op::ReadWrite(Self::op_dec)),
-                    0xC8 => self.do_op_implied(Op::Implied(Self::op_iny)),
-                    0xC9 => self.do_op_immed(Op::Read(Self::op_cmp)),
-                    0xCA => self.do_op_implied(Op::Implied(Self::op_dex)),
-                    0xCC => self.do_op_abs(Op::Read(Self::op_cpy)),
-                    0xCD => self.do_op_abs(Op::Read(Self::op_cmp)),
-                    0xCE => self.do_op_abs(Op::ReadWrite(Self::op_dec)),
-                    0xD0 => self.do_branch(Self::br_bne),
-                    0xD1 => self.do_op_indirect_indexed(Op::Read(Self::op_cmp)),
-                    0xD4 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0xD5 => self.do_op_zeropage_x(Op::Read(Self::op_cmp)),
-                    0xD6 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_dec)),
-                    0xD8 => self.do_op_implied(Op::Implied(Self::op_cld)),
-                    0xD9 => self.do_op_abs_y(Op::Read(Self::op_cmp)),
-                    0xDA => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0xDC => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0xDD => self.do_op_abs_x(Op::Read(Self::op_cmp)),
-                    0xDE => self.do_op_abs_x(Op::ReadWrite(Self::op_dec)),
-                    0xE0 => self.do_op_immed(Op::Read(Self::op_cpx)),
-                    0xE1 => self.do_op_indexed_indirect(Op::Read(Self::op_sbc)),
-                    0xE2 => self.do_op_immed(Op::Implied(Self::op_nop)),
-                    0xE4 => self.do_op_zeropage(Op::Read(Self::op_cpx)),
-                    0xE5 => self.do_op_zeropage(Op::Read(Self::op_sbc)),
-                    0xE6 => self.do_op_zeropage(Op::ReadWrite(Self::op_inc)),
-                    0xE8 => self.do_op_implied(Op::Implied(Self::op_inx)),
-                    0xE9 => self.do_op_immed(Op::Read(Self::op_sbc)),
-                    0xEA => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0xEC => self.do_op_abs(Op::Read(Self::op_cpx)),
-                    0xED => self.do_op_abs(Op::Read(Self::op_sbc)),
-                    0xEE => self.do_op_abs(Op::ReadWrite(Self::op_inc)),
-                    0xF0 => self.do_branch(Self::br_beq),
-                    0xF1 => self.do_op_indirect_indexed(Op::Read(Self::op_sbc)),
-                    0xF4 => self.do_op_zeropage_x(Op::Implied(Self::op_nop)),
-                    0xF5 => self.do_op_zeropage_x(Op::Read(Self::op_sbc)),
-                    0xF6 => self.do_op_zeropage_x(Op::ReadWrite(Self::op_inc)),
-                    0xF8 => self.do_op_implied(Op::Implied(Self::op_sed)),
-                    0xF9 => self.do_op_abs_y(Op::Read(Self::op_sbc)),
-                    0xFA => self.do_op_implied(Op::Implied(Self::op_nop)),
-                    0xFC => self.do_op_abs_x(Op::Implied(Self::op_nop)),
-                    0xFD => self.do_op_abs_x(Op::Read(Self::op_sbc)),
-                    0xFE => self.do_op_abs_x(Op::ReadWrite(Self::op_inc)),
-                    _ => panic!("Illegal instruction ${:02X} at ${:04X}", self.opcode, self.pc - 1),
+                let next_action = match OPCODE_TABLE[self.opcode as usize] {
+                    Some(Dispatch::Mode(mode, op)) => mode(self, op),
+                    Some(Dispatch::Branch(test)) => self.do_branch(test),
+                    Some(Dispatch::Special(f)) => f(self),
+                    None => panic!("Illegal instruction ${:02X} at ${:04X}", self.opcode, self.pc.wrapping_sub(1)),
                 };
 
                 match next_action {
@@ -308,18 +810,21 @@ impl C6502 {
                     CpuAction::CompleteAndFetch => {
                         // For instructions that don't write to memory, we need to pipeline the next
                         // opcode during this cycle.
-                        self.opcode = self.read_pc_byte();
-                        self.pc += 1;
-                        self.cycle = 2;
+                        self.fetch_opcode();
                     },
                 }
 
                 next_action
             },
 
-            CpuState::Off => CpuAction::Continue,
+            CpuState::Off | CpuState::Faulted => CpuAction::Continue,
 
             CpuState::Resetting => {
+                // Hold at the start of the reset sequence while the RESET line is asserted.
+                if self.reset_line {
+                    return CpuAction::Continue;
+                }
+
                 // Go through next cycle of reset sequence, until completed.
                 if self.do_reset_sequence() {
                     self.state = CpuState::Running;
@@ -333,62 +838,198 @@ impl C6502 {
         }
     }
 
-    fn read_byte(&self, addr: u16) -> u8 {
-        self.memory.read_byte(addr)
+    fn fetch_opcode(&mut self) {
+        if !self.rdy {
+            self.stalled = true;
+            return;
+        }
+        if !self.traps.is_empty() && self.polled.is_none() {
+            self.run_traps();
+        }
+        self.ins_pc = self.pc;
+        self.ins_start = self.total_cycles;
+        self.published.store(self.registers());
+        self.opcode = self.read_pc_byte();
+        // When an interrupt is serviced, the fetched opcode is discarded, and a BRK is executed in
+        // its place that vectors through the interrupt's vector. An NMI may since have been taken
+        // by a BRK it hijacked.
+        match self.polled {
+            Some(Interrupt::Nmi) if self.nmi_pending && !self.in_nmi => {
+                self.nmi_pending = false;
+                self.in_nmi = true;
+                self.interrupt = Some(Interrupt::Nmi);
+                self.opcode = 0x00;
+            },
+            Some(Interrupt::Irq) => {
+                self.interrupt = Some(Interrupt::Irq);
+                self.opcode = 0x00;
+            },
+            _ => {
+                self.interrupt = None;
+                self.pc = self.pc.wrapping_add(1);
+            },
+        }
+        self.cycle = 2;
+        if self.trace.is_some() {
+            self.record_trace();
+        }
     }
 
-    fn read_pc_byte(&self) -> u8 {
-        self.read_byte(self.pc)
+    /// Returns the interrupt that would be serviced given the lines and the interrupt mask as
+    /// they are now. NMI takes priority over IRQ.
+    ///
+    fn poll_interrupts(&self) -> Option<Interrupt> {
+        if self.nmi_pending && !self.in_nmi {
+            Some(Interrupt::Nmi)
+        } else if self.irq_line && self.p & Self::SR_INTERRUPT_MASK == 0 {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
     }
 
-    fn push_byte(&mut self, value: u8) {
-        if self.sp == 0 {
-            panic!("Stack overflow");
+    fn record_trace(&mut self) {
+        let entry = TraceEntry {
+            cycle: self.total_cycles,
+            pc: self.ins_pc,
+            opcode: self.opcode,
+            ac: self.ac,
+            x: self.x,
+            y: self.y,
+            p: self.p,
+            sp: self.sp,
+        };
+        if let Err(e) = self.trace.as_mut().unwrap().record(&entry) {
+            self.stop_trace(e);
         }
-        self.memory.write_byte(Self::STACK_BASE + self.sp as u16, value);
-        self.sp -= 1;
     }
 
-    fn incr_stack(&mut self) {
-        if self.sp == 0xff {
-            panic!("Stack underflow");
+    /// Stop tracing after the sink failed with `e`, keeping the error for `set_trace_sink`.
+    ///
+    fn stop_trace(&mut self, e: io::Error) {
+        *self.stats.trace_error.lock().unwrap() = Some(e.to_string());
+        self.trace_error = Some(e);
+        self.trace = None;
+    }
+
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        if !self.rdy {
+            self.stalled = true;
+            return 0;
+        }
+        let value = if self.io_port && addr <= 0x0001 { self.read_io_port(addr) } else { self.memory.read_byte(addr) };
+        self.bus_activity = Some(BusActivity { addr, data: value, is_write: false });
+        if self.bus_watched {
+            self.send_bus(addr, value, true);
+        }
+        value
+    }
+
+    fn read_pc_byte(&mut self) -> u8 {
+        if !self.rdy {
+            self.stalled = true;
+            return 0;
         }
-        self.sp += 1;
+        let value = self.memory.fetch_byte(self.pc);
+        self.bus_activity = Some(BusActivity { addr: self.pc, data: value, is_write: false });
+        if self.bus_watched {
+            self.send_bus(self.pc, value, true);
+        }
+        value
+    }
+
+    /// Pushes a byte on the stack. Like the hardware, the stack pointer wraps around within
+    /// page one, rather than overflowing.
+    ///
+    fn push_byte(&mut self, value: u8) {
+        self.write_byte(Self::STACK_BASE + self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn incr_stack(&mut self) {
+        self.sp = self.sp.wrapping_add(1);
     }
 
     fn read_stack_byte(&mut self) -> u8 {
-        self.memory.read_byte(Self::STACK_BASE + self.sp as u16)
+        self.read_byte(Self::STACK_BASE + self.sp as u16)
     }
 
     fn write_byte(&mut self, addr: u16, value: u8) {
+        // A cycle that has already stalled on a read is abandoned, so its writes are too.
+        if self.stalled {
+            return;
+        }
         self.memory.write_byte(addr, value);
+        if self.io_port && addr <= 0x0001 {
+            self.write_io_port(addr, value);
+        }
+        self.bus_activity = Some(BusActivity { addr, data: value, is_write: true });
+        if self.bus_watched {
+            self.send_bus(addr, value, false);
+        }
+    }
+
+    fn read_io_port(&mut self, addr: u16) -> u8 {
+        if addr == 0x0000 {
+            self.io_direction
+        } else {
+            self.port_in.drain();
+            (self.io_data & self.io_direction) | (self.port_in.value() & !self.io_direction)
+        }
     }
 
-    /// Go through reset cycle.
+    fn write_io_port(&mut self, addr: u16, value: u8) {
+        if addr == 0x0000 {
+            self.io_direction = value;
+        } else {
+            self.io_data = value;
+        }
+        self.port_out.send(self.io_data & self.io_direction);
+    }
+
+    fn send_bus(&mut self, addr: u16, value: u8, read: bool) {
+        self.addr_out.send(addr);
+        self.rw_out.send(read);
+        self.data_out.send(value);
+    }
+
+    /// Go through a cycle of the reset sequence, returning whether it's complete. The sequence
+    /// is that of an interrupt, with the writes to the stack turned into reads: the stack pointer
+    /// goes down by three, but nothing is stacked, before the PC is loaded from the reset vector.
     ///
-    /// The first few steps are just for simulation accuracy,
-    /// but the key parts start at step 7, where we load the PC from the reset vector ($FFFC).
+    /// The sequence takes 7 cycles.
     ///
     fn do_reset_sequence(&mut self) -> bool {
         match self.cycle {
-            1 => self.sp = 0x00,
-            2 | 3 => {},
-            4 => self.sp = 0xFF,
-            5 => self.sp = 0xFE,
-            6 => self.sp = 0xFD,
-            7 => set_lo_byte!(&mut self.pc, self.read_byte(Self::RESET_VECTOR)),
-            8 => set_hi_byte!(&mut self.pc, self.read_byte(Self::RESET_VECTOR + 1)),
+            1 | 2 => {
+                self.read_pc_byte();
+            },
+            3..=5 => {
+                self.read_stack_byte();
+                self.sp = self.sp.wrapping_sub(1);
+            },
+            6 => set_lo_byte!(&mut self.pc, self.read_byte(Self::RESET_VECTOR)),
+            7 => set_hi_byte!(&mut self.pc, self.read_byte(Self::RESET_VECTOR + 1)),
             _ => unreachable!(),
         }
-        self.cycle == 8
+        self.cycle == 7
     }
 
+    /// Execute a BRK instruction, or the equivalent sequence for a hardware interrupt.
+    ///
+    /// A BRK skips the byte following the opcode, and pushes the status with the break flag
+    /// set. A hardware interrupt pushes the address of the interrupted instruction, and the
+    /// status with the break flag clear.
+    ///
+    /// This instruction takes 7 cycles.
+    ///
     fn do_brk(&mut self) -> CpuAction {
-        // TODO: Need to figure out when to set the Interrupt mask.
         match self.cycle {
             2 => {
                 //self.read_pc_byte();
-                self.pc += 1;
+                if self.interrupt.is_none() {
+                    self.pc = self.pc.wrapping_add(1);
+                }
                 CpuAction::Continue
             },
             3 => {
@@ -400,23 +1041,47 @@ impl C6502 {
                 CpuAction::Continue
             },
             5 => {
-                self.push_byte(self.p | Self::SR_BREAK | Self::SR_UNUSED);
+                if self.interrupt.is_none() {
+                    self.push_byte(self.p | Self::SR_BREAK | Self::SR_UNUSED);
+                } else {
+                    self.push_byte((self.p & !Self::SR_BREAK) | Self::SR_UNUSED);
+                }
+                // Mask interrupts once the status is stacked, for BRK as well as for interrupts,
+                // so a pending IRQ can't re-enter the handler before it runs.
+                self.p |= Self::SR_INTERRUPT_MASK;
+                // An NMI that arrives before the vector is fetched hijacks the sequence: it
+                // completes as it started, with the status already stacked, but vectors through
+                // NMI instead.
+                if self.interrupt != Some(Interrupt::Nmi) && self.nmi_pending && !self.in_nmi {
+                    self.nmi_pending = false;
+                    self.in_nmi = true;
+                    self.interrupt = Some(Interrupt::Nmi);
+                }
                 CpuAction::Continue
             },
             6 => {
-                set_lo_byte!(&mut self.pc, self.read_byte(Self::IRQ_VECTOR));
+                set_lo_byte!(&mut self.pc, self.read_byte(self.interrupt_vector()));
                 CpuAction::Continue
             },
             7 => {
-                set_hi_byte!(&mut self.pc, self.read_byte(Self::IRQ_VECTOR + 1));
+                set_hi_byte!(&mut self.pc, self.read_byte(self.interrupt_vector() + 1));
                 CpuAction::Complete
             },
             _ => unreachable!(),
         }
     }
 
+    fn interrupt_vector(&self) -> u16 {
+        match self.interrupt {
+            Some(Interrupt::Nmi) => Self::NMI_VECTOR,
+            Some(Interrupt::Irq) | None => Self::IRQ_VECTOR,
+        }
+    }
+
+    /// Return from an interrupt or BRK. The interrupt mask is restored with the rest of the
+    /// status from the stack, so it goes back to whatever it was when the handler was entered.
+    ///
     fn do_rti(&mut self) -> CpuAction {
-        // TODO: Need to figure out when to clear the Interrupt mask.
         match self.cycle {
             2 => {
                 // self.read_pc_byte();
@@ -429,6 +1094,9 @@ impl C6502 {
             4 => {
                 self.p = self.read_stack_byte() & !(Self::SR_BREAK | Self::SR_UNUSED);
                 self.incr_stack();
+                // The handler is over once the status is restored, in time for an NMI that
+                // arrived during it to be polled before RTI completes.
+                self.in_nmi = false;
                 CpuAction::Continue
             },
             5 => {
@@ -447,7 +1115,7 @@ impl C6502 {
     fn do_pha(&mut self) -> CpuAction {
         match self.cycle {
             2 => {
-                // self.read_pc_byte()
+                self.read_pc_byte();
                 CpuAction::Continue
             },
             3 => {
@@ -461,7 +1129,7 @@ impl C6502 {
     fn do_php(&mut self) -> CpuAction {
         match self.cycle {
             2 => {
-                // self.read_pc_byte()
+                self.read_pc_byte();
                 CpuAction::Continue
             },
             3 => {
@@ -476,11 +1144,12 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
-                // The purpose of this cycle is unknown.
+                // The CPU holds the low byte of the address internally, while it reads the stack.
+                self.read_stack_byte();
                 CpuAction::Continue
             },
             4 => {
@@ -503,10 +1172,11 @@ impl C6502 {
     fn do_rts(&mut self) -> CpuAction {
         match self.cycle {
             2 => {
-                // self.read_pc_byte()
+                self.read_pc_byte();
                 CpuAction::Continue
             },
             3 => {
+                self.read_stack_byte();
                 self.incr_stack();
                 CpuAction::Continue
             },
@@ -520,7 +1190,10 @@ impl C6502 {
                 CpuAction::Continue
             },
             6 => {
-                self.pc += 1;
+                // The return address is that of the last byte of the JSR, which is read and
+                // skipped.
+                self.read_pc_byte();
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Complete
             },
             _ => unreachable!(),
@@ -576,7 +1249,7 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
@@ -604,12 +1277,12 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
                 set_hi_byte!(&mut self.addr, self.read_pc_byte());
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             4 => {
@@ -634,11 +1307,15 @@ impl C6502 {
     /// This instruction takes 2-4 cycles: 2 if there is no branch, 3 if there is
     /// a branch, and an extra cycle if the branch goes to a different page.
     ///
+    /// As on the hardware, the extra cycles read from the PC and throw the byte away: first
+    /// the byte after the branch, and then, if the branch crosses a page, the target address
+    /// with the high byte not yet fixed up.
+    ///
     fn do_branch(&mut self, test: fn(&C6502) -> bool) -> CpuAction {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as i8 as i16 as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 if test(self) {
                     CpuAction::Continue
                 } else {
@@ -646,15 +1323,20 @@ impl C6502 {
                 }
             },
             3 => {
+                self.read_byte(self.pc);
                 self.addr = self.pc.wrapping_add(self.addr);
                 set_lo_byte!(&mut self.pc, (self.addr & 0xFF) as u8);
                 if self.pc == self.addr {
+                    // A taken branch that stays on the page doesn't poll for interrupts in this
+                    // cycle, so the poll from before its second cycle is the one that counts.
+                    self.sampled = self.polled;
                     CpuAction::Complete
                 } else {
                     CpuAction::Continue
                 }
             },
             4 => {
+                self.read_byte(self.pc);
                 self.pc = self.addr;
                 CpuAction::Complete
             },
@@ -805,7 +1487,7 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.value = self.read_pc_byte();
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
@@ -834,7 +1516,7 @@ impl C6502 {
         if let Op::ReadWrite(op) = op {
             match self.cycle {
                 2 => {
-                    // self.read_pc_byte();
+                    self.read_pc_byte();
                     CpuAction::Continue
                 },
                 3 => {
@@ -862,7 +1544,7 @@ impl C6502 {
         if let Op::Implied(op) = op {
             match self.cycle {
                 2 => {
-                    // self.read_pc_byte();
+                    self.read_pc_byte();
                     CpuAction::Continue
                 },
                 3 => {
@@ -890,7 +1572,7 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             _ => self.do_op(op, 3),
@@ -935,11 +1617,11 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
-                //self.read_byte(self.addr);
+                self.read_byte(self.addr);
                 self.addr = (self.addr + offset as u16) & 0xFF;
                 CpuAction::Continue
             },
@@ -962,11 +1644,11 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.extra_addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
-                // self.read_byte(self.extra_addr);
+                self.read_byte(self.extra_addr);
                 self.extra_addr = (self.extra_addr + self.x as u16) & 0xFF;
                 CpuAction::Continue
             },
@@ -999,7 +1681,7 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.extra_addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
@@ -1017,8 +1699,9 @@ impl C6502 {
                 if is_read && self.extra_addr == 0 {
                     self.do_op(op, 5)
                 } else {
-                    //self.read_byte(self.addr);
-                    self.addr += self.extra_addr;
+                    // Read from the address before the carry is applied, as the hardware does.
+                    self.read_byte(self.addr);
+                    self.addr = self.addr.wrapping_add(self.extra_addr);
                     CpuAction::Continue
                 }
             },
@@ -1039,12 +1722,12 @@ impl C6502 {
         match self.cycle {
             2 => {
                 set_lo_byte!(&mut self.addr, self.read_pc_byte());
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
                 set_hi_byte!(&mut self.addr, self.read_pc_byte());
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             _ => self.do_op(op, 4),
@@ -1090,7 +1773,7 @@ impl C6502 {
         match self.cycle {
             2 => {
                 self.addr = self.read_pc_byte() as u16;
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             3 => {
@@ -1098,15 +1781,16 @@ impl C6502 {
                 self.addr = addr & 0xFF;
                 self.extra_addr = addr & 0x100;
                 set_hi_byte!(&mut self.addr, self.read_pc_byte());
-                self.pc += 1;
+                self.pc = self.pc.wrapping_add(1);
                 CpuAction::Continue
             },
             4 => {
                 if is_read && self.extra_addr == 0 {
                     self.do_op(op, 4)
                 } else {
-                    //self.read_byte(self.addr);
-                    self.addr += self.extra_addr;
+                    // Read from the address before the carry is applied, as the hardware does.
+                    self.read_byte(self.addr);
+                    self.addr = self.addr.wrapping_add(self.extra_addr);
                     CpuAction::Continue
                 }
             },
@@ -1132,7 +1816,8 @@ impl C6502 {
     ///   Write-only operations take 1 cycle after the address computation.
     /// * Read-write operations (`Op::ReadWrite`) specify a function that accepts the 8-bit
     ///   value found at the address, and returns a modified 8-bit value. The function usually
-    ///   sets some registers as well. The returned value is then written to the address.
+    ///   sets some registers as well. The original value is written back to the address in the
+    ///   second cycle, as on the hardware, and the returned value is written in the third.
     ///   Read-write operations take 3 cycles after the address computation.
     ///
     fn do_op(&mut self, op: Op, start_at: usize) -> CpuAction {
@@ -1159,6 +1844,9 @@ impl C6502 {
                     CpuAction::CompleteAndFetch
                 },
                 Op::ReadWrite(op) => {
+                    // The hardware writes the unmodified value back while it computes the new
+                    // one, which memory-mapped registers can see.
+                    self.write_byte(self.addr, self.value);
                     self.value = op(self, self.value);
                     CpuAction::Continue
                 },
@@ -1234,6 +1922,24 @@ impl C6502 {
         result
     }
 
+    /// Decrement the value by one, compare the result with the accumulator, and return the
+    /// result. Flags are set as for a compare. This is an undocumented instruction.
+    ///
+    fn op_dcp(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.op_cmp(result);
+        result
+    }
+
+    /// Increment the value by one, subtract the result from the accumulator, and return the
+    /// result. Flags are set as for a subtraction. This is an undocumented instruction.
+    ///
+    fn op_isc(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.op_sbc(result);
+        result
+    }
+
     /// Tests bits in the value together with the accumulator. Sets the zero flag if
     /// the bitwise AND of the value and the accumulator is zero, and sets the negative
     /// and overflow flags from the same bits in the value.
@@ -1265,6 +1971,101 @@ impl C6502 {
         result
     }
 
+    /// Shift the value left by one bit, OR the result into the accumulator, and return the
+    /// result. This is an undocumented instruction.
+    ///
+    fn op_slo(&mut self, value: u8) -> u8 {
+        let result = self.op_asl(value);
+        self.op_ora(result);
+        result
+    }
+
+    /// Rotate the value left by one bit through the carry flag, AND the result into the
+    /// accumulator, and return the result. This is an undocumented instruction.
+    ///
+    fn op_rla(&mut self, value: u8) -> u8 {
+        let result = self.op_rol(value);
+        self.op_and(result);
+        result
+    }
+
+    /// Shift the value right by one bit, XOR the result into the accumulator, and return the
+    /// result. This is an undocumented instruction.
+    ///
+    fn op_sre(&mut self, value: u8) -> u8 {
+        let result = self.op_lsr(value);
+        self.op_eor(result);
+        result
+    }
+
+    /// Rotate the value right by one bit through the carry flag, add the result to the
+    /// accumulator with the carry produced by the rotation, and return the result. This is an
+    /// undocumented instruction.
+    ///
+    fn op_rra(&mut self, value: u8) -> u8 {
+        let result = self.op_ror(value);
+        self.op_adc(result);
+        result
+    }
+
+    /// AND the value into the accumulator, setting the zero and negative flags as appropriate,
+    /// and copy bit 7 of the result into the carry flag. This is an undocumented instruction.
+    ///
+    fn op_anc(&mut self, value: u8) {
+        self.op_and(value);
+        self.set_carry(self.ac & 0x80 != 0);
+    }
+
+    /// AND the value into the accumulator, then shift the accumulator right by one bit. This is
+    /// an undocumented instruction.
+    ///
+    fn op_alr(&mut self, value: u8) {
+        self.ac &= value;
+        self.ac = self.op_lsr(self.ac);
+    }
+
+    /// AND the value into the accumulator, then rotate the accumulator right by one bit through
+    /// the carry flag. This is an undocumented instruction, and its flags don't follow ROR: the
+    /// carry flag is set from bit 6 of the result, and the overflow flag from bit 6 XOR bit 5.
+    ///
+    /// Decimal mode isn't emulated; the binary result is used regardless of the BCD flag.
+    ///
+    fn op_arr(&mut self, value: u8) {
+        let carry_in = if self.p & Self::SR_CARRY != 0 { 0x80 } else { 0 };
+        self.ac = ((self.ac & value) >> 1) | carry_in;
+        self.set_nz(self.ac);
+        self.set_carry(self.ac & 0x40 != 0);
+        self.set_overflow(((self.ac >> 6) ^ (self.ac >> 5)) & 0x01 != 0);
+    }
+
+    /// Subtract the value from the accumulator ANDed with the X register, without borrow, and
+    /// store the result in the X register. Flags are set as for a compare. This is an
+    /// undocumented instruction.
+    ///
+    fn op_sbx(&mut self, value: u8) {
+        let (result, borrow) = (self.ac & self.x).overflowing_sub(value);
+        self.x = result;
+        self.set_carry(!borrow);
+        self.set_nz(result);
+    }
+
+    /// AND the value with the X register and the accumulator, ORed with the magic constant, and
+    /// load the result into the accumulator. This is an unstable undocumented instruction.
+    ///
+    fn op_ane(&mut self, value: u8) {
+        self.ac = (self.ac | self.magic) & self.x & value;
+        self.set_nz(self.ac);
+    }
+
+    /// AND the value with the accumulator, ORed with the magic constant, and load the result
+    /// into both the accumulator and the X register. This is an unstable undocumented instruction.
+    ///
+    fn op_lxa(&mut self, value: u8) {
+        self.ac = (self.ac | self.magic) & value;
+        self.x = self.ac;
+        self.set_nz(self.ac);
+    }
+
     /// Loads the value into the accumulator, and sets the zero and negative flags as appropriate.
     ///
     fn op_lda(&mut self, value: u8) {
@@ -1279,6 +2080,15 @@ impl C6502 {
         self.set_nz(self.x);
     }
 
+    /// Loads the value into both the accumulator and the X register, and sets the zero and
+    /// negative flags as appropriate. This is an undocumented instruction.
+    ///
+    fn op_lax(&mut self, value: u8) {
+        self.ac = value;
+        self.x = value;
+        self.set_nz(value);
+    }
+
     /// Loads the value into the Y register, and sets the zero and negative flags as appropriate.
     ///
     fn op_ldy(&mut self, value: u8) {
@@ -1293,7 +2103,7 @@ impl C6502 {
     /// value.
     ///
     fn op_adc(&mut self, value: u8) {
-        if self.p & Self::SR_BCD == 0 {
+        if !self.is_decimal() {
             let (mut result, mut carry) = self.ac.overflowing_add(value);
             if (self.p & Self::SR_CARRY) != 0 {
                 if result == 0xFF {
@@ -1309,41 +2119,73 @@ impl C6502 {
             self.set_carry(carry);
             self.set_nz(self.ac);
         } else {
-            let d1 = bcd_add_digits!(self.ac & 0x0F, value & 0x0F, self.p & Self::SR_CARRY);
-            let d2 = bcd_add_digits!((self.ac >> 4), (value >> 4), d1 >> 4);
-            self.ac = (d1 & 0x0F) | (d2 << 4);
-            self.set_carry((d2 & 0x10) != 0);
+            // This follows the NMOS 6502's adder: each digit is added in binary, and adjusted by
+            // 6 if it's over 9. Digits that aren't valid BCD give the same odd results as on the
+            // hardware. The zero flag comes from the binary sum, and the negative and overflow
+            // flags from the sum before the high digit is adjusted.
+            let carry = (self.p & Self::SR_CARRY) as u16;
+            let binary = self.ac.wrapping_add(value).wrapping_add(carry as u8);
+            let mut lo = (self.ac & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+            if lo >= 0x0A {
+                lo = ((lo + 0x06) & 0x0F) + 0x10;
+            }
+            let mut sum = (self.ac & 0xF0) as u16 + (value & 0xF0) as u16 + lo;
+            let intermediate = sum as u8;
+            if sum >= 0xA0 {
+                sum += 0x60;
+            }
+            self.set_overflow(((self.ac ^ intermediate) & (value ^ intermediate) & 0x80) != 0);
+            self.ac = sum as u8;
+            self.set_carry(sum >= 0x100);
+            self.set_nz(binary);
+            self.p = self.p & !Self::SR_NEGATIVE | intermediate & Self::SR_NEGATIVE;
         }
     }
 
-    /// Adds the value to the accumulator, setting the zero, negative, carry, and overflow flags
-    /// as appropriate.
+    /// Whether ADC and SBC work in decimal: the decimal flag is set, and the CPU has decimal
+    /// mode (see `C6502::new_2a03`).
     ///
-    /// The overflow flag is set if a signed addition would result in an overflow of the signed
+    fn is_decimal(&self) -> bool {
+        self.decimal_mode && self.p & Self::SR_BCD != 0
+    }
+
+    /// Subtracts the value from the accumulator, setting the zero, negative, carry, and overflow
+    /// flags as appropriate.
+    ///
+    /// The overflow flag is set if a signed subtraction would result in an overflow of the signed
     /// value.
     ///
+    /// In decimal mode, the NMOS 6502 sets every flag exactly as it would for the binary
+    /// subtraction; only the result differs.
+    ///
     fn op_sbc(&mut self, value: u8) {
-        if self.p & Self::SR_BCD == 0 {
-            let (mut result, mut borrow) = self.ac.overflowing_sub(value);
-            if (self.p & Self::SR_CARRY) == 0 {
-                if result == 0x00 {
-                    result = 0xFF;
-                    borrow = true;
-                } else {
-                    result -= 1;
-                }
+        let ac = self.ac;
+        let borrow_in = if (self.p & Self::SR_CARRY) == 0 { 1 } else { 0 };
+        let (mut result, mut borrow) = self.ac.overflowing_sub(value);
+        if borrow_in != 0 {
+            if result == 0x00 {
+                result = 0xFF;
+                borrow = true;
+            } else {
+                result -= 1;
             }
-            let overflow = ((self.ac ^ result) & ((255 - value) ^ result) & 0x80) != 0;
-            self.ac = result;
-            self.set_overflow(overflow);
-            self.set_carry(!borrow);
-            self.set_nz(self.ac);
-        } else {
-            let borrow = if (self.p & Self::SR_CARRY) == 0 { 1 } else { 0 };
-            let d1 = bcd_add_digits!(self.ac & 0x0F, 10 - ((value & 0x0F) + borrow), 0);
-            let d2 = bcd_add_digits!((self.ac >> 4), 10 - ((value >> 4) + (1 - (d1 >> 4))), 0);
-            self.ac = (d1 & 0x0F) | (d2 << 4);
-            self.set_carry((d2 & 0x10) != 0);
+        }
+        let overflow = ((self.ac ^ result) & ((255 - value) ^ result) & 0x80) != 0;
+        self.ac = result;
+        self.set_overflow(overflow);
+        self.set_carry(!borrow);
+        self.set_nz(self.ac);
+        if self.is_decimal() {
+            // As with ADC, each digit is subtracted in binary, and adjusted by 6 if it borrowed.
+            let mut lo = (ac & 0x0F) as i16 - (value & 0x0F) as i16 - borrow_in;
+            if lo < 0 {
+                lo = ((lo - 0x06) & 0x0F) - 0x10;
+            }
+            let mut difference = (ac & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+            if difference < 0 {
+                difference -= 0x60;
+            }
+            self.ac = difference as u8;
         }
     }
 
@@ -1386,6 +2228,58 @@ impl C6502 {
         self.x
     }
 
+    /// Returns the accumulator ANDed with the X register, for storage. No flags are affected.
+    /// This is an undocumented instruction.
+    ///
+    fn op_sax(&mut self) -> u8 {
+        self.ac & self.x
+    }
+
+    /// Returns the accumulator ANDed with the X register, for storage with the SH* quirks (see
+    /// `C6502::sh_value`). This is an unstable undocumented instruction.
+    ///
+    fn op_sha(&mut self) -> u8 {
+        self.sh_value(self.ac & self.x)
+    }
+
+    /// Returns the X register, for storage with the SH* quirks (see `C6502::sh_value`). This is
+    /// an unstable undocumented instruction.
+    ///
+    fn op_shx(&mut self) -> u8 {
+        self.sh_value(self.x)
+    }
+
+    /// Returns the Y register, for storage with the SH* quirks (see `C6502::sh_value`). This is
+    /// an unstable undocumented instruction.
+    ///
+    fn op_shy(&mut self) -> u8 {
+        self.sh_value(self.y)
+    }
+
+    /// Sets the stack pointer to the accumulator ANDed with the X register, and returns it for
+    /// storage with the SH* quirks (see `C6502::sh_value`). This is an unstable undocumented
+    /// instruction.
+    ///
+    fn op_tas(&mut self) -> u8 {
+        self.sp = self.ac & self.x;
+        self.sh_value(self.sp)
+    }
+
+    /// Returns the value an SH* instruction stores: `value` ANDed with one more than the high
+    /// byte of the base address, before indexing. If indexing crossed a page, the stored value
+    /// also replaces the high byte of the effective address, so the store lands somewhere else.
+    ///
+    /// The indexed addressing modes leave the carry into the high byte in `extra_addr`.
+    ///
+    fn sh_value(&mut self, value: u8) -> u8 {
+        let base_hi = hi_byte!(self.addr.wrapping_sub(self.extra_addr));
+        let result = value & base_hi.wrapping_add(1);
+        if self.extra_addr != 0 {
+            set_hi_byte!(&mut self.addr, result);
+        }
+        result
+    }
+
     /// Returns the value in the Y register, for storage.
     ///
     fn op_sty(&mut self) -> u8 {
@@ -1424,39 +2318,137 @@ impl C6502 {
     }
 }
 
+impl Resettable for C6502 {
+    /// Both kinds of reset restart the CPU through the reset vector. A hard reset also returns
+    /// the registers to their power-on values.
+    ///
+    fn reset(&mut self, kind: ResetKind) {
+        if kind == ResetKind::Hard {
+            self.pc = 0x00FF;
+            self.ac = 0xAA;
+            self.x = 0x00;
+            self.y = 0x00;
+            self.p = 0x00;
+            self.sp = 0xFF;
+        }
+        C6502::reset(self);
+    }
+}
+
+/// Saves the registers, and whether the CPU is running. The state should be saved at an
+/// instruction boundary, and restoring it restarts the CPU at the start of an instruction.
+///
+impl Stateful for C6502 {
+    fn state_name(&self) -> &str {
+        "cpu"
+    }
+
+    fn state_version(&self) -> u16 {
+        2
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.restore(&CpuSnapshot::from_bytes(data)?);
+        Ok(())
+    }
+
+    fn migrate_state(&self, from_version: u16, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        // Version 1 held the registers and state, and was only taken between instructions.
+        let [pc_lo, pc_hi, ac, x, y, p, sp, state]: [u8; 8] = match from_version {
+            1 => data.try_into().map_err(|data: Vec<u8>| format!("expected 8 bytes, found {}", data.len()))?,
+            _ => return Err(format!("no migration from version {}", from_version)),
+        };
+        let pc = u16::from_le_bytes([pc_lo, pc_hi]);
+        let snapshot = CpuSnapshot {
+            registers: Registers { pc, ac, x, y, p, sp },
+            state: CpuSnapshot::decode_state(state)?,
+            cycle: 1,
+            total_cycles: 0,
+            ins_pc: pc,
+            ins_start: 0,
+            opcode: 0,
+            value: 0,
+            addr: 0,
+            extra_addr: 0,
+            reset_line: false,
+            irq_line: false,
+            nmi_line: false,
+            so_line: false,
+            nmi_pending: false,
+            in_nmi: false,
+            interrupt: None,
+            polled: None,
+            sampled: None,
+            io_direction: 0,
+            io_data: 0,
+        };
+        Ok(snapshot.to_bytes())
+    }
+}
+
 impl AsyncComponent for C6502 {
     fn run(&mut self, stop: Arc<AtomicBool>) {
-        let mut cycles = 0;
-        let mut start = Instant::now();
-        loop {
-            if cycles == 0 {
-                start = Instant::now();
-            }
-            let signal = self.phi0_in.recv();
-            if stop.load(Ordering::Relaxed) {
-                break;
-            }
+        self.stats.start();
+        // Catch a panic, e.g. on an illegal instruction, so the CPU can record it and shut down
+        // tidily before passing it on.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut cycles = 0;
+            // Stop once the clock has, too, rather than waiting for the stop signal, and check
+            // for the stop signal every so often even if the clock is paused.
+            loop {
+                let received = self.phi0_in.recv_timeout(STOP_POLL_INTERVAL);
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let signal = match received {
+                    Ok(signal) => signal,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
 
-            self.phi1_out.send(!signal);
-            self.phi2_out.send(signal);
-            if signal {
-                self.step();
-                cycles += 1;
-            } else {
-            }
+                // Apply every change of the control lines since the last edge, so a short pulse isn't
+                // missed.
+                while let Some(level) = self.reset_in.try_recv() {
+                    self.set_reset(!level);
+                }
+                while let Some(level) = self.nmi_in.try_recv() {
+                    self.set_nmi(!level);
+                }
+                while let Some(level) = self.irq_in.try_recv() {
+                    self.set_irq(!level);
+                }
+                while let Some(level) = self.rdy_in.try_recv() {
+                    self.set_rdy(level);
+                }
+                while let Some(level) = self.so_in.try_recv() {
+                    self.set_so(!level);
+                }
 
-            // TODO: Handle interrupts before next clock cycle
+                if self.clock_edge(signal).is_some() {
+                    cycles += 1;
+                    self.stats.cycles.store(cycles, Ordering::Relaxed);
+                }
+            }
+        }));
+        if let Err(payload) = &result {
+            self.state = CpuState::Faulted;
+            *self.stats.fault.lock().unwrap() = Some(panic_message(payload.as_ref()));
+        }
+        if let Some(Err(e)) = self.trace.as_mut().map(|trace| trace.flush()) {
+            self.stop_trace(e);
+        }
+        self.stats.stop();
+        if let Err(payload) = result {
+            panic::resume_unwind(payload);
         }
-        let elapsed = start.elapsed();
-        println!(
-            "Executed {} cycles in {} ms, speed {} MHz",
-            cycles,
-            elapsed.as_millis(),
-            cycles as f64 / elapsed.as_millis() as f64 / 1000.0
-        );
     }
 }
 
+#[derive(Copy, Clone)]
 enum Op {
     Read(fn(&mut C6502, u8)),
     ReadWrite(fn(&mut C6502, u8) -> u8),
@@ -1470,11 +2462,323 @@ impl Op {
     }
 }
 
+/// The complete state of a CPU, down to the cycle of the instruction in progress, taken with
+/// `C6502::snapshot` to restore later with `C6502::restore`.
+///
+/// With the `serde` feature, snapshots can be serialized.
+///
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub registers: Registers,
+    pub state: CpuState,
+    pub cycle: usize,
+    pub total_cycles: u64,
+    ins_pc: u16,
+    ins_start: u64,
+    opcode: u8,
+    value: u8,
+    addr: u16,
+    extra_addr: u16,
+    reset_line: bool,
+    irq_line: bool,
+    nmi_line: bool,
+    so_line: bool,
+    nmi_pending: bool,
+    in_nmi: bool,
+    interrupt: Option<Interrupt>,
+    polled: Option<Interrupt>,
+    sampled: Option<Interrupt>,
+    io_direction: u8,
+    io_data: u8,
+}
+
+impl CpuSnapshot {
+    const ENCODED_LEN: usize = 42;
+
+    /// Encode the snapshot as the CPU's save state chunk. Multi-byte values are little-endian.
+    ///
+    fn to_bytes(&self) -> Vec<u8> {
+        let r = &self.registers;
+        let state = match self.state {
+            CpuState::Off => 0,
+            CpuState::Resetting => 1,
+            CpuState::Running => 2,
+            CpuState::Faulted => 3,
+        };
+        let interrupt = |i: Option<Interrupt>| match i {
+            None => 0,
+            Some(Interrupt::Nmi) => 1,
+            Some(Interrupt::Irq) => 2,
+        };
+        let lines = [self.reset_line, self.irq_line, self.nmi_line, self.so_line, self.nmi_pending, self.in_nmi]
+            .iter()
+            .enumerate()
+            .fold(0u8, |lines, (i, &line)| lines | (line as u8) << i);
+
+        let mut bytes = Vec::with_capacity(Self::ENCODED_LEN);
+        bytes.extend_from_slice(&r.pc.to_le_bytes());
+        bytes.extend_from_slice(&[r.ac, r.x, r.y, r.p, r.sp, state]);
+        bytes.extend_from_slice(&(self.cycle as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.total_cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.ins_pc.to_le_bytes());
+        bytes.extend_from_slice(&self.ins_start.to_le_bytes());
+        bytes.extend_from_slice(&[self.opcode, self.value]);
+        bytes.extend_from_slice(&self.addr.to_le_bytes());
+        bytes.extend_from_slice(&self.extra_addr.to_le_bytes());
+        bytes.extend_from_slice(&[lines, interrupt(self.interrupt), interrupt(self.polled), interrupt(self.sampled)]);
+        bytes.extend_from_slice(&[self.io_direction, self.io_data]);
+        bytes
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != Self::ENCODED_LEN {
+            return Err(format!("expected {} bytes, found {}", Self::ENCODED_LEN, data.len()));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        let interrupt = |i: usize| match data[i] {
+            0 => Ok(None),
+            1 => Ok(Some(Interrupt::Nmi)),
+            2 => Ok(Some(Interrupt::Irq)),
+            n => Err(format!("invalid interrupt {}", n)),
+        };
+        let line = |i: u8| data[36] & (1 << i) != 0;
+        Ok(Self {
+            registers: Registers { pc: u16_at(0), ac: data[2], x: data[3], y: data[4], p: data[5], sp: data[6] },
+            state: Self::decode_state(data[7])?,
+            cycle: u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize,
+            total_cycles: u64_at(12),
+            ins_pc: u16_at(20),
+            ins_start: u64_at(22),
+            opcode: data[30],
+            value: data[31],
+            addr: u16_at(32),
+            extra_addr: u16_at(34),
+            reset_line: line(0),
+            irq_line: line(1),
+            nmi_line: line(2),
+            so_line: line(3),
+            nmi_pending: line(4),
+            in_nmi: line(5),
+            interrupt: interrupt(37)?,
+            polled: interrupt(38)?,
+            sampled: interrupt(39)?,
+            io_direction: data[40],
+            io_data: data[41],
+        })
+    }
+
+    fn decode_state(state: u8) -> Result<CpuState, String> {
+        match state {
+            0 => Ok(CpuState::Off),
+            1 => Ok(CpuState::Resetting),
+            2 => Ok(CpuState::Running),
+            3 => Ok(CpuState::Faulted),
+            _ => Err(format!("invalid CPU state {}", state)),
+        }
+    }
+}
+
+/// A handler for a trap set with `C6502::add_trap`.
+///
+pub type TrapHandler = Box<dyn FnMut(&mut Registers, &Memory) -> TrapAction + Send>;
+
+/// Where execution carries on after a trap handler runs.
+///
+/// * `FallThrough` executes the instruction at the PC in the handler's registers: the trap's
+///   address as usual, or wherever the handler set the PC to.
+/// * `Resume` carries on at the given address.
+/// * `Return` returns from the subroutine containing the trap, as RTS would, for a handler that
+///   replaces a whole subroutine.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TrapAction {
+    FallThrough,
+    Resume(u16),
+    Return,
+}
+
+/// The programmer-visible registers of the CPU.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub pc: u16,
+    pub ac: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+}
+
+impl Registers {
+    fn pack(&self) -> u64 {
+        (self.pc as u64) << 40
+            | (self.ac as u64) << 32
+            | (self.x as u64) << 24
+            | (self.y as u64) << 16
+            | (self.p as u64) << 8
+            | self.sp as u64
+    }
+
+    fn unpack(v: u64) -> Self {
+        Self {
+            pc: (v >> 40) as u16,
+            ac: (v >> 32) as u8,
+            x: (v >> 24) as u8,
+            y: (v >> 16) as u8,
+            p: (v >> 8) as u8,
+            sp: v as u8,
+        }
+    }
+}
+
+/// A lock-free view of the CPU's registers, shared between the CPU and any number of readers.
+///
+/// The CPU publishes its registers at the start of each instruction, so a snapshot is always
+/// consistent with an instruction boundary, and is at most one instruction behind the CPU.
+///
+#[derive(Clone, Default)]
+pub struct SharedRegisters(Arc<AtomicU64>);
+
+impl SharedRegisters {
+    pub fn load(&self) -> Registers {
+        Registers::unpack(self.0.load(Ordering::Acquire))
+    }
+
+    fn store(&self, registers: Registers) {
+        self.0.store(registers.pack(), Ordering::Release);
+    }
+}
+
+/// A reset requested of the CPU from another thread (see `C6502::reset_handle`), shared between
+/// the CPU and any number of requesters. Only the latest request is kept.
+///
+#[derive(Clone, Default)]
+pub struct ResetHandle(Arc<AtomicU8>);
+
+impl ResetHandle {
+    const NONE: u8 = 0;
+    const SOFT: u8 = 1;
+    const HARD: u8 = 2;
+
+    #[inline(always)]
+    fn take(&self) -> Option<ResetKind> {
+        if self.0.load(Ordering::Relaxed) == Self::NONE {
+            return None;
+        }
+        match self.0.swap(Self::NONE, Ordering::AcqRel) {
+            Self::SOFT => Some(ResetKind::Soft),
+            Self::HARD => Some(ResetKind::Hard),
+            _ => None,
+        }
+    }
+}
+
+impl Resettable for ResetHandle {
+    fn reset(&mut self, kind: ResetKind) {
+        let request = if kind == ResetKind::Hard { Self::HARD } else { Self::SOFT };
+        self.0.store(request, Ordering::Release);
+    }
+}
+
+/// Performance statistics for a CPU running as an async component, shared between the CPU and
+/// any number of readers, e.g. to show the effective clock speed while the machine runs.
+///
+/// The elapsed time runs from when the CPU starts running to when it stops, or to now while it
+/// runs.
+///
+#[derive(Debug, Default)]
+pub struct CpuStats {
+    cycles: AtomicU64,
+    running: AtomicBool,
+    times: Mutex<(Option<Instant>, Option<Instant>)>,
+    fault: Mutex<Option<String>>,
+    trace_error: Mutex<Option<String>>,
+}
+
+impl CpuStats {
+    pub fn cycles(&self) -> u64 {
+        self.cycles.load(Ordering::Relaxed)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// The message of the panic that stopped the CPU, if it faulted.
+    ///
+    pub fn fault(&self) -> Option<String> {
+        self.fault.lock().unwrap().clone()
+    }
+
+    /// The error that stopped the CPU's trace sink, if it failed.
+    ///
+    pub fn trace_error(&self) -> Option<String> {
+        self.trace_error.lock().unwrap().clone()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match *self.times.lock().unwrap() {
+            (Some(start), Some(end)) => end - start,
+            (Some(start), None) => start.elapsed(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// The effective clock speed, in MHz.
+    ///
+    pub fn mhz(&self) -> f64 {
+        let secs = self.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            self.cycles() as f64 / secs / 1_000_000.0
+        } else {
+            0.0
+        }
+    }
+
+    fn start(&self) {
+        self.cycles.store(0, Ordering::Relaxed);
+        *self.times.lock().unwrap() = (Some(Instant::now()), None);
+        self.running.store(true, Ordering::Relaxed);
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        self.times.lock().unwrap().1 = Some(Instant::now());
+    }
+}
+
+impl fmt::Display for CpuStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Executed {} cycles in {} ms, speed {:.2} MHz",
+            self.cycles(),
+            self.elapsed().as_millis(),
+            self.mhz()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Interrupt {
+    Nmi,
+    Irq,
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CpuState {
     Off,
     Resetting,
     Running,
+    /// The CPU panicked while running as an async component, and has stopped. The panic's
+    /// message is in its `CpuStats`.
+    ///
+    Faulted,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -1484,6 +2788,86 @@ pub enum CpuAction {
     CompleteAndFetch,
 }
 
+/// An instruction run by `C6502::run_instruction`.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct InstructionResult {
+    pub pc: u16,
+    pub opcode: u8,
+    pub cycles: u64,
+}
+
+/// How a CPU created by `C6502::new_with` starts.
+///
+/// * `Off` waits for a reset, as `C6502::new` does.
+/// * `Reset` runs the reset sequence from the first cycle, as if RESET had been pulsed at power-up.
+/// * `Run` fetches the first instruction from the configured PC straight away.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CpuStart {
+    Off,
+    Reset,
+    Run,
+}
+
+/// The registers a CPU created by `C6502::new_with` powers up with, and how it starts. The
+/// default is what `C6502::new` uses: PC $00FF, AC $AA, and the stack pointer $00, as on the
+/// hardware, which the reset sequence takes down to the familiar $FD.
+///
+/// The break and unused bits of `p` are ignored, since the CPU doesn't hold them: they're
+/// always pushed set, apart from the break bit for a hardware interrupt.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CpuConfig {
+    pub pc: u16,
+    pub ac: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub start: CpuStart,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self { pc: 0x00FF, ac: 0xAA, x: 0x00, y: 0x00, p: 0x00, sp: 0x00, start: CpuStart::Off }
+    }
+}
+
+/// A bus access made by the CPU: the address, the data read or written, and which it was.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct BusActivity {
+    pub addr: u16,
+    pub data: u8,
+    pub is_write: bool,
+}
+
+/// A condition for `C6502::run_until` to stop on.
+///
+/// * `PcEquals` stops when the next instruction to run is at the given address.
+/// * `BrkExecuted` stops once a BRK instruction has run, i.e. with the PC at the BRK handler.
+///   Interrupts, which the CPU runs as BRKs, don't count.
+/// * `MaxCycles` stops once instructions have taken at least the given number of cycles.
+/// * `PcUnchanged` stops once an instruction jumps or branches to itself, the way test suites
+///   for the 6502 signal that they're done.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum StopCondition {
+    PcEquals(u16),
+    BrkExecuted,
+    MaxCycles(usize),
+    PcUnchanged,
+}
+
+/// Why `C6502::run_until` stopped, and the cycles it ran for.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct RunResult {
+    pub condition: StopCondition,
+    pub cycles: u64,
+}
+
 #[cfg(test)]
 #[path = "./c6502_tests.rs"]
 mod tests;
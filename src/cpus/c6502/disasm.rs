@@ -0,0 +1,138 @@
+use std::iter;
+
+use super::opcodes::{opcode_info, AddressingMode};
+use crate::core::memory::Memory;
+use AddressingMode::*;
+
+/// Illegal opcodes, which the CPU doesn't implement, but which turn up in code written
+/// for real hardware often enough to be worth naming. The opcodes that jam the CPU are left as
+/// unknown bytes, since they're almost always data.
+///
+const UNSTABLE: [(u8, &str, AddressingMode); 2] = [(0xBB, "LAS", AbsoluteY), (0xEB, "SBC", Immediate)];
+
+fn decode(opcode: u8) -> Option<(&'static str, AddressingMode)> {
+    match opcode_info(opcode) {
+        Some(info) => Some((info.mnemonic, info.mode)),
+        None => UNSTABLE.iter().find(|(op, ..)| *op == opcode).map(|&(_, mnemonic, mode)| (mnemonic, mode)),
+    }
+}
+
+fn read(memory: &Memory, addr: u16) -> u8 {
+    let mut byte = [0u8];
+    memory.read_block(addr, &mut byte);
+    byte[0]
+}
+
+/// Disassemble the instruction at `addr`, returning it in the conventional syntax, e.g.
+/// "LDA ($80),Y", along with its length in bytes. Branch targets are given as absolute
+/// addresses, and a byte that isn't a known opcode is returned as ".byte $xx", one byte long.
+///
+/// Operands past $FFFF wrap around to $0000, as they would for the CPU.
+///
+pub fn disassemble(memory: &Memory, addr: u16) -> (String, u8) {
+    let opcode = read(memory, addr);
+    let Some((mnemonic, mode)) = decode(opcode) else {
+        return (format!(".byte ${:02X}", opcode), 1);
+    };
+    let lo = read(memory, addr.wrapping_add(1));
+    let word = u16::from_le_bytes([lo, read(memory, addr.wrapping_add(2))]);
+    let operand = match mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", lo),
+        ZeroPage => format!("${:02X}", lo),
+        ZeroPageX => format!("${:02X},X", lo),
+        ZeroPageY => format!("${:02X},Y", lo),
+        Absolute => format!("${:04X}", word),
+        AbsoluteX => format!("${:04X},X", word),
+        AbsoluteY => format!("${:04X},Y", word),
+        Indirect => format!("(${:04X})", word),
+        IndexedIndirect => format!("(${:02X},X)", lo),
+        IndirectIndexed => format!("(${:02X}),Y", lo),
+        Relative => format!("${:04X}", addr.wrapping_add(2).wrapping_add(lo as i8 as u16)),
+    };
+    let text = if operand.is_empty() { mnemonic.to_string() } else { format!("{} {}", mnemonic, operand) };
+    (text, mode.instruction_len())
+}
+
+/// Returns the bytes of the instruction at `addr`: the opcode followed by its operands.
+///
+pub fn instruction_bytes(memory: &Memory, addr: u16) -> Vec<u8> {
+    let len = decode(read(memory, addr)).map_or(1, |(_, mode)| mode.instruction_len());
+    (0..len as u16).map(|i| read(memory, addr.wrapping_add(i))).collect()
+}
+
+/// Disassemble the instructions from `start` to `end` inclusive, decoded in a single pass from
+/// `start`, as lines giving each instruction's address and bytes, e.g.
+/// "$0400  B1 80     LDA ($80),Y". Each line comes with the instruction's address, for callers
+/// that annotate the listing.
+///
+pub fn disassemble_range(memory: &Memory, start: u16, end: u16) -> impl Iterator<Item = (u16, String)> + '_ {
+    let mut addr = start as u32;
+    iter::from_fn(move || {
+        if addr > end as u32 {
+            return None;
+        }
+        let (text, len) = disassemble(memory, addr as u16);
+        let hex: Vec<String> = instruction_bytes(memory, addr as u16).iter().map(|b| format!("{:02X}", b)).collect();
+        let line = format!("${:04X}  {:<8}  {}", addr, hex.join(" "), text);
+        let line_addr = addr as u16;
+        addr += len as u32;
+        Some((line_addr, line))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addressing_modes() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0xEA], "NOP"),
+            (&[0x0A], "ASL A"),
+            (&[0xA9, 0x01], "LDA #$01"),
+            (&[0xA5, 0x80], "LDA $80"),
+            (&[0xB5, 0x80], "LDA $80,X"),
+            (&[0xB6, 0x80], "LDX $80,Y"),
+            (&[0xAD, 0x34, 0x12], "LDA $1234"),
+            (&[0xBD, 0x34, 0x12], "LDA $1234,X"),
+            (&[0xB9, 0x34, 0x12], "LDA $1234,Y"),
+            (&[0x6C, 0xFC, 0xFF], "JMP ($FFFC)"),
+            (&[0xA1, 0x80], "LDA ($80,X)"),
+            (&[0xB1, 0x80], "LDA ($80),Y"),
+            (&[0xD0, 0x10], "BNE $0412"),
+            (&[0xD0, 0xFE], "BNE $0400"),
+            (&[0xA7, 0x80], "LAX $80"),
+            (&[0x9E, 0x00, 0x30], "SHX $3000,Y"),
+            (&[0xEB, 0x01], "SBC #$01"),
+            (&[0x02], ".byte $02"),
+        ];
+        let memory = Memory::new();
+        for (bytes, text) in cases {
+            memory.write_block(0x0400, bytes);
+            assert_eq!(disassemble(&memory, 0x0400), (text.to_string(), bytes.len() as u8), "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn range() {
+        let memory = Memory::new();
+        memory.write_block(0x0400, &[0xA2, 0x00, 0xB1, 0x80, 0x02, 0x4C, 0x00, 0x04]);
+        let lines: Vec<String> = disassemble_range(&memory, 0x0400, 0x0405).map(|(_, line)| line).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "$0400  A2 00     LDX #$00",
+                "$0402  B1 80     LDA ($80),Y",
+                "$0404  02        .byte $02",
+                "$0405  4C 00 04  JMP $0400",
+            ]
+        );
+
+        // A range that runs to the end of memory stops there.
+        memory.write_block(0xFFFE, &[0xEA, 0xEA]);
+        let addrs: Vec<u16> = disassemble_range(&memory, 0xFFFE, 0xFFFF).map(|(addr, _)| addr).collect();
+        assert_eq!(addrs, vec![0xFFFE, 0xFFFF]);
+    }
+}
@@ -0,0 +1,323 @@
+use super::opcodes::{AddressingMode, OPCODES};
+use super::{CpuAction, Op, C6502};
+use AddressingMode::*;
+
+/// How an entry in the table below is executed.
+///
+/// * `Op` applies an operation to the operand, through the opcode's addressing mode as given
+///   by `OPCODES`.
+/// * `Branch` branches if the test passes.
+/// * `Special` is an instruction with its own cycle sequence, such as BRK or JSR.
+///
+#[derive(Copy, Clone)]
+enum Exec {
+    Op(Op),
+    Branch(fn(&C6502) -> bool),
+    Special(fn(&mut C6502) -> CpuAction),
+}
+
+/// An entry of `OPCODE_TABLE`: an `Exec` with its addressing mode resolved to the function that
+/// runs the mode's cycles.
+///
+#[derive(Copy, Clone)]
+pub(super) enum Dispatch {
+    Mode(fn(&mut C6502, Op) -> CpuAction, Op),
+    Branch(fn(&C6502) -> bool),
+    Special(fn(&mut C6502) -> CpuAction),
+}
+
+/// How the CPU executes each opcode, or `None` for the ones it doesn't implement. Indexed by
+/// opcode, so that dispatching an instruction's cycle is a single lookup.
+///
+pub(super) static OPCODE_TABLE: [Option<Dispatch>; 256] = build_table(&[
+    (0x00, Exec::Special(C6502::do_brk)),
+    (0x01, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x03, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x04, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x05, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x06, Exec::Op(Op::ReadWrite(C6502::op_asl))),
+    (0x07, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x08, Exec::Special(C6502::do_php)),
+    (0x09, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x0A, Exec::Op(Op::ReadWrite(C6502::op_asl))),
+    (0x0B, Exec::Op(Op::Read(C6502::op_anc))),
+    (0x0C, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x0D, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x0E, Exec::Op(Op::ReadWrite(C6502::op_asl))),
+    (0x0F, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x10, Exec::Branch(C6502::br_bpl)),
+    (0x11, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x13, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x14, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x15, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x16, Exec::Op(Op::ReadWrite(C6502::op_asl))),
+    (0x17, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x18, Exec::Op(Op::Implied(C6502::op_clc))),
+    (0x19, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x1A, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x1B, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x1C, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x1D, Exec::Op(Op::Read(C6502::op_ora))),
+    (0x1E, Exec::Op(Op::ReadWrite(C6502::op_asl))),
+    (0x1F, Exec::Op(Op::ReadWrite(C6502::op_slo))),
+    (0x20, Exec::Special(C6502::do_jsr)),
+    (0x21, Exec::Op(Op::Read(C6502::op_and))),
+    (0x23, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x24, Exec::Op(Op::Read(C6502::op_bit))),
+    (0x25, Exec::Op(Op::Read(C6502::op_and))),
+    (0x26, Exec::Op(Op::ReadWrite(C6502::op_rol))),
+    (0x27, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x28, Exec::Special(C6502::do_plp)),
+    (0x29, Exec::Op(Op::Read(C6502::op_and))),
+    (0x2A, Exec::Op(Op::ReadWrite(C6502::op_rol))),
+    (0x2B, Exec::Op(Op::Read(C6502::op_anc))),
+    (0x2C, Exec::Op(Op::Read(C6502::op_bit))),
+    (0x2D, Exec::Op(Op::Read(C6502::op_and))),
+    (0x2E, Exec::Op(Op::ReadWrite(C6502::op_rol))),
+    (0x2F, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x30, Exec::Branch(C6502::br_bmi)),
+    (0x31, Exec::Op(Op::Read(C6502::op_and))),
+    (0x33, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x34, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x35, Exec::Op(Op::Read(C6502::op_and))),
+    (0x36, Exec::Op(Op::ReadWrite(C6502::op_rol))),
+    (0x37, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x38, Exec::Op(Op::Implied(C6502::op_sec))),
+    (0x39, Exec::Op(Op::Read(C6502::op_and))),
+    (0x3A, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x3B, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x3C, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x3D, Exec::Op(Op::Read(C6502::op_and))),
+    (0x3E, Exec::Op(Op::ReadWrite(C6502::op_rol))),
+    (0x3F, Exec::Op(Op::ReadWrite(C6502::op_rla))),
+    (0x40, Exec::Special(C6502::do_rti)),
+    (0x41, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x43, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x44, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x45, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x46, Exec::Op(Op::ReadWrite(C6502::op_lsr))),
+    (0x47, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x48, Exec::Special(C6502::do_pha)),
+    (0x49, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x4A, Exec::Op(Op::ReadWrite(C6502::op_lsr))),
+    (0x4B, Exec::Op(Op::Read(C6502::op_alr))),
+    (0x4C, Exec::Special(C6502::do_jmp_abs)),
+    (0x4D, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x4E, Exec::Op(Op::ReadWrite(C6502::op_lsr))),
+    (0x4F, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x50, Exec::Branch(C6502::br_bvc)),
+    (0x51, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x53, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x54, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x55, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x56, Exec::Op(Op::ReadWrite(C6502::op_lsr))),
+    (0x57, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x58, Exec::Op(Op::Implied(C6502::op_cli))),
+    (0x59, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x5A, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x5B, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x5C, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x5D, Exec::Op(Op::Read(C6502::op_eor))),
+    (0x5E, Exec::Op(Op::ReadWrite(C6502::op_lsr))),
+    (0x5F, Exec::Op(Op::ReadWrite(C6502::op_sre))),
+    (0x60, Exec::Special(C6502::do_rts)),
+    (0x61, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x63, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x64, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x65, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x66, Exec::Op(Op::ReadWrite(C6502::op_ror))),
+    (0x67, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x68, Exec::Special(C6502::do_pla)),
+    (0x69, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x6A, Exec::Op(Op::ReadWrite(C6502::op_ror))),
+    (0x6B, Exec::Op(Op::Read(C6502::op_arr))),
+    (0x6C, Exec::Special(C6502::do_jmp_abs_indirect)),
+    (0x6D, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x6E, Exec::Op(Op::ReadWrite(C6502::op_ror))),
+    (0x6F, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x70, Exec::Branch(C6502::br_bvs)),
+    (0x71, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x73, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x74, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x75, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x76, Exec::Op(Op::ReadWrite(C6502::op_ror))),
+    (0x77, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x78, Exec::Op(Op::Implied(C6502::op_sei))),
+    (0x79, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x7A, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x7B, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x7C, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x7D, Exec::Op(Op::Read(C6502::op_adc))),
+    (0x7E, Exec::Op(Op::ReadWrite(C6502::op_ror))),
+    (0x7F, Exec::Op(Op::ReadWrite(C6502::op_rra))),
+    (0x80, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x81, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x82, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x83, Exec::Op(Op::Write(C6502::op_sax))),
+    (0x84, Exec::Op(Op::Write(C6502::op_sty))),
+    (0x85, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x86, Exec::Op(Op::Write(C6502::op_stx))),
+    (0x87, Exec::Op(Op::Write(C6502::op_sax))),
+    (0x88, Exec::Op(Op::Implied(C6502::op_dey))),
+    (0x89, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0x8A, Exec::Op(Op::Implied(C6502::op_txa))),
+    (0x8B, Exec::Op(Op::Read(C6502::op_ane))),
+    (0x8C, Exec::Op(Op::Write(C6502::op_sty))),
+    (0x8D, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x8E, Exec::Op(Op::Write(C6502::op_stx))),
+    (0x8F, Exec::Op(Op::Write(C6502::op_sax))),
+    (0x90, Exec::Branch(C6502::br_bcc)),
+    (0x91, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x93, Exec::Op(Op::Write(C6502::op_sha))),
+    (0x94, Exec::Op(Op::Write(C6502::op_sty))),
+    (0x95, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x96, Exec::Op(Op::Write(C6502::op_stx))),
+    (0x97, Exec::Op(Op::Write(C6502::op_sax))),
+    (0x98, Exec::Op(Op::Implied(C6502::op_tya))),
+    (0x99, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x9A, Exec::Op(Op::Implied(C6502::op_txs))),
+    (0x9B, Exec::Op(Op::Write(C6502::op_tas))),
+    (0x9C, Exec::Op(Op::Write(C6502::op_shy))),
+    (0x9D, Exec::Op(Op::Write(C6502::op_sta))),
+    (0x9E, Exec::Op(Op::Write(C6502::op_shx))),
+    (0x9F, Exec::Op(Op::Write(C6502::op_sha))),
+    (0xA0, Exec::Op(Op::Read(C6502::op_ldy))),
+    (0xA1, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xA2, Exec::Op(Op::Read(C6502::op_ldx))),
+    (0xA3, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xA4, Exec::Op(Op::Read(C6502::op_ldy))),
+    (0xA5, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xA6, Exec::Op(Op::Read(C6502::op_ldx))),
+    (0xA7, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xA8, Exec::Op(Op::Implied(C6502::op_tay))),
+    (0xA9, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xAA, Exec::Op(Op::Implied(C6502::op_tax))),
+    (0xAB, Exec::Op(Op::Read(C6502::op_lxa))),
+    (0xAC, Exec::Op(Op::Read(C6502::op_ldy))),
+    (0xAD, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xAE, Exec::Op(Op::Read(C6502::op_ldx))),
+    (0xAF, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xB0, Exec::Branch(C6502::br_bcs)),
+    (0xB1, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xB3, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xB4, Exec::Op(Op::Read(C6502::op_ldy))),
+    (0xB5, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xB6, Exec::Op(Op::Read(C6502::op_ldx))),
+    (0xB7, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xB8, Exec::Op(Op::Implied(C6502::op_clv))),
+    (0xB9, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xBA, Exec::Op(Op::Implied(C6502::op_tsx))),
+    (0xBC, Exec::Op(Op::Read(C6502::op_ldy))),
+    (0xBD, Exec::Op(Op::Read(C6502::op_lda))),
+    (0xBE, Exec::Op(Op::Read(C6502::op_ldx))),
+    (0xBF, Exec::Op(Op::Read(C6502::op_lax))),
+    (0xC0, Exec::Op(Op::Read(C6502::op_cpy))),
+    (0xC1, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xC2, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xC3, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xC4, Exec::Op(Op::Read(C6502::op_cpy))),
+    (0xC5, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xC6, Exec::Op(Op::ReadWrite(C6502::op_dec))),
+    (0xC7, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xC8, Exec::Op(Op::Implied(C6502::op_iny))),
+    (0xC9, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xCA, Exec::Op(Op::Implied(C6502::op_dex))),
+    (0xCB, Exec::Op(Op::Read(C6502::op_sbx))),
+    (0xCC, Exec::Op(Op::Read(C6502::op_cpy))),
+    (0xCD, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xCE, Exec::Op(Op::ReadWrite(C6502::op_dec))),
+    (0xCF, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xD0, Exec::Branch(C6502::br_bne)),
+    (0xD1, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xD3, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xD4, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xD5, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xD6, Exec::Op(Op::ReadWrite(C6502::op_dec))),
+    (0xD7, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xD8, Exec::Op(Op::Implied(C6502::op_cld))),
+    (0xD9, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xDA, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xDB, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xDC, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xDD, Exec::Op(Op::Read(C6502::op_cmp))),
+    (0xDE, Exec::Op(Op::ReadWrite(C6502::op_dec))),
+    (0xDF, Exec::Op(Op::ReadWrite(C6502::op_dcp))),
+    (0xE0, Exec::Op(Op::Read(C6502::op_cpx))),
+    (0xE1, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xE2, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xE3, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xE4, Exec::Op(Op::Read(C6502::op_cpx))),
+    (0xE5, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xE6, Exec::Op(Op::ReadWrite(C6502::op_inc))),
+    (0xE7, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xE8, Exec::Op(Op::Implied(C6502::op_inx))),
+    (0xE9, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xEA, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xEC, Exec::Op(Op::Read(C6502::op_cpx))),
+    (0xED, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xEE, Exec::Op(Op::ReadWrite(C6502::op_inc))),
+    (0xEF, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xF0, Exec::Branch(C6502::br_beq)),
+    (0xF1, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xF3, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xF4, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xF5, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xF6, Exec::Op(Op::ReadWrite(C6502::op_inc))),
+    (0xF7, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xF8, Exec::Op(Op::Implied(C6502::op_sed))),
+    (0xF9, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xFA, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xFB, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+    (0xFC, Exec::Op(Op::Implied(C6502::op_nop))),
+    (0xFD, Exec::Op(Op::Read(C6502::op_sbc))),
+    (0xFE, Exec::Op(Op::ReadWrite(C6502::op_inc))),
+    (0xFF, Exec::Op(Op::ReadWrite(C6502::op_isc))),
+]);
+
+const fn mode_fn(mode: AddressingMode) -> fn(&mut C6502, Op) -> CpuAction {
+    match mode {
+        Implied => C6502::do_op_implied,
+        Accumulator => C6502::do_op_ac,
+        Immediate => C6502::do_op_immed,
+        ZeroPage => C6502::do_op_zeropage,
+        ZeroPageX => C6502::do_op_zeropage_x,
+        ZeroPageY => C6502::do_op_zeropage_y,
+        Absolute => C6502::do_op_abs,
+        AbsoluteX => C6502::do_op_abs_x,
+        AbsoluteY => C6502::do_op_abs_y,
+        IndexedIndirect => C6502::do_op_indexed_indirect,
+        IndirectIndexed => C6502::do_op_indirect_indexed,
+        Indirect | Relative => panic!("No generic sequence for the addressing mode"),
+    }
+}
+
+const fn build_table(entries: &[(u8, Exec)]) -> [Option<Dispatch>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < entries.len() {
+        let (opcode, exec) = entries[i];
+        let Some(info) = OPCODES[opcode as usize] else {
+            panic!("Opcode missing from OPCODES");
+        };
+        table[opcode as usize] = Some(match exec {
+            Exec::Op(op) => Dispatch::Mode(mode_fn(info.mode), op),
+            Exec::Branch(test) => Dispatch::Branch(test),
+            Exec::Special(f) => Dispatch::Special(f),
+        });
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_opcodes() {
+        // Every opcode the CPU describes is one it can execute, and vice versa.
+        for opcode in 0..256 {
+            assert_eq!(OPCODE_TABLE[opcode].is_some(), OPCODES[opcode].is_some(), "${:02X}", opcode);
+        }
+    }
+}
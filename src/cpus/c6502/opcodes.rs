@@ -0,0 +1,319 @@
+use AddressingMode::*;
+
+/// The addressing modes of the 6502 instruction set.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirect,
+    IndirectIndexed,
+    Relative,
+}
+
+impl AddressingMode {
+    /// Returns the length in bytes of an instruction using this addressing mode, including
+    /// the opcode.
+    ///
+    pub const fn instruction_len(self) -> u8 {
+        match self {
+            Implied | Accumulator => 1,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndexedIndirect | IndirectIndexed | Relative => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
+        }
+    }
+}
+
+/// How an instruction accesses its operand, mirroring the kinds of operations the CPU performs
+/// once the effective address is resolved (see `C6502::do_op`).
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Access {
+    Implied,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Static description of an opcode.
+///
+/// `cycles` is the number of cycles the instruction takes when no page is crossed and no
+/// branch is taken.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub access: Access,
+    pub cycles: u8,
+}
+
+/// Returns the description of an opcode, or `None` if the CPU doesn't implement it.
+///
+pub fn opcode_info(opcode: u8) -> Option<&'static OpcodeInfo> {
+    OPCODES[opcode as usize].as_ref()
+}
+
+pub static OPCODES: [Option<OpcodeInfo>; 256] = build_table(&[
+    (0x00, "BRK", Implied, Access::Implied, 7),
+    (0x01, "ORA", IndexedIndirect, Access::Read, 6),
+    (0x03, "SLO", IndexedIndirect, Access::ReadWrite, 8),
+    (0x04, "NOP", ZeroPage, Access::Implied, 3),
+    (0x05, "ORA", ZeroPage, Access::Read, 3),
+    (0x06, "ASL", ZeroPage, Access::ReadWrite, 5),
+    (0x07, "SLO", ZeroPage, Access::ReadWrite, 5),
+    (0x08, "PHP", Implied, Access::Implied, 3),
+    (0x09, "ORA", Immediate, Access::Read, 2),
+    (0x0A, "ASL", Accumulator, Access::ReadWrite, 2),
+    (0x0B, "ANC", Immediate, Access::Read, 2),
+    (0x0C, "NOP", Absolute, Access::Implied, 4),
+    (0x0D, "ORA", Absolute, Access::Read, 4),
+    (0x0E, "ASL", Absolute, Access::ReadWrite, 6),
+    (0x0F, "SLO", Absolute, Access::ReadWrite, 6),
+    (0x10, "BPL", Relative, Access::Read, 2),
+    (0x11, "ORA", IndirectIndexed, Access::Read, 5),
+    (0x13, "SLO", IndirectIndexed, Access::ReadWrite, 8),
+    (0x14, "NOP", ZeroPageX, Access::Implied, 4),
+    (0x15, "ORA", ZeroPageX, Access::Read, 4),
+    (0x16, "ASL", ZeroPageX, Access::ReadWrite, 6),
+    (0x17, "SLO", ZeroPageX, Access::ReadWrite, 6),
+    (0x18, "CLC", Implied, Access::Implied, 2),
+    (0x19, "ORA", AbsoluteY, Access::Read, 4),
+    (0x1A, "NOP", Implied, Access::Implied, 2),
+    (0x1B, "SLO", AbsoluteY, Access::ReadWrite, 7),
+    (0x1C, "NOP", AbsoluteX, Access::Implied, 4),
+    (0x1D, "ORA", AbsoluteX, Access::Read, 4),
+    (0x1E, "ASL", AbsoluteX, Access::ReadWrite, 7),
+    (0x1F, "SLO", AbsoluteX, Access::ReadWrite, 7),
+    (0x20, "JSR", Absolute, Access::Implied, 6),
+    (0x21, "AND", IndexedIndirect, Access::Read, 6),
+    (0x23, "RLA", IndexedIndirect, Access::ReadWrite, 8),
+    (0x24, "BIT", ZeroPage, Access::Read, 3),
+    (0x25, "AND", ZeroPage, Access::Read, 3),
+    (0x26, "ROL", ZeroPage, Access::ReadWrite, 5),
+    (0x27, "RLA", ZeroPage, Access::ReadWrite, 5),
+    (0x28, "PLP", Implied, Access::Implied, 4),
+    (0x29, "AND", Immediate, Access::Read, 2),
+    (0x2A, "ROL", Accumulator, Access::ReadWrite, 2),
+    (0x2B, "ANC", Immediate, Access::Read, 2),
+    (0x2C, "BIT", Absolute, Access::Read, 4),
+    (0x2D, "AND", Absolute, Access::Read, 4),
+    (0x2E, "ROL", Absolute, Access::ReadWrite, 6),
+    (0x2F, "RLA", Absolute, Access::ReadWrite, 6),
+    (0x30, "BMI", Relative, Access::Read, 2),
+    (0x31, "AND", IndirectIndexed, Access::Read, 5),
+    (0x33, "RLA", IndirectIndexed, Access::ReadWrite, 8),
+    (0x34, "NOP", ZeroPageX, Access::Implied, 4),
+    (0x35, "AND", ZeroPageX, Access::Read, 4),
+    (0x36, "ROL", ZeroPageX, Access::ReadWrite, 6),
+    (0x37, "RLA", ZeroPageX, Access::ReadWrite, 6),
+    (0x38, "SEC", Implied, Access::Implied, 2),
+    (0x39, "AND", AbsoluteY, Access::Read, 4),
+    (0x3A, "NOP", Implied, Access::Implied, 2),
+    (0x3B, "RLA", AbsoluteY, Access::ReadWrite, 7),
+    (0x3C, "NOP", AbsoluteX, Access::Implied, 4),
+    (0x3D, "AND", AbsoluteX, Access::Read, 4),
+    (0x3E, "ROL", AbsoluteX, Access::ReadWrite, 7),
+    (0x3F, "RLA", AbsoluteX, Access::ReadWrite, 7),
+    (0x40, "RTI", Implied, Access::Implied, 6),
+    (0x41, "EOR", IndexedIndirect, Access::Read, 6),
+    (0x43, "SRE", IndexedIndirect, Access::ReadWrite, 8),
+    (0x44, "NOP", ZeroPage, Access::Implied, 3),
+    (0x45, "EOR", ZeroPage, Access::Read, 3),
+    (0x46, "LSR", ZeroPage, Access::ReadWrite, 5),
+    (0x47, "SRE", ZeroPage, Access::ReadWrite, 5),
+    (0x48, "PHA", Implied, Access::Implied, 3),
+    (0x49, "EOR", Immediate, Access::Read, 2),
+    (0x4A, "LSR", Accumulator, Access::ReadWrite, 2),
+    (0x4B, "ALR", Immediate, Access::Read, 2),
+    (0x4C, "JMP", Absolute, Access::Implied, 3),
+    (0x4D, "EOR", Absolute, Access::Read, 4),
+    (0x4E, "LSR", Absolute, Access::ReadWrite, 6),
+    (0x4F, "SRE", Absolute, Access::ReadWrite, 6),
+    (0x50, "BVC", Relative, Access::Read, 2),
+    (0x51, "EOR", IndirectIndexed, Access::Read, 5),
+    (0x53, "SRE", IndirectIndexed, Access::ReadWrite, 8),
+    (0x54, "NOP", ZeroPageX, Access::Implied, 4),
+    (0x55, "EOR", ZeroPageX, Access::Read, 4),
+    (0x56, "LSR", ZeroPageX, Access::ReadWrite, 6),
+    (0x57, "SRE", ZeroPageX, Access::ReadWrite, 6),
+    (0x58, "CLI", Implied, Access::Implied, 2),
+    (0x59, "EOR", AbsoluteY, Access::Read, 4),
+    (0x5A, "NOP", Implied, Access::Implied, 2),
+    (0x5B, "SRE", AbsoluteY, Access::ReadWrite, 7),
+    (0x5C, "NOP", AbsoluteX, Access::Implied, 4),
+    (0x5D, "EOR", AbsoluteX, Access::Read, 4),
+    (0x5E, "LSR", AbsoluteX, Access::ReadWrite, 7),
+    (0x5F, "SRE", AbsoluteX, Access::ReadWrite, 7),
+    (0x60, "RTS", Implied, Access::Implied, 6),
+    (0x61, "ADC", IndexedIndirect, Access::Read, 6),
+    (0x63, "RRA", IndexedIndirect, Access::ReadWrite, 8),
+    (0x64, "NOP", ZeroPage, Access::Implied, 3),
+    (0x65, "ADC", ZeroPage, Access::Read, 3),
+    (0x66, "ROR", ZeroPage, Access::ReadWrite, 5),
+    (0x67, "RRA", ZeroPage, Access::ReadWrite, 5),
+    (0x68, "PLA", Implied, Access::Implied, 4),
+    (0x69, "ADC", Immediate, Access::Read, 2),
+    (0x6A, "ROR", Accumulator, Access::ReadWrite, 2),
+    (0x6B, "ARR", Immediate, Access::Read, 2),
+    (0x6C, "JMP", Indirect, Access::Implied, 5),
+    (0x6D, "ADC", Absolute, Access::Read, 4),
+    (0x6E, "ROR", Absolute, Access::ReadWrite, 6),
+    (0x6F, "RRA", Absolute, Access::ReadWrite, 6),
+    (0x70, "BVS", Relative, Access::Read, 2),
+    (0x71, "ADC", IndirectIndexed, Access::Read, 5),
+    (0x73, "RRA", IndirectIndexed, Access::ReadWrite, 8),
+    (0x74, "NOP", ZeroPageX, Access::Implied, 4),
+    (0x75, "ADC", ZeroPageX, Access::Read, 4),
+    (0x76, "ROR", ZeroPageX, Access::ReadWrite, 6),
+    (0x77, "RRA", ZeroPageX, Access::ReadWrite, 6),
+    (0x78, "SEI", Implied, Access::Implied, 2),
+    (0x79, "ADC", AbsoluteY, Access::Read, 4),
+    (0x7A, "NOP", Implied, Access::Implied, 2),
+    (0x7B, "RRA", AbsoluteY, Access::ReadWrite, 7),
+    (0x7C, "NOP", AbsoluteX, Access::Implied, 4),
+    (0x7D, "ADC", AbsoluteX, Access::Read, 4),
+    (0x7E, "ROR", AbsoluteX, Access::ReadWrite, 7),
+    (0x7F, "RRA", AbsoluteX, Access::ReadWrite, 7),
+    (0x80, "NOP", Immediate, Access::Implied, 2),
+    (0x81, "STA", IndexedIndirect, Access::Write, 6),
+    (0x82, "NOP", Immediate, Access::Implied, 2),
+    (0x83, "SAX", IndexedIndirect, Access::Write, 6),
+    (0x84, "STY", ZeroPage, Access::Write, 3),
+    (0x85, "STA", ZeroPage, Access::Write, 3),
+    (0x86, "STX", ZeroPage, Access::Write, 3),
+    (0x87, "SAX", ZeroPage, Access::Write, 3),
+    (0x88, "DEY", Implied, Access::Implied, 2),
+    (0x89, "NOP", Immediate, Access::Implied, 2),
+    (0x8A, "TXA", Implied, Access::Implied, 2),
+    (0x8B, "ANE", Immediate, Access::Read, 2),
+    (0x8C, "STY", Absolute, Access::Write, 4),
+    (0x8D, "STA", Absolute, Access::Write, 4),
+    (0x8E, "STX", Absolute, Access::Write, 4),
+    (0x8F, "SAX", Absolute, Access::Write, 4),
+    (0x90, "BCC", Relative, Access::Read, 2),
+    (0x91, "STA", IndirectIndexed, Access::Write, 6),
+    (0x93, "SHA", IndirectIndexed, Access::Write, 6),
+    (0x94, "STY", ZeroPageX, Access::Write, 4),
+    (0x95, "STA", ZeroPageX, Access::Write, 4),
+    (0x96, "STX", ZeroPageY, Access::Write, 4),
+    (0x97, "SAX", ZeroPageY, Access::Write, 4),
+    (0x98, "TYA", Implied, Access::Implied, 2),
+    (0x99, "STA", AbsoluteY, Access::Write, 5),
+    (0x9A, "TXS", Implied, Access::Implied, 2),
+    (0x9B, "TAS", AbsoluteY, Access::Write, 5),
+    (0x9C, "SHY", AbsoluteX, Access::Write, 5),
+    (0x9D, "STA", AbsoluteX, Access::Write, 5),
+    (0x9E, "SHX", AbsoluteY, Access::Write, 5),
+    (0x9F, "SHA", AbsoluteY, Access::Write, 5),
+    (0xA0, "LDY", Immediate, Access::Read, 2),
+    (0xA1, "LDA", IndexedIndirect, Access::Read, 6),
+    (0xA2, "LDX", Immediate, Access::Read, 2),
+    (0xA3, "LAX", IndexedIndirect, Access::Read, 6),
+    (0xA4, "LDY", ZeroPage, Access::Read, 3),
+    (0xA5, "LDA", ZeroPage, Access::Read, 3),
+    (0xA6, "LDX", ZeroPage, Access::Read, 3),
+    (0xA7, "LAX", ZeroPage, Access::Read, 3),
+    (0xA8, "TAY", Implied, Access::Implied, 2),
+    (0xA9, "LDA", Immediate, Access::Read, 2),
+    (0xAA, "TAX", Implied, Access::Implied, 2),
+    (0xAB, "LXA", Immediate, Access::Read, 2),
+    (0xAC, "LDY", Absolute, Access::Read, 4),
+    (0xAD, "LDA", Absolute, Access::Read, 4),
+    (0xAE, "LDX", Absolute, Access::Read, 4),
+    (0xAF, "LAX", Absolute, Access::Read, 4),
+    (0xB0, "BCS", Relative, Access::Read, 2),
+    (0xB1, "LDA", IndirectIndexed, Access::Read, 5),
+    (0xB3, "LAX", IndirectIndexed, Access::Read, 5),
+    (0xB4, "LDY", ZeroPageX, Access::Read, 4),
+    (0xB5, "LDA", ZeroPageX, Access::Read, 4),
+    (0xB6, "LDX", ZeroPageY, Access::Read, 4),
+    (0xB7, "LAX", ZeroPageY, Access::Read, 4),
+    (0xB8, "CLV", Implied, Access::Implied, 2),
+    (0xB9, "LDA", AbsoluteY, Access::Read, 4),
+    (0xBA, "TSX", Implied, Access::Implied, 2),
+    (0xBC, "LDY", AbsoluteX, Access::Read, 4),
+    (0xBD, "LDA", AbsoluteX, Access::Read, 4),
+    (0xBE, "LDX", AbsoluteY, Access::Read, 4),
+    (0xBF, "LAX", AbsoluteY, Access::Read, 4),
+    (0xC0, "CPY", Immediate, Access::Read, 2),
+    (0xC1, "CMP", IndexedIndirect, Access::Read, 6),
+    (0xC2, "NOP", Immediate, Access::Implied, 2),
+    (0xC3, "DCP", IndexedIndirect, Access::ReadWrite, 8),
+    (0xC4, "CPY", ZeroPage, Access::Read, 3),
+    (0xC5, "CMP", ZeroPage, Access::Read, 3),
+    (0xC6, "DEC", ZeroPage, Access::ReadWrite, 5),
+    (0xC7, "DCP", ZeroPage, Access::ReadWrite, 5),
+    (0xC8, "INY", Implied, Access::Implied, 2),
+    (0xC9, "CMP", Immediate, Access::Read, 2),
+    (0xCA, "DEX", Implied, Access::Implied, 2),
+    (0xCB, "SBX", Immediate, Access::Read, 2),
+    (0xCC, "CPY", Absolute, Access::Read, 4),
+    (0xCD, "CMP", Absolute, Access::Read, 4),
+    (0xCE, "DEC", Absolute, Access::ReadWrite, 6),
+    (0xCF, "DCP", Absolute, Access::ReadWrite, 6),
+    (0xD0, "BNE", Relative, Access::Read, 2),
+    (0xD1, "CMP", IndirectIndexed, Access::Read, 5),
+    (0xD3, "DCP", IndirectIndexed, Access::ReadWrite, 8),
+    (0xD4, "NOP", ZeroPageX, Access::Implied, 4),
+    (0xD5, "CMP", ZeroPageX, Access::Read, 4),
+    (0xD6, "DEC", ZeroPageX, Access::ReadWrite, 6),
+    (0xD7, "DCP", ZeroPageX, Access::ReadWrite, 6),
+    (0xD8, "CLD", Implied, Access::Implied, 2),
+    (0xD9, "CMP", AbsoluteY, Access::Read, 4),
+    (0xDA, "NOP", Implied, Access::Implied, 2),
+    (0xDB, "DCP", AbsoluteY, Access::ReadWrite, 7),
+    (0xDC, "NOP", AbsoluteX, Access::Implied, 4),
+    (0xDD, "CMP", AbsoluteX, Access::Read, 4),
+    (0xDE, "DEC", AbsoluteX, Access::ReadWrite, 7),
+    (0xDF, "DCP", AbsoluteX, Access::ReadWrite, 7),
+    (0xE0, "CPX", Immediate, Access::Read, 2),
+    (0xE1, "SBC", IndexedIndirect, Access::Read, 6),
+    (0xE2, "NOP", Immediate, Access::Implied, 2),
+    (0xE3, "ISC", IndexedIndirect, Access::ReadWrite, 8),
+    (0xE4, "CPX", ZeroPage, Access::Read, 3),
+    (0xE5, "SBC", ZeroPage, Access::Read, 3),
+    (0xE6, "INC", ZeroPage, Access::ReadWrite, 5),
+    (0xE7, "ISC", ZeroPage, Access::ReadWrite, 5),
+    (0xE8, "INX", Implied, Access::Implied, 2),
+    (0xE9, "SBC", Immediate, Access::Read, 2),
+    (0xEA, "NOP", Implied, Access::Implied, 2),
+    (0xEC, "CPX", Absolute, Access::Read, 4),
+    (0xED, "SBC", Absolute, Access::Read, 4),
+    (0xEE, "INC", Absolute, Access::ReadWrite, 6),
+    (0xEF, "ISC", Absolute, Access::ReadWrite, 6),
+    (0xF0, "BEQ", Relative, Access::Read, 2),
+    (0xF1, "SBC", IndirectIndexed, Access::Read, 5),
+    (0xF3, "ISC", IndirectIndexed, Access::ReadWrite, 8),
+    (0xF4, "NOP", ZeroPageX, Access::Implied, 4),
+    (0xF5, "SBC", ZeroPageX, Access::Read, 4),
+    (0xF6, "INC", ZeroPageX, Access::ReadWrite, 6),
+    (0xF7, "ISC", ZeroPageX, Access::ReadWrite, 6),
+    (0xF8, "SED", Implied, Access::Implied, 2),
+    (0xF9, "SBC", AbsoluteY, Access::Read, 4),
+    (0xFA, "NOP", Implied, Access::Implied, 2),
+    (0xFB, "ISC", AbsoluteY, Access::ReadWrite, 7),
+    (0xFC, "NOP", AbsoluteX, Access::Implied, 4),
+    (0xFD, "SBC", AbsoluteX, Access::Read, 4),
+    (0xFE, "INC", AbsoluteX, Access::ReadWrite, 7),
+    (0xFF, "ISC", AbsoluteX, Access::ReadWrite, 7),
+]);
+
+const fn build_table(entries: &[(u8, &'static str, AddressingMode, Access, u8)]) -> [Option<OpcodeInfo>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < entries.len() {
+        let (opcode, mnemonic, mode, access, cycles) = entries[i];
+        table[opcode as usize] = Some(OpcodeInfo { mnemonic, mode, access, cycles });
+        i += 1;
+    }
+    table
+}
@@ -0,0 +1,358 @@
+use std::cmp::Reverse;
+use std::fmt;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use super::disasm;
+use super::opcodes::opcode_info;
+use crate::core::coverage::CoverageReport;
+use crate::core::memory::Memory;
+
+/// A division of the address space into regions that executed cycles are attributed to.
+///
+/// Regions are either fixed-size buckets (`RegionMap::buckets`) or a list of named ranges
+/// (`RegionMap::named`). With named ranges, addresses outside every range are attributed to
+/// a catch-all "other" region.
+///
+#[derive(Clone)]
+pub struct RegionMap {
+    names: Vec<String>,
+    region_of: Vec<u16>,
+}
+
+impl RegionMap {
+    /// Split the address space into buckets of `size` bytes, named by their address range.
+    ///
+    pub fn buckets(size: u32) -> Self {
+        assert!(size > 0 && size <= 0x10000);
+        let count = 0x10000u32.div_ceil(size);
+        let names = (0..count)
+            .map(|i| format!("${:04X}-${:04X}", i * size, u32::min((i + 1) * size, 0x10000) - 1))
+            .collect();
+        let region_of = (0..0x10000u32).map(|addr| (addr / size) as u16).collect();
+        Self { names, region_of }
+    }
+
+    /// Name ranges of the address space. Later ranges take precedence where ranges overlap.
+    ///
+    pub fn named(regions: &[(&str, RangeInclusive<u16>)]) -> Self {
+        assert!(regions.len() < 0xFFFF);
+        let mut names: Vec<String> = regions.iter().map(|(name, _)| name.to_string()).collect();
+        let other = names.len() as u16;
+        names.push("other".to_string());
+        let mut region_of = vec![other; 0x10000];
+        for (i, (_, range)) in regions.iter().enumerate() {
+            for addr in range.clone() {
+                region_of[addr as usize] = i as u16;
+            }
+        }
+        Self { names, region_of }
+    }
+}
+
+pub(crate) struct RegionProfile {
+    map: RegionMap,
+    cycles: Vec<u64>,
+}
+
+impl RegionProfile {
+    pub(crate) fn new(map: RegionMap) -> Self {
+        let cycles = vec![0; map.names.len()];
+        Self { map, cycles }
+    }
+
+    #[inline(always)]
+    pub(crate) fn record(&mut self, pc: u16) {
+        self.cycles[self.map.region_of[pc as usize] as usize] += 1;
+    }
+
+    pub(crate) fn report(&self) -> RegionReport {
+        let total: u64 = self.cycles.iter().sum();
+        let mut entries: Vec<RegionEntry> = self
+            .map
+            .names
+            .iter()
+            .zip(self.cycles.iter())
+            .filter(|(_, cycles)| **cycles > 0)
+            .map(|(name, cycles)| RegionEntry {
+                name: name.clone(),
+                cycles: *cycles,
+                share: *cycles as f64 / total as f64,
+            })
+            .collect();
+        entries.sort_by_key(|e| Reverse(e.cycles));
+        RegionReport { total_cycles: total, entries }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionEntry {
+    pub name: String,
+    pub cycles: u64,
+    pub share: f64,
+}
+
+/// Cycles attributed to each region, busiest first. Regions with no cycles are omitted.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionReport {
+    pub total_cycles: u64,
+    pub entries: Vec<RegionEntry>,
+}
+
+impl fmt::Display for RegionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in self.entries.iter() {
+            writeln!(f, "{:<24} {:>12} {:>6.1}%", e.name, e.cycles, e.share * 100.0)?;
+        }
+        write!(f, "{:<24} {:>12}", "total", self.total_cycles)
+    }
+}
+
+/// The profiles a CPU is collecting, kept together so a CPU checks whether it's profiling once
+/// a cycle however many profiles there are.
+///
+#[derive(Default)]
+pub(crate) struct Profiler {
+    pub(crate) regions: Option<RegionProfile>,
+    pub(crate) instructions: Option<InstructionProfile>,
+}
+
+impl Profiler {
+    /// Record a cycle of the instruction at `pc`. If the cycle also fetched an instruction,
+    /// `fetched` gives its address and opcode; for the last cycle of an instruction, which
+    /// fetches the next one, that's the next instruction, while the cycle stays with `pc`.
+    ///
+    #[inline(always)]
+    pub(crate) fn record(&mut self, pc: u16, opcode: u8, fetched: Option<(u16, u8)>) {
+        if let Some(profile) = self.regions.as_mut() {
+            profile.record(pc);
+        }
+        if let Some(profile) = self.instructions.as_mut() {
+            profile.record_cycle(pc, opcode);
+            if let Some((pc, opcode)) = fetched {
+                profile.record_fetch(pc, opcode);
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.regions.is_none() && self.instructions.is_none()
+    }
+}
+
+pub(crate) struct InstructionProfile {
+    opcode_counts: [u64; 256],
+    opcode_cycles: [u64; 256],
+    pc_counts: Vec<u64>,
+    pc_cycles: Vec<u64>,
+}
+
+impl InstructionProfile {
+    pub(crate) fn new() -> Self {
+        Self {
+            opcode_counts: [0; 256],
+            opcode_cycles: [0; 256],
+            pc_counts: vec![0; 0x10000],
+            pc_cycles: vec![0; 0x10000],
+        }
+    }
+
+    /// Record a cycle of the instruction at `pc`.
+    ///
+    #[inline(always)]
+    fn record_cycle(&mut self, pc: u16, opcode: u8) {
+        self.opcode_cycles[opcode as usize] += 1;
+        self.pc_cycles[pc as usize] += 1;
+    }
+
+    /// Count the instruction at `pc` as executed, when its opcode is fetched.
+    ///
+    #[inline(always)]
+    fn record_fetch(&mut self, pc: u16, opcode: u8) {
+        self.opcode_counts[opcode as usize] += 1;
+        self.pc_counts[pc as usize] += 1;
+    }
+
+    pub(crate) fn report(&self) -> ProfileReport {
+        let mut opcodes: Vec<OpcodeEntry> = (0..256)
+            .filter(|&op| self.opcode_cycles[op] > 0)
+            .map(|op| OpcodeEntry {
+                opcode: op as u8,
+                mnemonic: opcode_info(op as u8).map_or("???", |info| info.mnemonic),
+                count: self.opcode_counts[op],
+                cycles: self.opcode_cycles[op],
+            })
+            .collect();
+        opcodes.sort_by_key(|e| Reverse(e.cycles));
+        let mut hotspots: Vec<Hotspot> = (0..0x10000)
+            .filter(|&pc| self.pc_cycles[pc] > 0)
+            .map(|pc| Hotspot { pc: pc as u16, count: self.pc_counts[pc], cycles: self.pc_cycles[pc] })
+            .collect();
+        hotspots.sort_by_key(|h| Reverse(h.cycles));
+        ProfileReport {
+            instructions: self.opcode_counts.iter().sum(),
+            cycles: self.opcode_cycles.iter().sum(),
+            opcodes,
+            hotspots,
+        }
+    }
+}
+
+/// The instructions executed with an opcode, and the cycles they took.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeEntry {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// The instructions executed at an address, and the cycles they took.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotspot {
+    pub pc: u16,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Executed instructions and cycles by opcode and by address, each busiest first. Opcodes and
+/// addresses that never executed are omitted.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub opcodes: Vec<OpcodeEntry>,
+    pub hotspots: Vec<Hotspot>,
+}
+
+impl ProfileReport {
+    /// The `n` addresses that took the most cycles.
+    ///
+    pub fn top_n(&self, n: usize) -> &[Hotspot] {
+        &self.hotspots[..n.min(self.hotspots.len())]
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |cycles: u64| cycles as f64 * 100.0 / self.cycles.max(1) as f64;
+        writeln!(f, "{} instructions, {} cycles", self.instructions, self.cycles)?;
+        writeln!(f, "{:<8} {:>12} {:>12} {:>7}", "opcode", "count", "cycles", "share")?;
+        for e in self.opcodes.iter() {
+            let name = format!("{:02X} {}", e.opcode, e.mnemonic);
+            writeln!(f, "{:<8} {:>12} {:>12} {:>6.1}%", name, e.count, e.cycles, share(e.cycles))?;
+        }
+        write!(f, "{:<8} {:>12} {:>12} {:>7}", "address", "count", "cycles", "share")?;
+        for h in self.top_n(20) {
+            write!(f, "\n${:04X}    {:>12} {:>12} {:>6.1}%", h.pc, h.count, h.cycles, share(h.cycles))?;
+        }
+        Ok(())
+    }
+}
+
+/// List the instructions from `start` to `end` inclusive, as `disasm::disassemble_range` does,
+/// with a `*` marking each instruction whose opcode was never fetched according to `report`.
+///
+/// The listing is only as good as the decoding: data mixed in with the code throws off the
+/// instructions that follow it, until the decoding happens to fall back in step.
+///
+pub fn coverage_listing(memory: &Memory, report: &CoverageReport, start: u16, end: u16) -> String {
+    let mut listing = String::new();
+    for (addr, line) in disasm::disassemble_range(memory, start, end) {
+        let marker = if report.was_fetched(addr) { ' ' } else { '*' };
+        writeln!(listing, "{} {}", marker, line).unwrap();
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::coverage::Classification;
+    use crate::cpus::c6502::C6502;
+    use crate::roms::Rom;
+
+    #[test]
+    fn buckets() {
+        let map = RegionMap::buckets(0x1000);
+        assert_eq!(map.names.len(), 16);
+        assert_eq!(map.names[0], "$0000-$0FFF");
+        assert_eq!(map.names[15], "$F000-$FFFF");
+        assert_eq!(map.region_of[0x1FFF], 1);
+
+        let map = RegionMap::buckets(0x3000);
+        assert_eq!(map.names.len(), 6);
+        assert_eq!(map.names[5], "$F000-$FFFF");
+    }
+
+    #[test]
+    fn named() {
+        let mut profile = RegionProfile::new(RegionMap::named(&[("ROM", 0xE000..=0xFFFF), ("IO", 0xD000..=0xD0FF)]));
+        profile.record(0xE123);
+        profile.record(0xE124);
+        profile.record(0xD010);
+        profile.record(0x0200);
+        profile.record(0xF000);
+
+        let report = profile.report();
+        assert_eq!(report.total_cycles, 5);
+        let names: Vec<&str> = report.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["ROM", "IO", "other"]);
+        assert_eq!(report.entries[0].share, 0.6);
+    }
+
+    #[test]
+    fn coverage() {
+        const ROM: Rom = Rom {
+            name: "coverage",
+            origin: 0xE000,
+            entry: 0xE000,
+            code: &[
+                0xAD, 0x0C, 0xE0, // LDA table
+                0x8D, 0x00, 0x02, // STA $0200
+                0x4C, 0x06, 0xE0, // loop: JMP loop
+                0xA2, 0x01, //       LDX #$01 (never called)
+                0x60, //             RTS
+                0x42, //             table: .byte $42
+            ],
+        };
+        let memory = Memory::new();
+        ROM.install(&memory);
+        assert!(memory.coverage_report().is_none());
+        memory.enable_coverage();
+        let mut cpu = C6502::new(&memory);
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.step();
+        }
+
+        let report = memory.coverage_report().unwrap();
+        assert_eq!(report.regions.len(), 1);
+        assert_eq!((report.regions[0].start, report.regions[0].end), (0xE000, 0xFFFF));
+        assert_eq!(report.classify(0xE000), Classification::Code);
+        assert_eq!(report.classify(0xE008), Classification::Code);
+        for addr in 0xE009..=0xE00B {
+            assert_eq!(report.classify(addr), Classification::Untouched);
+        }
+        assert_eq!(report.classify(0xE00C), Classification::Data);
+        assert_eq!(report.classify(0xFFFC), Classification::Data);
+        assert_eq!(memory.read_byte(0x0200), 0x42);
+
+        let listing = coverage_listing(&memory, &report, 0xE000, 0xE00B);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  $E000  AD 0C E0  LDA $E00C",
+                "  $E003  8D 00 02  STA $0200",
+                "  $E006  4C 06 E0  JMP $E006",
+                "* $E009  A2 01     LDX #$01",
+                "* $E00B  60        RTS",
+            ]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::io::{self, Read};
+#[cfg(feature = "serde")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "serde")]
+use super::CpuAction;
+use super::{CpuState, Registers, C6502};
+#[cfg(feature = "serde")]
+use crate::core::memory::MemoryBank;
+use crate::core::memory::Memory;
+
+/// Where Klaus Dormann's 6502 functional test, as assembled in its repository's
+/// `6502_functional_test.bin`, traps once every test has passed.
+///
+pub const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+
+/// Where the functional test starts.
+///
+pub const FUNCTIONAL_TEST_START: u16 = 0x0400;
+
+/// Where a program trapped: the address of the instruction that jumps or branches to itself,
+/// the registers at the time, and how far the program got.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TrapReport {
+    pub pc: u16,
+    pub registers: Registers,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl fmt::Display for TrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.registers;
+        write!(
+            f,
+            "trapped at ${:04X} after {} instructions ({} cycles), A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.instructions, self.cycles, r.ac, r.x, r.y, r.p, r.sp
+        )
+    }
+}
+
+/// Run the program in `memory` from `start` until it traps, i.e. an instruction jumps or
+/// branches to itself, the way test suites for the 6502 signal that they're done. The program
+/// must trap eventually, or this never returns.
+///
+pub fn run_until_trap(memory: &Memory, start: u16) -> TrapReport {
+    let mut cpu = C6502::new(memory);
+    cpu.reset();
+    while cpu.state() != CpuState::Running {
+        cpu.step();
+    }
+    cpu.set_registers(Registers { pc: start, sp: 0xFF, ..cpu.registers() });
+    let start_cycles = cpu.total_cycles();
+    let mut instructions = 0;
+    let mut last_pc = None;
+    loop {
+        let result = cpu.run_instruction();
+        instructions += 1;
+        if last_pc == Some(result.pc) {
+            return TrapReport {
+                pc: result.pc,
+                registers: cpu.registers(),
+                instructions,
+                cycles: cpu.total_cycles() - start_cycles,
+            };
+        }
+        last_pc = Some(result.pc);
+    }
+}
+
+/// Run Klaus Dormann's 6502 functional test, given the 64K `rom` image it assembles to. The
+/// image is loaded into RAM at $0000, and run from `FUNCTIONAL_TEST_START` until it traps.
+/// Returns the trap if it isn't at `FUNCTIONAL_TEST_SUCCESS`, which identifies the failing
+/// test in the test's listing.
+///
+pub fn run_functional_test(rom: &[u8]) -> Result<(), TrapReport> {
+    assert!(rom.len() <= 0x10000, "Image larger than 64K");
+    let memory = Memory::new();
+    memory.write_block(0x0000, rom);
+    let report = run_until_trap(&memory, FUNCTIONAL_TEST_START);
+    if report.pc == FUNCTIONAL_TEST_SUCCESS {
+        Ok(())
+    } else {
+        Err(report)
+    }
+}
+
+/// A single-instruction test case from the ProcessorTests corpus
+/// (https://github.com/SingleStepTests/ProcessorTests), in its JSON layout: the state before
+/// and after the instruction, and the bus accesses it makes, one per cycle.
+///
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ProcessorTest {
+    pub name: String,
+    pub initial: ProcessorTestState,
+    #[serde(rename = "final")]
+    pub final_state: ProcessorTestState,
+    pub cycles: Vec<(u16, u8, String)>,
+}
+
+/// The registers and the contents of the memory the instruction touches, before or after a
+/// `ProcessorTest`.
+///
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ProcessorTestState {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+/// A `ProcessorTest` that failed, with a description of each difference from the expected
+/// outcome.
+///
+#[cfg(feature = "serde")]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ProcessorTestFailure {
+    pub name: String,
+    pub mismatches: Vec<String>,
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for ProcessorTestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.mismatches.join(", "))
+    }
+}
+
+/// A bank covering all of memory that records every access, in the corpus's layout.
+///
+#[cfg(feature = "serde")]
+struct BusRecorder(Arc<Mutex<Vec<(u16, u8, String)>>>);
+
+#[cfg(feature = "serde")]
+impl MemoryBank for BusRecorder {
+    fn size(&self) -> usize {
+        0x10000
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn read_byte(&self, addr: u16, _offset: u16, ram: &[u8]) -> u8 {
+        self.0.lock().unwrap().push((addr, ram[addr as usize], "read".to_string()));
+        ram[addr as usize]
+    }
+
+    fn write_byte(&mut self, addr: u16, _offset: u16, val: u8, ram: &mut [u8]) {
+        self.0.lock().unwrap().push((addr, val, "write".to_string()));
+        ram[addr as usize] = val;
+    }
+}
+
+/// Run a `ProcessorTest`: set up the registers and memory, step the CPU through exactly one
+/// instruction, and compare the registers and memory with the expected outcome, and if
+/// `check_bus` is set, the bus accesses too.
+///
+/// The B and unused bits of P aren't compared, since the CPU doesn't hold them in the register.
+///
+#[cfg(feature = "serde")]
+pub fn run_processor_test(test: &ProcessorTest, check_bus: bool) -> Result<(), ProcessorTestFailure> {
+    let memory = Memory::new();
+    let bus = Arc::new(Mutex::new(Vec::new()));
+    memory.configure_banks(
+        vec![Box::new(BusRecorder(bus.clone()))],
+        &[(0x0000, 0x8000, 1, 0x0000), (0x8000, 0x8000, 1, 0x0000)],
+    )
+    .unwrap();
+    let mut cpu = C6502::new(&memory);
+    cpu.reset();
+    while cpu.state() != CpuState::Running {
+        cpu.step();
+    }
+
+    let initial = &test.initial;
+    let (pc, ac, x, y, p, sp) = (initial.pc, initial.a, initial.x, initial.y, initial.p, initial.s);
+    cpu.set_registers(Registers { pc, ac, x, y, p, sp });
+    for &(addr, value) in initial.ram.iter() {
+        memory.write_byte(addr, value);
+    }
+    bus.lock().unwrap().clear();
+
+    let mut action = cpu.step();
+    while action == CpuAction::Continue {
+        action = cpu.step();
+    }
+    let mut accesses = bus.lock().unwrap().clone();
+    let mut registers = cpu.registers();
+    if action == CpuAction::CompleteAndFetch {
+        // The last cycle also fetched the next opcode, which belongs to the next instruction.
+        accesses.pop();
+        registers.pc = registers.pc.wrapping_sub(1);
+    }
+
+    let expected = &test.final_state;
+    let mut mismatches = Vec::new();
+    let mut compare = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            mismatches.push(format!("{} is ${:02X}, expected ${:02X}", name, actual, expected));
+        }
+    };
+    compare("PC", registers.pc, expected.pc);
+    compare("A", registers.ac as u16, expected.a as u16);
+    compare("X", registers.x as u16, expected.x as u16);
+    compare("Y", registers.y as u16, expected.y as u16);
+    compare("SP", registers.sp as u16, expected.s as u16);
+    compare("P", (registers.p & 0xCF) as u16, (expected.p & 0xCF) as u16);
+    for &(addr, value) in expected.ram.iter() {
+        let mut actual = [0u8];
+        memory.read_block(addr, &mut actual);
+        compare(&format!("${:04X}", addr), actual[0] as u16, value as u16);
+    }
+    if check_bus && accesses != test.cycles {
+        mismatches.push(format!("bus accesses were {:?}, expected {:?}", accesses, test.cycles));
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(ProcessorTestFailure { name: test.name.clone(), mismatches })
+    }
+}
+
+/// Run every `ProcessorTest` in a JSON file from the corpus, e.g. one of its per-opcode files,
+/// returning the ones that failed.
+///
+#[cfg(feature = "serde")]
+pub fn run_processor_tests<R: Read>(reader: R, check_bus: bool) -> io::Result<Vec<ProcessorTestFailure>> {
+    let tests: Vec<ProcessorTest> =
+        serde_json::from_reader(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(tests.iter().filter_map(|test| run_processor_test(test, check_bus).err()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traps() {
+        let mut image = vec![0; 0x10000];
+        // Count X down to zero, then jump to the success trap.
+        image[0x0400..0x0408].copy_from_slice(&[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x4C, 0x69, 0x34]);
+        image[0x3469..0x346C].copy_from_slice(&[0x4C, 0x69, 0x34]);
+        assert_eq!(run_functional_test(&image), Ok(()));
+
+        // Fail a comparison, and trap on the branch.
+        image[0x0400..0x0406].copy_from_slice(&[0xA9, 0x01, 0xC9, 0x02, 0xD0, 0xFE]);
+        let report = run_functional_test(&image).unwrap_err();
+        assert_eq!(report.pc, 0x0404);
+        assert_eq!(report.registers.ac, 0x01);
+        assert_eq!(report.instructions, 4);
+    }
+
+    // A few cases written by hand in the corpus's layout, not taken from the corpus, checked on
+    // every build with the serde feature. processor_tests below runs the real thing.
+    #[test]
+    #[cfg(feature = "serde")]
+    fn processor_tests_handwritten() {
+        let sample = include_str!("processor_tests_handwritten.json");
+        assert_eq!(run_processor_tests(sample.as_bytes(), true).unwrap(), Vec::new());
+
+        let tests: Vec<ProcessorTest> = serde_json::from_str(sample).unwrap();
+        let mut wrong = tests[0].clone();
+        wrong.final_state.a = 0x3F;
+        wrong.cycles.pop();
+        let failure = run_processor_test(&wrong, true).unwrap_err();
+        assert_eq!(failure.name, "a9 3e 00");
+        assert_eq!(failure.mismatches.len(), 2);
+        assert_eq!(failure.mismatches[0], "A is $3E, expected $3F");
+    }
+
+    // Needs the 6502/v1 files of the ProcessorTests corpus, from
+    // https://github.com/SingleStepTests/ProcessorTests, in the directory in
+    // RUSTYCOAT_PROCESSOR_TESTS. Only the files for opcodes the CPU implements are run.
+    #[test]
+    #[ignore]
+    #[cfg(feature = "serde")]
+    fn processor_tests() {
+        use crate::cpus::c6502::opcodes::opcode_info;
+
+        let dir = std::env::var("RUSTYCOAT_PROCESSOR_TESTS").expect("RUSTYCOAT_PROCESSOR_TESTS isn't set");
+        let mut failures = Vec::new();
+        for opcode in (0..=0xFF).filter(|&op| opcode_info(op).is_some()) {
+            let path = std::path::Path::new(&dir).join(format!("{:02x}.json", opcode));
+            let file = std::fs::File::open(&path).unwrap_or_else(|e| panic!("Couldn't read {}: {}", path.display(), e));
+            failures.extend(run_processor_tests(io::BufReader::new(file), true).unwrap());
+        }
+        if let Some(first) = failures.first() {
+            panic!("{} processor tests failed, the first {}: {:?}", failures.len(), first.name, first.mismatches);
+        }
+    }
+
+    // Needs the functional test image, from https://github.com/Klaus2m5/6502_65C02_functional_tests,
+    // at the path in RUSTYCOAT_FUNCTIONAL_TEST, or 6502_functional_test.bin by default.
+    #[test]
+    #[ignore]
+    fn functional_test() {
+        let path = std::env::var("RUSTYCOAT_FUNCTIONAL_TEST").unwrap_or("6502_functional_test.bin".to_string());
+        let rom = std::fs::read(&path).unwrap_or_else(|e| panic!("Couldn't read {}: {}", path, e));
+        if let Err(report) = run_functional_test(&rom) {
+            panic!("Functional test failed: {}", report);
+        }
+    }
+}
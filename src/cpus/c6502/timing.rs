@@ -0,0 +1,33 @@
+use super::opcodes::{opcode_info, Access, AddressingMode};
+
+/// Cycle timing of an instruction.
+///
+/// * `base` is the number of cycles taken when no page boundary is crossed and no branch is taken.
+/// * `page_cross_penalty` is added when indexing (or a taken branch) crosses a page boundary.
+/// * `branch_taken_penalty` is added when a branch is taken.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CycleInfo {
+    pub base: u8,
+    pub page_cross_penalty: u8,
+    pub branch_taken_penalty: u8,
+}
+
+/// Returns the cycle timing of an opcode without executing it, or `None` if the CPU doesn't
+/// implement the opcode.
+///
+pub fn instruction_cycles(opcode: u8) -> Option<CycleInfo> {
+    opcode_info(opcode).map(|info| {
+        let indexed_read = matches!(info.access, Access::Read | Access::Implied)
+            && matches!(
+                info.mode,
+                AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectIndexed
+            );
+        let branch = info.mode == AddressingMode::Relative;
+        CycleInfo {
+            base: info.cycles,
+            page_cross_penalty: if indexed_read || branch { 1 } else { 0 },
+            branch_taken_penalty: if branch { 1 } else { 0 },
+        }
+    })
+}
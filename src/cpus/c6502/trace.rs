@@ -0,0 +1,549 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::{disasm, Registers};
+use crate::core::memory::Memory;
+
+/// The state of the CPU as it fetched an instruction: the number of cycles executed before the
+/// fetch, the instruction's address and opcode, and the registers before it ran.
+///
+/// An interrupt is recorded as a BRK (opcode $00) at the address of the instruction it
+/// displaced.
+///
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u8,
+    pub ac: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+}
+
+/// The text layouts a trace can be written in.
+///
+/// * `Text` lists the cycle count first, then the instruction and registers.
+/// * `Nestest` follows the register and cycle columns of the widely used nestest.log, so traces
+///   can be compared against other emulators' with the usual tools.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TraceFormat {
+    Text,
+    Nestest,
+}
+
+impl TraceEntry {
+    pub fn write_line<W: Write>(&self, w: &mut W, format: TraceFormat) -> io::Result<()> {
+        match format {
+            TraceFormat::Text => writeln!(
+                w,
+                "{:>10}  {:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                self.cycle, self.pc, self.opcode, self.ac, self.x, self.y, self.p, self.sp
+            ),
+            TraceFormat::Nestest => writeln!(
+                w,
+                "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                self.pc, self.opcode, self.ac, self.x, self.y, self.p, self.sp, self.cycle
+            ),
+        }
+    }
+}
+
+/// Somewhere for the CPU to send a `TraceEntry` for each instruction it executes (see
+/// `C6502::set_trace_sink`).
+///
+pub trait TraceSink: Send {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Called when the CPU is reset, abandoning the instruction in progress, for sinks that
+    /// hold on to an entry until the instruction completes.
+    ///
+    fn reset(&mut self) {}
+}
+
+/// Writes a trace as text, one line per instruction.
+///
+pub struct TextTraceWriter<W: Write + Send> {
+    w: W,
+    format: TraceFormat,
+}
+
+impl<W: Write + Send> TextTraceWriter<W> {
+    pub fn new(w: W, format: TraceFormat) -> Self {
+        Self { w, format }
+    }
+}
+
+impl<W: Write + Send> TraceSink for TextTraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        entry.write_line(&mut self.w, self.format)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// A completed instruction, as passed to a `TraceHook`: its address, bytes and disassembly, the
+/// registers once it completed, and the number of cycles it took.
+///
+/// An interrupt is recorded as a BRK at the address of the instruction it displaced.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub registers: Registers,
+    pub cycles: u64,
+}
+
+impl TraceRecord {
+    pub fn write_line<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let r = &self.registers;
+        writeln!(
+            w,
+            "${:04X}  {:<8}  {:<12}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}  {} cycles",
+            self.pc,
+            hex.join(" "),
+            self.text,
+            r.ac,
+            r.x,
+            r.y,
+            r.p,
+            r.sp,
+            self.cycles
+        )
+    }
+}
+
+/// A sink that calls a function with a `TraceRecord` for each instruction once it completes,
+/// disassembling it from `memory`. An instruction completes when the next one is fetched, so
+/// the last instruction executed isn't reported until then. See `to_writer` for a function that
+/// writes the records out.
+///
+pub struct TraceHook<F: FnMut(&TraceRecord) + Send> {
+    memory: Memory,
+    hook: F,
+    pending: Option<TraceEntry>,
+}
+
+impl<F: FnMut(&TraceRecord) + Send> TraceHook<F> {
+    pub fn new(memory: &Memory, hook: F) -> Self {
+        Self { memory: memory.clone(), hook, pending: None }
+    }
+}
+
+impl<F: FnMut(&TraceRecord) + Send> TraceSink for TraceHook<F> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        if let Some(completed) = self.pending.replace(*entry) {
+            let (bytes, text) = if completed.opcode == 0x00 {
+                (vec![0x00], "BRK".to_string())
+            } else {
+                let (text, _) = disasm::disassemble(&self.memory, completed.pc);
+                (disasm::instruction_bytes(&self.memory, completed.pc), text)
+            };
+            // The registers the next instruction starts with are the ones this one left.
+            let registers = Registers { pc: entry.pc, ac: entry.ac, x: entry.x, y: entry.y, p: entry.p, sp: entry.sp };
+            let cycles = entry.cycle - completed.cycle;
+            (self.hook)(&TraceRecord { pc: completed.pc, bytes, text, registers, cycles });
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// Returns a function for a `TraceHook` that writes each record to `w`, one per line. Write
+/// errors are ignored, so a failing writer doesn't stop the CPU.
+///
+pub fn to_writer<W: Write + Send + 'static>(mut w: W) -> impl FnMut(&TraceRecord) + Send + 'static {
+    move |record| {
+        record.write_line(&mut w).ok();
+    }
+}
+
+const MAGIC: &[u8; 4] = b"RCTR";
+const INDEX_MAGIC: &[u8; 4] = b"RCTI";
+const VERSION: u16 = 1;
+const HEADER_LEN: u64 = 10;
+const INDEX_LEN: u64 = 12;
+const RECORD_LEN: u64 = 10;
+const FILLER_DELTA: u16 = 0xFFFF;
+
+/// Writes a trace in a compact binary format, a fraction of the size of a text trace and much
+/// cheaper to produce.
+///
+/// The trace starts with a header of the magic bytes `RCTR`, the format version (u16) and the
+/// index interval N (u32). Then come blocks of N records, each led by an index entry of the
+/// magic bytes `RCTI` and the cycle count of the block's first record (u64). A record is the
+/// number of cycles since the previous record (u16, zero for the first record of a block),
+/// followed by PC (u16), opcode, A, X, Y, P and SP. All values are little-endian.
+///
+/// A record too many cycles after the one before for the gap to fit, e.g. after the CPU was
+/// held by RDY, pads out the block with filler records instead, and starts a new block with its
+/// absolute cycle count. A filler record has a count of $FFFF, and zeroes for the rest.
+///
+/// As every block but the last has the same size, a reader can find the block containing a
+/// given cycle with a binary search over the index entries (see `BinaryTraceReader::seek_to_cycle`).
+///
+pub struct BinaryTraceWriter<W: Write + Send> {
+    w: W,
+    interval: u32,
+    in_block: u32,
+    last_cycle: u64,
+}
+
+impl<W: Write + Send> BinaryTraceWriter<W> {
+    pub const DEFAULT_INTERVAL: u32 = 4096;
+
+    pub fn new(w: W) -> io::Result<Self> {
+        Self::with_interval(w, Self::DEFAULT_INTERVAL)
+    }
+
+    pub fn with_interval(mut w: W, interval: u32) -> io::Result<Self> {
+        assert!(interval > 0);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&interval.to_le_bytes())?;
+        Ok(Self { w, interval, in_block: interval, last_cycle: 0 })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+impl<W: Write + Send> TraceSink for BinaryTraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        if self.in_block < self.interval && entry.cycle - self.last_cycle >= FILLER_DELTA as u64 {
+            let filler = [0u8; RECORD_LEN as usize - 2];
+            while self.in_block < self.interval {
+                self.w.write_all(&FILLER_DELTA.to_le_bytes())?;
+                self.w.write_all(&filler)?;
+                self.in_block += 1;
+            }
+        }
+        if self.in_block == self.interval {
+            self.w.write_all(INDEX_MAGIC)?;
+            self.w.write_all(&entry.cycle.to_le_bytes())?;
+            self.in_block = 0;
+            self.last_cycle = entry.cycle;
+        }
+        let pc = entry.pc.to_le_bytes();
+        let delta = ((entry.cycle - self.last_cycle) as u16).to_le_bytes();
+        self.w.write_all(&[
+            delta[0], delta[1], pc[0], pc[1], entry.opcode, entry.ac, entry.x, entry.y, entry.p, entry.sp,
+        ])?;
+        self.in_block += 1;
+        self.last_cycle = entry.cycle;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Reads a trace written by `BinaryTraceWriter`, as an iterator of entries.
+///
+pub struct BinaryTraceReader<R: Read + Seek> {
+    r: R,
+    interval: u32,
+    in_block: u32,
+    cycle: u64,
+}
+
+impl<R: Read + Seek> BinaryTraceReader<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        r.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(invalid_data("not a binary trace"));
+        }
+        if u16::from_le_bytes([header[4], header[5]]) != VERSION {
+            return Err(invalid_data("unsupported trace version"));
+        }
+        let interval = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+        if interval == 0 {
+            return Err(invalid_data("invalid index interval"));
+        }
+        Ok(Self { r, interval, in_block: interval, cycle: 0 })
+    }
+
+    /// Position the reader at the first entry whose cycle is at or after `cycle`.
+    ///
+    pub fn seek_to_cycle(&mut self, cycle: u64) -> io::Result<()> {
+        let block_len = INDEX_LEN + self.interval as u64 * RECORD_LEN;
+        let len = self.r.seek(SeekFrom::End(0))?;
+        let blocks = (len - HEADER_LEN).div_ceil(block_len);
+
+        // Find the last block that starts at or before the cycle.
+        let (mut lo, mut hi) = (0, blocks);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.block_start(mid, block_len)? <= cycle {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        self.r.seek(SeekFrom::Start(HEADER_LEN + lo * block_len))?;
+        self.in_block = self.interval;
+
+        // Read forward to the entry, then step back to just before it.
+        loop {
+            let saved = (self.r.stream_position()?, self.in_block, self.cycle);
+            match self.read_entry()? {
+                Some(entry) if entry.cycle < cycle => continue,
+                Some(_) => {
+                    let (position, in_block, last_cycle) = saved;
+                    self.r.seek(SeekFrom::Start(position))?;
+                    self.in_block = in_block;
+                    self.cycle = last_cycle;
+                    return Ok(());
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn block_start(&mut self, block: u64, block_len: u64) -> io::Result<u64> {
+        self.r.seek(SeekFrom::Start(HEADER_LEN + block * block_len))?;
+        let mut index = [0u8; INDEX_LEN as usize];
+        self.r.read_exact(&mut index)?;
+        if &index[0..4] != INDEX_MAGIC {
+            return Err(invalid_data("missing index entry"));
+        }
+        Ok(u64::from_le_bytes(index[4..12].try_into().unwrap()))
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<TraceEntry>> {
+        loop {
+            if self.in_block == self.interval {
+                let mut index = [0u8; INDEX_LEN as usize];
+                if !read_or_eof(&mut self.r, &mut index)? {
+                    return Ok(None);
+                }
+                if &index[0..4] != INDEX_MAGIC {
+                    return Err(invalid_data("missing index entry"));
+                }
+                self.cycle = u64::from_le_bytes(index[4..12].try_into().unwrap());
+                self.in_block = 0;
+            }
+            let mut record = [0u8; RECORD_LEN as usize];
+            if !read_or_eof(&mut self.r, &mut record)? {
+                return Ok(None);
+            }
+            self.in_block += 1;
+            let delta = u16::from_le_bytes([record[0], record[1]]);
+            if delta == FILLER_DELTA {
+                continue;
+            }
+            self.cycle += delta as u64;
+            return Ok(Some(TraceEntry {
+                cycle: self.cycle,
+                pc: u16::from_le_bytes([record[2], record[3]]),
+                opcode: record[4],
+                ac: record[5],
+                x: record[6],
+                y: record[7],
+                p: record[8],
+                sp: record[9],
+            }));
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for BinaryTraceReader<R> {
+    type Item = io::Result<TraceEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+/// Convert a binary trace to text, one line per entry.
+///
+pub fn convert<R: Read + Seek, W: Write>(r: R, w: &mut W, format: TraceFormat) -> io::Result<()> {
+    for entry in BinaryTraceReader::new(r)? {
+        entry?.write_line(w, format)?;
+    }
+    w.flush()
+}
+
+/// Fill `buf`, returning false if the reader is already at its end.
+///
+fn read_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated trace")),
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::Memory;
+    use crate::cpus::c6502::{CpuState, C6502};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(sink: Box<dyn TraceSink>) {
+        let mem = Memory::new();
+        mem.write_block(0x0400, &[0xA2, 0x00, 0xE8, 0x86, 0x10, 0x4C, 0x02, 0x04]); // LDX #0; INX; STX $10; JMP
+        let mut cpu = C6502::new(&mem);
+        cpu.state = CpuState::Running;
+        cpu.pc = 0x0400;
+        cpu.sp = 0xFF;
+        cpu.set_trace_sink(Some(sink)).unwrap();
+        for _ in 0..5000 {
+            cpu.step();
+        }
+        cpu.set_trace_sink(None).unwrap();
+    }
+
+    #[test]
+    fn convert_matches_text() {
+        let text = SharedBuf::default();
+        capture(Box::new(TextTraceWriter::new(text.clone(), TraceFormat::Nestest)));
+        let binary = SharedBuf::default();
+        capture(Box::new(BinaryTraceWriter::with_interval(binary.clone(), 64).unwrap()));
+
+        let binary = binary.0.lock().unwrap().clone();
+        let text = text.0.lock().unwrap().clone();
+        let mut converted = Vec::new();
+        convert(Cursor::new(&binary), &mut converted, TraceFormat::Nestest).unwrap();
+        assert_eq!(String::from_utf8(converted).unwrap(), String::from_utf8(text.clone()).unwrap());
+        assert!(binary.len() * 4 < text.len(), "{} vs {}", binary.len(), text.len());
+
+        let lines: Vec<&str> = std::str::from_utf8(&text).unwrap().lines().take(3).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0400  A2  A:AA X:00 Y:00 P:00 SP:FF CYC:0",
+                "0402  E8  A:AA X:00 Y:00 P:02 SP:FF CYC:2",
+                "0403  86  A:AA X:01 Y:00 P:00 SP:FF CYC:4",
+            ]
+        );
+    }
+
+    /// A sink that fails once it's been given `limit` entries.
+    ///
+    struct FailingSink {
+        limit: usize,
+    }
+
+    impl TraceSink for FailingSink {
+        fn record(&mut self, _: &TraceEntry) -> io::Result<()> {
+            if self.limit == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.limit -= 1;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_errors() {
+        let mem = Memory::new();
+        mem.write_block(0x0400, &[0xE8, 0x4C, 0x00, 0x04]); // INX; JMP $0400
+        let mut cpu = C6502::new(&mem);
+        cpu.state = CpuState::Running;
+        cpu.pc = 0x0400;
+        let stats = cpu.stats();
+        cpu.set_trace_sink(Some(Box::new(FailingSink { limit: 10 }))).unwrap();
+        for _ in 0..100 {
+            cpu.step();
+        }
+
+        // Tracing stops at the failure, which is reported once.
+        assert_eq!(stats.trace_error().as_deref(), Some("disk full"));
+        assert_eq!(cpu.set_trace_sink(None).unwrap_err().to_string(), "disk full");
+        assert_eq!(stats.trace_error(), None);
+        cpu.set_trace_sink(None).unwrap();
+    }
+
+    #[test]
+    fn seek_to_cycle() {
+        let mut writer = BinaryTraceWriter::with_interval(Vec::new(), 4).unwrap();
+        for i in 0..10u64 {
+            writer.record(&TraceEntry { cycle: i * 3, pc: i as u16, ..Default::default() }).unwrap();
+        }
+        let bytes = writer.into_inner();
+        let mut reader = BinaryTraceReader::new(Cursor::new(&bytes)).unwrap();
+
+        for (cycle, pc) in [(0, 0), (12, 4), (13, 5), (20, 7), (27, 9)] {
+            reader.seek_to_cycle(cycle).unwrap();
+            let entry = reader.next().unwrap().unwrap();
+            assert_eq!((entry.pc, entry.cycle), (pc, pc as u64 * 3));
+        }
+        let following: Vec<u16> = reader.map(|e| e.unwrap().pc).collect();
+        assert!(following.is_empty());
+
+        let mut reader = BinaryTraceReader::new(Cursor::new(&bytes)).unwrap();
+        reader.seek_to_cycle(28).unwrap();
+        assert!(reader.next().is_none());
+        reader.seek_to_cycle(5).unwrap();
+        let pcs: Vec<u16> = reader.map(|e| e.unwrap().pc).collect();
+        assert_eq!(pcs, vec![2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn long_gaps() {
+        // Gaps too long for a record's count start new blocks, including one only a record into its
+        // block. A gap of $FFFE cycles still fits.
+        let cycles = [0, 3, 0xFFFE + 3, 0xFFFE + 3 + 0xFFFF, 0x1_0000_0000, 0x1_0000_0002, 0x1_0000_0004];
+        let mut writer = BinaryTraceWriter::with_interval(Vec::new(), 4).unwrap();
+        for (i, &cycle) in cycles.iter().enumerate() {
+            writer.record(&TraceEntry { cycle, pc: i as u16, ..Default::default() }).unwrap();
+        }
+        let bytes = writer.into_inner();
+        assert_eq!(bytes.len() as u64, HEADER_LEN + 3 * (INDEX_LEN + 4 * RECORD_LEN) - RECORD_LEN);
+
+        let mut reader = BinaryTraceReader::new(Cursor::new(&bytes)).unwrap();
+        let read: Vec<(u64, u16)> = reader.by_ref().map(|e| e.map(|e| (e.cycle, e.pc)).unwrap()).collect();
+        let expected: Vec<(u64, u16)> = cycles.iter().enumerate().map(|(i, &c)| (c, i as u16)).collect();
+        assert_eq!(read, expected);
+
+        for (cycle, pc) in [(4, 2), (0xFFFE + 3, 2), (0xFFFE + 4, 3), (0x1_0000_0001, 5)] {
+            reader.seek_to_cycle(cycle).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap().pc, pc);
+        }
+    }
+}
@@ -1,4 +1,7 @@
 use super::*;
+use crate::core::Computer;
+use crate::core::ports::{Connections, InputPort16, InputPort8};
+use std::sync::Mutex;
 
 struct CpuTest {
     mem: Memory,
@@ -64,27 +67,22 @@ impl CpuTest {
     }
 
     fn run(&mut self, instruction_count: usize) -> &mut Self {
-        let mut cpu = &mut self.cpu;
+        let cpu = &mut self.cpu;
         cpu.pc = self.pc;
         cpu.ac = self.ac;
         cpu.x = self.x;
         cpu.y = self.y;
         cpu.sp = self.sp;
         cpu.p = self.p;
-        cpu.state = CpuState::Running;
+        if cpu.state != CpuState::Running {
+            // Lines set up before the CPU starts have been held for a while by its first fetch.
+            cpu.sampled = cpu.poll_interrupts();
+            cpu.polled = cpu.sampled;
+            cpu.state = CpuState::Running;
+        }
 
-        let mut last_action = CpuAction::Continue;
         for _ in 0..instruction_count {
-            loop {
-                self.cycles += 1;
-                last_action = cpu.step();
-                if last_action != CpuAction::Continue {
-                    break;
-                }
-            }
-        }
-        if last_action == CpuAction::CompleteAndFetch {
-            self.cycles -= 1;
+            self.cycles += cpu.run_instruction().cycles as usize;
         }
 
         self.pc = cpu.pc;
@@ -93,8 +91,6 @@ impl CpuTest {
         self.y = cpu.y;
         self.sp = cpu.sp;
         self.p = cpu.p;
-
-        drop(cpu);
         self
     }
 
@@ -567,6 +563,30 @@ fn test_branch_cycle_counts() {
             .values(|c| (c.pc, c.cycles)),
         (0x0402, 2)
     );
+
+    // Taken branches read the byte after the branch, and when crossing a page, the target with
+    // the old high byte.
+    let accesses_for = |pc: u16, offset: u8| {
+        let mut test = CpuTest::new();
+        let accesses = RecordingBank::map_at(&test.mem, 0x1000);
+        test.with_pc(pc).with_instruction(&[0xF0, offset]).with_state(|c| c.p = C6502::SR_ZERO);
+        accesses.lock().unwrap().clear();
+        test.run_one();
+        let accesses = accesses.lock().unwrap().clone();
+        (accesses, test.cycles)
+    };
+    assert_eq!(
+        accesses_for(0x1040, 0x10),
+        (vec![Access::Read(0x1040), Access::Read(0x1041), Access::Read(0x1042)], 3)
+    );
+    assert_eq!(
+        accesses_for(0x10F0, 0x10),
+        (vec![Access::Read(0x10F0), Access::Read(0x10F1), Access::Read(0x10F2), Access::Read(0x1002)], 4)
+    );
+    assert_eq!(
+        accesses_for(0x1100, 0xF0),
+        (vec![Access::Read(0x1100), Access::Read(0x1101), Access::Read(0x1102), Access::Read(0x11F2)], 4)
+    );
 }
 
 #[test]
@@ -701,7 +721,8 @@ fn test_adc() {
         (0x51, C6502::SR_BCD)
     );
 
-    // Add two numbers in BCD mode with carry-out
+    // Add two numbers in BCD mode with carry-out. N and V come from the sum before the high
+    // digit is adjusted ($A1).
     assert_eq_hex!(
         CpuTest::new()
             .with_instruction(&[0x69, 0x29])
@@ -709,7 +730,40 @@ fn test_adc() {
             .with_state(|c| c.p = C6502::SR_BCD)
             .run_one()
             .values(|c| (c.ac, c.p)),
-        (0x01, C6502::SR_BCD | C6502::SR_CARRY)
+        (0x01, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_NEGATIVE | C6502::SR_OVERFLOW)
+    );
+
+    // A BCD result of zero doesn't set Z, as Z comes from the binary sum ($9A).
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x69, 0x01])
+            .with_state(|c| c.ac = 0x99)
+            .with_state(|c| c.p = C6502::SR_BCD)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_NEGATIVE)
+    );
+
+    // Z is set when the binary sum is zero, even though the BCD result isn't.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x69, 0x80])
+            .with_state(|c| c.ac = 0x80)
+            .with_state(|c| c.p = C6502::SR_BCD)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x60, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_OVERFLOW | C6502::SR_ZERO)
+    );
+
+    // Signed overflow of the unadjusted sum ($A0) sets V, and its top bit sets N.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x69, 0x50])
+            .with_state(|c| c.ac = 0x50)
+            .with_state(|c| c.p = C6502::SR_BCD)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_NEGATIVE | C6502::SR_OVERFLOW)
     );
 }
 
@@ -746,6 +800,165 @@ fn test_and() {
     );
 }
 
+#[test]
+fn test_anc() {
+    // Negative result sets the carry
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x0B, 0xF0])
+            .with_state(|c| c.ac = 0x8F)
+            .run_one()
+            .values(|c| (c.ac, c.p, c.cycles)),
+        (0x80, C6502::SR_NEGATIVE | C6502::SR_CARRY, 2)
+    );
+
+    // Positive result clears the carry, for both opcodes
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x2B, 0x0F])
+            .with_state(|c| c.ac = 0x8F)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p, c.cycles)),
+        (0x0F, 0x00, 2)
+    );
+}
+
+#[test]
+fn test_alr() {
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x4B, 0x0F])
+            .with_state(|c| c.ac = 0xF3)
+            .run_one()
+            .values(|c| (c.ac, c.p, c.cycles)),
+        (0x01, C6502::SR_CARRY, 2)
+    );
+
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x4B, 0xFE])
+            .with_state(|c| c.ac = 0x01)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_ZERO)
+    );
+}
+
+#[test]
+fn test_arr() {
+    // Carry from bit 6, overflow from bit 6 XOR bit 5, with the carry rotated in
+    let arr = |ac, operand, p| {
+        let mut test = CpuTest::new();
+        test.with_instruction(&[0x6B, operand]).with_data(0x10, &[ac, p]);
+        test.with_state(|c| c.ac = c.data(0x10)).with_state(|c| c.p = c.data(0x11)).run_one();
+        (test.ac, test.p, test.cycles)
+    };
+    assert_eq_hex!(arr(0xFF, 0xFF, C6502::SR_CARRY), (0xFF, C6502::SR_NEGATIVE | C6502::SR_CARRY, 2));
+    assert_eq_hex!(arr(0xFF, 0xC0, 0x00), (0x60, C6502::SR_CARRY, 2));
+    assert_eq_hex!(arr(0xFF, 0x80, 0x00), (0x40, C6502::SR_CARRY | C6502::SR_OVERFLOW, 2));
+    assert_eq_hex!(arr(0xFF, 0x40, 0x00), (0x20, C6502::SR_OVERFLOW, 2));
+    assert_eq_hex!(arr(0x01, 0x01, 0x00), (0x00, C6502::SR_ZERO, 2));
+}
+
+#[test]
+fn test_sbx() {
+    // No borrow
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xCB, 0x02])
+            .with_state(|c| c.ac = 0x0F)
+            .with_state(|c| c.x = 0x3C)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0x0F, 0x0A, C6502::SR_CARRY, 2)
+    );
+
+    // Borrow, ignoring the carry flag and decimal mode, and leaving overflow alone
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xCB, 0x10])
+            .with_state(|c| c.ac = 0x0F)
+            .with_state(|c| c.x = 0x3C)
+            .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_OVERFLOW)
+            .run_one()
+            .values(|c| (c.x, c.p)),
+        (0xFC, C6502::SR_BCD | C6502::SR_OVERFLOW | C6502::SR_NEGATIVE)
+    );
+
+    // Equal
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xCB, 0x0C])
+            .with_state(|c| c.ac = 0x0F)
+            .with_state(|c| c.x = 0x3C)
+            .run_one()
+            .values(|c| (c.x, c.p)),
+        (0x00, C6502::SR_ZERO | C6502::SR_CARRY)
+    );
+}
+
+#[test]
+fn test_ane() {
+    // With the default magic constant of $EE
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x8B, 0x5A])
+            .with_state(|c| c.ac = 0x11)
+            .with_state(|c| c.x = 0xF0)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0x50, 0xF0, 0x00, 2)
+    );
+
+    // Bits clear in the magic constant come from the accumulator
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x8B, 0xFF])
+            .with_state(|c| c.ac = 0x00)
+            .with_state(|c| c.x = 0x11)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_ZERO)
+    );
+
+    // With a magic constant of $FF, the accumulator drops out
+    let mut test = CpuTest::new();
+    test.cpu.set_unstable_magic(0xFF);
+    assert_eq_hex!(
+        test.with_instruction(&[0x8B, 0xC3])
+            .with_state(|c| c.x = 0x81)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x81, C6502::SR_NEGATIVE)
+    );
+}
+
+#[test]
+fn test_lxa() {
+    // With the default magic constant of $EE
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xAB, 0xF0])
+            .with_state(|c| c.ac = 0x01)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0xE0, 0xE0, C6502::SR_NEGATIVE, 2)
+    );
+
+    // Bits clear in the magic constant come from the accumulator
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xAB, 0x11])
+            .with_state(|c| c.ac = 0x00)
+            .with_state(|c| c.x = 0xFF)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.p)),
+        (0x00, 0x00, C6502::SR_ZERO)
+    );
+}
+
 #[test]
 fn test_asl() {
     // Shift left a number with no carry
@@ -959,9 +1172,28 @@ fn test_brk() {
             .with_data(0xFFFE, &[0x48, 0x84])
             .with_state(|c| c.p = C6502::SR_ZERO)
             .run_one()
-            .values(|c| (c.pc, c.sp, c.stack(0), c.stack(1), c.stack(2), c.cycles)),
-        (0x8448, 0xFC, C6502::SR_ZERO | C6502::SR_BREAK | C6502::SR_UNUSED, 0x02, 0x04, 7)
-    );
+            .values(|c| (c.pc, c.sp, c.p, c.stack(0), c.stack(1), c.stack(2), c.cycles)),
+        (
+            0x8448,
+            0xFC,
+            C6502::SR_ZERO | C6502::SR_INTERRUPT_MASK,
+            C6502::SR_ZERO | C6502::SR_BREAK | C6502::SR_UNUSED,
+            0x02,
+            0x04,
+            7
+        )
+    );
+
+    // RTI from the handler restores the interrupt mask as it was before the BRK
+    for p in [C6502::SR_CARRY, C6502::SR_CARRY | C6502::SR_INTERRUPT_MASK] {
+        let mut test = CpuTest::new();
+        test.with_instruction(&[0x00, 0xEA]).with_data(0xFFFE, &[0x48, 0x84]).with_data(0x8448, &[0x40]);
+        test.p = p;
+        test.run_one();
+        assert_eq_hex!(test.p, p | C6502::SR_INTERRUPT_MASK);
+        test.run_one();
+        assert_eq_hex!((test.pc, test.sp, test.p), (0x0402, 0xFF, p));
+    }
 }
 
 #[test]
@@ -1252,6 +1484,136 @@ fn test_dey() {
     );
 }
 
+#[test]
+fn test_dcp() {
+    // Decrement to a value equal to the accumulator
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xC7, 0x20])
+            .with_data(0x20, &[0x41])
+            .with_state(|c| c.ac = 0x40)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x40, 0x40, C6502::SR_ZERO | C6502::SR_CARRY, 5)
+    );
+
+    // Decrement wrapping to a value greater than the accumulator
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xCF, 0x40, 0x10])
+            .with_data(0x1040, &[0x00])
+            .with_state(|c| c.ac = 0x40)
+            .with_state(|c| c.p = C6502::SR_CARRY | C6502::SR_OVERFLOW)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.p, c.cycles)),
+        (0xFF, C6502::SR_OVERFLOW, 6)
+    );
+
+    // Decrement to a value less than the accumulator, with each indexed mode taking the full
+    // read-modify-write time whether or not a page is crossed
+    for (ins, target, cycles) in [
+        (&[0xD7, 0x10][..], 0x0020, 6),
+        (&[0xDF, 0x10, 0x10][..], 0x1020, 7),
+        (&[0xDB, 0x10, 0x10][..], 0x1020, 7),
+        (&[0xC3, 0x30][..], 0x1020, 8),
+        (&[0xD3, 0x50][..], 0x1020, 8),
+    ] {
+        let mut test = CpuTest::new();
+        test.with_instruction(ins)
+            .with_data(target, &[0x11])
+            .with_data(0x40, &[0x20, 0x10])
+            .with_data(0x50, &[0x10, 0x10])
+            .with_state(|c| c.ac = 0x80)
+            .with_state(|c| c.x = 0x10)
+            .with_state(|c| c.y = 0x10)
+            .run_one();
+        assert_eq_hex!((test.data(target), test.p, test.cycles), (0x10, C6502::SR_CARRY, cycles));
+    }
+}
+
+#[test]
+fn test_isc() {
+    // Increment and subtract, with the carry set (no borrow)
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE7, 0x20])
+            .with_data(0x20, &[0x0F])
+            .with_state(|c| c.ac = 0x50)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x10, 0x40, C6502::SR_CARRY, 5)
+    );
+
+    // Increment wrapping to zero, with a borrow in
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xEF, 0x40, 0x10])
+            .with_data(0x1040, &[0xFF])
+            .with_state(|c| c.ac = 0x00)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.ac, c.p, c.cycles)),
+        (0x00, 0xFF, C6502::SR_NEGATIVE, 6)
+    );
+
+    // Signed overflow
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE7, 0x20])
+            .with_data(0x20, &[0x00])
+            .with_state(|c| c.ac = 0x80)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x7F, C6502::SR_CARRY | C6502::SR_OVERFLOW)
+    );
+
+    // Decimal mode subtracts the incremented value as BCD
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE7, 0x20])
+            .with_data(0x20, &[0x09])
+            .with_state(|c| c.ac = 0x50)
+            .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p & C6502::SR_CARRY)),
+        (0x0A, 0x40, C6502::SR_CARRY)
+    );
+
+    // Decimal mode with a borrow out
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE7, 0x20])
+            .with_data(0x20, &[0x19])
+            .with_state(|c| c.ac = 0x10)
+            .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p & C6502::SR_CARRY)),
+        (0x90, 0x00)
+    );
+
+    // Indexed modes
+    for (ins, target, cycles) in [
+        (&[0xF7, 0x10][..], 0x0020, 6),
+        (&[0xFF, 0x10, 0x10][..], 0x1020, 7),
+        (&[0xFB, 0x10, 0x10][..], 0x1020, 7),
+        (&[0xE3, 0x30][..], 0x1020, 8),
+        (&[0xF3, 0x50][..], 0x1020, 8),
+    ] {
+        let mut test = CpuTest::new();
+        test.with_instruction(ins)
+            .with_data(target, &[0x01])
+            .with_data(0x40, &[0x20, 0x10])
+            .with_data(0x50, &[0x10, 0x10])
+            .with_state(|c| c.ac = 0x05)
+            .with_state(|c| c.x = 0x10)
+            .with_state(|c| c.y = 0x10)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one();
+        assert_eq_hex!((test.data(target), test.ac, test.cycles), (0x02, 0x03, cycles));
+    }
+}
+
 #[test]
 fn test_eor() {
     // XORr #$28 and #$48 to get #$60
@@ -1436,88 +1798,311 @@ fn test_jsr() {
 }
 
 #[test]
-fn test_lda() {
-    // Load non-zero number
+fn test_slo() {
+    // Shift out a carry, and OR into the accumulator
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA9, 0x10]).run_one().values(|c| (c.ac, c.p)),
-        (0x10, 0x00)
+        CpuTest::new()
+            .with_instruction(&[0x07, 0x20])
+            .with_data(0x20, &[0x81])
+            .with_state(|c| c.ac = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x02, 0x12, C6502::SR_CARRY, 5)
     );
 
-    // Load zero
+    // Negative result
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA9, 0x00]).run_one().values(|c| (c.ac, c.p)),
-        (0x00, C6502::SR_ZERO)
+        CpuTest::new()
+            .with_instruction(&[0x0F, 0x40, 0x10])
+            .with_data(0x1040, &[0x40])
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.ac, c.p, c.cycles)),
+        (0x80, 0x80, C6502::SR_NEGATIVE, 6)
     );
+}
 
-    // Load negative number
+#[test]
+fn test_rla() {
+    // Rotate in the carry, rotate out a carry, and AND into the accumulator
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA9, 0x80]).run_one().values(|c| (c.ac, c.p)),
-        (0x80, C6502::SR_NEGATIVE)
+        CpuTest::new()
+            .with_instruction(&[0x27, 0x20])
+            .with_data(0x20, &[0x81])
+            .with_state(|c| c.ac = 0x0F)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x03, 0x03, C6502::SR_CARRY, 5)
+    );
+
+    // Zero result
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x2F, 0x40, 0x10])
+            .with_data(0x1040, &[0x40])
+            .with_state(|c| c.ac = 0x7F)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.ac, c.p, c.cycles)),
+        (0x80, 0x00, C6502::SR_ZERO, 6)
     );
 }
 
 #[test]
-fn test_ldx() {
-    // Load non-zero number
+fn test_sre() {
+    // Shift out a carry, and XOR into the accumulator
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA2, 0x10]).run_one().values(|c| (c.x, c.p)),
-        (0x10, 0x00)
+        CpuTest::new()
+            .with_instruction(&[0x47, 0x20])
+            .with_data(0x20, &[0x03])
+            .with_state(|c| c.ac = 0x81)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x01, 0x80, C6502::SR_NEGATIVE | C6502::SR_CARRY, 5)
     );
 
-    // Load zero
+    // Zero result
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA2, 0x00]).run_one().values(|c| (c.x, c.p)),
-        (0x00, C6502::SR_ZERO)
+        CpuTest::new()
+            .with_instruction(&[0x4F, 0x40, 0x10])
+            .with_data(0x1040, &[0x80])
+            .with_state(|c| c.ac = 0x40)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.ac, c.p, c.cycles)),
+        (0x40, 0x00, C6502::SR_ZERO, 6)
     );
+}
 
-    // Load negative number
+#[test]
+fn test_rra() {
+    // The carry rotated out is added in
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA2, 0x80]).run_one().values(|c| (c.x, c.p)),
-        (0x80, C6502::SR_NEGATIVE)
+        CpuTest::new()
+            .with_instruction(&[0x67, 0x20])
+            .with_data(0x20, &[0x03])
+            .with_state(|c| c.ac = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x20), c.ac, c.p, c.cycles)),
+        (0x01, 0x12, 0x00, 5)
+    );
+
+    // The carry rotated in comes from before the instruction, and the addition's carry out
+    // replaces the rotation's
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x6F, 0x40, 0x10])
+            .with_data(0x1040, &[0x02])
+            .with_state(|c| c.ac = 0x80)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.ac, c.p, c.cycles)),
+        (0x81, 0x01, C6502::SR_CARRY | C6502::SR_OVERFLOW, 6)
     );
 }
 
 #[test]
-fn test_ldy() {
+fn test_shift_logic_addressing_modes() {
+    // Each of SLO, RLA, SRE and RRA takes the full read-modify-write time in the indexed
+    // modes, whether or not a page is crossed.
+    for base in [0x00u8, 0x20, 0x40, 0x60] {
+        for (ins, target, cycles) in [
+            ([0x17, 0x10, 0xEA], 0x0020, 6),
+            ([0x1F, 0x10, 0x10], 0x1020, 7),
+            ([0x1B, 0x10, 0x10], 0x1020, 7),
+            ([0x03, 0x30, 0xEA], 0x1020, 8),
+            ([0x13, 0x50, 0xEA], 0x1020, 8),
+        ] {
+            let mut test = CpuTest::new();
+            test.with_instruction(&[ins[0] + base, ins[1], ins[2]])
+                .with_data(target, &[0x02])
+                .with_data(0x40, &[0x20, 0x10])
+                .with_data(0x50, &[0x10, 0x10])
+                .with_state(|c| c.x = 0x10)
+                .with_state(|c| c.y = 0x10)
+                .run_one();
+            let expected = if base < 0x40 { 0x04 } else { 0x01 };
+            assert_eq_hex!((test.data(target), test.cycles), (expected, cycles));
+        }
+    }
+}
+
+#[test]
+fn test_lda() {
     // Load non-zero number
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA0, 0x10]).run_one().values(|c| (c.y, c.p)),
+        CpuTest::new().with_instruction(&[0xA9, 0x10]).run_one().values(|c| (c.ac, c.p)),
         (0x10, 0x00)
     );
 
     // Load zero
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA0, 0x00]).run_one().values(|c| (c.y, c.p)),
+        CpuTest::new().with_instruction(&[0xA9, 0x00]).run_one().values(|c| (c.ac, c.p)),
         (0x00, C6502::SR_ZERO)
     );
 
     // Load negative number
     assert_eq_hex!(
-        CpuTest::new().with_instruction(&[0xA0, 0x80]).run_one().values(|c| (c.y, c.p)),
+        CpuTest::new().with_instruction(&[0xA9, 0x80]).run_one().values(|c| (c.ac, c.p)),
         (0x80, C6502::SR_NEGATIVE)
     );
 }
 
 #[test]
-fn test_lsr() {
-    // Shift right a number with no carry
+fn test_lax() {
+    // Zero-page - LAX $50
     assert_eq_hex!(
         CpuTest::new()
-            .with_instruction(&[0x4A])
-            .with_state(|c| c.ac = 0x08)
+            .with_instruction(&[0xA7, 0x50])
+            .with_data(0x50, &[0x48])
             .run_one()
-            .values(|c| (c.ac, c.p)),
-        (0x04, 0x00)
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0x48, 0x48, 0x00, 3)
     );
 
-    // Shift right a zero
+    // Zero-page, Y-indexed with zero-page wrapping - LAX $80,Y
     assert_eq_hex!(
         CpuTest::new()
-            .with_instruction(&[0x4A])
-            .with_state(|c| c.ac = 0x00)
+            .with_instruction(&[0xB7, 0x80])
+            .with_data(0x10, &[0x00])
+            .with_state(|c| c.y = 0x90)
+            .with_state(|c| c.ac = 0x12)
             .run_one()
-            .values(|c| (c.ac, c.p)),
-        (0x00, C6502::SR_ZERO)
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0x00, 0x00, C6502::SR_ZERO, 4)
+    );
+
+    // Absolute - LAX $1040
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xAF, 0x40, 0x10])
+            .with_data(0x1040, &[0x80])
+            .run_one()
+            .values(|c| (c.ac, c.x, c.p, c.cycles)),
+        (0x80, 0x80, C6502::SR_NEGATIVE, 4)
+    );
+
+    // Absolute, Y-indexed - LAX $1000,Y
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xBF, 0x00, 0x10])
+            .with_data(0x1040, &[0x48])
+            .with_state(|c| c.y = 0x40)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.cycles)),
+        (0x48, 0x48, 4)
+    );
+
+    // Absolute, Y-indexed with page crossing
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xBF, 0xF0, 0x1F])
+            .with_data(0x2000, &[0x48])
+            .with_state(|c| c.y = 0x10)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.cycles)),
+        (0x48, 0x48, 5)
+    );
+
+    // Indexed indirect - LAX ($40,X)
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xA3, 0x40])
+            .with_data(0x50, &[0x00, 0x10])
+            .with_data(0x1000, &[0x48])
+            .with_state(|c| c.x = 0x10)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.cycles)),
+        (0x48, 0x48, 6)
+    );
+
+    // Indirect indexed - LAX ($80),Y
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xB3, 0x80])
+            .with_data(0x80, &[0x00, 0x10])
+            .with_data(0x1040, &[0x48])
+            .with_state(|c| c.y = 0x40)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.cycles)),
+        (0x48, 0x48, 5)
+    );
+
+    // Indirect indexed with page crossing
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xB3, 0x80])
+            .with_data(0x80, &[0xF0, 0x1F])
+            .with_data(0x2000, &[0x48])
+            .with_state(|c| c.y = 0x10)
+            .run_one()
+            .values(|c| (c.ac, c.x, c.cycles)),
+        (0x48, 0x48, 6)
+    );
+}
+
+#[test]
+fn test_ldx() {
+    // Load non-zero number
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA2, 0x10]).run_one().values(|c| (c.x, c.p)),
+        (0x10, 0x00)
+    );
+
+    // Load zero
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA2, 0x00]).run_one().values(|c| (c.x, c.p)),
+        (0x00, C6502::SR_ZERO)
+    );
+
+    // Load negative number
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA2, 0x80]).run_one().values(|c| (c.x, c.p)),
+        (0x80, C6502::SR_NEGATIVE)
+    );
+}
+
+#[test]
+fn test_ldy() {
+    // Load non-zero number
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA0, 0x10]).run_one().values(|c| (c.y, c.p)),
+        (0x10, 0x00)
+    );
+
+    // Load zero
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA0, 0x00]).run_one().values(|c| (c.y, c.p)),
+        (0x00, C6502::SR_ZERO)
+    );
+
+    // Load negative number
+    assert_eq_hex!(
+        CpuTest::new().with_instruction(&[0xA0, 0x80]).run_one().values(|c| (c.y, c.p)),
+        (0x80, C6502::SR_NEGATIVE)
+    );
+}
+
+#[test]
+fn test_lsr() {
+    // Shift right a number with no carry
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x4A])
+            .with_state(|c| c.ac = 0x08)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x04, 0x00)
+    );
+
+    // Shift right a zero
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x4A])
+            .with_state(|c| c.ac = 0x00)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_ZERO)
     );
 
     // Shift right to clear a negative
@@ -1712,6 +2297,98 @@ fn test_plp() {
     );
 }
 
+#[test]
+fn test_stack_wrap() {
+    // Pushing with the stack pointer at $00 writes $0100, and wraps the stack pointer to $FF.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x48, 0x08]) // PHA; PHP
+            .with_state(|c| c.ac = 0x24)
+            .with_state(|c| c.sp = 0x00)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run(2)
+            .values(|c| (c.data(0x0100), c.data(0x01FF), c.sp, c.p, c.pc, c.cycles)),
+        (0x24, C6502::SR_CARRY | C6502::SR_BREAK | C6502::SR_UNUSED, 0xFE, C6502::SR_CARRY, 0x0402, 6)
+    );
+
+    // Pulling with the stack pointer at $FF reads $0100, and wraps the stack pointer to $00.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x68]) // PLA
+            .with_data(0x0100, &[0x80])
+            .with_state(|c| c.sp = 0xFF)
+            .run_one()
+            .values(|c| (c.ac, c.sp, c.p, c.pc, c.cycles)),
+        (0x80, 0x00, C6502::SR_NEGATIVE, 0x0401, 4)
+    );
+
+    // A subroutine call and return across the wrap.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x20, 0x00, 0x05]) // JSR $0500
+            .with_data(0x0500, &[0x60]) // RTS
+            .with_state(|c| c.sp = 0x00)
+            .run(1)
+            .values(|c| (c.data(0x0100), c.data(0x01FF), c.sp, c.pc)),
+        (0x04, 0x02, 0xFE, 0x0500)
+    );
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x20, 0x00, 0x05]) // JSR $0500
+            .with_data(0x0500, &[0x60]) // RTS
+            .with_state(|c| c.sp = 0x00)
+            .run(2)
+            .values(|c| (c.sp, c.pc)),
+        (0x00, 0x0403)
+    );
+}
+
+#[test]
+fn test_pc_wrap() {
+    // LDA #$42 with the opcode at $FFFF and the operand at $0000. The next opcode is fetched
+    // from $0001 as the load completes.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_pc(0xFFFF)
+            .with_data(0xFFFF, &[0xA9])
+            .with_data(0x0000, &[0x42])
+            .run_one()
+            .values(|c| (c.ac, c.pc, c.cycles)),
+        (0x42, 0x0002, 2)
+    );
+
+    // BNE from the top of memory, forward past $FFFF.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_pc(0xFFFC)
+            .with_data(0xFFFC, &[0xD0, 0x10])
+            .run_one()
+            .values(|c| (c.pc, c.cycles)),
+        (0x000E, 4)
+    );
+
+    // BNE from the bottom of memory, back past $0000.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_pc(0x0000)
+            .with_data(0x0000, &[0xD0, 0xEC])
+            .run_one()
+            .values(|c| (c.pc, c.cycles)),
+        (0xFFEE, 4)
+    );
+
+    // JSR with its operand straddling $FFFF/$0000 pushes the address of its last byte.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_pc(0xFFFE)
+            .with_data(0xFFFE, &[0x20, 0x00])
+            .with_data(0x0000, &[0x05])
+            .run_one()
+            .values(|c| (c.pc, c.stack(0), c.stack(1))),
+        (0x0500, 0x00, 0x00)
+    );
+}
+
 #[test]
 fn test_rol() {
     // Rotate left with no carry-in or carry-out
@@ -2008,7 +2685,224 @@ fn test_sbc() {
             .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_CARRY)
             .run_one()
             .values(|c| (c.ac, c.p)),
-        (0x99, C6502::SR_BCD)
+        (0x99, C6502::SR_BCD | C6502::SR_NEGATIVE)
+    );
+
+    // In BCD mode, the flags are those of the binary subtraction.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE9, 0x01])
+            .with_state(|c| c.ac = 0x01)
+            .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x00, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_ZERO)
+    );
+
+    // Signed overflow of the binary subtraction sets V.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xE9, 0x01])
+            .with_state(|c| c.ac = 0x80)
+            .with_state(|c| c.p = C6502::SR_BCD | C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.ac, c.p)),
+        (0x79, C6502::SR_BCD | C6502::SR_CARRY | C6502::SR_OVERFLOW)
+    );
+}
+
+#[test]
+fn test_2a03_ignores_decimal() {
+    // SED; CLC; LDA #$19; ADC #$28; PHP; SEC; SBC #$05
+    let program = [0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28, 0x08, 0x38, 0xE9, 0x05];
+    for (is_2a03, sum, difference) in [(false, 0x47, 0x42), (true, 0x41, 0x3C)] {
+        let mut test = CpuTest::new();
+        if is_2a03 {
+            test.cpu = C6502::new_2a03(&test.mem);
+        }
+        test.with_instruction(&program).run(5);
+        assert_eq_hex!(test.ac, sum);
+        assert_eq_hex!(test.stack(0) & C6502::SR_BCD, C6502::SR_BCD);
+        test.run(2);
+        assert_eq_hex!(test.ac, difference);
+    }
+
+    // The flag still reads back as set.
+    let mut test = CpuTest::new();
+    test.cpu = C6502::new_2a03(&test.mem);
+    test.with_instruction(&[0xF8, 0xA9, 0x09, 0x18, 0x69, 0x01]).run(4);
+    assert_eq_hex!((test.ac, test.p & C6502::SR_BCD), (0x0A, C6502::SR_BCD));
+}
+
+#[test]
+fn test_bcd_invalid_operands() {
+    // Results of decimal-mode ADC and SBC on operands that aren't valid BCD, as produced by an
+    // NMOS 6502: (instruction, A, operand, carry in, result, status).
+    const N: u8 = C6502::SR_NEGATIVE;
+    const V: u8 = C6502::SR_OVERFLOW;
+    const D: u8 = C6502::SR_BCD;
+    const Z: u8 = C6502::SR_ZERO;
+    const C: u8 = C6502::SR_CARRY;
+    let cases = [
+        (0x69, 0x0F, 0x01, false, 0x16, D),
+        (0x69, 0x0A, 0x00, false, 0x10, D),
+        (0x69, 0x1F, 0x1F, true, 0x35, D),
+        (0x69, 0x0B, 0x0C, false, 0x1D, D),
+        (0x69, 0xAA, 0xAA, false, 0xBA, D | V | C),
+        (0x69, 0xF0, 0x10, false, 0x60, D | Z | C),
+        (0x69, 0x9F, 0x01, false, 0x06, N | D | C),
+        (0x69, 0xFF, 0xFF, true, 0x55, N | D | C),
+        (0xE9, 0x00, 0x0F, true, 0x9B, N | D),
+        (0xE9, 0x0A, 0x00, true, 0x0A, D | C),
+        (0xE9, 0x10, 0x0B, true, 0x0F, D | C),
+        (0xE9, 0xFF, 0xFF, false, 0x99, N | D),
+        (0xE9, 0x00, 0xAA, true, 0xF0, D),
+        (0xE9, 0x1F, 0x0A, false, 0x14, D | C),
+        (0xE9, 0x20, 0x1F, true, 0x0B, D | C),
+        (0xE9, 0xB0, 0x0C, true, 0xAE, N | D | C),
+    ];
+    for (opcode, ac, operand, carry, result, status) in cases {
+        let mut test = CpuTest::new();
+        test.with_instruction(&[opcode, operand]);
+        test.ac = ac;
+        test.p = if carry { D | C } else { D };
+        test.run_one();
+        assert_eq!(
+            (test.ac, test.p),
+            (result, status),
+            "{:02X} with A={:02X}, operand={:02X}, carry={}",
+            opcode,
+            ac,
+            operand,
+            carry
+        );
+    }
+}
+
+#[test]
+fn test_sax() {
+    // Zero-page - SAX $20
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x87, 0x20])
+            .with_state(|c| c.ac = 0xF0)
+            .with_state(|c| c.x = 0x3C)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x20), c.p, c.cycles)),
+        (0x30, C6502::SR_CARRY, 3)
+    );
+
+    // Zero-page, Y-indexed - SAX $10,Y, with a zero result leaving the flags alone
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x97, 0x10])
+            .with_data(0x20, &[0xFF])
+            .with_state(|c| c.ac = 0x0F)
+            .with_state(|c| c.x = 0xF0)
+            .with_state(|c| c.y = 0x10)
+            .with_state(|c| c.p = C6502::SR_NEGATIVE)
+            .run_one()
+            .values(|c| (c.data(0x20), c.p, c.cycles)),
+        (0x00, C6502::SR_NEGATIVE, 4)
+    );
+
+    // Absolute - SAX $1040
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x8F, 0x40, 0x10])
+            .with_state(|c| c.ac = 0xC3)
+            .with_state(|c| c.x = 0x81)
+            .run_one()
+            .values(|c| (c.data(0x1040), c.p, c.cycles)),
+        (0x81, 0x00, 4)
+    );
+
+    // Indexed indirect - SAX ($40,X)
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x83, 0x40])
+            .with_data(0x50, &[0x00, 0x10])
+            .with_state(|c| c.ac = 0x5F)
+            .with_state(|c| c.x = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x1000), c.p, c.cycles)),
+        (0x10, 0x00, 6)
+    );
+}
+
+#[test]
+fn test_sh() {
+    // Absolute, Y-indexed - SHX $1234,Y stores X ANDed with the high byte of the address plus 1
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x9E, 0x34, 0x12])
+            .with_state(|c| c.x = 0xFF)
+            .with_state(|c| c.y = 0x10)
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .run_one()
+            .values(|c| (c.data(0x1244), c.p, c.cycles)),
+        (0x13, C6502::SR_CARRY, 5)
+    );
+
+    // Crossing a page, the stored value replaces the high byte of the address - SHX $12F0,Y
+    // stores to $0110 rather than $1310
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x9E, 0xF0, 0x12])
+            .with_state(|c| c.x = 0x05)
+            .with_state(|c| c.y = 0x20)
+            .run_one()
+            .values(|c| (c.data(0x0110), c.data(0x1310), c.cycles)),
+        (0x01, 0x00, 5)
+    );
+
+    // Absolute, X-indexed - SHY $12F0,X, crossing a page
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x9C, 0xF0, 0x12])
+            .with_state(|c| c.x = 0x20)
+            .with_state(|c| c.y = 0x07)
+            .run_one()
+            .values(|c| (c.data(0x0310), c.data(0x1310), c.cycles)),
+        (0x03, 0x00, 5)
+    );
+
+    // Absolute, Y-indexed - SHA $1234,Y stores A AND X AND $13
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x9F, 0x34, 0x12])
+            .with_state(|c| c.ac = 0xF3)
+            .with_state(|c| c.x = 0x3F)
+            .with_state(|c| c.y = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x1244), c.ac, c.x, c.cycles)),
+        (0x13, 0xF3, 0x3F, 5)
+    );
+
+    // Indirect, Y-indexed - SHA ($40),Y
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x93, 0x40])
+            .with_data(0x40, &[0x34, 0x12])
+            .with_state(|c| c.ac = 0xF3)
+            .with_state(|c| c.x = 0x3F)
+            .with_state(|c| c.y = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x1244), c.cycles)),
+        (0x13, 6)
+    );
+
+    // TAS $1234,Y sets the stack pointer to A AND X, and stores it as SHA does
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0x9B, 0x34, 0x12])
+            .with_state(|c| c.ac = 0xF3)
+            .with_state(|c| c.x = 0x3F)
+            .with_state(|c| c.y = 0x10)
+            .run_one()
+            .values(|c| (c.data(0x1244), c.sp, c.p, c.cycles)),
+        (0x13, 0x33, 0x00, 5)
     );
 }
 
@@ -2151,3 +3045,1420 @@ fn test_tya() {
         (0x48, 2)
     );
 }
+
+#[test]
+fn test_reset_kinds() {
+    let mem = Memory::new();
+    mem.set_vectors(Vectors { nmi: None, reset: 0xE000, irq: None });
+    mem.write_byte(0x0200, 0x5A);
+    let mut cpu = C6502::new(&mem);
+    cpu.ac = 0x12;
+
+    // A soft reset keeps memory and registers, and restarts at the reset vector.
+    let mut computer = Computer::new();
+    computer.add_resettable(mem.clone());
+    computer.reset(ResetKind::Soft);
+    Resettable::reset(&mut cpu, ResetKind::Soft);
+    while cpu.step() == CpuAction::Continue {}
+    assert_eq!(cpu.state(), CpuState::Running);
+    assert_eq_hex!(cpu.pc, 0xE000);
+    assert_eq_hex!(cpu.ac, 0x12);
+    assert_eq_hex!(mem.read_byte(0x0200), 0x5A);
+
+    // A hard reset clears RAM, including the vectors, and the CPU's registers.
+    computer.reset(ResetKind::Hard);
+    Resettable::reset(&mut cpu, ResetKind::Hard);
+    while cpu.step() == CpuAction::Continue {}
+    assert_eq_hex!(cpu.pc, 0x0000);
+    assert_eq_hex!(cpu.ac, 0xAA);
+    assert_eq_hex!(mem.read_byte(0x0200), 0x00);
+}
+
+#[test]
+fn test_reset_running_cpu() {
+    use crate::core::clock::Clock;
+
+    // INC $10; hang: JMP hang, in ROM so it survives a hard reset, with the reset vector at it.
+    let mut rom = vec![0; 0x100];
+    rom[0..5].copy_from_slice(&[0xE6, 0x10, 0x4C, 0x02, 0xFF]);
+    rom[0xFC..0xFE].copy_from_slice(&[0x00, 0xFF]);
+    let mem = Memory::new();
+    mem.configure_banks(vec![RomBank::with_bytes(&rom)], &[(0xFF00, 0x100, 1, 0x0000)]).unwrap();
+    let mut cpu = C6502::new(&mem);
+    cpu.reset();
+    let mut clock = Clock::new(1_000_000);
+    clock.output().connect_to(cpu.phi0_in());
+
+    // The CPU is reset through its handle while it runs on its own thread.
+    let mut computer = Computer::new();
+    computer.add_resettable(mem.clone());
+    computer.add_resettable(cpu.reset_handle());
+    computer.add_async(cpu);
+    computer.add_async(clock);
+    computer.start().unwrap();
+    let wait_for = |value| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while mem.read_byte(0x10) != value {
+            assert!(Instant::now() < deadline, "$10 is {}, expected {}", mem.read_byte(0x10), value);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+    wait_for(1);
+
+    // A soft reset keeps RAM, so the CPU counts another reset; a hard reset clears it first.
+    computer.reset(ResetKind::Soft);
+    wait_for(2);
+    computer.reset(ResetKind::Hard);
+    wait_for(1);
+    computer.stop();
+}
+
+#[test]
+fn test_timing_table_matches_execution() {
+    for opcode in 0..=255u8 {
+        if let Some(info) = timing::instruction_cycles(opcode) {
+            let cycles_with_flags = |p: u8| {
+                let mut test = CpuTest::new();
+                test.with_instruction(&[opcode, 0x10, 0x20]).with_stack(&[0x00, 0x00, 0x00]);
+                test.p = p;
+                test.run_one().cycles
+            };
+            let (clear, set) = (cycles_with_flags(0x00), cycles_with_flags(0xFF));
+            if info.branch_taken_penalty > 0 {
+                // Exactly one of the two flag settings takes the branch.
+                assert_eq!(clear.min(set), info.base as usize, "opcode ${:02X}", opcode);
+                assert_eq!(clear.max(set), (info.base + info.branch_taken_penalty) as usize, "opcode ${:02X}", opcode);
+            } else {
+                assert_eq!(clear, info.base as usize, "opcode ${:02X}", opcode);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_timing_penalties() {
+    // LDA $1000,X
+    assert_eq!(
+        timing::instruction_cycles(0xBD),
+        Some(timing::CycleInfo { base: 4, page_cross_penalty: 1, branch_taken_penalty: 0 })
+    );
+    // STA $1000,X
+    assert_eq!(
+        timing::instruction_cycles(0x9D),
+        Some(timing::CycleInfo { base: 5, page_cross_penalty: 0, branch_taken_penalty: 0 })
+    );
+    // BNE
+    assert_eq!(
+        timing::instruction_cycles(0xD0),
+        Some(timing::CycleInfo { base: 2, page_cross_penalty: 1, branch_taken_penalty: 1 })
+    );
+    assert_eq!(timing::instruction_cycles(0x02), None);
+}
+
+#[test]
+fn test_clock_edge_drives_phase_outputs() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xEA, 0x4C, 0x00, 0x04]); // NOP; JMP $0400
+    let mut cpu = C6502::new(&mem);
+    let mut phi1 = InputPin::new();
+    let mut phi2 = InputPin::new();
+    cpu.phi1_out().connect_to(&mut phi1);
+    cpu.phi2_out().connect_to(&mut phi2);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+
+    let mut cycles = 0;
+    for _ in 0..100 {
+        for signal in [true, false] {
+            if cpu.clock_edge(signal).is_some() {
+                cycles += 1;
+            }
+        }
+    }
+
+    let mut rising_edges = 0;
+    let mut last = phi2.value();
+    while let Some(value) = phi2.try_recv() {
+        if value && !last {
+            rising_edges += 1;
+        }
+        assert_eq!(phi1.try_recv(), Some(!value));
+        last = value;
+    }
+    assert_eq!(cycles, 100);
+    assert_eq!(rising_edges, cycles);
+}
+
+#[test]
+fn test_region_profile() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0x20, 0x00, 0x05, 0x20, 0x00, 0x06, 0x4C, 0x00, 0x04]); // JSR $0500; JSR $0600; JMP $0400
+    mem.write_block(0x0500, &[0xA2, 0x40, 0xCA, 0xD0, 0xFD, 0x60]); // LDX #$40; DEX; BNE *-1; RTS
+    mem.write_block(0x0600, &[0xA2, 0x04, 0xCA, 0xD0, 0xFD, 0x60]); // LDX #$04; DEX; BNE *-1; RTS
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    cpu.enable_region_profiling(profile::RegionMap::named(&[
+        ("main", 0x0400..=0x04FF),
+        ("copy_loop", 0x0500..=0x05FF),
+        ("short_loop", 0x0600..=0x06FF),
+    ]));
+
+    for _ in 0..20000 {
+        cpu.step();
+    }
+
+    let report = cpu.profile_report().unwrap();
+    let names: Vec<&str> = report.entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["copy_loop", "short_loop", "main"]);
+    assert!(report.entries[0].share > 0.75);
+    assert_eq!(report.total_cycles, 20000);
+
+    // The last cycle of an instruction, which fetches the next, counts towards the region of the
+    // instruction it completes: NOP at the end of one region takes the cycle that fetches JMP,
+    // at the start of the next.
+    mem.write_block(0x04FF, &[0xEA, 0x4C, 0xFF, 0x04]);
+    cpu.pc = 0x04FF;
+    cpu.cycle = 1;
+    cpu.enable_region_profiling(profile::RegionMap::named(&[("nop", 0x0400..=0x04FF), ("jmp", 0x0500..=0x05FF)]));
+    for _ in 0..5000 {
+        cpu.step();
+    }
+    let report = cpu.profile_report().unwrap();
+    let cycles: Vec<(&str, u64)> = report.entries.iter().map(|e| (e.name.as_str(), e.cycles)).collect();
+    assert_eq!(cycles, vec![("nop", 3000), ("jmp", 2000)]);
+}
+
+#[test]
+fn test_nmi() {
+    // NMI is serviced at the next instruction boundary, even with interrupts masked, and pushes
+    // the address of the interrupted instruction with the break flag clear.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xEA])
+            .with_data(0xFFFA, &[0x00, 0x20])
+            .with_state(|c| c.p = C6502::SR_INTERRUPT_MASK | C6502::SR_CARRY)
+            .with_state(|c| c.cpu.set_nmi(true))
+            .run_one()
+            .values(|c| (c.pc, c.sp, c.stack(0), c.stack(1), c.stack(2), c.p, c.cycles)),
+        (
+            0x2000,
+            0xFC,
+            C6502::SR_INTERRUPT_MASK | C6502::SR_CARRY | C6502::SR_UNUSED,
+            0x00,
+            0x04,
+            C6502::SR_INTERRUPT_MASK | C6502::SR_CARRY,
+            7
+        )
+    );
+
+    // Holding the line asserted doesn't retrigger the interrupt after the handler returns.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xEA, 0xEA, 0xEA])
+        .with_data(0xFFFA, &[0x00, 0x20])
+        .with_data(0x2000, &[0x40]) // RTI
+        .with_state(|c| c.cpu.set_nmi(true))
+        .run(2);
+    assert_eq_hex!(test.pc, 0x0400);
+    test.run(2);
+    assert_eq_hex!(test.pc, 0x0403); // The third NOP has already been fetched.
+
+    // A second edge while the handler is running is serviced after RTI.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xEA, 0xEA])
+        .with_data(0xFFFA, &[0x00, 0x20])
+        .with_data(0x2000, &[0xEA, 0x40]) // NOP; RTI
+        .with_state(|c| c.cpu.set_nmi(true))
+        .run_one();
+    assert_eq_hex!(test.pc, 0x2000);
+    test.cpu.set_nmi(false);
+    test.cpu.set_nmi(true);
+    test.run_one();
+    assert_eq_hex!(test.pc, 0x2002); // The handler's NOP ran, and RTI has been fetched.
+    test.run_one();
+    assert_eq_hex!(test.pc, 0x0400);
+    test.run_one();
+    assert_eq_hex!((test.pc, test.sp), (0x2000, 0xFC));
+}
+
+#[test]
+fn test_nmi_hijacks_brk() {
+    // An NMI edge during the first four cycles of a BRK sends it through the NMI vector, with
+    // the break flag still set on the stacked status. Later edges wait for the next boundary.
+    for (edge_after, pc, handled) in [(1, 0x2000, true), (4, 0x2000, true), (5, 0x8448, false)] {
+        let mem = Memory::new();
+        mem.write_block(0x0400, &[0x00, 0xEA]);
+        mem.write_word(C6502::NMI_VECTOR, 0x2000);
+        mem.write_word(C6502::IRQ_VECTOR, 0x8448);
+        let mut cpu = C6502::new(&mem);
+        cpu.state = CpuState::Running;
+        cpu.pc = 0x0400;
+        cpu.sp = 0xFF;
+        cpu.p = C6502::SR_CARRY;
+        let mut cycles = 0;
+        loop {
+            let action = cpu.step();
+            cycles += 1;
+            if cycles == edge_after {
+                cpu.set_nmi(true);
+            }
+            if action == CpuAction::Complete {
+                break;
+            }
+        }
+        assert_eq!(cycles, 7);
+        assert_eq_hex!(cpu.pc, pc);
+        assert_eq_hex!(mem.read_byte(0x01FD), C6502::SR_CARRY | C6502::SR_BREAK | C6502::SR_UNUSED);
+        assert_eq!(cpu.nmi_pending, !handled);
+    }
+}
+
+#[test]
+fn test_reset_line() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x4C, 0x00, 0x04]); // INX; JMP $0400
+    mem.write_word(C6502::RESET_VECTOR, 0x0400);
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    for _ in 0..10 {
+        cpu.step();
+    }
+
+    // The CPU stays in the reset sequence for as long as the line is held.
+    cpu.set_reset(true);
+    for _ in 0..100 {
+        assert_eq!(cpu.step(), CpuAction::Continue);
+    }
+    assert_eq!(cpu.state(), CpuState::Resetting);
+    assert_eq!(cpu.cycle, 1);
+
+    // Releasing the line completes the reset sequence.
+    cpu.set_reset(false);
+    let mut cycles = 1;
+    while cpu.step() != CpuAction::Complete {
+        cycles += 1;
+    }
+    assert_eq!(cpu.state(), CpuState::Running);
+    assert_eq_hex!(cpu.pc, 0x0400);
+    assert_eq!(cycles, 7);
+}
+
+#[test]
+fn test_reset_bus() {
+    let mem = Memory::new();
+    mem.write_word(C6502::RESET_VECTOR, 0x0400);
+    let mut cpu = C6502::new(&mem);
+    let mut addr = InputPort16::new();
+    let mut rw = InputPin::new();
+    cpu.addr_out().connect_to(&mut addr);
+    cpu.rw_out().connect_to(&mut rw);
+    let mut reset = |cpu: &mut C6502| {
+        cpu.reset();
+        while cpu.step() != CpuAction::Complete {}
+        let mut accesses = Vec::new();
+        while let Some(a) = addr.try_recv() {
+            accesses.push(a);
+            assert!(rw.try_recv().unwrap(), "write to ${:04X}", a);
+        }
+        accesses
+    };
+
+    // The power-on reset reads the PC twice, then the stack three times, taking the stack pointer
+    // from $00 down to $FD, and then the vector.
+    assert_eq!(reset(&mut cpu), vec![0x00FF, 0x00FF, 0x0100, 0x01FF, 0x01FE, 0xFFFC, 0xFFFD]);
+    assert_eq_hex!((cpu.pc, cpu.sp), (0x0400, 0xFD));
+
+    // Later resets take the stack pointer down by three from wherever it is.
+    assert_eq!(reset(&mut cpu), vec![0x0400, 0x0400, 0x01FD, 0x01FC, 0x01FB, 0xFFFC, 0xFFFD]);
+    assert_eq_hex!((cpu.pc, cpu.sp), (0x0400, 0xFA));
+}
+
+#[test]
+fn test_irq() {
+    // IRQ is serviced at the next instruction boundary, and sets the interrupt mask.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xEA])
+            .with_data(0xFFFE, &[0x00, 0x30])
+            .with_state(|c| c.p = C6502::SR_CARRY)
+            .with_state(|c| c.cpu.set_irq(true))
+            .run_one()
+            .values(|c| (c.pc, c.sp, c.stack(0), c.stack(1), c.stack(2), c.p, c.cycles)),
+        (0x3000, 0xFC, C6502::SR_CARRY | C6502::SR_UNUSED, 0x00, 0x04, C6502::SR_INTERRUPT_MASK | C6502::SR_CARRY, 7)
+    );
+
+    // IRQ is ignored while the interrupt mask is set.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xEA])
+            .with_data(0xFFFE, &[0x00, 0x30])
+            .with_state(|c| c.p = C6502::SR_INTERRUPT_MASK)
+            .with_state(|c| c.cpu.set_irq(true))
+            .run_one()
+            .values(|c| (c.pc, c.cycles)),
+        (0x0402, 2)
+    );
+
+    // IRQ is level-sensitive, so a handler that returns without the line being released is
+    // interrupted again straight away.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xEA, 0xEA])
+        .with_data(0xFFFE, &[0x00, 0x30])
+        .with_data(0x3000, &[0x40]) // RTI
+        .with_state(|c| c.cpu.set_irq(true))
+        .run(2);
+    assert_eq_hex!(test.pc, 0x0400);
+    test.run_one();
+    assert_eq_hex!(test.pc, 0x3000);
+
+    // Once released, execution resumes where it was interrupted.
+    test.with_state(|c| c.cpu.set_irq(false)).run(2);
+    assert_eq_hex!(test.pc, 0x0402);
+
+    // NMI takes priority over IRQ.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_instruction(&[0xEA])
+            .with_data(0xFFFA, &[0x00, 0x20, 0x00, 0x00, 0x00, 0x30])
+            .with_state(|c| c.cpu.set_irq(true))
+            .with_state(|c| c.cpu.set_nmi(true))
+            .run_one()
+            .values(|c| c.pc),
+        0x2000
+    );
+}
+
+#[test]
+fn test_shared_registers() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x8A, 0x4C, 0x00, 0x04]); // INX; TXA; JMP $0400
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    // Start with A and X agreeing, as the reader may see the first snapshot.
+    cpu.ac = 0x00;
+    cpu.step();
+    let shared = cpu.shared_registers();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = stop.clone();
+    let reader = std::thread::spawn(move || {
+        let mut reads = 0;
+        while !stop_clone.load(Ordering::Relaxed) {
+            // Every snapshot is taken at an instruction boundary, so A and X always agree with
+            // the instruction about to run.
+            let r = shared.load();
+            match r.pc {
+                0x0400 | 0x0402 => assert_eq!(r.ac, r.x, "{:?}", r),
+                0x0401 => assert_eq!(r.ac, r.x.wrapping_sub(1), "{:?}", r),
+                _ => panic!("Snapshot outside the program: {:?}", r),
+            }
+            reads += 1;
+        }
+        reads
+    });
+
+    for _ in 0..3_000_000 {
+        cpu.step();
+    }
+    stop.store(true, Ordering::Relaxed);
+    assert!(reader.join().unwrap() > 0);
+    assert_eq!(cpu.shared_registers().load().pc, cpu.ins_pc);
+}
+
+#[test]
+fn test_save_state() {
+    use crate::core::savestate::SaveState;
+
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x86, 0x10, 0x4C, 0x00, 0x04]); // INX; STX $10; JMP $0400
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    // Save just after the INX completes, having overlapped its last cycle with the STX's fetch,
+    // so the STX is under way.
+    for _ in 0..100 {
+        cpu.step();
+    }
+    while (cpu.opcode, cpu.cycle) != (0x86, 2) {
+        cpu.step();
+    }
+    let mut bytes = Vec::new();
+    SaveState::capture(&[&cpu, &mem]).write_to(&mut bytes).unwrap();
+
+    let mut restored_mem = Memory::new();
+    let mut restored_cpu = C6502::new(&restored_mem);
+    let state = SaveState::read_from(&bytes[..]).unwrap();
+    state.restore(&mut [&mut restored_cpu, &mut restored_mem]).unwrap();
+    assert_eq!(restored_cpu.snapshot(), cpu.snapshot());
+    for _ in 0..100 {
+        cpu.step();
+        restored_cpu.step();
+    }
+    assert_eq!(restored_cpu.registers(), cpu.registers());
+    assert_eq!(restored_cpu.total_cycles(), cpu.total_cycles());
+    assert_eq!(restored_mem.read_byte(0x10), mem.read_byte(0x10));
+
+    // A version 1 chunk, which held only the registers, restores between instructions.
+    let data = cpu.migrate_state(1, vec![0x00, 0x04, 0x01, 0x02, 0x03, 0x24, 0xFD, 2]).unwrap();
+    restored_cpu.load_state(&data).unwrap();
+    assert_eq!(restored_cpu.registers(), Registers { pc: 0x0400, ac: 0x01, x: 0x02, y: 0x03, p: 0x24, sp: 0xFD });
+    assert_eq!((restored_cpu.cycle, restored_cpu.state), (1, CpuState::Running));
+    assert!(restored_cpu.load_state(&data[1..]).is_err());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Access {
+    Read(u16),
+    Write(u16, u8),
+}
+
+/// A bank of RAM that records every access made to it, to check the bus activity of
+/// instructions as seen by memory-mapped I/O.
+///
+struct RecordingBank {
+    mem: Vec<u8>,
+    accesses: Arc<Mutex<Vec<Access>>>,
+}
+
+impl RecordingBank {
+    fn map_at(mem: &Memory, start: u16) -> Arc<Mutex<Vec<Access>>> {
+        let accesses = Arc::new(Mutex::new(Vec::new()));
+        let bank = RecordingBank { mem: vec![0; 0x1000], accesses: accesses.clone() };
+        mem.configure_banks(vec![Box::new(bank)], &[(start, 0x1000, 1, 0x0000)]).unwrap();
+        accesses
+    }
+}
+
+impl MemoryBank for RecordingBank {
+    fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        self.accesses.lock().unwrap().push(Access::Read(addr));
+        self.mem[(addr - offset) as usize]
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, value: u8, _ram: &mut [u8]) {
+        self.accesses.lock().unwrap().push(Access::Write(addr, value));
+        self.mem[(addr - offset) as usize] = value;
+    }
+}
+
+#[test]
+fn test_rmw_double_write() {
+    let mut test = CpuTest::new();
+    let accesses = RecordingBank::map_at(&test.mem, 0x1000);
+    test.with_data(0x1040, &[0x41]).with_instruction(&[0x0E, 0x40, 0x10]); // ASL $1040
+    accesses.lock().unwrap().clear();
+    test.run_one();
+    assert_eq!(test.cycles, 6);
+    assert_eq!(
+        *accesses.lock().unwrap(),
+        vec![Access::Read(0x1040), Access::Write(0x1040, 0x41), Access::Write(0x1040, 0x82)]
+    );
+}
+
+#[test]
+fn test_indexed_dummy_reads() {
+    fn accesses_for(instruction: &[u8], init_fn: fn(&mut CpuTest)) -> Vec<Access> {
+        let mut test = CpuTest::new();
+        let accesses = RecordingBank::map_at(&test.mem, 0x1000);
+        test.with_data(0x0040, &[0xF0, 0x10]).with_instruction(instruction).with_state(init_fn);
+        accesses.lock().unwrap().clear();
+        test.run_one();
+        let accesses = accesses.lock().unwrap().clone();
+        accesses
+    }
+
+    // Reads only touch the un-carried address when crossing a page.
+    let lda_abs_x = [0xBD, 0xF0, 0x10]; // LDA $10F0,X
+    assert_eq!(accesses_for(&lda_abs_x, |t| t.x = 0x08), vec![Access::Read(0x10F8)]);
+    assert_eq!(accesses_for(&lda_abs_x, |t| t.x = 0x20), vec![Access::Read(0x1010), Access::Read(0x1110)]);
+    let lda_ind_y = [0xB1, 0x40]; // LDA ($40),Y
+    assert_eq!(accesses_for(&lda_ind_y, |t| t.y = 0x08), vec![Access::Read(0x10F8)]);
+    assert_eq!(accesses_for(&lda_ind_y, |t| t.y = 0x20), vec![Access::Read(0x1010), Access::Read(0x1110)]);
+
+    // Stores and read-modify-write instructions always do.
+    let sta_abs_y = [0x99, 0xF0, 0x10]; // STA $10F0,Y
+    assert_eq!(accesses_for(&sta_abs_y, |t| t.y = 0x08), vec![Access::Read(0x10F8), Access::Write(0x10F8, 0)]);
+    assert_eq!(accesses_for(&sta_abs_y, |t| t.y = 0x20), vec![Access::Read(0x1010), Access::Write(0x1110, 0)]);
+    let sta_ind_y = [0x91, 0x40]; // STA ($40),Y
+    assert_eq!(accesses_for(&sta_ind_y, |t| t.y = 0x20), vec![Access::Read(0x1010), Access::Write(0x1110, 0)]);
+    let inc_abs_x = [0xFE, 0xF0, 0x10]; // INC $10F0,X
+    assert_eq!(
+        accesses_for(&inc_abs_x, |t| t.x = 0x20),
+        vec![Access::Read(0x1010), Access::Read(0x1110), Access::Write(0x1110, 0), Access::Write(0x1110, 1)]
+    );
+}
+
+#[test]
+fn test_traps() {
+    // Prints "HI!" through a CHROUT-style routine at $F000, which stores the character at $0300.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[
+        0xA2, 0x00, //       LDX #$00
+        0xBD, 0x20, 0x04, // loop: LDA $0420,X
+        0xF0, 0x07, //       BEQ done
+        0x20, 0x00, 0xF0, // JSR $F000
+        0xE8, //             INX
+        0x4C, 0x02, 0x04, // JMP loop
+        0x4C, 0x0E, 0x04, // done: JMP done
+    ])
+    .with_data(0x0420, b"HI!\0")
+    .with_data(0xF000, &[0x8D, 0x00, 0x03, 0x60]); // STA $0300; RTS
+
+    let output = Arc::new(Mutex::new(String::new()));
+    let printed = output.clone();
+    test.cpu.add_trap(0xF000, move |registers, _| {
+        printed.lock().unwrap().push(registers.ac as char);
+        TrapAction::Return
+    });
+    test.run(30);
+    assert_eq!(*output.lock().unwrap(), "HI!");
+    assert_eq_hex!((test.data(0x0300), test.x, test.sp), (0x00, 0x03, 0xFF));
+
+    // A trap that falls through lets the routine run as well, and sees the registers as set
+    // by the handler.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xA9, 0x41, 0x20, 0x00, 0xF0, 0xEA]).with_data(0xF000, &[0x8D, 0x00, 0x03, 0x60]);
+    test.cpu.add_trap(0xF000, |registers, memory| {
+        memory.write_byte(0x0301, registers.ac);
+        registers.ac += 1;
+        TrapAction::FallThrough
+    });
+    test.run(4);
+    assert_eq_hex!((test.data(0x0300), test.data(0x0301), test.pc), (0x42, 0x41, 0x0405));
+
+    // A trap can resume somewhere else, and is gone once removed.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xE8, 0xE8, 0xC8]);
+    test.cpu.add_trap(0x0401, |_, _| TrapAction::Resume(0x0402));
+    test.run(2);
+    assert_eq_hex!((test.x, test.y), (0x01, 0x01));
+
+    // Setting the PC in the registers redirects execution too.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xE8, 0xE8, 0xC8]);
+    test.cpu.add_trap(0x0401, |registers, _| {
+        registers.pc = 0x0402;
+        TrapAction::FallThrough
+    });
+    test.run(2);
+    assert_eq_hex!((test.x, test.y), (0x01, 0x01));
+
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xE8, 0xE8, 0xC8]);
+    test.cpu.add_trap(0x0401, |_, _| TrapAction::Resume(0x0402));
+    test.cpu.remove_trap(0x0401);
+    test.run(2);
+    assert_eq_hex!((test.x, test.y), (0x02, 0x00));
+}
+
+#[test]
+fn test_register_access() {
+    // Seed and inspect the CPU through its public API only, as a harness in another crate would.
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x69, 0x01]); // INX; ADC #$01
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0300, irq: None });
+    let mut cpu = C6502::new(&mem);
+    cpu.reset();
+    while cpu.state() != CpuState::Running {
+        cpu.step();
+    }
+    let start = cpu.total_cycles();
+    assert_eq!(cpu.cycle(), 1);
+    cpu.set_registers(Registers { pc: 0x0400, ac: 0x10, x: 0x7F, y: 0x22, p: C6502::SR_CARRY, sp: 0xF0 });
+    // Each instruction's last step also fetches the next opcode.
+    while cpu.step() != CpuAction::CompleteAndFetch {}
+    assert_eq!(cpu.total_cycles() - start, 3);
+    assert_eq!(cpu.cycle(), 2);
+    while cpu.step() != CpuAction::CompleteAndFetch {}
+    assert_eq!(cpu.total_cycles() - start, 5);
+    assert_eq!(
+        cpu.registers(),
+        Registers { pc: 0x0404, ac: 0x12, x: 0x80, y: 0x22, p: 0x00, sp: 0xF0 }
+    );
+}
+
+#[test]
+fn test_snapshot() {
+    // Snapshot after cycle 3 of ASL $1040, and finish the instruction in a fresh CPU over a copy
+    // of the memory.
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0x0E, 0x40, 0x10, 0xE8, 0x4C, 0x00, 0x04]); // ASL $1040; INX; JMP $0400
+    mem.write_byte(0x1040, 0x01);
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    cpu.reset();
+    while cpu.state() != CpuState::Running {
+        cpu.step();
+    }
+    for _ in 0..3 {
+        cpu.step();
+    }
+    assert_eq!(cpu.cycle(), 4);
+    let snapshot = cpu.snapshot();
+    let copied_mem = Memory::new();
+    let mut contents = vec![0; 0x10000];
+    mem.read_block(0x0000, &mut contents);
+    copied_mem.write_block(0x0000, &contents);
+
+    let mut restored = C6502::new(&copied_mem);
+    restored.restore(&snapshot);
+    assert_eq!(restored.snapshot(), snapshot);
+    for _ in 0..100 {
+        cpu.step();
+        restored.step();
+    }
+    assert_eq!(restored.registers(), cpu.registers());
+    assert_eq!(restored.total_cycles(), cpu.total_cycles());
+    assert_eq!(copied_mem.read_byte(0x1040), mem.read_byte(0x1040));
+    assert_ne!(mem.read_byte(0x1040), 0x01);
+}
+
+#[test]
+fn test_trace_hook() {
+    let mem = Memory::new();
+    // LDA #$01; STA $0200; LDX #$05; INX; JMP $0400
+    mem.write_block(0x0400, &[0xA9, 0x01, 0x8D, 0x00, 0x02, 0xA2, 0x05, 0xE8, 0x4C, 0x00, 0x04]);
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let hook_records = records.clone();
+    cpu.set_trace(move |r: &trace::TraceRecord| hook_records.lock().unwrap().push(r.clone())).unwrap();
+    cpu.reset();
+    while records.lock().unwrap().len() < 5 {
+        cpu.step();
+    }
+
+    let recorded = records.lock().unwrap().clone();
+    let pcs: Vec<u16> = recorded.iter().map(|r| r.pc).collect();
+    assert_eq!(pcs, vec![0x0400, 0x0402, 0x0405, 0x0407, 0x0408]);
+    let cycles: Vec<u64> = recorded.iter().map(|r| r.cycles).collect();
+    assert_eq!(cycles, vec![2, 4, 2, 2, 3]);
+    assert_eq!(recorded[1].bytes, vec![0x8D, 0x00, 0x02]);
+    assert_eq!(recorded[3].registers.x, 0x06);
+
+    let mut line = Vec::new();
+    recorded[1].write_line(&mut line).unwrap();
+    assert_eq!(
+        String::from_utf8(line).unwrap(),
+        "$0402  8D 00 02  STA $0200     A:01 X:00 Y:00 P:00 SP:FD  4 cycles\n"
+    );
+
+    // A reset abandons the instruction in progress, which isn't reported.
+    cpu.step();
+    cpu.reset();
+    records.lock().unwrap().clear();
+    while records.lock().unwrap().is_empty() {
+        cpu.step();
+    }
+    let first = records.lock().unwrap()[0].clone();
+    assert_eq!((first.pc, first.cycles), (0x0400, 2));
+
+    cpu.set_trace_sink(None).unwrap();
+    records.lock().unwrap().clear();
+    for _ in 0..20 {
+        cpu.step();
+    }
+    assert!(records.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_run_for_cycles() {
+    let mem = Memory::new();
+    // LDA #$01; STA $0200; INX; JMP $0400
+    mem.write_block(0x0400, &[0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0x4C, 0x00, 0x04]);
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    cpu.reset();
+
+    let results: Vec<InstructionResult> = (0..5).map(|_| cpu.run_instruction()).collect();
+    let summary: Vec<(u16, u8, u64)> = results.iter().map(|r| (r.pc, r.opcode, r.cycles)).collect();
+    assert_eq!(
+        summary,
+        vec![(0x0400, 0xA9, 2), (0x0402, 0x8D, 4), (0x0405, 0xE8, 2), (0x0406, 0x4C, 3), (0x0400, 0xA9, 2)]
+    );
+
+    // Stop part way through STA, and carry on from there.
+    mem.write_byte(0x0200, 0x00);
+    assert_eq!(cpu.run_for_cycles(2), 0);
+    assert_eq!(mem.read_byte(0x0200), 0x00);
+    assert_eq!(cpu.run_for_cycles(2), 1);
+    assert_eq!(mem.read_byte(0x0200), 0x01);
+    assert_eq!(cpu.run_instruction(), InstructionResult { pc: 0x0405, opcode: 0xE8, cycles: 2 });
+}
+
+#[test]
+fn test_profiling() {
+    let mut test = CpuTest::new();
+    // LDX #$00; loop: INX; BNE loop; JMP $0400
+    test.with_instruction(&[0xA2, 0x00, 0xE8, 0xD0, 0xFD, 0x4C, 0x00, 0x04]);
+    test.cpu.enable_profiling();
+    test.run(3000);
+
+    let report = test.cpu.profile();
+    // The last instruction run has already fetched the next one.
+    assert_eq!(report.instructions, 3001);
+    let top: Vec<u16> = report.top_n(2).iter().map(|h| h.pc).collect();
+    assert_eq!(top, vec![0x0402, 0x0403]);
+    let loop_cycles: u64 = report.top_n(2).iter().map(|h| h.cycles).sum();
+    assert!(loop_cycles * 100 / report.cycles >= 95);
+    assert_eq!(report.opcodes[0].mnemonic, "INX");
+    assert!(report.to_string().contains("$0402"));
+
+    test.cpu.disable_profiling();
+    assert_eq!(test.cpu.profile(), profile::ProfileReport::default());
+
+    // An instruction's last cycle, which fetches the next, counts towards the instruction it
+    // completes: NOP's fetches JMP, but JMP's doesn't fetch NOP, which is fetched in a cycle of
+    // its own.
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0xEA, 0x4C, 0x00, 0x04]); // NOP; JMP $0400
+    test.cpu.enable_profiling();
+    test.run(2000);
+    let report = test.cpu.profile();
+    let hotspots: Vec<(u16, u64, u64)> = report.hotspots.iter().map(|h| (h.pc, h.count, h.cycles)).collect();
+    assert_eq!(hotspots, vec![(0x0400, 1000, 3000), (0x0401, 1000, 2000)]);
+}
+
+#[test]
+fn test_sync_out() {
+    let mem = Memory::new();
+    // LDA #$01; STA $0200; INX; JMP $0400
+    mem.write_block(0x0400, &[0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0x4C, 0x00, 0x04]);
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    let mut sync = InputPin::new();
+    cpu.sync_out().connect_to(&mut sync);
+    cpu.reset();
+    while cpu.state() != CpuState::Running {
+        cpu.step();
+    }
+
+    let mut levels = Vec::new();
+    for _ in 0..12 {
+        cpu.step();
+        while sync.try_recv().is_some() {}
+        levels.push(sync.value() as u8);
+    }
+    // LDA, STA, INX and JMP take 2, 4, 2 and 3 cycles, and SYNC is high for the first of each.
+    assert_eq!(levels, vec![1, 0, 1, 0, 0, 0, 1, 0, 1, 0, 0, 1]);
+}
+
+#[test]
+fn test_bus_out() {
+    let mut test = CpuTest::new();
+    test.with_instruction(&[0x8D, 0x34, 0x12]).with_state(|t| t.ac = 0x42); // STA $1234
+    let mut addr = InputPort16::new();
+    let mut data = InputPort8::new();
+    let mut rw = InputPin::new();
+    test.cpu.addr_out().connect_to(&mut addr);
+    test.cpu.data_out().connect_to(&mut data);
+    test.cpu.rw_out().connect_to(&mut rw);
+    let mut accesses = || {
+        let mut accesses = Vec::new();
+        while let Some(a) = addr.try_recv() {
+            accesses.push((a, rw.try_recv().unwrap(), data.try_recv().unwrap()));
+        }
+        accesses
+    };
+    test.run_one();
+    assert_eq!(
+        accesses(),
+        vec![(0x0400, true, 0x8D), (0x0401, true, 0x34), (0x0402, true, 0x12), (0x1234, false, 0x42)]
+    );
+
+    // JSR reads the stack before pushing the return address, and RTS reads the byte after the
+    // opcode, the stack before pulling the return address, and the byte at the return address.
+    test.with_instruction(&[0x20, 0x00, 0x20]).with_data(0x2000, &[0x60]).run(2); // JSR $2000; RTS
+    assert_eq!(
+        accesses(),
+        vec![
+            (0x0403, true, 0x20),
+            (0x0404, true, 0x00),
+            (0x01FF, true, 0x00),
+            (0x01FF, false, 0x04),
+            (0x01FE, false, 0x05),
+            (0x0405, true, 0x20),
+            (0x2000, true, 0x60),
+            (0x2001, true, 0x00),
+            (0x01FD, true, 0x00),
+            (0x01FE, true, 0x05),
+            (0x01FF, true, 0x04),
+            (0x0405, true, 0x20),
+        ]
+    );
+}
+
+#[test]
+fn test_rdy_stall() {
+    // Count the phi0 ticks LDA $1234 takes, holding RDY low for 5 ticks after `stall_at` ticks.
+    fn run_lda(stall_at: Option<usize>) -> (usize, u8) {
+        let mut test = CpuTest::new();
+        test.with_instruction(&[0xAD, 0x34, 0x12]).with_data(0x1234, &[0x5A]); // LDA $1234
+        let cpu = &mut test.cpu;
+        cpu.set_registers(Registers { pc: 0x0400, ..Registers::default() });
+        cpu.state = CpuState::Running;
+        let mut ticks = 0;
+        loop {
+            if Some(ticks) == stall_at {
+                cpu.set_rdy(false);
+            } else if Some(ticks) == stall_at.map(|t| t + 5) {
+                cpu.set_rdy(true);
+            }
+            cpu.clock_edge(false);
+            let action = cpu.clock_edge(true).unwrap();
+            ticks += 1;
+            if action != CpuAction::Continue {
+                return (ticks, cpu.registers().ac);
+            }
+        }
+    }
+
+    let (ticks, ac) = run_lda(None);
+    assert_eq!(ac, 0x5A);
+    assert_eq!(run_lda(Some(2)), (ticks + 5, ac));
+    assert_eq!(run_lda(Some(3)), (ticks + 5, ac));
+}
+
+/// A CPU running under `run` on a thread of its own, clocked from the test, to test its input
+/// pins as a machine drives them.
+///
+struct RunningCpu {
+    clock: OutputPin,
+    stats: Arc<CpuStats>,
+    registers: SharedRegisters,
+    cycles: u64,
+    stop: Arc<AtomicBool>,
+    handle: std::thread::JoinHandle<()>,
+}
+
+impl RunningCpu {
+    fn start(mut cpu: C6502) -> Self {
+        let mut clock = OutputPin::new();
+        clock.connect_to(cpu.phi0_in());
+        let (stats, registers) = (cpu.stats(), cpu.shared_registers());
+        let stop = Arc::new(AtomicBool::new(false));
+        let cpu_stop = stop.clone();
+        let handle = std::thread::spawn(move || cpu.run(cpu_stop));
+        Self { clock, stats, registers, cycles: 0, stop, handle }
+    }
+
+    /// Clock the CPU through `cycles` more cycles, wait for it to run them, and return its
+    /// registers.
+    ///
+    fn run_cycles(&mut self, cycles: u64) -> Registers {
+        for _ in 0..cycles {
+            self.clock.send(false);
+            self.clock.send(true);
+        }
+        self.cycles += cycles;
+        while self.stats.cycles() < self.cycles {
+            std::thread::yield_now();
+        }
+        self.registers.load()
+    }
+
+    fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.clock.send(false);
+        self.handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_so_in() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xB8, 0x50, 0xFE, 0xA2, 0x01, 0x4C, 0x05, 0x04]); // CLV; BVC *; LDX #$01; JMP *
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let mut so = OutputPin::new();
+    so.connect_to(cpu.so_in());
+    let mut running = RunningCpu::start(cpu);
+
+    // BVC loops for as long as SO is left alone, and falls through once it's pulled low.
+    assert_eq_hex!(running.run_cycles(100).pc, 0x0401);
+    so.send(false);
+    let r = running.run_cycles(100);
+    assert_eq_hex!((r.pc, r.x), (0x0405, 0x01));
+    assert_ne!(r.p & C6502::SR_OVERFLOW, 0);
+    running.stop();
+}
+
+#[test]
+fn test_rdy_in() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x4C, 0x00, 0x04]); // INX; JMP $0400
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let mut rdy = OutputPin::with_initial_value(true);
+    rdy.connect_to(cpu.rdy_in());
+    let mut running = RunningCpu::start(cpu);
+    assert_ne!(running.run_cycles(100).x, 0);
+
+    // Pulling RDY low stalls the CPU at its next read, for as long as it's held low.
+    rdy.send(false);
+    let stalled = running.run_cycles(10);
+    assert_eq!(running.run_cycles(100), stalled);
+    rdy.send(true);
+    assert_ne!(running.run_cycles(100).x, stalled.x);
+    running.stop();
+}
+
+#[test]
+fn test_irq_in() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0x58, 0x4C, 0x01, 0x04]); // CLI; JMP $0401
+    mem.write_block(0x0500, &[0xE6, 0x10, 0x4C, 0x02, 0x05]); // INC $10; JMP $0502
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: Some(0x0500) });
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let mut irq = OutputPin::with_initial_value(true);
+    irq.connect_to(cpu.irq_in());
+    let mut running = RunningCpu::start(cpu);
+    let before = running.run_cycles(100);
+    assert_eq_hex!((before.pc, mem.read_byte(0x10)), (0x0401, 0x00));
+
+    // Pulling IRQ low takes the vector, pushing the return address and status, and masks
+    // further interrupts.
+    irq.send(false);
+    let after = running.run_cycles(100);
+    assert_eq_hex!((after.pc, mem.read_byte(0x10)), (0x0502, 0x01));
+    assert_eq_hex!(after.sp, before.sp.wrapping_sub(3));
+    assert_ne!(after.p & C6502::SR_INTERRUPT_MASK, 0);
+    running.stop();
+}
+
+#[test]
+fn test_nmi_in() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0x78, 0x4C, 0x01, 0x04]); // SEI; JMP $0401
+    mem.write_block(0x0600, &[0xE6, 0x11, 0x40]); // INC $11; RTI
+    mem.set_vectors(Vectors { nmi: Some(0x0600), reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let mut nmi = OutputPin::with_initial_value(true);
+    nmi.connect_to(cpu.nmi_in());
+    let mut running = RunningCpu::start(cpu);
+    let before = running.run_cycles(100);
+    assert_eq_hex!((before.pc, mem.read_byte(0x11)), (0x0401, 0x00));
+
+    // Each pulse is serviced once, even with interrupts masked, and the handler returns to the
+    // loop.
+    for count in 1..=2 {
+        nmi.send(false);
+        nmi.send(true);
+        let after = running.run_cycles(100);
+        assert_eq_hex!((after.pc, after.sp, mem.read_byte(0x11)), (0x0401, before.sp, count));
+    }
+
+    // Holding the line low only triggers one more.
+    nmi.send(false);
+    running.run_cycles(100);
+    assert_eq_hex!(mem.read_byte(0x11), 0x03);
+    running.stop();
+}
+
+#[test]
+fn test_reset_in() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE6, 0x12, 0x4C, 0x02, 0x04]); // INC $12; JMP $0402
+    mem.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let mut reset = OutputPin::with_initial_value(true);
+    reset.connect_to(cpu.reset_in());
+    let mut running = RunningCpu::start(cpu);
+    let before = running.run_cycles(100);
+    assert_eq_hex!((before.pc, mem.read_byte(0x12)), (0x0402, 0x01));
+
+    // The CPU holds in reset for as long as the line is low, then runs the reset sequence, which
+    // drops the stack pointer by three, and restarts from the reset vector.
+    reset.send(false);
+    running.run_cycles(100);
+    assert_eq_hex!(mem.read_byte(0x12), 0x01);
+    reset.send(true);
+    let after = running.run_cycles(100);
+    assert_eq_hex!((after.pc, mem.read_byte(0x12)), (0x0402, 0x02));
+    assert_eq_hex!(after.sp, before.sp.wrapping_sub(3));
+    running.stop();
+}
+
+#[test]
+fn test_stop_while_clock_paused() {
+    let mem = Memory::new();
+    let mut cpu = C6502::new(&mem);
+    let mut clock = OutputPin::new();
+    clock.connect_to(cpu.phi0_in());
+    let stop = Arc::new(AtomicBool::new(false));
+    let cpu_stop = stop.clone();
+    let handle = std::thread::spawn(move || cpu.run(cpu_stop));
+
+    // The CPU stops without another clock edge, or the clock going away.
+    let start = std::time::Instant::now();
+    stop.store(true, Ordering::Relaxed);
+    handle.join().unwrap();
+    assert!(start.elapsed() < STOP_POLL_INTERVAL * 10);
+    drop(clock);
+}
+
+#[test]
+fn test_stats() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xE8, 0x4C, 0x00, 0x04]); // INX; JMP $0400
+    let mut cpu = C6502::new(&mem);
+    cpu.state = CpuState::Running;
+    cpu.pc = 0x0400;
+    let stats = cpu.stats();
+    assert!(!stats.is_running());
+    assert_eq!((stats.cycles(), stats.mhz()), (0, 0.0));
+
+    let mut clock = OutputPin::new();
+    let connections = Connections::new();
+    clock.connect_named(cpu.phi0_in(), &connections, "stats.clock", "stats.cpu");
+    let stop = Arc::new(AtomicBool::new(false));
+    let cpu_stop = stop.clone();
+    let handle = std::thread::spawn(move || cpu.run(cpu_stop));
+    for _ in 0..1000 {
+        clock.send(false);
+        clock.send(true);
+    }
+    while connections.depths().iter().any(|c| c.depth > 0) {
+        std::thread::yield_now();
+    }
+    assert!(stats.is_running());
+
+    stop.store(true, Ordering::Relaxed);
+    clock.send(false);
+    handle.join().unwrap();
+    assert!(!stats.is_running());
+    assert_eq!(stats.cycles(), 1000);
+    assert!(stats.mhz() > 0.0);
+    assert!(stats.to_string().starts_with("Executed 1000 cycles in "));
+}
+
+#[test]
+fn test_run_until() {
+    let mem = Memory::new();
+    // LDX #$03; loop: DEX; BNE loop; BRK; ... handler: JMP handler
+    mem.write_block(0x0400, &[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x00]);
+    mem.write_block(0x2000, &[0x4C, 0x00, 0x20]);
+    mem.write_word(C6502::IRQ_VECTOR, 0x2000);
+    let mut cpu = C6502::new(&mem);
+    let start = |cpu: &mut C6502| {
+        cpu.state = CpuState::Running;
+        cpu.cycle = 1;
+        cpu.pc = 0x0400;
+    };
+    use StopCondition::*;
+
+    // LDX, then DEX and BNE three times, the last not taken.
+    start(&mut cpu);
+    let result = cpu.run_until(&[PcEquals(0x0405), MaxCycles(1000)]);
+    assert_eq!(result, RunResult { condition: PcEquals(0x0405), cycles: 2 + 3 * 2 + 3 + 3 + 2 });
+    assert_eq!(cpu.registers().x, 0);
+
+    start(&mut cpu);
+    let result = cpu.run_until(&[BrkExecuted]);
+    assert_eq!(result, RunResult { condition: BrkExecuted, cycles: 16 + 7 });
+    assert_eq_hex!(cpu.pc, 0x2000);
+
+    start(&mut cpu);
+    let result = cpu.run_until(&[PcUnchanged, PcEquals(0x0400)]);
+    assert_eq!(result, RunResult { condition: PcUnchanged, cycles: 16 + 7 + 3 });
+
+    start(&mut cpu);
+    assert_eq!(cpu.run_until(&[MaxCycles(5)]), RunResult { condition: MaxCycles(5), cycles: 7 });
+}
+
+#[test]
+fn test_interrupt_polling() {
+    // Returns the address IRQ's handler returns to, with the line asserted after `steps` cycles.
+    fn interrupted_at(program: &[u8], p: u8, steps: usize) -> u16 {
+        let mem = Memory::new();
+        mem.write_block(0x0400, program);
+        mem.write_word(C6502::IRQ_VECTOR, 0x3000);
+        let mut cpu = C6502::new(&mem);
+        cpu.state = CpuState::Running;
+        cpu.pc = 0x0400;
+        cpu.sp = 0xFF;
+        cpu.p = p;
+        for _ in 0..steps {
+            cpu.step();
+        }
+        cpu.set_irq(true);
+        cpu.run_until(&[StopCondition::PcEquals(0x3000)]);
+        mem.read_word(0x01FE)
+    }
+
+    // Asserted during the first cycle of a NOP, IRQ is serviced straight after it; asserted on
+    // its final cycle, after the next instruction.
+    let nops = [0xEA; 4];
+    assert_eq_hex!(interrupted_at(&nops, 0, 1), 0x0401);
+    assert_eq_hex!(interrupted_at(&nops, 0, 2), 0x0402);
+
+    // A taken branch that doesn't cross a page only polls before its second cycle.
+    let branch = [0xD0, 0x00, 0xEA, 0xEA, 0xEA]; // BNE *+2
+    assert_eq_hex!(interrupted_at(&branch, 0, 1), 0x0402);
+    assert_eq_hex!(interrupted_at(&branch, 0, 2), 0x0403);
+
+    // Changes to the interrupt mask by CLI and SEI only take effect after the next instruction.
+    let cli = [0x58, 0xEA, 0xEA, 0xEA];
+    assert_eq_hex!(interrupted_at(&cli, C6502::SR_INTERRUPT_MASK, 0), 0x0402);
+    let sei = [0x78, 0xEA, 0xEA, 0xEA];
+    assert_eq_hex!(interrupted_at(&sei, 0, 0), 0x0401);
+}
+
+#[test]
+fn test_6510_io_port() {
+    let mut test = CpuTest::new();
+    test.cpu = C6502::new_6510(&test.mem);
+    let mut output = InputPort8::new();
+    test.cpu.port_out().connect_to(&mut output);
+    let mut input = OutputPort8::new();
+    input.connect_to(test.cpu.port_in());
+    input.send(0xA8);
+
+    // Make the low three bits outputs, and write to the data register.
+    test.with_instruction(&[0xA9, 0x07, 0x85, 0x00]) // LDA #$07; STA $00
+        .with_instruction(&[0xA9, 0xFD, 0x85, 0x01]) // LDA #$FD; STA $01
+        .run(4);
+    let mut sent = Vec::new();
+    while let Some(value) = output.try_recv() {
+        sent.push(value);
+    }
+    assert_eq!(sent, vec![0x00, 0x05]);
+
+    // The outputs read back from the data register, and the rest from the input port.
+    test.with_instruction(&[0xA5, 0x01, 0xA6, 0x00]).run(2); // LDA $01; LDX $00
+    assert_eq_hex!((test.ac, test.x), (0xAD, 0x07));
+
+    // The writes reach the memory underneath too.
+    assert_eq_hex!((test.data(0x0000), test.data(0x0001)), (0x07, 0xFD));
+
+    // A plain 6502 has no port.
+    assert_eq_hex!(
+        CpuTest::new()
+            .with_data(0x0001, &[0x42])
+            .with_instruction(&[0xA5, 0x01])
+            .run_one()
+            .values(|c| c.ac),
+        0x42
+    );
+}
+
+#[test]
+fn test_fault() {
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0xEA, 0x02]); // NOP; JAM
+    mem.write_word(C6502::RESET_VECTOR, 0x0400);
+    let mut cpu = C6502::new(&mem);
+    cpu.reset();
+    let stats = cpu.stats();
+    let mut clock = OutputPin::new();
+    clock.connect_to(cpu.phi0_in());
+    for _ in 0..20 {
+        clock.send(false);
+        clock.send(true);
+    }
+
+    let mut computer = Computer::new();
+    computer.add_async(cpu);
+    computer.start().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while computer.health().is_ok() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    computer.stop();
+
+    let faults = computer.health().unwrap_err();
+    assert_eq!(faults.len(), 1);
+    assert_eq!(faults[0].component, std::any::type_name::<C6502>());
+    assert_eq!(faults[0].message, "Illegal instruction $02 at $0401");
+    assert_eq!(stats.fault(), Some(faults[0].message.clone()));
+    assert!(!stats.is_running());
+}
+
+#[test]
+fn test_step_cycles() {
+    let run = |chunk: usize| {
+        let mem = Memory::new();
+        crate::roms::MULTIPLY.install(&mem);
+        let mut cpu = C6502::new(&mem);
+        assert_eq!(cpu.step_cycles(10), 10);
+        cpu.reset();
+        let mut remaining = 1000;
+        while remaining > 0 {
+            let budget = remaining.min(chunk);
+            assert_eq!(cpu.step_cycles(budget), 0);
+            remaining -= budget;
+        }
+        let mut ram = vec![0; 0x10000];
+        mem.read_block(0x0000, &mut ram);
+        (cpu.snapshot(), ram)
+    };
+
+    let (snapshot, ram) = run(1000);
+    assert_eq!(snapshot.total_cycles, 1000);
+    assert!(run(3) == (snapshot, ram));
+}
+
+#[test]
+fn test_bus_activity() {
+    // The bus accesses of one instruction per addressing mode, cycle by cycle, as in the
+    // published 6502 cycle tables, with X = $15, Y = $10 and A = $42. Each instruction is at
+    // $0400, and followed by zeros.
+    let r = |addr: u16, data: u8| BusActivity { addr, data, is_write: false };
+    let w = |addr: u16, data: u8| BusActivity { addr, data, is_write: true };
+    type Case<'a> = (&'a [u8], &'a [(u16, &'a [u8])], Vec<BusActivity>);
+    #[rustfmt::skip]
+    let cases: &[Case] = &[
+        // INX: the byte after the opcode is read and discarded.
+        (&[0xE8], &[], vec![r(0x0400, 0xE8), r(0x0401, 0x00)]),
+        // ASL A
+        (&[0x0A], &[], vec![r(0x0400, 0x0A), r(0x0401, 0x00)]),
+        // LDA #$5A
+        (&[0xA9, 0x5A], &[], vec![r(0x0400, 0xA9), r(0x0401, 0x5A)]),
+        // LDA $80
+        (&[0xA5, 0x80], &[(0x0080, &[0x11])], vec![r(0x0400, 0xA5), r(0x0401, 0x80), r(0x0080, 0x11)]),
+        // LDA $80,X: the base address is read while X is added.
+        (
+            &[0xB5, 0x80],
+            &[(0x0095, &[0x22])],
+            vec![r(0x0400, 0xB5), r(0x0401, 0x80), r(0x0080, 0x00), r(0x0095, 0x22)],
+        ),
+        // LDX $F8,Y: the effective address wraps within the zero page.
+        (
+            &[0xB6, 0xF8],
+            &[(0x0008, &[0x33])],
+            vec![r(0x0400, 0xB6), r(0x0401, 0xF8), r(0x00F8, 0x00), r(0x0008, 0x33)],
+        ),
+        // LDA $1234
+        (
+            &[0xAD, 0x34, 0x12],
+            &[(0x1234, &[0x44])],
+            vec![r(0x0400, 0xAD), r(0x0401, 0x34), r(0x0402, 0x12), r(0x1234, 0x44)],
+        ),
+        // LDA $12F0,X: crossing a page, the address without the carry is read first.
+        (
+            &[0xBD, 0xF0, 0x12],
+            &[(0x1305, &[0x55])],
+            vec![r(0x0400, 0xBD), r(0x0401, 0xF0), r(0x0402, 0x12), r(0x1205, 0x00), r(0x1305, 0x55)],
+        ),
+        // LDA $1234,Y
+        (
+            &[0xB9, 0x34, 0x12],
+            &[(0x1244, &[0x66])],
+            vec![r(0x0400, 0xB9), r(0x0401, 0x34), r(0x0402, 0x12), r(0x1244, 0x66)],
+        ),
+        // LDA ($80,X)
+        (
+            &[0xA1, 0x80],
+            &[(0x0095, &[0x00, 0x20]), (0x2000, &[0x77])],
+            vec![r(0x0400, 0xA1), r(0x0401, 0x80), r(0x0080, 0x00), r(0x0095, 0x00), r(0x0096, 0x20), r(0x2000, 0x77)],
+        ),
+        // LDA ($A0),Y: crossing a page, the address without the carry is read first.
+        (
+            &[0xB1, 0xA0],
+            &[(0x00A0, &[0xF8, 0x20]), (0x2108, &[0x88])],
+            vec![r(0x0400, 0xB1), r(0x0401, 0xA0), r(0x00A0, 0xF8), r(0x00A1, 0x20), r(0x2008, 0x00), r(0x2108, 0x88)],
+        ),
+        // JMP ($3000)
+        (
+            &[0x6C, 0x00, 0x30],
+            &[(0x3000, &[0x34, 0x12])],
+            vec![r(0x0400, 0x6C), r(0x0401, 0x00), r(0x0402, 0x30), r(0x3000, 0x34), r(0x3001, 0x12)],
+        ),
+        // BEQ $0412, not taken.
+        (&[0xF0, 0x10], &[], vec![r(0x0400, 0xF0), r(0x0401, 0x10)]),
+        // STA $1260
+        (&[0x8D, 0x60, 0x12], &[], vec![r(0x0400, 0x8D), r(0x0401, 0x60), r(0x0402, 0x12), w(0x1260, 0x42)]),
+        // INC $1250: the unmodified value is written back before the result.
+        (
+            &[0xEE, 0x50, 0x12],
+            &[(0x1250, &[0x99])],
+            vec![
+                r(0x0400, 0xEE), r(0x0401, 0x50), r(0x0402, 0x12), r(0x1250, 0x99), w(0x1250, 0x99), w(0x1250, 0x9A),
+            ],
+        ),
+    ];
+    for (instruction, data, expected) in cases {
+        let mut test = CpuTest::new();
+        test.with_instruction(instruction);
+        for (addr, bytes) in data.iter() {
+            test.with_data(*addr, bytes);
+        }
+        let cpu = &mut test.cpu;
+        cpu.set_registers(Registers { pc: 0x0400, ac: 0x42, x: 0x15, y: 0x10, ..Registers::default() });
+        cpu.state = CpuState::Running;
+        let mut activity = Vec::new();
+        loop {
+            let (action, bus) = cpu.step_with_bus();
+            assert_eq!(bus, cpu.last_bus_activity());
+            activity.push(bus.unwrap());
+            match action {
+                CpuAction::Continue => continue,
+                // The last cycle also fetched the next opcode, which belongs to the next instruction.
+                CpuAction::CompleteAndFetch => activity.pop(),
+                CpuAction::Complete => None,
+            };
+            break;
+        }
+        assert_eq!(&activity, expected, "{:02X?}", instruction);
+    }
+
+    // A CPU that's off doesn't touch the bus.
+    let mut test = CpuTest::new();
+    assert_eq!(test.cpu.step_with_bus(), (CpuAction::Continue, None));
+}
+
+#[test]
+fn test_new_with() {
+    // Started running, the first instruction sees the configured registers: PHP pushes the
+    // status at the configured stack pointer, with the break and unused bits set as ever.
+    let mem = Memory::new();
+    mem.write_block(0x0400, &[0x08]); // PHP
+    let config = CpuConfig {
+        pc: 0x0400,
+        ac: 0x11,
+        x: 0x22,
+        y: 0x33,
+        p: C6502::SR_CARRY | C6502::SR_UNUSED,
+        sp: 0xF0,
+        start: CpuStart::Run,
+    };
+    let mut cpu = C6502::new_with(&mem, config);
+    assert_eq!(cpu.registers(), Registers { pc: 0x0400, ac: 0x11, x: 0x22, y: 0x33, p: C6502::SR_CARRY, sp: 0xF0 });
+    let result = cpu.run_instruction();
+    assert_eq!((result.pc, result.opcode), (0x0400, 0x08));
+    assert_eq_hex!(mem.read_byte(0x01F0), C6502::SR_CARRY | C6502::SR_BREAK | C6502::SR_UNUSED);
+    assert_eq_hex!(cpu.registers().sp, 0xEF);
+
+    // Started with a reset, the first instruction is at the reset vector, with the stack
+    // pointer three below the configured one. NOP's last cycle fetches the next opcode, so the
+    // PC has moved on past it.
+    mem.write_word(C6502::RESET_VECTOR, 0x0500);
+    mem.write_block(0x0500, &[0xEA]); // NOP
+    let mut cpu = C6502::new_with(&mem, CpuConfig { start: CpuStart::Reset, ..config });
+    assert_eq!(cpu.state(), CpuState::Resetting);
+    assert_eq!(cpu.run_instruction().pc, 0x0500);
+    assert_eq!(cpu.registers(), Registers { pc: 0x0502, ac: 0x11, x: 0x22, y: 0x33, p: C6502::SR_CARRY, sp: 0xED });
+
+    // The default is what new uses.
+    let cpu = C6502::new_with(&mem, CpuConfig::default());
+    assert_eq!(cpu.state(), CpuState::Off);
+    assert_eq!(cpu.registers(), C6502::new(&mem).registers());
+}
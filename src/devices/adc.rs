@@ -0,0 +1,184 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::core::memory::MemoryBank;
+use crate::core::ports::{InputPin, InputPortF64};
+use crate::core::AsyncComponent;
+
+/// An 8-bit analog-to-digital converter: it samples an analog level on `input`, from 0.0 to
+/// 1.0, and converts it to a value from $00 to $FF that code can read from a register.
+///
+/// The converter takes a sample every `sample_cycles` rising edges on `clock_in`, which is
+/// usually wired to the CPU's phi2 output. Levels outside 0.0 to 1.0 are clamped.
+///
+/// The converter's registers (see `bank`) repeat through each page the bank is mapped to:
+///
+/// * `DATA` (read): the latest conversion. Reading it clears `READY`.
+/// * `STATUS` (read): `READY` is set when a conversion completes.
+///
+pub struct Adc8 {
+    state: Arc<Mutex<AdcState>>,
+    input: InputPortF64,
+    clock_in: InputPin,
+}
+
+struct AdcState {
+    sample_cycles: u32,
+    remaining: u32,
+    data: u8,
+    ready: bool,
+}
+
+impl Adc8 {
+    pub const DATA: usize = 0;
+    pub const STATUS: usize = 1;
+    pub const REGISTER_COUNT: usize = 2;
+
+    pub const READY: u8 = 0b10000000;
+
+    pub fn new(sample_cycles: u32) -> Self {
+        assert!(sample_cycles > 0);
+        Self {
+            state: Arc::new(Mutex::new(AdcState { sample_cycles, remaining: sample_cycles, data: 0, ready: false })),
+            input: InputPortF64::new(),
+            clock_in: InputPin::new(),
+        }
+    }
+
+    /// Returns the converter's registers, to map into memory.
+    ///
+    pub fn bank(&self) -> Box<AdcBank> {
+        Box::new(AdcBank { state: self.state.clone() })
+    }
+
+    pub fn input(&mut self) -> &mut InputPortF64 {
+        &mut self.input
+    }
+
+    pub fn clock_in(&mut self) -> &mut InputPin {
+        &mut self.clock_in
+    }
+
+    /// Count one cycle, converting the latest input level if a sample is due.
+    ///
+    pub fn cycle(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.remaining -= 1;
+        if state.remaining == 0 {
+            state.remaining = state.sample_cycles;
+            while self.input.try_recv().is_some() {}
+            state.data = Self::convert(self.input.value());
+            state.ready = true;
+        }
+    }
+
+    fn convert(level: f64) -> u8 {
+        (level.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+impl AsyncComponent for Adc8 {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        loop {
+            let signal = self.clock_in.recv();
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            if signal {
+                self.cycle();
+            }
+        }
+    }
+}
+
+/// The registers of an `Adc8`.
+///
+pub struct AdcBank {
+    state: Arc<Mutex<AdcState>>,
+}
+
+impl MemoryBank for AdcBank {
+    fn size(&self) -> usize {
+        Adc8::REGISTER_COUNT
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let mut state = self.state.lock().unwrap();
+        match (addr - offset) as usize % Adc8::REGISTER_COUNT {
+            Adc8::DATA => {
+                state.ready = false;
+                state.data
+            },
+            _ if state.ready => Adc8::READY,
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, _addr: u16, _offset: u16, _value: u8, _ram: &mut [u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::{Memory, Vectors};
+    use crate::core::ports::OutputPortF64;
+    use crate::cpus::c6502::C6502;
+
+    // Waits for each conversion, and stores it at $10, $11, ... in turn.
+    const FIRMWARE: &[u8] = &[
+        0xA2, 0x00, //       LDX #$00
+        0xAD, 0x01, 0xD0, // wait: LDA $D001
+        0x10, 0xFB, //       BPL wait
+        0xAD, 0x00, 0xD0, // LDA $D000
+        0x95, 0x10, //       STA $10,X
+        0xE8, //             INX
+        0x4C, 0x02, 0x04, // JMP wait
+    ];
+
+    #[test]
+    fn conversion() {
+        assert_eq!(Adc8::convert(0.0), 0x00);
+        assert_eq!(Adc8::convert(0.5), 0x80);
+        assert_eq!(Adc8::convert(1.0), 0xFF);
+        assert_eq!(Adc8::convert(-0.2), 0x00);
+        assert_eq!(Adc8::convert(1.7), 0xFF);
+    }
+
+    #[test]
+    fn read_from_code() {
+        let memory = Memory::new();
+        memory.write_block(0x0400, FIRMWARE);
+        memory.set_vectors(Vectors { nmi: None, reset: 0x0400, irq: None });
+        let mut adc = Adc8::new(100);
+        memory.configure_banks(vec![adc.bank()], &[(0xD000, 0x100, 1, 0x0000)]).unwrap();
+        let mut level = OutputPortF64::new();
+        level.connect_to(adc.input());
+
+        let mut cpu = C6502::new(&memory);
+        cpu.reset();
+        let mut run = |cycles| {
+            for _ in 0..cycles {
+                cpu.step();
+                adc.cycle();
+            }
+        };
+        for sample in [0.25, 0.75, 1.0] {
+            // Only the latest level before the sample counts.
+            level.send(0.0);
+            level.send(sample);
+            run(100);
+        }
+        run(20);
+        let mut samples = [0; 4];
+        memory.read_block(0x10, &mut samples);
+        assert_eq!(samples, [0x40, 0xBF, 0xFF, 0x00]);
+    }
+}
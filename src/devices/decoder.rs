@@ -0,0 +1,155 @@
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::core::ports::{InputPin, InputPort16, OutputPin};
+use crate::core::AsyncComponent;
+
+/// An address decoder: a set of chip-select outputs, one per region of the address space, each
+/// high while an access falls in its region.
+///
+/// The decoder is driven by `address_in`, a 16-bit address bus, and `strobe_in`, which is high
+/// while an access is in progress. Each time the strobe changes, the decoder takes the latest
+/// address and raises the pins of the regions containing it, and lowers the rest. Once the strobe
+/// goes low, every pin is low. The producer must send the address before raising the strobe.
+///
+/// Regions may overlap, in which case an access in both selects both. A pin is only sent a
+/// value when its level changes.
+///
+pub struct AddressDecoder {
+    address_in: InputPort16,
+    strobe_in: InputPin,
+    regions: Vec<(RangeInclusive<u16>, OutputPin)>,
+}
+
+impl AddressDecoder {
+    pub fn new() -> Self {
+        Self { address_in: InputPort16::new(), strobe_in: InputPin::new(), regions: Vec::new() }
+    }
+
+    pub fn address_in(&mut self) -> &mut InputPort16 {
+        &mut self.address_in
+    }
+
+    pub fn strobe_in(&mut self) -> &mut InputPin {
+        &mut self.strobe_in
+    }
+
+    /// Add a region, returning its chip-select pin, which starts low.
+    ///
+    pub fn add_region(&mut self, range: RangeInclusive<u16>) -> &mut OutputPin {
+        self.regions.push((range, OutputPin::new()));
+        &mut self.regions.last_mut().unwrap().1
+    }
+
+    /// Decode an access to `addr`, or the end of one if `active` is false, updating the pins.
+    ///
+    pub fn decode(&mut self, addr: u16, active: bool) {
+        for (range, pin) in self.regions.iter_mut() {
+            let selected = active && range.contains(&addr);
+            if pin.value() != selected {
+                pin.send(selected);
+            }
+        }
+    }
+}
+
+impl Default for AddressDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncComponent for AddressDecoder {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        loop {
+            let strobe = self.strobe_in.recv();
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            self.address_in.drain();
+            self.decode(self.address_in.value(), strobe);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ports::OutputPort16;
+    use std::thread;
+
+    #[test]
+    fn decode() {
+        let mut decoder = AddressDecoder::new();
+        let mut ram = InputPin::new();
+        let mut io = InputPin::new();
+        let mut rom = InputPin::new();
+        decoder.add_region(0x0000..=0x7FFF).connect_to(&mut ram);
+        decoder.add_region(0xD000..=0xD0FF).connect_to(&mut io);
+        decoder.add_region(0xC000..=0xFFFF).connect_to(&mut rom);
+
+        decoder.decode(0x1234, true);
+        assert_eq!(ram.try_recv(), Some(true));
+        assert_eq!(io.try_recv(), None);
+        assert_eq!(rom.try_recv(), None);
+
+        // A second access to the same region doesn't resend the pin.
+        decoder.decode(0x0200, true);
+        assert_eq!(ram.try_recv(), None);
+
+        // Overlapping regions are both selected.
+        decoder.decode(0xD020, true);
+        assert_eq!(ram.try_recv(), Some(false));
+        assert_eq!(io.try_recv(), Some(true));
+        assert_eq!(rom.try_recv(), Some(true));
+
+        decoder.decode(0xFFFC, true);
+        assert_eq!(io.try_recv(), Some(false));
+        assert_eq!(rom.try_recv(), None);
+
+        // Addresses outside every region select nothing, and neither does the end of an access.
+        decoder.decode(0xB000, true);
+        assert_eq!(rom.try_recv(), Some(false));
+        decoder.decode(0x0000, true);
+        assert_eq!(ram.try_recv(), Some(true));
+        decoder.decode(0x0000, false);
+        assert_eq!(ram.try_recv(), Some(false));
+    }
+
+    #[test]
+    fn driven_by_ports() {
+        let mut decoder = AddressDecoder::new();
+        let mut address = OutputPort16::new();
+        let mut strobe = OutputPin::new();
+        let mut io = InputPin::new();
+        let mut any = InputPin::new();
+        address.connect_to(decoder.address_in());
+        strobe.connect_to(decoder.strobe_in());
+        decoder.add_region(0xD000..=0xD0FF).connect_to(&mut io);
+        decoder.add_region(0x0000..=0xFFFF).connect_to(&mut any);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || decoder.run(stop_clone));
+
+        let mut levels = Vec::new();
+        for addr in [0x0400, 0xD000, 0xD0FF, 0xD100, 0xD010] {
+            address.send(addr);
+            strobe.send(true);
+            strobe.send(false);
+            // Wait for the decoder to finish with each access before starting the next.
+            assert!(any.recv());
+            assert!(!any.recv());
+            while let Some(level) = io.try_recv() {
+                levels.push(level);
+            }
+        }
+        assert_eq!(levels, vec![true, false, true, false, true, false]);
+        assert!(io.try_recv().is_none());
+
+        stop.store(true, Ordering::Relaxed);
+        strobe.send(false);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,3 @@
+pub mod adc;
+pub mod decoder;
+pub mod watchdog;
@@ -0,0 +1,323 @@
+use crossbeam_channel::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::core::memory::MemoryBank;
+use crate::core::ports::{InputPin, OutputPin};
+use crate::core::{AsyncComponent, STOP_POLL_INTERVAL};
+
+/// A watchdog timer: once enabled, it counts down a number of CPU cycles, and resets the
+/// machine if the firmware doesn't kick it before the count runs out.
+///
+/// The watchdog is programmed through a bank of registers (see `bank`), which repeat through
+/// each page the bank is mapped to:
+///
+/// * `CONTROL` (read/write): `ENABLE` starts the countdown. With `NMI_FIRST`, the first expiry
+///   pulses NMI and restarts the countdown, so the firmware gets a chance to recover, and only
+///   a second expiry resets. Setting `LOCK` ignores further writes to `CONTROL`, so runaway code
+///   can't turn the watchdog off.
+/// * `KICK` (write): writing `KICK_VALUE` restarts the countdown. Other values are ignored.
+/// * `CAUSE` (read/write): the number of resets caused by the watchdog, so firmware can tell
+///   after restarting why it was reset. Any write clears it.
+///
+/// When the watchdog resets the machine, `CONTROL` is cleared, so the watchdog is off again
+/// until the firmware re-enables it.
+///
+/// The watchdog counts the rising edges on `clock_in`, which is usually wired to the CPU's phi2
+/// output. `reset_out` and `nmi_out` are active low, to wire to the CPU's reset and NMI inputs.
+///
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+    clock_in: InputPin,
+    reset_out: OutputPin,
+    nmi_out: OutputPin,
+}
+
+struct WatchdogState {
+    timeout: u32,
+    remaining: u32,
+    control: u8,
+    cause: u8,
+    nmi_sent: bool,
+}
+
+enum Expiry {
+    Nmi,
+    Reset,
+}
+
+impl Watchdog {
+    pub const CONTROL: usize = 0;
+    pub const KICK: usize = 1;
+    pub const CAUSE: usize = 2;
+    pub const REGISTER_COUNT: usize = 3;
+
+    pub const ENABLE: u8 = 0b00000001;
+    pub const NMI_FIRST: u8 = 0b00000010;
+    pub const LOCK: u8 = 0b10000000;
+
+    pub const KICK_VALUE: u8 = 0xA5;
+
+    pub fn new(timeout_cycles: u32) -> Self {
+        assert!(timeout_cycles > 0);
+        Self {
+            state: Arc::new(Mutex::new(WatchdogState {
+                timeout: timeout_cycles,
+                remaining: timeout_cycles,
+                control: 0,
+                cause: 0,
+                nmi_sent: false,
+            })),
+            clock_in: InputPin::new(),
+            reset_out: OutputPin::with_initial_value(true),
+            nmi_out: OutputPin::with_initial_value(true),
+        }
+    }
+
+    /// Returns the watchdog's registers, to map into memory.
+    ///
+    pub fn bank(&self) -> Box<WatchdogBank> {
+        Box::new(WatchdogBank { state: self.state.clone() })
+    }
+
+    pub fn clock_in(&mut self) -> &mut InputPin {
+        &mut self.clock_in
+    }
+
+    pub fn reset_out(&mut self) -> &mut OutputPin {
+        &mut self.reset_out
+    }
+
+    pub fn nmi_out(&mut self) -> &mut OutputPin {
+        &mut self.nmi_out
+    }
+
+    /// Count down one cycle, pulsing the NMI or reset line if the watchdog expires.
+    ///
+    pub fn cycle(&mut self) {
+        let expiry = self.state.lock().unwrap().cycle();
+        let line = match expiry {
+            Some(Expiry::Nmi) => &mut self.nmi_out,
+            Some(Expiry::Reset) => &mut self.reset_out,
+            None => return,
+        };
+        line.send(false);
+        line.send(true);
+    }
+}
+
+impl WatchdogState {
+    fn cycle(&mut self) -> Option<Expiry> {
+        if self.control & Watchdog::ENABLE == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return None;
+        }
+        self.remaining = self.timeout;
+        if self.control & Watchdog::NMI_FIRST != 0 && !self.nmi_sent {
+            self.nmi_sent = true;
+            Some(Expiry::Nmi)
+        } else {
+            self.nmi_sent = false;
+            self.control = 0;
+            self.cause = self.cause.saturating_add(1);
+            Some(Expiry::Reset)
+        }
+    }
+
+    fn kick(&mut self) {
+        self.remaining = self.timeout;
+        self.nmi_sent = false;
+    }
+}
+
+impl AsyncComponent for Watchdog {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        while !stop.load(Ordering::Relaxed) {
+            match self.clock_in.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(true) => self.cycle(),
+                Ok(false) | Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+/// The registers of a `Watchdog`.
+///
+pub struct WatchdogBank {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl WatchdogBank {
+    fn register(addr: u16, offset: u16) -> usize {
+        (addr - offset) as usize % Watchdog::REGISTER_COUNT
+    }
+}
+
+impl MemoryBank for WatchdogBank {
+    fn size(&self) -> usize {
+        Watchdog::REGISTER_COUNT
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let state = self.state.lock().unwrap();
+        match Self::register(addr, offset) {
+            Watchdog::CONTROL => state.control,
+            Watchdog::CAUSE => state.cause,
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, value: u8, _ram: &mut [u8]) {
+        let mut state = self.state.lock().unwrap();
+        match Self::register(addr, offset) {
+            Watchdog::CONTROL if state.control & Watchdog::LOCK == 0 => {
+                if value & Watchdog::ENABLE != 0 && state.control & Watchdog::ENABLE == 0 {
+                    state.kick();
+                }
+                state.control = value;
+            },
+            Watchdog::KICK if value == Watchdog::KICK_VALUE => state.kick(),
+            Watchdog::CAUSE => state.cause = 0,
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::{Memory, Vectors};
+    use crate::cpus::c6502::C6502;
+
+    // Counts boots at $11, and stores the cause register at $12 on boot. It then enables the
+    // watchdog with the control value at $15, and kicks it in a loop until $13 is non-zero.
+    // The NMI handler counts NMIs at $14.
+    const FIRMWARE: &[u8] = &[
+        0xE6, 0x11, //       INC $11
+        0xAD, 0x02, 0xD0, // LDA $D002
+        0x85, 0x12, //       STA $12
+        0xA5, 0x15, //       LDA $15
+        0x8D, 0x00, 0xD0, // STA $D000
+        0xA9, 0xA5, //       loop: LDA #$A5
+        0x8D, 0x01, 0xD0, // STA $D001
+        0xE6, 0x10, //       INC $10
+        0xA5, 0x13, //       LDA $13
+        0xF0, 0xF5, //       BEQ loop
+        0x4C, 0x17, 0x04, // hang: JMP hang
+    ];
+    const NMI_HANDLER: &[u8] = &[
+        0xE6, 0x14, // INC $14
+        0x40, //       RTI
+    ];
+
+    fn run_firmware(control: u8, stop_kicking: bool, cycles: usize) -> Memory {
+        let memory = Memory::new();
+        memory.write_block(0x0400, FIRMWARE);
+        memory.write_block(0x0420, NMI_HANDLER);
+        memory.set_vectors(Vectors { nmi: Some(0x0420), reset: 0x0400, irq: None });
+        memory.write_byte(0x13, stop_kicking as u8);
+        memory.write_byte(0x15, control);
+
+        let mut watchdog = Watchdog::new(100);
+        memory.configure_banks(vec![watchdog.bank()], &[(0xD000, 0x100, 1, 0x0000)]).unwrap();
+        let mut reset = InputPin::with_initial_value(true);
+        let mut nmi = InputPin::with_initial_value(true);
+        watchdog.reset_out().connect_to(&mut reset);
+        watchdog.nmi_out().connect_to(&mut nmi);
+
+        let mut cpu = C6502::new(&memory);
+        cpu.reset();
+        for _ in 0..cycles {
+            cpu.step();
+            watchdog.cycle();
+            while let Some(level) = reset.try_recv() {
+                cpu.set_reset(!level);
+            }
+            while let Some(level) = nmi.try_recv() {
+                cpu.set_nmi(!level);
+            }
+        }
+        memory
+    }
+
+    #[test]
+    fn kicked_on_time() {
+        let memory = run_firmware(Watchdog::ENABLE, false, 2000);
+        assert_eq!(memory.read_byte(0x11), 1);
+        assert_eq!(memory.read_byte(0x12), 0);
+        assert!(memory.read_byte(0x10) > 100);
+    }
+
+    #[test]
+    fn expiry_resets() {
+        let memory = run_firmware(Watchdog::ENABLE, true, 200);
+        assert_eq!(memory.read_byte(0x11), 2);
+        assert_eq!(memory.read_byte(0x12), 1);
+        assert_eq!(memory.read_byte(0x14), 0);
+    }
+
+    #[test]
+    fn nmi_before_reset() {
+        let memory = run_firmware(Watchdog::ENABLE | Watchdog::NMI_FIRST, true, 200);
+        assert_eq!(memory.read_byte(0x11), 1);
+        assert_eq!(memory.read_byte(0x14), 1);
+
+        let memory = run_firmware(Watchdog::ENABLE | Watchdog::NMI_FIRST, true, 300);
+        assert_eq!(memory.read_byte(0x11), 2);
+        assert_eq!(memory.read_byte(0x12), 1);
+        assert_eq!(memory.read_byte(0x14), 1);
+    }
+
+    #[test]
+    fn stops_with_idle_clock() {
+        let mut watchdog = Watchdog::new(10);
+        let mut clock = OutputPin::new();
+        clock.connect_to(watchdog.clock_in());
+        let stop = Arc::new(AtomicBool::new(false));
+        let watchdog_stop = stop.clone();
+        let handle = std::thread::spawn(move || watchdog.run(watchdog_stop));
+
+        // The watchdog stops while waiting for a clock edge that never comes.
+        std::thread::sleep(STOP_POLL_INTERVAL * 2);
+        let start = std::time::Instant::now();
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        assert!(start.elapsed() < STOP_POLL_INTERVAL * 10);
+        drop(clock);
+    }
+
+    #[test]
+    fn lock() {
+        let mut watchdog = Watchdog::new(10);
+        let mut bank = watchdog.bank();
+        let mut reset = InputPin::with_initial_value(true);
+        watchdog.reset_out().connect_to(&mut reset);
+
+        bank.write_byte(0xD000, 0xD000, Watchdog::ENABLE | Watchdog::LOCK, &mut []);
+        bank.write_byte(0xD000, 0xD000, 0, &mut []);
+        assert_eq!(bank.read_byte(0xD000, 0xD000, &[]), Watchdog::ENABLE | Watchdog::LOCK);
+        for _ in 0..10 {
+            watchdog.cycle();
+        }
+        assert_eq!(reset.try_recv(), Some(false));
+        assert_eq!(reset.try_recv(), Some(true));
+
+        // The reset unlocks the watchdog.
+        assert_eq!(bank.read_byte(0xD000, 0xD000, &[]), 0);
+        assert_eq!(bank.read_byte(0xD002, 0xD000, &[]), 1);
+        bank.write_byte(0xD002, 0xD000, 0, &mut []);
+        assert_eq!(bank.read_byte(0xD002, 0xD000, &[]), 0);
+    }
+}
@@ -1,8 +1,9 @@
+use crossbeam_channel::RecvTimeoutError;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use crate::core::ports::{InputPin, OutputPin};
-use crate::core::AsyncComponent;
+use crate::core::ports::{InputPin, InputPort8, OutputPin, OutputPort8, Selected};
+use crate::core::{AsyncComponent, SyncComponent, STOP_POLL_INTERVAL};
 
 pub struct BinaryGate<T>
 where
@@ -58,11 +59,7 @@ where
     T: BinaryOp + Send,
 {
     fn run(&mut self, stop: Arc<AtomicBool>) {
-        loop {
-            InputPin::wait_any(&mut [&mut self.input_a, &mut self.input_b]);
-            if stop.load(Ordering::Relaxed) {
-                break;
-            }
+        while wait_for_input(&mut [&mut self.input_a, &mut self.input_b], &stop) {
             let output = T::op(self.input_a.value(), self.input_b.value());
             println!("{}", output);
             self.output.send(output);
@@ -70,6 +67,26 @@ where
     }
 }
 
+/// Wait for a value on any of `inputs`, returning true once one arrives, or false once the
+/// component should stop: when `stop` is set, or every input's output port has gone away. An
+/// input whose output port goes away is disconnected, keeping its last value, so it isn't
+/// waited on again.
+///
+fn wait_for_input(inputs: &mut [&mut InputPin], stop: &AtomicBool) -> bool {
+    loop {
+        let selected = InputPin::select(inputs, Some(STOP_POLL_INTERVAL));
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        match selected {
+            Selected::Received(_) => return true,
+            Selected::Disconnected(i) => inputs[i].disconnect(),
+            Selected::TimedOut => {},
+            Selected::NoneConnected => return false,
+        }
+    }
+}
+
 pub trait BinaryOp {
     fn op(a: bool, b: bool) -> bool;
 }
@@ -112,3 +129,400 @@ impl BinaryOp for NorOp {
         !(a || b)
     }
 }
+
+/// An open-collector line, such as IRQ: any number of drivers can pull it low, and a pull-up
+/// holds it high when none do. Each driver connects an output pin to its own input, with `true`
+/// releasing the line and `false` pulling it low. The effective level is sent to `output` as it
+/// changes.
+///
+pub struct WiredAnd {
+    drivers: Vec<(String, InputPin)>,
+    output: OutputPin,
+    probe: WiredAndProbe,
+}
+
+impl WiredAnd {
+    pub fn new() -> Self {
+        Self { drivers: Vec::new(), output: OutputPin::with_initial_value(true), probe: WiredAndProbe::default() }
+    }
+
+    /// Add a driver to the line, returning the input to connect its output pin to. The driver
+    /// starts out released.
+    ///
+    pub fn add_driver(&mut self, name: &str) -> &mut InputPin {
+        self.drivers.push((name.to_string(), InputPin::with_initial_value(true)));
+        &mut self.drivers.last_mut().unwrap().1
+    }
+
+    pub fn output(&mut self) -> &mut OutputPin {
+        &mut self.output
+    }
+
+    /// Returns a handle for observing the line from other threads while it runs.
+    ///
+    pub fn probe(&self) -> WiredAndProbe {
+        self.probe.clone()
+    }
+
+    fn update(&mut self) {
+        let pulling: Vec<String> =
+            self.drivers.iter().filter(|(_, input)| !input.value()).map(|(name, _)| name.clone()).collect();
+        let level = pulling.is_empty();
+        *self.probe.0.lock().unwrap() = pulling;
+        if level != self.output.value() {
+            self.output.send(level);
+        }
+    }
+}
+
+impl Default for WiredAnd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncComponent for WiredAnd {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        loop {
+            let mut inputs: Vec<&mut InputPin> = self.drivers.iter_mut().map(|(_, input)| input).collect();
+            InputPin::wait_any(&mut inputs);
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            self.update();
+        }
+    }
+}
+
+/// Shows which drivers of a `WiredAnd` are pulling the line low.
+///
+#[derive(Clone, Default)]
+pub struct WiredAndProbe(Arc<Mutex<Vec<String>>>);
+
+impl WiredAndProbe {
+    /// The names of the drivers pulling the line low, in the order they were added.
+    ///
+    pub fn pulling(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn level(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// Splits a byte into its bits: each value received on `input` is sent to `bit(0)` through
+/// `bit(7)`, least significant first, e.g. to drive a row of LEDs from a data bus. Only the
+/// bits that changed are sent.
+///
+pub struct BitSplitter {
+    input: InputPort8,
+    bits: [OutputPin; 8],
+}
+
+impl BitSplitter {
+    pub fn new() -> Self {
+        Self { input: InputPort8::new(), bits: Default::default() }
+    }
+
+    pub fn input(&mut self) -> &mut InputPort8 {
+        &mut self.input
+    }
+
+    pub fn bit(&mut self, n: usize) -> &mut OutputPin {
+        &mut self.bits[n]
+    }
+
+    fn update(&mut self, value: u8) {
+        for (n, bit) in self.bits.iter_mut().enumerate() {
+            let level = value & (1 << n) != 0;
+            if level != bit.value() {
+                bit.send(level);
+            }
+        }
+    }
+}
+
+impl Default for BitSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncComponent for BitSplitter {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        loop {
+            let received = self.input.recv_timeout(STOP_POLL_INTERVAL);
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            match received {
+                Ok(value) => self.update(value),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+/// Run on the UI thread instead, the splitter passes on the newest byte each tick.
+///
+impl SyncComponent for BitSplitter {
+    fn start(&mut self) {}
+
+    fn tick(&mut self) {
+        if let Some(value) = self.input.try_recv_newest() {
+            self.update(value);
+        }
+    }
+
+    fn stop(&mut self) {}
+}
+
+/// Combines bits into a byte: whenever one of `bit(0)` through `bit(7)` changes, the byte they
+/// make up, least significant first, is sent to `output`, e.g. to read a bank of DIP switches.
+/// The byte is only sent if it changed.
+///
+pub struct BitCombiner {
+    bits: [InputPin; 8],
+    output: OutputPort8,
+}
+
+impl BitCombiner {
+    pub fn new() -> Self {
+        Self { bits: Default::default(), output: OutputPort8::new() }
+    }
+
+    pub fn bit(&mut self, n: usize) -> &mut InputPin {
+        &mut self.bits[n]
+    }
+
+    pub fn output(&mut self) -> &mut OutputPort8 {
+        &mut self.output
+    }
+
+    fn update(&mut self) {
+        let value = self.bits.iter().enumerate().fold(0, |value, (n, bit)| value | ((bit.value() as u8) << n));
+        if value != self.output.value() {
+            self.output.send(value);
+        }
+    }
+}
+
+impl Default for BitCombiner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncComponent for BitCombiner {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        loop {
+            let mut bits: Vec<&mut InputPin> = self.bits.iter_mut().collect();
+            if !wait_for_input(&mut bits, &stop) {
+                break;
+            }
+            self.update();
+        }
+    }
+}
+
+/// Run on the UI thread instead, the combiner sends the byte made up of the newest bits each
+/// tick.
+///
+impl SyncComponent for BitCombiner {
+    fn start(&mut self) {}
+
+    fn tick(&mut self) {
+        let received: usize = self.bits.iter_mut().map(|bit| bit.drain()).sum();
+        if received > 0 {
+            self.update();
+        }
+    }
+
+    fn stop(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn wait_until(f: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn stop_without_input() {
+        let mut gate = AndGate::new();
+        let mut a = OutputPin::new();
+        let mut b = OutputPin::new();
+        let mut sink = InputPin::new();
+        a.connect_to(gate.input_a());
+        b.connect_to(gate.input_b());
+        gate.output().connect_to(&mut sink);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || gate.run(stop_clone));
+        a.send(true);
+        b.send(true);
+        assert!(!sink.recv());
+        assert!(sink.recv());
+
+        let start = Instant::now();
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        assert!(start.elapsed() < STOP_POLL_INTERVAL * 10);
+
+        // Once both inputs have gone away, the gate stops by itself, without spinning on them.
+        let mut gate = AndGate::new();
+        let mut a = OutputPin::new();
+        let mut b = OutputPin::new();
+        a.connect_to(gate.input_a());
+        b.connect_to(gate.input_b());
+        let handle = thread::spawn(move || gate.run(Arc::new(AtomicBool::new(false))));
+        drop(a);
+        thread::sleep(STOP_POLL_INTERVAL * 2);
+        assert!(!handle.is_finished());
+        drop(b);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn bit_splitter() {
+        let mut splitter = BitSplitter::new();
+        let mut data = OutputPort8::new();
+        data.connect_to(splitter.input());
+        let mut bits: Vec<InputPin> = (0..8).map(|_| InputPin::new()).collect();
+        for (n, bit) in bits.iter_mut().enumerate() {
+            splitter.bit(n).connect_to(bit);
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || splitter.run(stop_clone));
+        for value in [0x01, 0x03, 0x82, 0x82, 0x00] {
+            data.send(value);
+        }
+        // Bits 0, 1 and 7 each go high and then low again, and the rest never change.
+        let expected: [&[bool]; 8] = [&[true, false], &[true, false], &[], &[], &[], &[], &[], &[true, false]];
+        for (bit, expected) in bits.iter_mut().zip(expected) {
+            let history: Vec<bool> = expected.iter().map(|_| bit.recv()).collect();
+            assert_eq!(history, expected);
+        }
+
+        // The splitter stops once its input goes away.
+        drop(data);
+        handle.join().unwrap();
+        assert!(!stop.load(Ordering::Relaxed));
+        assert!(bits.iter_mut().all(|bit| bit.try_recv().is_none()));
+
+        // Ticked instead, it passes on only the newest byte.
+        let mut splitter = BitSplitter::new();
+        let mut data = OutputPort8::new();
+        data.connect_to(splitter.input());
+        let mut bit0 = InputPin::new();
+        splitter.bit(0).connect_to(&mut bit0);
+        data.send(0x01);
+        data.send(0x00);
+        data.send(0x01);
+        splitter.tick();
+        assert_eq!(bit0.try_recv(), Some(true));
+        assert_eq!(bit0.try_recv(), None);
+    }
+
+    #[test]
+    fn bit_combiner() {
+        let mut combiner = BitCombiner::new();
+        let mut switches: Vec<OutputPin> = (0..8).map(|_| OutputPin::new()).collect();
+        for (n, switch) in switches.iter_mut().enumerate() {
+            switch.connect_to(combiner.bit(n));
+        }
+        let mut byte = InputPort8::new();
+        combiner.output().connect_to(&mut byte);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || combiner.run(stop_clone));
+        switches[0].send(true);
+        assert_eq!(byte.recv(), 0x01);
+        switches[7].send(true);
+        assert_eq!(byte.recv(), 0x81);
+        switches[3].send(true);
+        assert_eq!(byte.recv(), 0x89);
+        switches[0].send(false);
+        assert_eq!(byte.recv(), 0x88);
+
+        // Setting a bit to the level it already has doesn't resend the byte.
+        switches[3].send(true);
+        switches[4].send(true);
+        assert_eq!(byte.recv(), 0x98);
+
+        // Once every switch has gone away, the combiner stops.
+        switches.clear();
+        handle.join().unwrap();
+        assert!(!stop.load(Ordering::Relaxed));
+        assert!(byte.try_recv().is_none());
+
+        // Ticked instead, it sends the byte once per tick.
+        let mut combiner = BitCombiner::new();
+        let mut switches: Vec<OutputPin> = (0..8).map(|_| OutputPin::new()).collect();
+        for (n, switch) in switches.iter_mut().enumerate() {
+            switch.connect_to(combiner.bit(n));
+        }
+        let mut byte = InputPort8::new();
+        combiner.output().connect_to(&mut byte);
+        switches[1].send(true);
+        switches[2].send(true);
+        combiner.tick();
+        assert_eq!(byte.try_recv(), Some(0x06));
+        assert_eq!(byte.try_recv(), None);
+        combiner.tick();
+        assert_eq!(byte.try_recv(), None);
+    }
+
+    #[test]
+    fn wired_and() {
+        let mut line = WiredAnd::new();
+        let mut a = OutputPin::with_initial_value(true);
+        let mut b = OutputPin::with_initial_value(true);
+        let mut sink = InputPin::with_initial_value(true);
+        a.connect_to(line.add_driver("a"));
+        b.connect_to(line.add_driver("b"));
+        line.output().connect_to(&mut sink);
+        let probe = line.probe();
+
+        // With no driver pulling, the line floats high.
+        assert!(probe.level());
+        assert!(line.output().value());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || line.run(stop_clone));
+
+        a.send(false);
+        assert!(!sink.recv());
+        assert_eq!(probe.pulling(), vec!["a"]);
+
+        // The line stays low until the last driver releases it, whatever the order.
+        b.send(false);
+        wait_until(|| probe.pulling().len() == 2);
+        a.send(true);
+        wait_until(|| probe.pulling() == vec!["b"]);
+        assert!(!probe.level());
+        b.send(true);
+        assert!(sink.recv());
+        assert!(probe.pulling().is_empty());
+        assert!(sink.try_recv().is_none());
+
+        stop.store(true, Ordering::Relaxed);
+        a.send(true);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::core::ports::{InputPin, OutputPin};
+use crate::core::{AsyncComponent, ShutdownPhase};
+
+/// Access to the GPIO lines of the host, such as a Raspberry Pi's header pins.
+///
+pub trait GpioBackend: Send {
+    fn set_direction(&mut self, line: u32, output: bool) -> io::Result<()>;
+    fn write(&mut self, line: u32, value: bool) -> io::Result<()>;
+    fn read(&mut self, line: u32) -> io::Result<bool>;
+}
+
+/// GPIO backend using the Linux sysfs interface under `/sys/class/gpio`.
+///
+/// Lines are exported on first use. The process needs write access to the sysfs files, which
+/// usually means being in the `gpio` group.
+///
+pub struct SysfsGpio {
+    root: PathBuf,
+}
+
+impl SysfsGpio {
+    pub fn new() -> Self {
+        Self::with_root("/sys/class/gpio")
+    }
+
+    pub fn with_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn line_path(&self, line: u32, file: &str) -> io::Result<PathBuf> {
+        let dir = self.root.join(format!("gpio{}", line));
+        if !dir.exists() {
+            fs::write(self.root.join("export"), line.to_string())?;
+        }
+        Ok(dir.join(file))
+    }
+}
+
+impl Default for SysfsGpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpioBackend for SysfsGpio {
+    fn set_direction(&mut self, line: u32, output: bool) -> io::Result<()> {
+        fs::write(self.line_path(line, "direction")?, if output { "out" } else { "in" })
+    }
+
+    fn write(&mut self, line: u32, value: bool) -> io::Result<()> {
+        fs::write(self.line_path(line, "value")?, if value { "1" } else { "0" })
+    }
+
+    fn read(&mut self, line: u32) -> io::Result<bool> {
+        Ok(fs::read_to_string(self.line_path(line, "value")?)?.trim() == "1")
+    }
+}
+
+/// Drives a GPIO line from an input pin, e.g. to light a physical LED in place of
+/// `widgets::leds::Led`.
+///
+pub struct GpioOutput<B: GpioBackend> {
+    line: u32,
+    backend: B,
+    input: InputPin,
+}
+
+impl<B: GpioBackend> GpioOutput<B> {
+    pub fn new(backend: B, line: u32) -> Self {
+        Self { line, backend, input: InputPin::new() }
+    }
+
+    pub fn input(&mut self) -> &mut InputPin {
+        &mut self.input
+    }
+}
+
+impl<B: GpioBackend> AsyncComponent for GpioOutput<B> {
+    fn shutdown_phase(&self) -> ShutdownPhase {
+        ShutdownPhase::Sink
+    }
+
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        self.backend.set_direction(self.line, true).expect("Couldn't configure GPIO line");
+        self.backend.write(self.line, self.input.value()).expect("Couldn't write GPIO line");
+        while let Some(value) = self.input.recv_checked() {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            self.backend.write(self.line, value).expect("Couldn't write GPIO line");
+        }
+    }
+}
+
+/// Samples a GPIO line at a fixed interval, and sends its value to an output pin whenever
+/// it changes, e.g. to read a physical switch.
+///
+pub struct GpioInput<B: GpioBackend> {
+    line: u32,
+    backend: B,
+    poll_interval: Duration,
+    output: OutputPin,
+}
+
+impl<B: GpioBackend> GpioInput<B> {
+    pub fn new(backend: B, line: u32, poll_interval: Duration) -> Self {
+        Self { line, backend, poll_interval, output: OutputPin::new() }
+    }
+
+    pub fn output(&mut self) -> &mut OutputPin {
+        &mut self.output
+    }
+}
+
+impl<B: GpioBackend> AsyncComponent for GpioInput<B> {
+    fn shutdown_phase(&self) -> ShutdownPhase {
+        ShutdownPhase::Source
+    }
+
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        self.backend.set_direction(self.line, false).expect("Couldn't configure GPIO line");
+        while !stop.load(Ordering::Relaxed) {
+            let value = self.backend.read(self.line).expect("Couldn't read GPIO line");
+            if value != self.output.value() {
+                self.output.send(value);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    #[derive(Clone, Default)]
+    struct MockGpio {
+        writes: Arc<Mutex<Vec<(u32, bool)>>>,
+        reads: Arc<Mutex<VecDeque<bool>>>,
+        directions: Arc<Mutex<Vec<(u32, bool)>>>,
+    }
+
+    impl GpioBackend for MockGpio {
+        fn set_direction(&mut self, line: u32, output: bool) -> io::Result<()> {
+            self.directions.lock().unwrap().push((line, output));
+            Ok(())
+        }
+
+        fn write(&mut self, line: u32, value: bool) -> io::Result<()> {
+            self.writes.lock().unwrap().push((line, value));
+            Ok(())
+        }
+
+        fn read(&mut self, _line: u32) -> io::Result<bool> {
+            let mut reads = self.reads.lock().unwrap();
+            Ok(if reads.len() > 1 { reads.pop_front().unwrap() } else { reads[0] })
+        }
+    }
+
+    fn wait_until(f: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn output_writes_edges() {
+        let mock = MockGpio::default();
+        let mut gpio = GpioOutput::new(mock.clone(), 17);
+        let mut source = OutputPin::new();
+        source.connect_to(gpio.input());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || gpio.run(stop_clone));
+        for value in [true, false, true] {
+            source.send(value);
+        }
+        wait_until(|| mock.writes.lock().unwrap().len() == 4);
+        stop.store(true, Ordering::Relaxed);
+        drop(source);
+        handle.join().unwrap();
+
+        assert_eq!(*mock.directions.lock().unwrap(), vec![(17, true)]);
+        assert_eq!(*mock.writes.lock().unwrap(), vec![(17, false), (17, true), (17, false), (17, true)]);
+    }
+
+    #[test]
+    fn input_sends_changes() {
+        let mock = MockGpio::default();
+        mock.reads.lock().unwrap().extend([false, true, true, false, false, true]);
+        let mut gpio = GpioInput::new(mock.clone(), 4, Duration::from_micros(100));
+        let mut sink = InputPin::new();
+        gpio.output().connect_to(&mut sink);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || gpio.run(stop_clone));
+        let received: Vec<bool> = (0..3).map(|_| sink.recv()).collect();
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert_eq!(*mock.directions.lock().unwrap(), vec![(4, false)]);
+        assert_eq!(received, vec![true, false, true]);
+    }
+}
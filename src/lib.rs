@@ -1,7 +1,17 @@
 #[macro_use]
 pub(crate) mod macros;
 
+pub mod bench;
 pub mod core;
 pub mod cpus;
+pub mod devices;
 pub mod gates;
+#[cfg(feature = "gpio")]
+pub mod gpio;
+pub mod roms;
+pub mod selftest;
+pub mod testing;
+#[cfg(feature = "ui")]
 pub mod widgets;
+
+pub use selftest::selftest;
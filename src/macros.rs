@@ -22,17 +22,6 @@ macro_rules! hi_byte {
     };
 }
 
-macro_rules! bcd_add_digits {
-    ($x:expr, $y:expr, $carry:expr) => {{
-        let r = $x + $y + $carry;
-        if r > 9 {
-            r + 6
-        } else {
-            r
-        }
-    }};
-}
-
 #[macro_export]
 macro_rules! assert_eq_hex {
     ($left:expr, $right:expr $(,)?) => {{
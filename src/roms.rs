@@ -0,0 +1,208 @@
+use crate::core::memory::{Memory, RomBank, Vectors};
+
+/// A small built-in program for demonstrating and exercising the emulator, along with where it
+/// lives in the address space.
+///
+/// The built-in ROMs are assembled by hand, with the source listed alongside the bytes. All of
+/// them are 8K images for $E000-$FFFF, start at $E000, and use no RAM beyond what is documented.
+///
+pub struct Rom {
+    pub name: &'static str,
+    pub origin: u16,
+    pub entry: u16,
+    pub code: &'static [u8],
+}
+
+impl Rom {
+    /// Map the ROM into `memory` as its only bank, with the reset vector pointing at the entry
+    /// point. Any previously configured banks are replaced.
+    ///
+    pub fn install(&self, memory: &Memory) {
+        let size = (0x10000 - self.origin as u32) as usize;
+        let mut image = vec![0; size];
+        image[0..self.code.len()].copy_from_slice(self.code);
+        memory
+            .configure_banks(vec![RomBank::with_bytes(&image)], &[(self.origin, size as u16, 1, 0x0000)])
+            .unwrap();
+        memory.set_vectors(Vectors { nmi: None, reset: self.entry, irq: None });
+    }
+}
+
+/// Counts up forever, storing the count at $0200.
+///
+pub const COUNT: Rom = Rom {
+    name: "count",
+    origin: 0xE000,
+    entry: 0xE000,
+    code: &[
+        0xA9, 0x00, //       LDA #$00
+        0x8D, 0x00, 0x02, // loop: STA $0200
+        0x18, //             CLC
+        0x69, 0x01, //       ADC #$01
+        0x4C, 0x02, 0xE0, // JMP loop
+    ],
+};
+
+/// Toggles bit 0 of $0200 roughly every 330,000 cycles, which blinks an LED driven from that
+/// bit at about 1.5Hz with a 1MHz clock. Uses the X and Y registers for the delay loop.
+///
+pub const BLINK: Rom = Rom {
+    name: "blink",
+    origin: 0xE000,
+    entry: 0xE000,
+    code: &[
+        0xA9, 0x00, //       LDA #$00
+        0x8D, 0x00, 0x02, // loop: STA $0200
+        0xA2, 0x00, //       LDX #$00
+        0xA0, 0x00, //       LDY #$00
+        0x88, //             delay: DEY
+        0xD0, 0xFD, //       BNE delay
+        0xCA, //             DEX
+        0xD0, 0xFA, //       BNE delay
+        0x49, 0x01, //       EOR #$01
+        0x4C, 0x02, 0xE0, // JMP loop
+    ],
+};
+
+/// Multiplies each 16-bit number from 0 up by $1235 with a shift-and-add loop, forever,
+/// storing the 32-bit product at $0200-$0203, low byte first. A compute-heavy workload for
+/// benchmarking. Uses $00-$09 as work space.
+///
+pub const MULTIPLY: Rom = Rom {
+    name: "multiply",
+    origin: 0xE000,
+    entry: 0xE000,
+    code: &[
+        0xA9, 0x00, //       LDA #$00
+        0x85, 0x00, //       STA $00         ; n
+        0x85, 0x01, //       STA $01
+        0xA5, 0x00, //       next: LDA $00   ; multiplicand = n
+        0x85, 0x02, //       STA $02
+        0xA5, 0x01, //       LDA $01
+        0x85, 0x03, //       STA $03
+        0xA9, 0x35, //       LDA #$35        ; multiplier = $1235
+        0x85, 0x04, //       STA $04
+        0xA9, 0x12, //       LDA #$12
+        0x85, 0x05, //       STA $05
+        0xA9, 0x00, //       LDA #$00        ; product = 0
+        0x85, 0x06, //       STA $06
+        0x85, 0x07, //       STA $07
+        0x85, 0x08, //       STA $08
+        0x85, 0x09, //       STA $09
+        0xA2, 0x10, //       LDX #$10
+        0x46, 0x05, //       bit: LSR $05
+        0x66, 0x04, //       ROR $04
+        0x90, 0x0D, //       BCC shift
+        0x18, //             CLC
+        0xA5, 0x08, //       LDA $08
+        0x65, 0x02, //       ADC $02
+        0x85, 0x08, //       STA $08
+        0xA5, 0x09, //       LDA $09
+        0x65, 0x03, //       ADC $03
+        0x85, 0x09, //       STA $09
+        0x66, 0x09, //       shift: ROR $09
+        0x66, 0x08, //       ROR $08
+        0x66, 0x07, //       ROR $07
+        0x66, 0x06, //       ROR $06
+        0xCA, //             DEX
+        0xD0, 0xE2, //       BNE bit
+        0xA2, 0x03, //       LDX #$03
+        0xB5, 0x06, //       copy: LDA $06,X
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0xCA, //             DEX
+        0x10, 0xF8, //       BPL copy
+        0xE6, 0x00, //       INC $00
+        0xD0, 0xB8, //       BNE next
+        0xE6, 0x01, //       INC $01
+        0x4C, 0x06, 0xE0, // JMP next
+    ],
+};
+
+pub const ALL: &[Rom] = &[COUNT, BLINK, MULTIPLY];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::WatchKind;
+    use crate::cpus::c6502::C6502;
+    use std::sync::{Arc, Mutex};
+
+    fn run(rom: &Rom, cycles: usize, mut observe: impl FnMut(u8)) {
+        let memory = Memory::new();
+        rom.install(&memory);
+        let mut cpu = C6502::new(&memory);
+        cpu.reset();
+        for _ in 0..cycles {
+            cpu.step();
+            observe(memory.read_byte(0x0200));
+        }
+    }
+
+    #[test]
+    fn count() {
+        let mut last = 0;
+        let mut increments = 0;
+        run(&COUNT, 10_000, |value| {
+            if value != last {
+                assert_eq!(value, last.wrapping_add(1));
+                increments += 1;
+                last = value;
+            }
+        });
+        assert!(increments > 500);
+    }
+
+    #[test]
+    fn blink() {
+        let mut values = vec![0];
+        run(&BLINK, 1_000_000, |value| {
+            if value != *values.last().unwrap() {
+                values.push(value);
+            }
+        });
+        assert_eq!(values, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn multiply() {
+        let memory = Memory::new();
+        MULTIPLY.install(&memory);
+        // Follow n and the product from the writes, checking each product as the copy of its
+        // low byte completes it.
+        let products = Arc::new(Mutex::new(Vec::new()));
+        let observed = products.clone();
+        let seen = Mutex::new([0u8; 0x204]);
+        memory.add_watch(0x0000..=0x0203, WatchKind::Write, move |addr, value, _| {
+            let mut seen = seen.lock().unwrap();
+            if (addr as usize) < seen.len() {
+                seen[addr as usize] = value;
+            }
+            if addr == 0x0200 {
+                let n = u16::from_le_bytes([seen[0x00], seen[0x01]]) as u32;
+                let product = u32::from_le_bytes(seen[0x200..0x204].try_into().unwrap());
+                observed.lock().unwrap().push((n, product));
+            }
+        });
+        let mut cpu = C6502::new(&memory);
+        cpu.reset();
+        for _ in 0..200_000 {
+            cpu.step();
+        }
+        let products = products.lock().unwrap();
+        assert!(products.len() > 100, "{}", products.len());
+        for (i, &(n, product)) in products.iter().enumerate() {
+            assert_eq!(n, i as u32);
+            assert_eq!(product, n * 0x1235);
+        }
+    }
+
+    #[test]
+    fn install() {
+        for rom in ALL {
+            let memory = Memory::new();
+            rom.install(&memory);
+            assert_eq!(memory.vectors().reset, rom.entry, "{}", rom.name);
+            assert_eq!(memory.read_byte(rom.origin), rom.code[0], "{}", rom.name);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::core::clock::Clock;
+use crate::core::memory::{Memory, RomBank, Vectors};
+use crate::core::ports::{InputPin, InputPort8, OutputPort8};
+use crate::core::AsyncComponent;
+use crate::cpus::c6502::{CpuState, C6502};
+
+type Check = fn() -> Result<(), String>;
+
+/// Outcome of a single self-test check.
+///
+#[derive(Debug, Clone)]
+pub struct SelfTestResult {
+    pub subsystem: &'static str,
+    pub outcome: Result<(), String>,
+}
+
+/// Results of `selftest`, one entry per subsystem checked.
+///
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in self.results.iter() {
+            match &r.outcome {
+                Ok(()) => writeln!(f, "{:<8} ok", r.subsystem)?,
+                Err(e) => writeln!(f, "{:<8} FAILED: {}", r.subsystem, e)?,
+            }
+        }
+        write!(f, "selftest {}", if self.passed() { "passed" } else { "FAILED" })
+    }
+}
+
+/// Run a quick battery of runtime checks of the emulator on the current platform.
+///
+/// This executes a short CPU program against known results, and exercises memory bank mapping,
+/// port round trips (on one thread and across threads), clock tick generation, and thread
+/// spawn/join. It takes well under a second.
+///
+pub fn selftest() -> SelfTestReport {
+    let checks: [(&'static str, Check); 5] = [
+        ("cpu", check_cpu),
+        ("memory", check_memory),
+        ("ports", check_ports),
+        ("clock", check_clock),
+        ("threads", check_threads),
+    ];
+    let results = checks
+        .iter()
+        .map(|(subsystem, check)| SelfTestResult {
+            subsystem,
+            outcome: thread::spawn(*check).join().unwrap_or_else(|_| Err("check panicked".to_string())),
+        })
+        .collect();
+    SelfTestReport { results }
+}
+
+fn expect_eq<T: PartialEq + fmt::Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{}: expected {:?}, got {:?}", what, expected, actual))
+    }
+}
+
+fn check_cpu() -> Result<(), String> {
+    const PROGRAM: &[u8] = &[
+        0xA2, 0x00, // LDX #$00
+        0xA9, 0x00, // LDA #$00
+        0xD8, // CLD
+        0x18, // CLC
+        0x69, 0x03, // ADC #$03
+        0x48, // PHA
+        0x68, // PLA
+        0xE8, // INX
+        0xE0, 0x64, // CPX #$64
+        0xD0, 0xF6, // BNE $0205
+        0x85, 0x10, // STA $10
+        0x86, 0x11, // STX $11
+        0x4C, 0x13, 0x02, // JMP $0213
+    ];
+    let memory = Memory::new();
+    memory.write_block(0x0200, PROGRAM);
+    memory.set_vectors(Vectors { nmi: None, reset: 0x0200, irq: None });
+
+    let mut cpu = C6502::new(&memory);
+    cpu.reset();
+    for _ in 0..5000 {
+        cpu.step();
+    }
+    expect_eq("cpu state", cpu.state(), CpuState::Running)?;
+    expect_eq("sum of 100 additions", memory.read_byte(0x10), (300 & 0xFF) as u8)?;
+    expect_eq("loop counter", memory.read_byte(0x11), 100)
+}
+
+fn check_memory() -> Result<(), String> {
+    let memory = Memory::new();
+    memory
+        .configure_banks(vec![RomBank::with_bytes(&[0xDE, 0xAD])], &[(0xC000, 0x100, 1, 0x0000)])
+        .map_err(|e| e.to_string())?;
+    memory.write_byte(0x1234, 0x5A);
+    expect_eq("ram", memory.read_byte(0x1234), 0x5A)?;
+    expect_eq("rom", memory.read_byte(0xC001), 0xAD)?;
+    memory.write_byte(0xC001, 0x00);
+    expect_eq("rom after write", memory.read_byte(0xC001), 0xAD)?;
+    expect_eq("unmapped rom", memory.read_byte(0xC0FF), 0x00)
+}
+
+fn check_ports() -> Result<(), String> {
+    let mut output = OutputPort8::new();
+    let mut input = InputPort8::new();
+    output.connect_to(&mut input);
+    output.send(0x42);
+    expect_eq("same thread", input.recv(), 0x42)?;
+
+    let sender = thread::spawn(move || {
+        for i in 0..=255 {
+            output.send(i);
+        }
+    });
+    let received: Vec<u8> = (0..=255).map(|_| input.recv()).collect();
+    sender.join().map_err(|_| "sender panicked".to_string())?;
+    expect_eq("cross thread", received, (0..=255).collect())
+}
+
+fn check_clock() -> Result<(), String> {
+    let mut clock = Clock::new(1000);
+    let mut input = InputPin::new();
+    clock.output().connect_to(&mut input);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = stop.clone();
+    let handle = thread::spawn(move || clock.run(stop_clone));
+    thread::sleep(Duration::from_millis(50));
+    stop.store(true, Ordering::Relaxed);
+    handle.join().map_err(|_| "clock panicked".to_string())?;
+
+    if input.drain() > 0 {
+        Ok(())
+    } else {
+        Err("no clock edges generated".to_string())
+    }
+}
+
+fn check_threads() -> Result<(), String> {
+    let handles: Vec<_> = (0..4u32).map(|i| thread::spawn(move || i * 2)).collect();
+    let results: Vec<u32> = handles.into_iter().filter_map(|h| h.join().ok()).collect();
+    expect_eq("thread results", results, vec![0, 2, 4, 6])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selftest_passes() {
+        let report = selftest();
+        assert!(report.passed(), "{}", report);
+    }
+}
@@ -0,0 +1,225 @@
+use crossbeam_channel::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::core::memory::MemoryBank;
+use crate::core::ports::{InputPort8, OutputPort8};
+use crate::core::{AsyncComponent, STOP_POLL_INTERVAL};
+
+/// A serial device that sends back whatever it receives. Each byte takes `delay` to send back,
+/// like a line with a fixed byte rate, so bytes that arrive together come back `delay` apart.
+///
+/// This is a predictable counterpart for testing serial routines: wire a serial port's output
+/// to `input`, and `output` back to the port's input.
+///
+pub struct LoopbackSerial {
+    delay: Duration,
+    input: InputPort8,
+    output: OutputPort8,
+}
+
+impl LoopbackSerial {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, input: InputPort8::new(), output: OutputPort8::new() }
+    }
+
+    pub fn input(&mut self) -> &mut InputPort8 {
+        &mut self.input
+    }
+
+    pub fn output(&mut self) -> &mut OutputPort8 {
+        &mut self.output
+    }
+}
+
+impl AsyncComponent for LoopbackSerial {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        while !stop.load(Ordering::Relaxed) {
+            match self.input.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(value) => {
+                    thread::sleep(self.delay);
+                    self.output.send(value);
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+/// An access to an `EchoDevice` register.
+///
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Access {
+    Read { register: usize, value: u8 },
+    Write { register: usize, value: u8 },
+}
+
+/// The accesses made to an `EchoDevice`, in order. Clones share the same history.
+///
+#[derive(Clone, Default)]
+pub struct AccessHistory(Arc<Mutex<Vec<Access>>>);
+
+impl AccessHistory {
+    pub fn accesses(&self) -> Vec<Access> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// The values written to `register`, oldest first.
+    ///
+    pub fn writes_to(&self, register: usize) -> Vec<u8> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|a| match *a {
+                Access::Write { register: r, value } if r == register => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    fn record(&self, access: Access) {
+        self.0.lock().unwrap().push(access);
+    }
+}
+
+/// A memory-mapped device with a bank of registers, where reading a register returns the value
+/// last written to it. Every access is recorded in an `AccessHistory`, for tests to check the
+/// I/O performed by the code under test.
+///
+/// The registers repeat through each page the device is mapped to.
+///
+pub struct EchoDevice {
+    registers: Vec<u8>,
+    history: AccessHistory,
+}
+
+impl EchoDevice {
+    pub fn new(register_count: usize) -> Box<Self> {
+        assert!(register_count > 0);
+        Box::new(Self { registers: vec![0; register_count], history: AccessHistory::default() })
+    }
+
+    pub fn history(&self) -> AccessHistory {
+        self.history.clone()
+    }
+
+    fn register(&self, addr: u16, offset: u16) -> usize {
+        (addr - offset) as usize % self.registers.len()
+    }
+}
+
+impl MemoryBank for EchoDevice {
+    fn size(&self) -> usize {
+        self.registers.len()
+    }
+
+    fn is_writeable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    fn uses_ram(&self) -> bool {
+        false
+    }
+
+    fn read_byte(&self, addr: u16, offset: u16, _ram: &[u8]) -> u8 {
+        let register = self.register(addr, offset);
+        let value = self.registers[register];
+        self.history.record(Access::Read { register, value });
+        value
+    }
+
+    fn write_byte(&mut self, addr: u16, offset: u16, value: u8, _ram: &mut [u8]) {
+        let register = self.register(addr, offset);
+        self.registers[register] = value;
+        self.history.record(Access::Write { register, value });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::memory::Memory;
+    use std::time::Instant;
+
+    #[test]
+    fn loopback_delay() {
+        let mut serial = LoopbackSerial::new(Duration::from_millis(20));
+        let mut tx = OutputPort8::new();
+        let mut rx = InputPort8::new();
+        tx.connect_to(serial.input());
+        serial.output().connect_to(&mut rx);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let handle = thread::spawn(move || serial.run(stop_clone));
+
+        let start = Instant::now();
+        tx.send(0x41);
+        assert_eq!(rx.recv(), 0x41);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        tx.send(0x42);
+        tx.send(0x43);
+        assert_eq!(rx.recv(), 0x42);
+        assert_eq!(rx.recv(), 0x43);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        // It stops while waiting for input, without any more arriving.
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        drop(tx);
+    }
+
+    #[test]
+    fn loopback_disconnected() {
+        let mut serial = LoopbackSerial::new(Duration::ZERO);
+        let mut tx = OutputPort8::new();
+        let mut rx = InputPort8::new();
+        tx.connect_to(serial.input());
+        serial.output().connect_to(&mut rx);
+        tx.send(0x41);
+
+        // Once the sender has gone, it stops, having sent back only what it received.
+        drop(tx);
+        serial.run(Arc::new(AtomicBool::new(false)));
+        assert_eq!(rx.try_recv(), Some(0x41));
+        assert_eq!(rx.try_recv(), None);
+    }
+
+    #[test]
+    fn echo_device_history() {
+        let device = EchoDevice::new(4);
+        let history = device.history();
+        let memory = Memory::new();
+        memory.configure_banks(vec![device], &[(0xD000, 0x100, 1, 0x0000)]).unwrap();
+
+        memory.write_byte(0xD001, 0x12);
+        memory.write_byte(0xD002, 0x34);
+        assert_eq!(memory.read_byte(0xD001), 0x12);
+        assert_eq!(memory.read_byte(0xD005), 0x12);
+        memory.write_byte(0xD001, 0x56);
+        assert_eq!(memory.read_byte(0xD000), 0x00);
+
+        assert_eq!(
+            history.accesses(),
+            vec![
+                Access::Write { register: 1, value: 0x12 },
+                Access::Write { register: 2, value: 0x34 },
+                Access::Read { register: 1, value: 0x12 },
+                Access::Read { register: 1, value: 0x12 },
+                Access::Write { register: 1, value: 0x56 },
+                Access::Read { register: 0, value: 0x00 },
+            ]
+        );
+        assert_eq!(history.writes_to(1), vec![0x12, 0x56]);
+        history.clear();
+        assert!(history.accesses().is_empty());
+    }
+}
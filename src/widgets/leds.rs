@@ -32,7 +32,10 @@ impl Led {
 
     fn update(&mut self) {
         self.draw_state.borrow_mut().state = self.input.value();
-        self.area.as_ref().unwrap().queue_redraw_all(&self.ui.as_ref().unwrap());
+        // The control may never have been created, if the UI is unavailable.
+        if let (Some(area), Some(ui)) = (self.area.as_ref(), self.ui.as_ref()) {
+            area.queue_redraw_all(ui);
+        }
     }
 }
 
@@ -48,7 +51,7 @@ impl SyncComponent for Led {
     }
 
     fn tick(&mut self) {
-        if self.input.try_recv().is_some() {
+        if self.input.drain() > 0 {
             self.update();
         }
     }
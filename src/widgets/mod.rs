@@ -24,3 +24,5 @@ impl From<&Color> for SolidBrush {
 }
 
 pub mod leds;
+pub mod pages;
+pub mod potentiometer;
@@ -0,0 +1,270 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iui::controls::*;
+use iui::draw::*;
+use iui::UI;
+
+use crate::core::memory::{Memory, WatchHandle, WatchKind};
+use crate::core::{SyncComponent, UiComponent};
+use crate::widgets::Color;
+
+const COLUMNS: usize = 16;
+const ROWS_PER_PAGE: usize = 16;
+
+/// Shows the zero page, and optionally the stack page, as a grid of cells, one per byte. Cells
+/// light up when written to, and fade over `FADE`, so pointer corruption and stack creep are
+/// easy to spot. Clicking or hovering over a cell shows its address and value.
+///
+/// The view is fed by a watch on the memory, rather than by polling it. The watch is removed
+/// when the view is dropped.
+///
+pub struct PageView {
+    memory: Memory,
+    watch: WatchHandle,
+    activity: Arc<Mutex<PageActivity>>,
+    changed: Arc<AtomicBool>,
+    ui: Option<UI>,
+    area: Option<Area>,
+    draw_state: Rc<RefCell<DrawState>>,
+}
+
+impl PageView {
+    /// How long a written cell takes to fade back to the idle color.
+    pub const FADE: Duration = Duration::from_secs(2);
+
+    pub fn new(memory: &Memory, include_stack: bool) -> Self {
+        let pages = if include_stack { 2 } else { 1 };
+        // Start from what's in memory already, so the values shown are right before anything is
+        // written.
+        let mut values = vec![0; pages * 0x100];
+        memory.read_block(0x0000, &mut values);
+        let activity = Arc::new(Mutex::new(PageActivity::new(values)));
+        let changed = Arc::new(AtomicBool::new(true));
+        let (observed, observed_changed) = (activity.clone(), changed.clone());
+        let end = (pages * 0x100 - 1) as u16;
+        let watch = memory.add_watch(0x0000..=end, WatchKind::Write, move |addr, value, _| {
+            observed.lock().unwrap().record(addr, value, Instant::now());
+            observed_changed.store(true, Ordering::Relaxed);
+        });
+        Self {
+            memory: memory.clone(),
+            watch,
+            activity: activity.clone(),
+            changed,
+            ui: None,
+            area: None,
+            draw_state: Rc::new(RefCell::new(DrawState { pages, activity, label: None, ui: None, selected: None })),
+        }
+    }
+
+    fn update(&mut self) {
+        if self.changed.swap(false, Ordering::Relaxed) {
+            // The control may never have been created, if the UI is unavailable.
+            if let (Some(area), Some(ui)) = (self.area.as_ref(), self.ui.as_ref()) {
+                area.queue_redraw_all(ui);
+                self.draw_state.borrow_mut().update_label();
+            }
+        }
+    }
+}
+
+impl Drop for PageView {
+    fn drop(&mut self) {
+        self.memory.remove_watch(self.watch);
+    }
+}
+
+impl SyncComponent for PageView {
+    fn start(&mut self) {
+        self.update();
+    }
+
+    fn tick(&mut self) {
+        // Keep redrawing while cells are fading.
+        if self.activity.lock().unwrap().last_write.is_some_and(|t| t.elapsed() < Self::FADE) {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        self.update();
+    }
+
+    fn stop(&mut self) {}
+}
+
+impl UiComponent for PageView {
+    fn create_control(&mut self, ui: UI) -> Control {
+        let area = Area::new(&ui, self.draw_state.clone());
+        let label = Label::new(&ui, "");
+        let mut vbox = VerticalBox::new(&ui);
+        vbox.append(&ui, area.clone(), LayoutStrategy::Stretchy);
+        vbox.append(&ui, label.clone(), LayoutStrategy::Compact);
+        {
+            let mut draw_state = self.draw_state.borrow_mut();
+            draw_state.label = Some(label);
+            draw_state.ui = Some(ui.clone());
+        }
+        self.area = Some(area);
+        self.ui = Some(ui);
+        vbox.into()
+    }
+}
+
+/// The value of each byte shown, and when it was last written.
+///
+struct PageActivity {
+    values: Vec<u8>,
+    written: Vec<Option<Instant>>,
+    last_write: Option<Instant>,
+}
+
+impl PageActivity {
+    fn new(values: Vec<u8>) -> Self {
+        let written = vec![None; values.len()];
+        Self { values, written, last_write: None }
+    }
+
+    fn record(&mut self, addr: u16, value: u8, at: Instant) {
+        self.values[addr as usize] = value;
+        self.written[addr as usize] = Some(at);
+        self.last_write = Some(at);
+    }
+}
+
+/// The color of a cell last written `age` ago, or never written if `None`: bright when just
+/// written, fading linearly to the idle color over `PageView::FADE`.
+///
+fn cell_color(age: Option<Duration>) -> Color {
+    const IDLE: (f64, f64, f64) = (0.25, 0.25, 0.25);
+    const HOT: (f64, f64, f64) = (1.0, 0.6, 0.0);
+    let heat = match age {
+        Some(age) if age < PageView::FADE => 1.0 - age.as_secs_f64() / PageView::FADE.as_secs_f64(),
+        _ => 0.0,
+    };
+    let mix = |idle: f64, hot: f64| idle + (hot - idle) * heat;
+    Color::new(mix(IDLE.0, HOT.0), mix(IDLE.1, HOT.1), mix(IDLE.2, HOT.2))
+}
+
+/// The address of the cell at (`x`, `y`) in a grid of `pages` pages filling `width` by
+/// `height`, or `None` if the point is outside the grid.
+///
+fn cell_at(x: f64, y: f64, width: f64, height: f64, pages: usize) -> Option<u16> {
+    let rows = pages * ROWS_PER_PAGE;
+    if x < 0.0 || y < 0.0 || x >= width || y >= height {
+        return None;
+    }
+    let column = (x / (width / COLUMNS as f64)) as usize;
+    let row = (y / (height / rows as f64)) as usize;
+    Some((row.min(rows - 1) * COLUMNS + column.min(COLUMNS - 1)) as u16)
+}
+
+struct DrawState {
+    pages: usize,
+    activity: Arc<Mutex<PageActivity>>,
+    label: Option<Label>,
+    ui: Option<UI>,
+    selected: Option<u16>,
+}
+
+impl DrawState {
+    fn update_label(&mut self) {
+        let text = match self.selected {
+            Some(addr) => format!("${:04X}: ${:02X}", addr, self.activity.lock().unwrap().values[addr as usize]),
+            None => String::new(),
+        };
+        if let (Some(label), Some(ui)) = (self.label.as_mut(), self.ui.as_ref()) {
+            label.set_text(ui, &text);
+        }
+    }
+}
+
+impl AreaHandler for DrawState {
+    fn draw(&mut self, _: &Area, draw_params: &AreaDrawParams) {
+        let ctx = &draw_params.context;
+        let rows = self.pages * ROWS_PER_PAGE;
+        let cell_width = draw_params.area_width / COLUMNS as f64;
+        let cell_height = draw_params.area_height / rows as f64;
+        let now = Instant::now();
+        let activity = self.activity.lock().unwrap();
+        for (addr, written) in activity.written.iter().enumerate() {
+            let (row, column) = (addr / COLUMNS, addr % COLUMNS);
+            let path = Path::new(ctx, FillMode::Winding);
+            path.add_rectangle(
+                ctx,
+                column as f64 * cell_width + 1.0,
+                row as f64 * cell_height + 1.0,
+                cell_width - 2.0,
+                cell_height - 2.0,
+            );
+            path.end(ctx);
+            let color = cell_color(written.map(|t| now.saturating_duration_since(t)));
+            ctx.fill(&path, &Brush::Solid(SolidBrush::from(&color)));
+        }
+    }
+
+    fn mouse_event(&mut self, _: &Area, event: &AreaMouseEvent) {
+        self.selected = cell_at(event.x, event.y, event.area_width, event.area_height, self.pages);
+        self.update_label();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        let idle = cell_color(None);
+        assert_eq!((idle.r, idle.g, idle.b), (0.25, 0.25, 0.25));
+        let hot = cell_color(Some(Duration::ZERO));
+        assert_eq!((hot.r, hot.g, hot.b), (1.0, 0.6, 0.0));
+        let half = cell_color(Some(PageView::FADE / 2));
+        assert!((half.r - 0.625).abs() < 1e-9);
+        let faded = cell_color(Some(PageView::FADE * 2));
+        assert_eq!((faded.r, faded.g, faded.b), (0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn hit_testing() {
+        assert_eq!(cell_at(0.0, 0.0, 160.0, 160.0, 1), Some(0x00));
+        assert_eq!(cell_at(15.0, 15.0, 160.0, 160.0, 1), Some(0x11));
+        assert_eq!(cell_at(159.0, 159.0, 160.0, 160.0, 1), Some(0xFF));
+        assert_eq!(cell_at(159.0, 319.0, 160.0, 320.0, 2), Some(0x1FF));
+        assert_eq!(cell_at(160.0, 0.0, 160.0, 160.0, 1), None);
+    }
+
+    #[test]
+    fn observes_writes() {
+        let memory = Memory::new();
+        memory.write_byte(0x0010, 0x55);
+        let view = PageView::new(&memory, false);
+        {
+            // Values already in memory are shown, but not as written.
+            let activity = view.activity.lock().unwrap();
+            assert_eq!((activity.values.len(), activity.values[0x10]), (0x100, 0x55));
+            assert!(activity.written[0x10].is_none());
+        }
+        view.changed.store(false, Ordering::Relaxed);
+        memory.write_byte(0x0200, 0x11);
+        assert!(!view.changed.load(Ordering::Relaxed));
+        memory.write_byte(0x0042, 0x99);
+        assert!(view.changed.load(Ordering::Relaxed));
+
+        memory.write_block(0x0080, &[0x12, 0x34]);
+        {
+            let activity = view.activity.lock().unwrap();
+            assert_eq!(activity.values[0x42], 0x99);
+            assert!(activity.written[0x42].is_some());
+            assert!(activity.written[0x41].is_none());
+            assert_eq!((activity.values[0x80], activity.values[0x81]), (0x12, 0x34));
+        }
+
+        // Dropping the view removes its watch, so the zero page goes back to the fast path.
+        let activity = view.activity.clone();
+        drop(view);
+        memory.write_byte(0x0043, 0x77);
+        assert!(activity.lock().unwrap().written[0x43].is_none());
+    }
+}
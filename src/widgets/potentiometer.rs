@@ -0,0 +1,92 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use iui::controls::*;
+
+use crate::core::ports::OutputPortF64;
+use crate::core::{SyncComponent, UiComponent};
+
+/// A slider that produces an analog level from 0.0 to 1.0, e.g. a paddle position or sensor
+/// reading to feed to an `Adc8`. The level is sent on `output` whenever the slider moves.
+///
+pub struct Potentiometer {
+    output: OutputPortF64,
+    initial_level: f64,
+    moved_to: Rc<Cell<Option<i32>>>,
+}
+
+impl Potentiometer {
+    /// The number of steps the slider's travel is divided into.
+    pub const STEPS: i32 = 1000;
+
+    pub fn new(initial_level: f64) -> Self {
+        Self {
+            output: OutputPortF64::with_initial_value(initial_level.clamp(0.0, 1.0)),
+            initial_level: initial_level.clamp(0.0, 1.0),
+            moved_to: Rc::new(Cell::new(None)),
+        }
+    }
+
+    pub fn output(&mut self) -> &mut OutputPortF64 {
+        &mut self.output
+    }
+
+    fn level(position: i32) -> f64 {
+        position.clamp(0, Self::STEPS) as f64 / Self::STEPS as f64
+    }
+}
+
+impl Default for Potentiometer {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+impl SyncComponent for Potentiometer {
+    fn start(&mut self) {
+        self.output.send(self.initial_level);
+    }
+
+    fn tick(&mut self) {
+        // The slider's callback can't reach the port, so it leaves the position for the tick.
+        if let Some(position) = self.moved_to.take() {
+            self.output.send(Self::level(position));
+        }
+    }
+
+    fn stop(&mut self) {}
+}
+
+impl UiComponent for Potentiometer {
+    fn create_control(&mut self, ui: iui::UI) -> Control {
+        let mut slider = Slider::new(&ui, 0, Self::STEPS);
+        slider.set_value(&ui, (self.initial_level * Self::STEPS as f64).round() as i32);
+        let moved_to = self.moved_to.clone();
+        slider.on_changed(&ui, move |position| moved_to.set(Some(position)));
+        slider.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ports::InputPortF64;
+
+    #[test]
+    fn sends_level_when_moved() {
+        let mut pot = Potentiometer::new(0.25);
+        let mut input = InputPortF64::new();
+        pot.output().connect_to(&mut input);
+
+        pot.start();
+        assert_eq!(input.try_recv(), Some(0.25));
+        pot.tick();
+        assert_eq!(input.try_recv(), None);
+
+        pot.moved_to.set(Some(600));
+        pot.moved_to.set(Some(750));
+        pot.tick();
+        assert_eq!(input.try_recv(), Some(0.75));
+        assert_eq!(input.try_recv(), None);
+    }
+}